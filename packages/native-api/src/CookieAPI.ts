@@ -0,0 +1,89 @@
+import NodePlugin from "./NodePlugin";
+
+let svc: any;
+
+export interface CookieOptions {
+    path?: string;
+    domain?: string;
+    maxAge?: number;
+    expires?: Date | number;
+    secure?: boolean;
+    httpOnly?: boolean;
+    sameSite?: "strict" | "lax" | "none";
+    partitioned?: boolean;
+}
+
+export function serializeCookie(name: string, value: string, options: CookieOptions = {}) {
+    svc = NodePlugin.setup();
+
+    const { path, domain, maxAge, expires, secure, httpOnly, sameSite, partitioned } = options;
+    const ms = expires instanceof Date ? expires.getTime() : expires;
+    return svc.cookie_serialize(name, value, path, domain, maxAge, ms, secure, httpOnly, sameSite, partitioned) as string;
+}
+
+export class CookieKeys {
+    readonly ref: [unknown];
+
+    constructor(ref: [unknown]) {
+        this.ref = ref;
+    }
+
+    // The first secret signs and seals, the others are only used to open values issued before a rotation.
+    static create(secrets: string[]) {
+        svc = NodePlugin.setup();
+
+        const ref = svc.cookie_keys_create(secrets);
+        return new this([ref]);
+    }
+
+    handle() {
+        const { ref } = this;
+        if (ref[0]) {
+            return ref[0];
+        }
+
+        return undefined;
+    }
+
+    close() {
+        const { ref } = this;
+        if (ref[0]) {
+            svc.cookie_keys_close(ref.pop());
+        }
+    }
+
+    sign(name: string, value: string) {
+        return svc.cookie_sign(this.handle(), name, value) as string;
+    }
+
+    seal(name: string, value: string) {
+        return svc.cookie_seal(this.handle(), name, value) as string;
+    }
+
+    open(name: string, value: string) {
+        return svc.cookie_open(this.handle(), name, value) as string | null;
+    }
+}
+
+export class CookieAPI {
+    static create() {
+        svc = NodePlugin.setup();
+        return new this();
+    }
+
+    parse(header: string) {
+        const list = svc.cookie_parse(header) as string[];
+        const result: [name: string, value: string][] = [];
+        for (let i = 0; i < list.length; i += 2) {
+            result.push([list[i], list[i + 1]]);
+        }
+
+        return result;
+    }
+
+    serialize(name: string, value: string, options: CookieOptions = {}) {
+        return serializeCookie(name, value, options);
+    }
+}
+
+export default CookieAPI;
@@ -0,0 +1,715 @@
+/*
+    An in-memory stand-in for the native plugin. It implements the request exports used by
+    SystemHttpRequest so that handlers can be tested without an HTTP.sys queue:
+
+    1. Testing is viable without accessing a windows system.
+    2. Every send and send_data call is captured with its flags, headers and trailers.
+    3. Outbound client calls go through Node's http module, so they can target a local server.
+    4. trace_config works as in the native plugin; request operations are traced with "fake" as the target.
+    5. Tokens, certificates, signatures and Accept headers are not checked. An injected request states
+       the identity, signed cookies, negotiated values and session it would have had; they are
+       reported only while the matching feature is configured on the queue, as the plugin does.
+    6. Handover events are kept in memory and are auto reset, like the named events they stand for.
+*/
+
+import { appendFileSync } from "fs";
+import { ClientRequest, IncomingMessage, request } from "http";
+import { NodePlugin } from "./NodePlugin";
+import type { Session } from "./SessionAPI";
+import { AcceptKind, RequestIdentity, toBuffer } from "./io/Request";
+import { mapper } from "./io/SystemHttpRequest";
+import { TraceCallback, TraceLevel } from "./TraceAPI";
+import { UserGroup } from "./UserAPI";
+
+export const ERROR_HANDLE_EOF = 38;
+export const ERROR_MORE_DATA = 234;
+export const ERROR_OPERATION_ABORTED = 995;
+export const ERROR_CONNECTION_INVALID = 1229;
+
+const TRACE_LEVELS: TraceLevel[] = ["off", "error", "warn", "info", "debug", "trace"];
+
+export type HeaderList = [name: string, value: string][];
+
+export interface FakeRequestInit {
+    method?: string;
+    url: string;
+    version?: string;
+    headers?: HeaderList;
+    body?: string | Buffer | (string | Buffer)[];
+    http2?: boolean;
+    group?: string;
+    userSid?: string;
+    groups?: UserGroup[];
+    identity?: RequestIdentity & { claims?: Record<string, unknown>; sid?: string };
+    signedCookies?: HeaderList;
+    negotiated?: Partial<Record<AcceptKind, string | null>>;
+    session?: Session;
+}
+
+export interface FakeFlags {
+    opaque: boolean;
+    more: boolean;
+    disconnect: boolean;
+    options: number;
+    cache: number;
+}
+
+export interface FakeCall {
+    op: string;
+    id: number;
+    flags?: FakeFlags;
+    status?: number;
+    reason?: string;
+    version?: string;
+    headers: HeaderList;
+    data: Buffer[];
+    trailers: HeaderList;
+}
+
+interface FakeEntry {
+    id: number;
+    init: FakeRequestInit;
+    body: Buffer[];
+    open: boolean;
+}
+
+interface FakeRef {
+    name: string;
+    open: boolean;
+    stopped: boolean;
+    jwt: Map<string, { required: boolean }>;
+    certs?: { required: boolean; untrusted: boolean };
+    offers?: Partial<Record<AcceptKind, string[]>>;
+    cookieKeys?: unknown;
+    sessions?: { cookie: string; byUser: boolean };
+}
+
+interface FakeEvent {
+    set: boolean;
+    waiters: ((value: boolean) => void)[];
+}
+
+interface FakeUser {
+    sid: string;
+    groups: UserGroup[];
+}
+
+export interface FakeClientOpen {
+    method: string;
+    url: string;
+    headers: HeaderList;
+    credentials: boolean;
+}
+
+interface FakeClientRequest {
+    method: string;
+    url: string;
+    headers: HeaderList;
+    open: boolean;
+    req?: ClientRequest;
+    res?: Promise<IncomingMessage>;
+    body?: AsyncIterator<Buffer>;
+}
+
+function read(block: Buffer, off: number, len: number, enc: BufferEncoding = "utf-8") {
+    return block.toString(enc, off, off + len);
+}
+
+export class FakePlugin {
+    readonly calls: FakeCall[] = [];
+    readonly clientOpens: FakeClientOpen[] = [];
+    clientRequests = 0;
+
+    private seq = 0;
+    private pending: FakeEntry[] = [];
+    private active = new Map<number, FakeEntry>();
+    private waiters: (() => void)[] = [];
+    private errors = new Map<string, number[]>();
+    private names = new Map<string, string>();
+    private events = new Map<string, FakeEvent>();
+    private traceRank = 0;
+    private traceSink?: TraceCallback | string;
+
+    install() {
+        NodePlugin.install(this.exports());
+        return this;
+    }
+
+    inject(init: FakeRequestInit) {
+        const id = ++this.seq;
+        const body = init.body === undefined ? [] : Array.isArray(init.body) ? init.body : [init.body];
+        this.pending.push({ id, init, body: body.map(toBuffer), open: true });
+        this.wake();
+
+        return id;
+    }
+
+    fail(op: string, code: number, times = 1) {
+        const list = this.errors.get(op) || [];
+        while (times-- > 0) {
+            list.push(code);
+        }
+
+        this.errors.set(op, list);
+    }
+
+    drop(id: number) {
+        const entry = this.active.get(id);
+        if (entry) {
+            entry.open = false;
+        }
+    }
+
+    name(sid: string, name: string) {
+        this.names.set(sid, name);
+    }
+
+    callsFor(id: number) {
+        return this.calls.filter(x => x.id === id);
+    }
+
+    private wake() {
+        const { waiters } = this;
+        this.waiters = [];
+        waiters.forEach(x => x());
+    }
+
+    private trace(span: string, fields: string, message: string) {
+        const { traceRank, traceSink } = this;
+        if (traceRank < TRACE_LEVELS.indexOf("trace") || traceSink === undefined) {
+            return;
+        }
+
+        const line = `TRACE fake ${span}{${fields}}: ${message}`;
+        if (typeof traceSink === "string") {
+            appendFileSync(traceSink, `${line}\n`);
+        } else {
+            traceSink("trace", line);
+        }
+    }
+
+    private error(op: string) {
+        const list = this.errors.get(op);
+        return list && list.length > 0 ? list.shift() : undefined;
+    }
+
+    private check(op: string, id: number) {
+        const code = this.error(op);
+        if (code !== undefined) {
+            return code;
+        }
+
+        const entry = this.active.get(id);
+        if (entry === undefined || !entry.open) {
+            return ERROR_CONNECTION_INVALID;
+        }
+
+        return 0;
+    }
+
+    private headers(args: any[], block: Buffer, i: number, name: (id: number) => string) {
+        const result: HeaderList = [];
+        while (i < args.length) {
+            const id = args[i++] as number;
+            const value = read(block, args[i++], args[i++]);
+            if (id < 0) {
+                result.push([read(block, args[i++], args[i++]), value]);
+            } else {
+                result.push([name(id), value]);
+            }
+        }
+
+        return result;
+    }
+
+    private event(name: string) {
+        let event = this.events.get(name);
+        if (event === undefined) {
+            event = { set: false, waiters: [] };
+            this.events.set(name, event);
+        }
+
+        return event;
+    }
+
+    private jwtFor(ref: FakeRef, init: FakeRequestInit) {
+        return ref.jwt.get(init.group || "") || ref.jwt.get("");
+    }
+
+    // Answers a request the way the plugin does before JS sees it, when a required identity is missing.
+    private reject(ref: FakeRef, entry: FakeEntry) {
+        const kind = entry.init.identity?.kind;
+        let status = 0;
+        if (this.jwtFor(ref, entry.init)?.required && kind !== "jwt") {
+            status = 401;
+        } else if (ref.certs?.required && kind !== "cert") {
+            status = 403;
+        }
+
+        if (status > 0) {
+            const reason = status === 401 ? "Unauthorized" : "Forbidden";
+            this.calls.push({ op: "reject", id: entry.id, status, reason, headers: [], data: [], trailers: [] });
+        }
+
+        return status > 0;
+    }
+
+    private async receive(ref: FakeRef) {
+        let entry: FakeEntry | undefined;
+        while (entry === undefined) {
+            while (ref.open && !ref.stopped && this.pending.length < 1) {
+                await new Promise<void>(resolve => this.waiters.push(resolve));
+            }
+
+            if (!ref.open || ref.stopped) {
+                return { code: ERROR_OPERATION_ABORTED };
+            }
+
+            entry = this.pending.shift()!;
+            if (this.reject(ref, entry)) {
+                entry = undefined;
+            }
+        }
+
+        const code = this.error("receive");
+        this.trace("receive", `id=${entry.id}`, `complete err=${code || 0}`);
+        if (code !== undefined) {
+            return code === ERROR_MORE_DATA ? { code, id: entry.id } : { code };
+        }
+
+        this.active.set(entry.id, entry);
+
+        const { init } = entry;
+        const knownHeaders: string[] = [];
+        const unknownHeaders: string[] = [];
+        for (const [name, value] of init.headers || []) {
+            const id = mapper.requestId(name);
+            if (id < 0) {
+                unknownHeaders.push(name, value);
+            } else {
+                knownHeaders[id] = knownHeaders[id] ? `${knownHeaders[id]}, ${value}` : value;
+            }
+        }
+
+        const method = init.method || "GET";
+        const verb = mapper.method(method);
+        const result: Record<string, unknown> = {
+            code: 0,
+            id: entry.id,
+            connection: entry.id,
+            verb,
+            version: init.version || "1.1",
+            url: init.url,
+            body: entry.body.length > 0,
+            http2: !!init.http2,
+            knownHeaders,
+            unknownHeaders,
+        };
+
+        if (verb === 0) {
+            result.customVerb = method;
+        }
+
+        if (init.userSid) {
+            const user: FakeUser = { sid: init.userSid, groups: init.groups || [["user", init.userSid]] };
+            result.user = user;
+            result.user_sid = init.userSid;
+        }
+
+        const { identity } = init;
+        const verified = identity && ((identity.kind === "jwt" && this.jwtFor(ref, init)) || (identity.kind === "cert" && ref.certs));
+        if (identity && verified && !init.userSid) {
+            result.identity = { kind: identity.kind, subject: identity.subject };
+            result.claims = JSON.stringify(identity.claims || {});
+            result.groups = init.groups || [];
+            if (identity.sid) {
+                result.user_sid = identity.sid;
+            }
+        }
+
+        if (ref.offers) {
+            const negotiated: Record<string, string | null> = {};
+            for (const [kind, offers] of Object.entries(ref.offers)) {
+                const given = init.negotiated?.[kind as AcceptKind];
+                negotiated[kind] = given !== undefined ? given : offers[0] ?? null;
+            }
+
+            result.negotiated = negotiated;
+        }
+
+        const cookies: string[] = [];
+        for (const [name, value] of init.headers || []) {
+            if (name.toLowerCase() !== "cookie") {
+                continue;
+            }
+
+            for (const pair of value.split(";")) {
+                const at = pair.indexOf("=");
+                if (at > 0) {
+                    cookies.push(pair.slice(0, at).trim(), pair.slice(at + 1).trim());
+                }
+            }
+        }
+
+        if (cookies.length > 0) {
+            result.cookies = cookies;
+        }
+
+        if (ref.cookieKeys && init.signedCookies) {
+            result.signedCookies = init.signedCookies.flat();
+        }
+
+        if (ref.sessions && init.session) {
+            result.session = { ...init.session, data: JSON.stringify(init.session.data) };
+        }
+
+        return result;
+    }
+
+    exports() {
+        const self = this;
+        return {
+            trace_config(level: string, target?: TraceCallback | string) {
+                const rank = TRACE_LEVELS.findIndex(x => x === level.toLowerCase());
+                if (rank < 0) {
+                    throw new TypeError(`Unknown trace level: ${level}`);
+                }
+
+                self.traceSink = target;
+                self.traceRank = target === undefined ? 0 : rank;
+            },
+
+            http_request_create(name: string): FakeRef {
+                return { name, open: true, stopped: false, jwt: new Map() };
+            },
+
+            async http_request_jwt(ref: FakeRef, jwks: string, _issuer?: string, _audience?: string, _skew?: number, required = false, _expiry?: boolean, group = "") {
+                if (/^http:\/\//i.test(jwks)) {
+                    throw new TypeError(`Key sets must be fetched over https: ${jwks}`);
+                }
+
+                if (jwks) {
+                    ref.jwt.set(group, { required });
+                } else {
+                    ref.jwt.delete(group);
+                }
+            },
+
+            http_request_cert_map(ref: FakeRef, rules: string[], required = false, untrusted = false) {
+                ref.certs = rules.length > 0 ? { required, untrusted } : undefined;
+            },
+
+            http_request_negotiate(ref: FakeRef, type: string[], language: string[], encoding: string[], charset: string[]) {
+                const offers: Partial<Record<AcceptKind, string[]>> = {};
+                const lists: [AcceptKind, string[]][] = [["type", type], ["language", language], ["encoding", encoding], ["charset", charset]];
+                for (const [kind, list] of lists) {
+                    if (list.length > 0) {
+                        offers[kind] = list;
+                    }
+                }
+
+                ref.offers = Object.keys(offers).length > 0 ? offers : undefined;
+            },
+
+            http_request_cookie_keys(ref: FakeRef, keys?: unknown) {
+                ref.cookieKeys = keys;
+            },
+
+            http_request_sessions(ref: FakeRef, store?: unknown, cookie = "sid", byUser = false) {
+                ref.sessions = store ? { cookie, byUser } : undefined;
+            },
+
+            http_request_stop(ref: FakeRef) {
+                ref.stopped = true;
+                self.wake();
+            },
+
+            async http_request_drain(_: FakeRef, timeout: number) {
+                const start = Date.now();
+                while (self.active.size > 0 && Date.now() - start < timeout) {
+                    await new Promise(resolve => setTimeout(resolve, 20));
+                }
+
+                return self.active.size;
+            },
+
+            handover_signal(name: string) {
+                const event = self.event(name);
+                const next = event.waiters.shift();
+                if (next) {
+                    next(true);
+                } else {
+                    event.set = true;
+                }
+            },
+
+            async handover_wait(name: string, timeout: number) {
+                const event = self.event(name);
+                if (event.set) {
+                    event.set = false;
+                    return true;
+                }
+
+                return await new Promise<boolean>(resolve => {
+                    const timer = setTimeout(() => {
+                        event.waiters = event.waiters.filter(x => x !== done);
+                        resolve(false);
+                    }, timeout);
+
+                    const done = (value: boolean) => {
+                        clearTimeout(timer);
+                        resolve(value);
+                    };
+
+                    event.waiters.push(done);
+                });
+            },
+
+            http_session_close(ref: FakeRef) {
+                ref.open = false;
+                self.wake();
+            },
+
+            http_request_receive(ref: FakeRef) {
+                return self.receive(ref);
+            },
+
+            async http_request_receive_data(_: FakeRef, id: number, data: Buffer) {
+                const code = self.check("receive_data", id);
+                self.trace("receive_data", `id=${id} len=${data.byteLength}`, `issue err=${code}`);
+                if (code !== 0) {
+                    return { code, size: 0, eof: code === ERROR_HANDLE_EOF, limit: false };
+                }
+
+                const entry = self.active.get(id)!;
+                const next = entry.body.shift();
+                if (next === undefined) {
+                    return { code: ERROR_HANDLE_EOF, size: 0, eof: true, limit: false };
+                }
+
+                const size = next.copy(data);
+                if (size < next.byteLength) {
+                    entry.body.unshift(next.subarray(size));
+                }
+
+                return { code: 0, size, eof: false, limit: false };
+            },
+
+            async http_request_send(_: FakeRef, id: number, block: Buffer, ...args: any[]) {
+                const [opaque, more, disconnect, options, cache, status, major, minor, off, len] = args;
+                self.calls.push({
+                    op: "send",
+                    id,
+                    flags: { opaque, more, disconnect, options, cache },
+                    status,
+                    reason: read(block, off, len),
+                    version: `${major}.${minor}`,
+                    headers: self.headers(args, block, 10, mapper.responseName),
+                    data: [],
+                    trailers: [],
+                });
+
+                const code = self.check("send", id);
+                self.trace("send", `id=${id} more=${more} status=${status}`, `issue err=${code}`);
+                if (code === 0 && !more) {
+                    self.active.delete(id);
+                }
+
+                return { code, size: 0, eof: code === ERROR_HANDLE_EOF };
+            },
+
+            async http_request_send_data(_: FakeRef, id: number, count: number, ...args: any[]) {
+                const data = args.slice(0, count).map(x => Buffer.from(x as Buffer));
+                const [block, opaque, more, disconnect, options] = args.slice(count);
+                const trailers: HeaderList = [];
+                for (let i = count + 5; i < args.length; i += 4) {
+                    trailers.push([read(block, args[i], args[i + 1]), read(block, args[i + 2], args[i + 3])]);
+                }
+
+                self.calls.push({
+                    op: "send_data",
+                    id,
+                    flags: { opaque, more, disconnect, options, cache: 0 },
+                    headers: [],
+                    data,
+                    trailers,
+                });
+
+                const code = self.check("send_data", id);
+                const size = data.reduce((a, x) => a + x.byteLength, 0);
+                self.trace("send_data", `id=${id} more=${more} chunks=${count}`, `issue err=${code}`);
+                if (code === 0 && !more) {
+                    self.active.delete(id);
+                }
+
+                return { code, size: code ? 0 : size, eof: code === ERROR_HANDLE_EOF };
+            },
+
+            http_request_push(_: FakeRef, id: number, block: Buffer, ...args: any[]) {
+                const [verb, pathOff, pathLen, queryOff, queryLen] = args;
+                const path = read(block, pathOff, pathLen, "ucs-2");
+                const query = read(block, queryOff, queryLen);
+                self.calls.push({
+                    op: "push",
+                    id,
+                    reason: `${mapper.verb(verb)} ${path}${query}`,
+                    headers: self.headers(args, block, 5, mapper.request),
+                    data: [],
+                    trailers: [],
+                });
+            },
+
+            async http_request_cancel(_: FakeRef, id: number) {
+                self.calls.push({ op: "cancel", id, headers: [], data: [], trailers: [] });
+                self.active.delete(id);
+
+                const code = self.error("cancel") || 0;
+                self.trace("cancel", `id=${id}`, `issue err=${code}`);
+                return code;
+            },
+
+            async http_request_reset(_: FakeRef, id: number, code: number) {
+                self.calls.push({ op: "reset", id, status: code, headers: [], data: [], trailers: [] });
+                self.active.delete(id);
+
+                const err = self.error("reset") || 0;
+                self.trace("reset", `id=${id} code=${code}`, `issue err=${err}`);
+                return err;
+            },
+
+            async http_request_flush(_: FakeRef, url: string, recursive: boolean) {
+                self.calls.push({ op: "flush", id: 0, reason: url, status: recursive ? 1 : 0, headers: [], data: [], trailers: [] });
+                return self.error("flush") || 0;
+            },
+
+            http_request_tcp_info() {
+                return { version: -1 };
+            },
+
+            user_groups(_: string, user: FakeUser) {
+                return user.groups.map(x => [x[0], x[1]]);
+            },
+
+            async user_lookup_sid(sid: string) {
+                return self.names.get(sid);
+            },
+
+            user_close() {
+
+            },
+
+            http_client_create(_agent?: string, redirect = "follow", maxRedirects = 10) {
+                return { redirect, maxRedirects };
+            },
+
+            http_client_destroy() {
+
+            },
+
+            http_client_open(_: unknown, method: string, url: string, headers: string[], _user?: unknown, credentials = true): FakeClientRequest {
+                const list: HeaderList = [];
+                for (let i = 0; i < headers.length; i += 2) {
+                    list.push([headers[i], headers[i + 1]]);
+                }
+
+                self.clientOpens.push({ method, url, headers: list, credentials });
+                self.clientRequests++;
+                return { method, url, headers: list, open: true };
+            },
+
+            // Mirrors next_redirect in the plugin.
+            http_client_redirect(client: { redirect: string, maxRedirects: number }, origin: string, url: string, method: string, status: number, location: string, hops: number) {
+                const never = client.redirect === "manual" || client.redirect === "never";
+                if (never || hops >= client.maxRedirects || ![301, 302, 303, 307, 308].includes(status)) {
+                    return null;
+                }
+
+                const current = new URL(url);
+                const target = new URL(location, current);
+                if (target.protocol !== "https:" && target.protocol !== "http:") {
+                    return null;
+                }
+
+                if (client.redirect !== "always" && current.protocol === "https:" && target.protocol === "http:") {
+                    return null;
+                }
+
+                const rewrite = (status === 303 && method !== "HEAD") || ((status === 301 || status === 302) && method === "POST");
+                return {
+                    url: target.href,
+                    method: rewrite ? "GET" : method,
+                    body: !rewrite,
+                    credentials: target.origin === new URL(origin).origin,
+                };
+            },
+
+            async http_client_send(ref: FakeClientRequest, length: number) {
+                const headers: Record<string, string[]> = {};
+                for (const [name, value] of ref.headers) {
+                    (headers[name.toLowerCase()] ||= []).push(value);
+                }
+
+                if (length > 0) {
+                    headers["content-length"] = [String(length)];
+                }
+
+                const req = request(ref.url, { method: ref.method, headers });
+                ref.req = req;
+                ref.res = new Promise((resolve, reject) => {
+                    req.on("response", resolve);
+                    req.on("error", reject);
+                });
+
+                ref.res.catch(() => {});
+                return self.error("client_send") || 0;
+            },
+
+            async http_client_write(ref: FakeClientRequest, data: Buffer) {
+                await new Promise(resolve => ref.req!.write(Buffer.from(data), resolve));
+                return self.error("client_write") || 0;
+            },
+
+            async http_client_response(ref: FakeClientRequest) {
+                const code = self.error("client_response");
+                if (code !== undefined) {
+                    return { code };
+                }
+
+                ref.req!.end();
+
+                let res: IncomingMessage;
+                try {
+                    res = await ref.res!;
+                } catch {
+                    return { code: ERROR_CONNECTION_INVALID };
+                }
+
+                ref.body = res[Symbol.asyncIterator]();
+                return {
+                    code: 0,
+                    status: res.statusCode,
+                    reason: res.statusMessage,
+                    version: res.httpVersion,
+                    url: ref.url,
+                    headers: res.rawHeaders,
+                };
+            },
+
+            async http_client_read(ref: FakeClientRequest) {
+                const next = await ref.body!.next();
+                return { code: 0, data: next.done ? Buffer.alloc(0) : Buffer.from(next.value) };
+            },
+
+            http_client_close(ref: FakeClientRequest) {
+                if (ref.open) {
+                    ref.open = false;
+                    self.clientRequests--;
+                }
+
+                ref.req?.destroy();
+            },
+        };
+    }
+}
+
+export default FakePlugin;
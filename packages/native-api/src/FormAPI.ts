@@ -0,0 +1,113 @@
+/*
+    Form bodies parsed by the native plugin off the event loop:
+
+    1. URL encoded and multipart/form-data bodies are read straight from the request queue.
+    2. With "temp" storage uploaded files land in temporary files that the caller must remove, with
+       "memory" storage they are returned as buffers; both keep whole parts, up to the part limit.
+    3. streamForm hands every part to JS as it arrives; the parser waits while JS is behind, so a
+       slow reader holds the upload back instead of buffering it.
+    4. A malformed or oversized body is answered natively (400, 413, 415 or 431) and reported with
+       its status; a failed read reports the error code.
+*/
+
+import NodePlugin from "./NodePlugin";
+import type SystemHttpRequest from "./io/SystemHttpRequest";
+
+let svc: any;
+
+export interface FormOptions {
+    part?: number;
+    total?: number;
+    storage?: "temp" | "memory";
+    dir?: string;
+}
+
+export interface FormFile {
+    name: string;
+    filename: string;
+    contentType: string;
+    size: number;
+    path?: string;
+    data?: Buffer;
+}
+
+export type FormResult =
+    | { code: 0; fields: [name: string, value: string][]; files: FormFile[] }
+    | { code: number; status?: number; error?: string };
+
+export type FormEvent =
+    | { type: "part"; name: string; filename?: string; contentType: string }
+    | { type: "data"; data: Buffer }
+    | { type: "end" }
+    | { type: "done" }
+    | { type: "error"; code?: number; status?: number; error?: string };
+
+function contentType(req: SystemHttpRequest) {
+    return req.request.headers.get("Content-Type") || "";
+}
+
+export async function parseForm(req: SystemHttpRequest, options: FormOptions = {}) {
+    svc = NodePlugin.setup();
+
+    const { part, total, storage, dir } = options;
+    return await svc.http_form_parse(req.handle(), req.id[0], contentType(req), part, total, storage, dir) as FormResult;
+}
+
+export class FormStream implements AsyncIterable<FormEvent> {
+    readonly ref: [unknown];
+
+    constructor(ref: [unknown]) {
+        this.ref = ref;
+    }
+
+    static open(req: SystemHttpRequest, options: Omit<FormOptions, "storage" | "dir"> = {}) {
+        svc = NodePlugin.setup();
+
+        const { part, total } = options;
+        const ref = svc.http_form_stream(req.handle(), req.id[0], contentType(req), part, total);
+        return new this([ref]);
+    }
+
+    handle() {
+        const { ref } = this;
+        if (ref[0]) {
+            return ref[0];
+        }
+
+        return undefined;
+    }
+
+    async next(): Promise<FormEvent> {
+        if (!this.ref[0]) {
+            return { type: "done" };
+        }
+
+        return await svc.http_form_next(this.handle());
+    }
+
+    async *[Symbol.asyncIterator]() {
+        try {
+            for (let event = await this.next(); event.type !== "done"; event = await this.next()) {
+                yield event;
+                if (event.type === "error") {
+                    break;
+                }
+            }
+        } finally {
+            this.close();
+        }
+    }
+
+    close() {
+        const { ref } = this;
+        if (ref[0]) {
+            svc.http_form_close(ref.pop());
+        }
+    }
+}
+
+export function streamForm(req: SystemHttpRequest, options: Omit<FormOptions, "storage" | "dir"> = {}) {
+    return FormStream.open(req, options);
+}
+
+export default parseForm;
@@ -0,0 +1,115 @@
+/*
+    gRPC calls served by the native plugin on top of a SystemHttpRequest:
+
+    1. Methods are registered by path with their streaming kind; unknown paths are answered with
+       UNIMPLEMENTED before JS sees a call.
+    2. Messages are framed and unframed natively; the compressed flag is passed through untouched.
+    3. Finishing a call sends grpc-status and grpc-message as trailers, or as headers when nothing
+       was written yet.
+*/
+
+import NodePlugin from "./NodePlugin";
+import type SystemHttpRequest from "./io/SystemHttpRequest";
+
+let svc: any;
+
+export type GrpcCallKind = "unary" | "client-stream" | "server-stream" | "bidi";
+
+export type GrpcMessage =
+    | { data: Buffer; compressed: boolean }
+    | { done: true }
+    | { status: number; message: string };
+
+export class GrpcCall {
+    readonly ref: [unknown];
+    readonly kind: GrpcCallKind;
+
+    constructor(ref: [unknown], kind: GrpcCallKind) {
+        this.ref = ref;
+        this.kind = kind;
+    }
+
+    handle() {
+        const { ref } = this;
+        if (ref[0]) {
+            return ref[0];
+        }
+
+        return undefined;
+    }
+
+    metadata(...pairs: string[]) {
+        return svc.http_grpc_metadata(this.handle(), ...pairs) as boolean;
+    }
+
+    async read() {
+        return await svc.http_grpc_read(this.handle()) as GrpcMessage;
+    }
+
+    async write(data: Buffer, compressed = false) {
+        return await svc.http_grpc_write(this.handle(), data, compressed) as number;
+    }
+
+    async finish(status: number, message = "", ...metadata: string[]) {
+        return await svc.http_grpc_finish(this.handle(), status, message, ...metadata) as number;
+    }
+
+    close() {
+        const { ref } = this;
+        if (ref[0]) {
+            svc.http_grpc_close(ref.pop());
+        }
+    }
+}
+
+export class GrpcServer {
+    readonly ref: [unknown];
+
+    constructor(ref: [unknown]) {
+        this.ref = ref;
+    }
+
+    static create(limit?: number) {
+        svc = NodePlugin.setup();
+
+        const ref = svc.http_grpc_create(limit);
+        return new this([ref]);
+    }
+
+    handle() {
+        const { ref } = this;
+        if (ref[0]) {
+            return ref[0];
+        }
+
+        return undefined;
+    }
+
+    register(path: string, kind: GrpcCallKind) {
+        svc.http_grpc_register(this.handle(), path, kind);
+        return this;
+    }
+
+    accept(req: SystemHttpRequest) {
+        const { request } = req;
+        const path = request.url.split("?")[0];
+        const type = request.headers.get("Content-Type") || "";
+        const timeout = request.headers.get("grpc-timeout");
+        const result = svc.http_grpc_accept(this.handle(), req.handle(), req.id[0], request.speedy, path, type, timeout);
+        if (!result) {
+            req.ok();
+            return undefined;
+        }
+
+        return new GrpcCall([result.call], result.kind as GrpcCallKind);
+    }
+
+    close() {
+        const { ref } = this;
+        if (ref[0]) {
+            svc.http_grpc_server_close(ref.pop());
+        }
+    }
+}
+
+export default GrpcServer;
@@ -0,0 +1,23 @@
+import NodePlugin from "./NodePlugin";
+import { AcceptKind } from "./io/Request";
+
+export type { AcceptKind };
+
+let svc: any;
+
+export class NegotiateAPI {
+    static create() {
+        svc = NodePlugin.setup();
+        return new this();
+    }
+
+    parse(kind: AcceptKind, header: string | undefined) {
+        return svc.negotiate_parse(kind, header) as [value: string, q: number][];
+    }
+
+    best(kind: AcceptKind, header: string | undefined, offers: string[]) {
+        return svc.negotiate_best(kind, header, offers) as string | null;
+    }
+}
+
+export default NegotiateAPI;
@@ -7,6 +7,10 @@ let svc: any;
 const ENV_HINT = "NODE_RUST_WINDOWS_PLUGIN_PATH";
 
 export namespace NodePlugin {
+    export function install(value: any) {
+        svc = value;
+    }
+
     export function setup(path?: string, hint?: string) {
         if (svc !== undefined) {
             return svc;
@@ -0,0 +1,120 @@
+/*
+    Server-side sessions kept by the native plugin:
+
+    1. Sessions live in memory with LRU eviction and a time to live, renewed on access when sliding.
+    2. With a directory every session is also a file there; processes attached to the same queue
+       must share that directory, since it is the only state they have in common.
+    3. A store bound to a SystemHttpRequest attaches the matching session to every received request,
+       keyed by the authenticated user or by the session cookie.
+    4. Store operations run off the event loop and reject when a session file stays locked by
+       another process.
+*/
+
+import NodePlugin from "./NodePlugin";
+import CookieAPI, { CookieKeys, CookieOptions } from "./CookieAPI";
+import type Request from "./io/Request";
+
+let svc: any;
+
+export interface SessionStoreOptions {
+    ttl?: number;
+    max?: number;
+    sliding?: boolean;
+    path?: string;
+}
+
+export interface Session<T = Record<string, unknown>> {
+    id: string;
+    data: T;
+    created: number;
+    expires: number;
+}
+
+export function toSession(value: any): Session | undefined {
+    if (!value) {
+        return undefined;
+    }
+
+    return { ...value, data: JSON.parse(value.data) };
+}
+
+export class SessionStore {
+    readonly ref: [unknown];
+
+    constructor(ref: [unknown]) {
+        this.ref = ref;
+    }
+
+    static create(options: SessionStoreOptions = {}) {
+        svc = NodePlugin.setup();
+
+        const { ttl = 20 * 60 * 1000, max = 10000, sliding = true, path } = options;
+        const ref = svc.session_store_create(ttl, max, sliding, path);
+        return new this([ref]);
+    }
+
+    handle() {
+        const { ref } = this;
+        if (ref[0]) {
+            return ref[0];
+        }
+
+        return undefined;
+    }
+
+    close() {
+        const { ref } = this;
+        if (ref[0]) {
+            svc.session_store_close(ref.pop());
+        }
+    }
+
+    async create() {
+        return toSession(await svc.session_create(this.handle()))!;
+    }
+
+    async get(id: string) {
+        return toSession(await svc.session_get(this.handle(), id));
+    }
+
+    async save(session: Session) {
+        return await svc.session_set(this.handle(), session.id, JSON.stringify(session.data)) as boolean;
+    }
+
+    async destroy(id: string) {
+        await svc.session_destroy(this.handle(), id);
+    }
+
+    async prune() {
+        return await svc.session_prune(this.handle()) as number;
+    }
+
+    // Returns the bound session or starts one, issuing its cookie on the response (signed when keys are given).
+    async start(req: Request, cookie = "sid", options: CookieOptions = {}, keys?: CookieKeys) {
+        const { session } = req.request;
+        if (session) {
+            return session;
+        }
+
+        const created = await this.create();
+        const value = keys ? keys.sign(cookie, created.id) : created.id;
+        const header = CookieAPI.create().serialize(cookie, value, { path: "/", httpOnly: true, sameSite: "lax", ...options });
+        req.response.headers.add("Set-Cookie", header);
+        req.request.session = created;
+
+        return created;
+    }
+
+    async end(req: Request, cookie = "sid", options: CookieOptions = {}) {
+        const { session } = req.request;
+        if (session) {
+            await this.destroy(session.id);
+            req.request.session = undefined;
+        }
+
+        const header = CookieAPI.create().serialize(cookie, "", { path: "/", ...options, maxAge: 0 });
+        req.response.headers.add("Set-Cookie", header);
+    }
+}
+
+export default SessionStore;
@@ -0,0 +1,137 @@
+/*
+    Server-sent event streams served by the native plugin on top of a SystemHttpRequest:
+
+    1. Opening a stream sends the response head; events are queued and written off the event loop.
+    2. The queue is bounded; send reports "full" instead of buffering for a client that stopped
+       reading, and "closed" once the stream ended.
+    3. A stream ends as soon as the client disconnects, and keep-alive comments are sent while idle.
+    4. A hub fans events out to many streams and drops the ones that closed.
+*/
+
+import NodePlugin from "./NodePlugin";
+import type SystemHttpRequest from "./io/SystemHttpRequest";
+
+let svc: any;
+
+export type SseSendStatus = "sent" | "full" | "closed";
+
+export interface SseEvent {
+    data: string;
+    event?: string;
+    id?: string;
+    retry?: number;
+}
+
+export interface SseOptions {
+    chunked?: boolean;
+    keepAlive?: number;
+    retry?: number;
+    capacity?: number;
+}
+
+function eventArgs({ data, event, id, retry }: SseEvent) {
+    return [data, event, id, retry];
+}
+
+export class SseChannel {
+    readonly ref: [unknown];
+
+    constructor(ref: [unknown]) {
+        this.ref = ref;
+    }
+
+    static open(req: SystemHttpRequest, options: SseOptions = {}) {
+        svc = NodePlugin.setup();
+
+        const { chunked = req.request.version === "1.1", keepAlive, retry, capacity } = options;
+        const ref = svc.http_sse_open(req.handle(), req.id[0], req.connection, chunked, keepAlive, retry, capacity);
+        return new this([ref]);
+    }
+
+    handle() {
+        const { ref } = this;
+        if (ref[0]) {
+            return ref[0];
+        }
+
+        return undefined;
+    }
+
+    send(event: SseEvent | string): SseSendStatus {
+        if (!this.ref[0]) {
+            return "closed";
+        }
+
+        const value = typeof event === "string" ? { data: event } : event;
+        return svc.http_sse_send(this.handle(), ...eventArgs(value));
+    }
+
+    comment(text: string): SseSendStatus {
+        if (!this.ref[0]) {
+            return "closed";
+        }
+
+        return svc.http_sse_comment(this.handle(), text);
+    }
+
+    async closed() {
+        if (this.ref[0]) {
+            await svc.http_sse_closed(this.handle());
+        }
+    }
+
+    close() {
+        const { ref } = this;
+        if (ref[0]) {
+            svc.http_sse_close(ref.pop());
+        }
+    }
+}
+
+export class SseHub {
+    readonly ref: [unknown];
+
+    constructor(ref: [unknown]) {
+        this.ref = ref;
+    }
+
+    static create() {
+        svc = NodePlugin.setup();
+
+        const ref = svc.http_sse_hub_create();
+        return new this([ref]);
+    }
+
+    handle() {
+        const { ref } = this;
+        if (ref[0]) {
+            return ref[0];
+        }
+
+        return undefined;
+    }
+
+    add(channel: SseChannel) {
+        svc.http_sse_hub_add(this.handle(), channel.handle());
+        return this;
+    }
+
+    remove(channel: SseChannel) {
+        svc.http_sse_hub_remove(this.handle(), channel.handle());
+        return this;
+    }
+
+    send(event: SseEvent | string) {
+        const value = typeof event === "string" ? { data: event } : event;
+        return svc.http_sse_hub_send(this.handle(), ...eventArgs(value)) as number;
+    }
+
+    close() {
+        const { ref } = this;
+        if (ref[0]) {
+            svc.http_sse_hub_close(ref.pop());
+        }
+    }
+}
+
+export default SseChannel;
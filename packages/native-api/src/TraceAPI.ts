@@ -0,0 +1,27 @@
+import NodePlugin from "./NodePlugin";
+
+export type TraceLevel = "off" | "error" | "warn" | "info" | "debug" | "trace";
+export type TraceCallback = (level: TraceLevel, line: string) => void;
+
+let svc: any;
+
+export class TraceAPI {
+    static create() {
+        svc = NodePlugin.setup();
+        return new this();
+    }
+
+    toCallback(level: TraceLevel, callback: TraceCallback) {
+        svc.trace_config(level, callback);
+    }
+
+    toFile(level: TraceLevel, path: string) {
+        svc.trace_config(level, path);
+    }
+
+    off() {
+        svc.trace_config("off");
+    }
+}
+
+export default TraceAPI;
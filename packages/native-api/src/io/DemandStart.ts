@@ -0,0 +1,63 @@
+/*
+    Controller side of on-demand activation, similar to WAS in IIS:
+
+    1. The controller creates the named queue with SystemHttpSession and registers its URLs.
+    2. It blocks in HttpWaitForDemandStart until the first request arrives on the queue.
+    3. It then starts the worker (or signals "demand" to one already waiting in attach) which
+       attaches to the queue by name with SystemHttpRequest.create and serves the queued request.
+    4. When a spawned worker exits the controller goes back to waiting for demand.
+
+    The controller itself never receives, so requests stay queued in HTTP.sys until the worker attaches.
+*/
+
+import { spawn } from "child_process";
+import Handover from "./Handover";
+import SystemHttpSession from "./SystemHttpSession";
+
+export interface DemandStartOptions {
+    command?: string;
+    args?: string[];
+    signal?: string;
+    env?: Record<string, string>;
+}
+
+export namespace DemandStart {
+    export async function once(session: SystemHttpSession, options: DemandStartOptions) {
+        const { command, args = [], signal, env } = options;
+        await session.waitForDemand();
+
+        if (signal) {
+            Handover.signal(signal, "demand");
+        }
+
+        if (!command) {
+            return 0;
+        }
+
+        const child = spawn(command, args, {
+            env: { ...process.env, ...env },
+            stdio: "inherit",
+        });
+
+        return await new Promise<number>((resolve, reject) => {
+            child.on("error", reject);
+            child.on("exit", code => resolve(code ?? 0));
+        });
+    }
+
+    export async function run(session: SystemHttpSession, options: DemandStartOptions) {
+        if (!options.command) {
+            return await once(session, options);
+        }
+
+        for (;;) {
+            await once(session, options);
+        }
+    }
+
+    export async function attach(signal: string, timeout = -1) {
+        return await Handover.wait(signal, "demand", timeout >>> 0);
+    }
+}
+
+export default DemandStart;
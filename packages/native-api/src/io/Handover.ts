@@ -0,0 +1,73 @@
+/*
+    Hands receiving on a named request queue over from a running worker to its replacement:
+
+    1. The URLs stay registered on the controller session that created the queue (SystemHttpSession,
+       for example the DemandStart controller); a handover never adds or removes them.
+    2. The new process attaches to the queue by name and starts receiving alongside the old one.
+    3. It signals "ready"; the old process stops receiving and drains its in-flight requests.
+    4. The old process closes its queue handles and signals "released".
+
+    Requests waiting in the queue are not bound to either process, so whatever the old process no
+    longer receives is picked up by the new one and no connection is dropped. A process that owns the
+    controller session itself cannot hand the URLs over; run the controller separately when workers
+    need to be replaced.
+*/
+
+import { NodePlugin } from "../NodePlugin";
+import SystemHttpRequest from "./SystemHttpRequest";
+
+let svc: any;
+
+export interface HandoverOptions {
+    timeout?: number;
+}
+
+// "demand" is raised by DemandStart for a worker waiting to attach; it is separate from "ready" so a
+// demand signal is never taken as a worker being ready for a handover, or the other way around.
+export type HandoverState = "ready" | "released" | "demand";
+
+export namespace Handover {
+    export function signal(key: string, state: HandoverState) {
+        svc = NodePlugin.setup();
+        svc.handover_signal(`${key}-${state}`);
+    }
+
+    export async function wait(key: string, state: HandoverState, timeout: number) {
+        svc = NodePlugin.setup();
+        return await svc.handover_wait(`${key}-${state}`, timeout) as boolean;
+    }
+
+    export function takeOver(key: string, queue: string) {
+        const next = SystemHttpRequest.create(queue);
+        signal(key, "ready");
+
+        return next;
+    }
+
+    export async function released(key: string, options: HandoverOptions = {}) {
+        const { timeout = 60000 } = options;
+        return await wait(key, "released", timeout);
+    }
+
+    export async function handOff(key: string, requests: SystemHttpRequest[], options: HandoverOptions = {}) {
+        const { timeout = 60000 } = options;
+        if (!await wait(key, "ready", timeout)) {
+            return false;
+        }
+
+        for (const req of requests) {
+            req.stop();
+        }
+
+        await Promise.all(requests.map(x => x.drain(timeout)));
+
+        for (const req of requests) {
+            req.close();
+        }
+
+        signal(key, "released");
+        return true;
+    }
+}
+
+export default Handover;
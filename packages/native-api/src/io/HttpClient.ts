@@ -0,0 +1,186 @@
+/*
+    Outbound HTTP through WinHTTP with integrated Windows authentication:
+
+    1. Without a user token requests to intranet hosts authenticate (Negotiate/NTLM) as the process identity.
+    2. With the token of a received request (SystemHttpRequest.user, before resolveIdentity closes it)
+       each native call impersonates that user; delegation to another host still needs Kerberos
+       constrained delegation configured for the service account.
+    3. Connections are pooled per identity, so sockets authenticated for one user are never reused for another.
+    4. Redirects are followed here rather than by WinHTTP: only a target on the original origin receives
+       integrated auth, the user token or credential headers; anything else is requested anonymously.
+*/
+
+import { Readable } from "stream";
+import { NodePlugin } from "../NodePlugin";
+import { toBuffer } from "./Request";
+
+export const ERROR_WINHTTP_RESEND_REQUEST = 12032;
+
+const CREDENTIAL_HEADERS = ["authorization", "cookie", "proxy-authorization"];
+const BODY_HEADERS = ["content-length", "content-type", "content-encoding", "transfer-encoding"];
+
+let svc: any;
+
+export type HttpClientBody = string | Buffer | Iterable<string | Buffer> | AsyncIterable<string | Buffer>;
+
+export interface HttpClientOptions {
+    agent?: string;
+    redirect?: "manual" | "never" | "follow" | "safe" | "always";
+    maxRedirects?: number;
+    timeout?: number;
+}
+
+export interface HttpClientInit {
+    method?: string;
+    headers?: [name: string, value: string][] | Record<string, string>;
+    body?: HttpClientBody;
+    user?: unknown;
+    retries?: number;
+}
+
+export interface HttpClientResponse {
+    status: number;
+    reason: string;
+    version: string;
+    url: string;
+    headers: [name: string, value: string][];
+    body: Readable;
+}
+
+function check(code: number, op: string) {
+    if (code) {
+        throw new Error(`${op}: ${code}`);
+    }
+}
+
+export class HttpClient {
+    readonly ref: [unknown];
+
+    constructor(ref: [unknown]) {
+        this.ref = ref;
+    }
+
+    static create(options: HttpClientOptions = {}) {
+        svc = NodePlugin.setup();
+
+        const { agent, redirect, maxRedirects, timeout } = options;
+        const ref = svc.http_client_create(agent, redirect, maxRedirects, timeout);
+        return new this([ref]);
+    }
+
+    handle() {
+        const { ref } = this;
+        if (ref[0]) {
+            return ref[0];
+        }
+
+        return undefined;
+    }
+
+    close() {
+        const { ref } = this;
+        if (ref[0]) {
+            svc.http_client_destroy(ref.pop());
+        }
+    }
+
+    async request(url: string, init: HttpClientInit = {}): Promise<HttpClientResponse> {
+        const { method = "GET", body, user, retries = 2 } = init;
+        let headers = Array.isArray(init.headers) ? init.headers : Object.entries(init.headers || {});
+        let next = { url, method, body, credentials: true };
+
+        for (let hops = 0; ; hops++) {
+            const { req, response } = await this.exchange(next.url, next.method, headers, next.body, next.credentials ? user : undefined, next.credentials, retries);
+            const location = response.headers.find(([name]) => name.toLowerCase() === "location");
+            const step = location && svc.http_client_redirect(this.handle(), url, next.url, next.method, response.status, location[1], hops);
+            const replay = next.body === undefined || typeof next.body === "string" || Buffer.isBuffer(next.body);
+            if (!step || (step.body && !replay)) {
+                return { ...response, body: Readable.from(this.read(req)) };
+            }
+
+            svc.http_client_close(req);
+
+            // Credentials the caller attached by hand must not follow the request to another origin either.
+            headers = headers.filter(([name]) => {
+                const key = name.toLowerCase();
+                if (!step.credentials && CREDENTIAL_HEADERS.includes(key)) {
+                    return false;
+                }
+
+                return step.body || !BODY_HEADERS.includes(key);
+            });
+
+            next = { url: step.url, method: step.method, body: step.body ? next.body : undefined, credentials: next.credentials && step.credentials };
+        }
+    }
+
+    private async exchange(url: string, method: string, headers: [string, string][], body: HttpClientBody | undefined, user: unknown, credentials: boolean, retries: number) {
+        const replay = typeof body === "string" || Buffer.isBuffer(body);
+        const flat = headers.flat();
+        const req = svc.http_client_open(this.handle(), method, url, flat, user, credentials);
+
+        try {
+            // A Negotiate challenge after the body went out asks for a resend; only whole bodies can be replayed.
+            for (let attempt = 0; ; attempt++) {
+                if (body === undefined || replay) {
+                    const data = body === undefined ? undefined : toBuffer(body);
+                    check(await svc.http_client_send(req, data ? data.byteLength : 0), "http_client_send");
+                    data && check(await svc.http_client_write(req, data), "http_client_write");
+                } else {
+                    const known = headers.find(([name]) => name.toLowerCase() === "content-length");
+                    check(await svc.http_client_send(req, known ? Number(known[1]) : -1), "http_client_send");
+                    for await (const chunk of body) {
+                        check(await svc.http_client_write(req, toBuffer(chunk)), "http_client_write");
+                    }
+                }
+
+                const { code, headers: list, ...rest } = await svc.http_client_response(req);
+                if (code === ERROR_WINHTTP_RESEND_REQUEST && replay && attempt < retries) {
+                    continue;
+                }
+
+                check(code, "http_client_response");
+
+                const pairs: [string, string][] = [];
+                for (let i = 0; i < list.length; i += 2) {
+                    pairs.push([list[i], list[i + 1]]);
+                }
+
+                const response = { ...rest, headers: pairs } as Omit<HttpClientResponse, "body">;
+                return { req, response };
+            }
+        } catch (ex) {
+            svc.http_client_close(req);
+            throw ex;
+        }
+    }
+
+    async text(url: string, init: HttpClientInit = {}) {
+        const response = await this.request(url, init);
+        const data: Buffer[] = [];
+        for await (const chunk of response.body) {
+            data.push(chunk);
+        }
+
+        return { ...response, text: Buffer.concat(data).toString() };
+    }
+
+    private async *read(req: unknown) {
+        try {
+            for (;;) {
+                const { code, data } = await svc.http_client_read(req);
+                check(code, "http_client_read");
+
+                if (data.byteLength < 1) {
+                    return;
+                }
+
+                yield data as Buffer;
+            }
+        } finally {
+            svc.http_client_close(req);
+        }
+    }
+}
+
+export default HttpClient;
@@ -0,0 +1,384 @@
+/*
+    Traffic recordings are JSON-lines files. Every line is one event and every event
+    carries the "seq" of the exchange it belongs to, so events of concurrent
+    exchanges may interleave:
+
+    {"type":"request","seq":1,"time":"...","method":"GET","url":"/","version":"1.1","headers":[["Host","a"]]}
+    {"type":"request-data","seq":1,"data":"<base64>"}
+    {"type":"request-end","seq":1}
+    {"type":"response","seq":1,"status":200,"reason":"OK","version":"1.1","headers":[["Content-Type","text/plain"]]}
+    {"type":"response-data","seq":1,"chunks":["<base64>"]}
+    {"type":"response-end","seq":1,"trailers":[]}
+
+    Response data is recorded before any chunked framing so one "response-data" line
+    holds the chunks given to a single sendData call.
+
+    Credentials never reach the file: the values of Authorization, Cookie and Set-Cookie
+    (or whatever list the recorder is given) are written as "[redacted]".
+*/
+
+import { createWriteStream, readFileSync } from "fs";
+import { Writable } from "stream";
+
+import Headers from "./Headers";
+import Request, { Data, RequestData, ResponseData, toBuffer } from "./Request";
+import { UserGroup } from "../UserAPI";
+
+export type HeaderList = [name: string, value: string][];
+
+export interface RecordedRequest {
+    time: string;
+    method: string;
+    url: string;
+    version: string;
+    headers: HeaderList;
+    body: Buffer[];
+}
+
+export interface RecordedResponse {
+    status: number;
+    reason: string;
+    version: string;
+    headers: HeaderList;
+    trailers: HeaderList;
+    chunks: Buffer[][];
+    complete: boolean;
+}
+
+export interface Exchange {
+    seq: number;
+    request: RecordedRequest;
+    response: RecordedResponse;
+}
+
+export const REDACTED_HEADERS = ["Authorization", "Cookie", "Set-Cookie"];
+
+export const REDACTED = "[redacted]";
+
+export function headerList(headers: Headers): HeaderList {
+    return Array.from(headers.renderFlat()) as HeaderList;
+}
+
+export function redactList(list: HeaderList, names: Set<string>): HeaderList {
+    return list.map(([name, value]) => [name, names.has(name.toLowerCase()) ? REDACTED : value]);
+}
+
+export function emptyResponse(): RecordedResponse {
+    return {
+        status: 0,
+        reason: "",
+        version: "",
+        headers: [],
+        trailers: [],
+        chunks: [],
+        complete: false,
+    };
+}
+
+// Exchanges retain the recorder while they are recorded; closing waits until every one is released.
+export class Recorder {
+    private seq = 0;
+    private users = 0;
+    private closing?: () => void;
+    private readonly sink: Writable;
+    private readonly redact: Set<string>;
+
+    constructor(sink: Writable, redact = REDACTED_HEADERS) {
+        this.sink = sink;
+        this.redact = new Set(redact.map(x => x.toLowerCase()));
+    }
+
+    static open(path: string, redact?: string[]) {
+        return new this(createWriteStream(path, { flags: "a" }), redact);
+    }
+
+    headers(headers: Headers) {
+        return redactList(headerList(headers), this.redact);
+    }
+
+    next() {
+        return ++this.seq;
+    }
+
+    retain() {
+        if (this.closing) {
+            return false;
+        }
+
+        this.users++;
+        return true;
+    }
+
+    release() {
+        if (--this.users === 0 && this.closing) {
+            this.closing();
+        }
+    }
+
+    write(type: string, seq: number, fields: Record<string, unknown> = {}) {
+        this.sink.write(JSON.stringify({ type, seq, ...fields }) + "\n");
+    }
+
+    request(seq: number, data: RequestData) {
+        this.write("request", seq, {
+            time: new Date().toISOString(),
+            method: data.method,
+            url: data.url,
+            version: data.version,
+            headers: this.headers(data.headers),
+        });
+    }
+
+    requestData(seq: number, data: Buffer) {
+        this.write("request-data", seq, { data: data.toString("base64") });
+    }
+
+    requestEnd(seq: number) {
+        this.write("request-end", seq);
+    }
+
+    response(seq: number, data: ResponseData) {
+        this.write("response", seq, {
+            status: data.status,
+            reason: data.reason,
+            version: data.version,
+            headers: this.headers(data.headers),
+        });
+    }
+
+    responseData(seq: number, chunks: Buffer[]) {
+        this.write("response-data", seq, { chunks: chunks.map(x => x.toString("base64")) });
+    }
+
+    responseEnd(seq: number, trailers: Headers) {
+        this.write("response-end", seq, { trailers: this.headers(trailers) });
+    }
+
+    close() {
+        return new Promise<void>(resolve => {
+            this.closing = () => this.sink.end(resolve);
+            if (this.users === 0) {
+                this.closing();
+            }
+        });
+    }
+}
+
+export function parseRecording(text: string) {
+    const map = new Map<number, Exchange>();
+    for (const line of text.split("\n")) {
+        if (!line.trim()) {
+            continue;
+        }
+
+        const event = JSON.parse(line);
+        const seq = event.seq as number;
+        if (event.type === "request") {
+            map.set(seq, {
+                seq,
+                request: {
+                    time: event.time || "",
+                    method: event.method || "",
+                    url: event.url || "",
+                    version: event.version || "",
+                    headers: event.headers || [],
+                    body: [],
+                },
+                response: emptyResponse(),
+            });
+
+            continue;
+        }
+
+        const exchange = map.get(seq);
+        if (exchange === undefined) {
+            continue;
+        }
+
+        const { request, response } = exchange;
+        switch (event.type) {
+            case "request-data":
+                request.body.push(Buffer.from(event.data, "base64"));
+                break;
+
+            case "response":
+                response.status = event.status;
+                response.reason = event.reason || "";
+                response.version = event.version || "";
+                response.headers = event.headers || [];
+                break;
+
+            case "response-data":
+                response.chunks.push((event.chunks as string[]).map(x => Buffer.from(x, "base64")));
+                break;
+
+            case "response-end":
+                response.trailers = event.trailers || [];
+                response.complete = true;
+                break;
+        }
+    }
+
+    return Array.from(map.values());
+}
+
+export function loadRecording(path: string) {
+    return parseRecording(readFileSync(path, "utf-8"));
+}
+
+export class RecordingRequest implements Request {
+    readonly inner: Request;
+    readonly recorder: Recorder;
+    private seq = 0;
+    private sent = false;
+    private active = false;
+
+    constructor(inner: Request, recorder: Recorder) {
+        this.inner = inner;
+        this.recorder = recorder;
+    }
+
+    get request() { return this.inner.request; }
+    get response() { return this.inner.response; }
+
+    get opaque() { return this.inner.opaque; }
+    set opaque(value) { this.inner.opaque = value; }
+
+    get disconnect() { return this.inner.disconnect; }
+    set disconnect(value) { this.inner.disconnect = value; }
+
+    get bufferData() { return this.inner.bufferData; }
+    set bufferData(value) { this.inner.bufferData = value; }
+
+    get nagling() { return this.inner.nagling; }
+    set nagling(value) { this.inner.nagling = value; }
+
+    get ranges() { return this.inner.ranges; }
+    set ranges(value) { this.inner.ranges = value; }
+
+    get goaway() { return this.inner.goaway; }
+    set goaway(value) { this.inner.goaway = value; }
+
+    get cache() { return this.inner.cache; }
+    set cache(value) { this.inner.cache = value; }
+
+    done() {
+        return this.inner.done();
+    }
+
+    cancel() {
+        return this.inner.cancel();
+    }
+
+    tcpInfo() {
+        return this.inner.tcpInfo();
+    }
+
+    reset(code?: number) {
+        return this.inner.reset(code);
+    }
+
+    close() {
+        this.inner.close();
+    }
+
+    ok() {
+        this.inner.ok();
+    }
+
+    async receive(size?: number) {
+        const result = await this.inner.receive(size);
+        if (result === true) {
+            this.end();
+            this.active = this.recorder.retain();
+            this.seq = this.recorder.next();
+            this.sent = false;
+
+            if (this.active) {
+                this.recorder.request(this.seq, this.request);
+            }
+        }
+
+        return result;
+    }
+
+    // Releases the recorder once the exchange is over; later events of it are not recorded.
+    end() {
+        if (this.active) {
+            this.active = false;
+            this.recorder.release();
+        }
+    }
+
+    async receiveData(size?: number) {
+        const result = await this.inner.receiveData(size);
+        if (!this.active) {
+            return result;
+        }
+
+        if (Buffer.isBuffer(result)) {
+            this.recorder.requestData(this.seq, result);
+        } else if (result === undefined) {
+            this.recorder.requestEnd(this.seq);
+        }
+
+        return result;
+    }
+
+    async send(final = false) {
+        if (!this.active) {
+            return await this.inner.send(final);
+        }
+
+        if (!this.sent) {
+            this.sent = true;
+            this.recorder.response(this.seq, this.response);
+        }
+
+        const result = await this.inner.send(final);
+        if (final && this.active) {
+            this.recorder.responseEnd(this.seq, this.response.trailers);
+        }
+
+        return result;
+    }
+
+    async sendData(data: Data, final = false) {
+        if (!this.active) {
+            return await this.inner.sendData(data, final);
+        }
+
+        if (!this.sent) {
+            this.sent = true;
+            this.recorder.response(this.seq, this.response);
+        }
+
+        const chunks = (Array.isArray(data) ? data : [data]).map(toBuffer);
+        this.recorder.responseData(this.seq, chunks);
+
+        const result = await this.inner.sendData(data, final);
+        if (final && this.active) {
+            this.recorder.responseEnd(this.seq, this.response.trailers);
+        }
+
+        return result;
+    }
+
+    flush(url: string, recursive?: boolean) {
+        return this.inner.flush(url, recursive);
+    }
+
+    push(method: string, url: string, headers: Headers) {
+        this.inner.push(method, url, headers);
+    }
+
+    dropIdentity() {
+        this.inner.dropIdentity();
+    }
+
+    resolveIdentity(): UserGroup[] {
+        return this.inner.resolveIdentity();
+    }
+}
+
+export default Recorder;
@@ -0,0 +1,211 @@
+import Request, { Data, RequestData, ResponseData, toBuffer } from "./Request";
+import { emptyResponse, Exchange, HeaderList, headerList, RecordedResponse, REDACTED_HEADERS } from "./Recording";
+import { UserGroup } from "../UserAPI";
+
+export class ReplayRequest implements Request {
+    readonly exchange: Exchange;
+    readonly request = new RequestData();
+    readonly response = new ResponseData();
+    readonly output: RecordedResponse = emptyResponse();
+
+    opaque = false;
+    disconnect = false;
+
+    bufferData = false;
+    nagling = false;
+    ranges = false;
+    goaway = false;
+    cache = 0;
+
+    private body: Buffer[] = [];
+    private state = 0;
+
+    constructor(exchange: Exchange) {
+        this.exchange = exchange;
+    }
+
+    done() {
+        return this.state > 1;
+    }
+
+    async cancel() {
+        this.state = 2;
+        return 0;
+    }
+
+    tcpInfo() {
+        return undefined;
+    }
+
+    async reset() {
+        this.state = 2;
+        return 0;
+    }
+
+    close() {
+        this.state = 2;
+    }
+
+    ok() {
+        this.state = 2;
+    }
+
+    async receive() {
+        if (this.state > 0) {
+            return 1;
+        }
+
+        const { request } = this;
+        const recorded = this.exchange.request;
+        request.method = recorded.method;
+        request.url = recorded.url;
+        request.version = recorded.version;
+        request.headers.load(recorded.headers.flat());
+
+        this.response.version = recorded.version;
+        this.body = recorded.body.slice();
+        this.state = 1;
+
+        return true as const;
+    }
+
+    async receiveData() {
+        return this.body.shift();
+    }
+
+    async send(final = false) {
+        const { output, response } = this;
+        output.status = response.status;
+        output.reason = response.reason;
+        output.version = response.version;
+        output.headers = headerList(response.headers);
+
+        if (final) {
+            output.trailers = headerList(response.trailers);
+            output.complete = true;
+        }
+
+        return 0;
+    }
+
+    async sendData(data: Data, final = false) {
+        const { output } = this;
+        const chunks = (Array.isArray(data) ? data : [data]).map(toBuffer);
+        output.chunks.push(chunks);
+
+        if (final) {
+            output.trailers = headerList(this.response.trailers);
+            output.complete = true;
+        }
+
+        return 0;
+    }
+
+    async flush() {
+        return 0;
+    }
+
+    push() {
+
+    }
+
+    dropIdentity() {
+
+    }
+
+    resolveIdentity(): UserGroup[] {
+        return [];
+    }
+}
+
+export interface ReplayOptions {
+    chunks?: boolean;
+    ignoreHeaders?: string[];
+    // Headers the recorder redacted; their recorded values are meaningless, so they are never compared.
+    redactedHeaders?: string[];
+}
+
+export interface ReplayResult {
+    exchange: Exchange;
+    actual: RecordedResponse;
+    diffs: string[];
+}
+
+function normalHeaders(list: HeaderList, ignore: Set<string>) {
+    return list
+        .map(([name, value]) => `${name.toLowerCase()}: ${value}`)
+        .filter(x => !ignore.has(x.substring(0, x.indexOf(":"))))
+        .sort();
+}
+
+function diffHeaders(label: string, expected: HeaderList, actual: HeaderList, ignore: Set<string>, diffs: string[]) {
+    const a = normalHeaders(expected, ignore);
+    const b = normalHeaders(actual, ignore);
+    for (const x of a) {
+        if (!b.includes(x)) {
+            diffs.push(`${label} missing: ${x}`);
+        }
+    }
+
+    for (const x of b) {
+        if (!a.includes(x)) {
+            diffs.push(`${label} unexpected: ${x}`);
+        }
+    }
+}
+
+export function diffResponse(expected: RecordedResponse, actual: RecordedResponse, options: ReplayOptions = {}) {
+    const diffs: string[] = [];
+    const names = [...(options.ignoreHeaders || []), ...(options.redactedHeaders || REDACTED_HEADERS)];
+    const ignore = new Set(names.map(x => x.toLowerCase()));
+    if (expected.status !== actual.status) {
+        diffs.push(`status: expected ${expected.status}, got ${actual.status}`);
+    }
+
+    if (expected.reason !== actual.reason) {
+        diffs.push(`reason: expected ${JSON.stringify(expected.reason)}, got ${JSON.stringify(actual.reason)}`);
+    }
+
+    diffHeaders("header", expected.headers, actual.headers, ignore, diffs);
+    diffHeaders("trailer", expected.trailers, actual.trailers, ignore, diffs);
+
+    const a = Buffer.concat(expected.chunks.flat());
+    const b = Buffer.concat(actual.chunks.flat());
+    if (!a.equals(b)) {
+        let i = 0;
+        while (i < a.length && i < b.length && a[i] === b[i]) {
+            i++;
+        }
+
+        diffs.push(`body: differs at offset ${i} (expected ${a.length} bytes, got ${b.length})`);
+    } else if (options.chunks) {
+        const x = expected.chunks.map(x => x.map(y => y.length).join("+")).join(",");
+        const y = actual.chunks.map(x => x.map(y => y.length).join("+")).join(",");
+        if (x !== y) {
+            diffs.push(`chunks: expected ${x}, got ${y}`);
+        }
+    }
+
+    if (expected.complete !== actual.complete) {
+        diffs.push(`complete: expected ${expected.complete}, got ${actual.complete}`);
+    }
+
+    return diffs;
+}
+
+export async function replay(exchanges: Exchange[], handler: (req: Request) => unknown, options: ReplayOptions = {}) {
+    const results: ReplayResult[] = [];
+    for (const exchange of exchanges) {
+        const req = new ReplayRequest(exchange);
+        await req.receive();
+        await handler(req);
+
+        const actual = req.output;
+        const diffs = diffResponse(exchange.response, actual, options);
+        results.push({ exchange, actual, diffs });
+    }
+
+    return results;
+}
+
+export default ReplayRequest;
@@ -1,6 +1,15 @@
 import { UserGroup } from "../UserAPI";
+import type { CookieOptions } from "../CookieAPI";
+import type { Session } from "../SessionAPI";
 import Headers from "./Headers";
 
+export type AcceptKind = "type" | "language" | "encoding" | "charset";
+
+export interface RequestIdentity {
+    kind: "jwt" | "cert";
+    subject: string;
+}
+
 export class RequestData {
     method = "";
     url = "";
@@ -9,6 +18,15 @@ export class RequestData {
     ip = "";
     speedy = false;
     userId = "";
+    timing: Record<string, number> = {};
+    identity?: RequestIdentity;
+    claims: Record<string, unknown> = {};
+    negotiated: Partial<Record<AcceptKind, string | null>> = {};
+    cookies: [name: string, value: string][] = [];
+    signedCookies: Record<string, string> = {};
+    session?: Session;
+    // Set when an Expect: 100-continue was held back; reading the body sends the 100 Continue.
+    deferred = false;
 
     readonly headers = new Headers();
 }
@@ -17,6 +35,8 @@ export class ResponseData {
     status = 0;
     reason = "";
     version = "";
+    // Rendered into Set-Cookie headers when the response head is sent.
+    readonly cookies: [name: string, value: string, options: CookieOptions][] = [];
 
     readonly headers = new Headers();
     readonly trailers = new Headers();
@@ -24,6 +44,10 @@ export class ResponseData {
 
 export type Data = string | Buffer | (string | Buffer)[];
 
+export function toBuffer(data: string | Buffer) {
+    return typeof data === "string" ? Buffer.from(data) : data;
+}
+
 export interface Request {
     readonly request: RequestData;
     readonly response: ResponseData;
@@ -31,9 +55,17 @@ export interface Request {
     opaque: boolean;
     disconnect: boolean;
 
+    bufferData: boolean;
+    nagling: boolean;
+    ranges: boolean;
+    goaway: boolean;
+    cache: number;
+
     done(): boolean;
     
     cancel(): Promise<number>;
+    tcpInfo(): Record<string, number | boolean> | undefined;
+    reset(code?: number): Promise<number>;
     close(): void;
     ok(): void;
 
@@ -42,6 +74,7 @@ export interface Request {
     send(final?: boolean): Promise<number>;
     sendData(data: Data, final?: boolean): Promise<number>;
 
+    flush(url: string, recursive?: boolean): Promise<number>;
     push(method: string, url: string, headers: Headers): void;
 
     dropIdentity(): void;
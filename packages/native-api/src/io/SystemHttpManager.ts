@@ -1,5 +1,6 @@
 import { EventEmitter } from "events";
 
+import Recorder, { RecordingRequest } from "./Recording";
 import RelayHelper, { RelayHelperEvents } from "./RelayHelper";
 import SystemHttpRequest from "./SystemHttpRequest";
 import SystemHttpSession from "./SystemHttpSession";
@@ -21,6 +22,7 @@ export class SystemHttpManager extends EventEmitter {
     private requests = new Set<RelayHelper>();
     private sessions = new Set<SystemHttpSession>();
     private session?: SystemHttpSession;
+    private recorder?: Recorder;
 
     public async process(name: string) {
         await (0 as any);
@@ -28,19 +30,36 @@ export class SystemHttpManager extends EventEmitter {
         const { requests } = this;
         const native = SystemHttpRequest.create(name);
         while (!native.done()) {
-            const next = native.clone();
+            const { recorder } = this;
+            const clone = native.clone();
+            const next = recorder ? new RecordingRequest(clone, recorder) : clone;
             const helper = new RelayHelper(next);
             requests.add(helper);
 
+            const done = () => {
+                requests.delete(helper);
+                if (next instanceof RecordingRequest) {
+                    next.end();
+                }
+            };
+
             const result = await next.receive();
             if (result === true) {
-                helper.relay(this).finally(() => requests.delete(helper));
+                helper.relay(this).finally(done);
             } else {
-                helper.cancel().finally(() => requests.delete(helper));
+                helper.cancel().finally(done);
             }
         }
     }
 
+    // The previous recorder closes once the requests still writing to it are finished.
+    record(path?: string, redact?: string[]) {
+        const { recorder } = this;
+        this.recorder = path ? Recorder.open(path, redact) : undefined;
+
+        return recorder ? recorder.close() : Promise.resolve();
+    }
+
     createSession(name: string) {
         const session = this.session = SystemHttpSession.create(name);
         this.sessions.add(session);
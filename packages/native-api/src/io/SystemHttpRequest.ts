@@ -1,9 +1,11 @@
 import { Headers } from "./Headers";
 import { NodePlugin } from "../NodePlugin";
 import { UserGroup } from "../UserAPI";
+import { CookieKeys, serializeCookie } from "../CookieAPI";
+import { SessionStore, toSession } from "../SessionAPI";
 import { endianness } from "os";
 
-import Request, { RequestData, ResponseData } from "./Request";
+import Request, { AcceptKind, RequestData, ResponseData, toBuffer } from "./Request";
 
 function initMapper() {
     let requestHeaders = Object.assign(Object.create(null) as Record<string, number>, {
@@ -64,6 +66,8 @@ function initMapper() {
     });
 
     let requestHeadersByIndex = [] as string[];
+    let requestHeadersByName = Object.create(null) as Record<string, number>;
+    let responseHeadersByIndex = [] as string[];
     let responseHeadersByName = Object.create(null) as Record<string, number>;
 
     let max = 0;
@@ -73,14 +77,17 @@ function initMapper() {
         max = Math.max(id + 1, max)
         set.add(id);
 
+        responseHeadersByIndex[id] = key;
         responseHeadersByName[key.toLowerCase()] = id;
     }
 
     for (const key in requestHeaders) {
         const id = requestHeaders[key];
         requestHeadersByIndex[id] = key;
+        requestHeadersByName[key.toLowerCase()] = id;
 
         if (id < max && !set.has(id)) {
+            responseHeadersByIndex[id] = key;
             responseHeadersByName[key.toLowerCase()] = id;
         }
     }
@@ -94,6 +101,15 @@ function initMapper() {
         return i !== undefined ? i : -1;
     }
 
+    function requestId(name: string) {
+        const i = requestHeadersByName[name.toLowerCase()];
+        return i !== undefined ? i : -1;
+    }
+
+    function responseName(id: number) {
+        return responseHeadersByIndex[id] || `X-Header-${id}`;
+    }
+
     const verbsByIndex = [
         undefined,
         undefined,
@@ -121,10 +137,10 @@ function initMapper() {
         return verbsByName[name] || 0;
     }
 
-    return { request, response, verb, method };
+    return { request, response, requestId, responseName, verb, method };
 }
 
-const mapper = initMapper();
+export const mapper = initMapper();
 let svc: any;
 
 type BlockItem = Buffer | boolean | number | string | [string, BufferEncoding];
@@ -181,8 +197,14 @@ function addBlockHeader(array: BlockItem[], name: string, value: string) {
 
 type Data = string | Buffer | (string | Buffer)[];
 
-function toBuffer(data: string | Buffer) {
-    return typeof data === "string" ? Buffer.from(data) : data;
+export interface JwtOptions {
+    jwks: string;
+    issuer?: string;
+    audience?: string;
+    skew?: number;
+    required?: boolean;
+    requireExpiry?: boolean;
+    group?: string;
 }
 
 export class SystemHttpRequest implements Request {
@@ -199,7 +221,15 @@ export class SystemHttpRequest implements Request {
     chunked = false;
     disconnect = false;
     opaque = false;
+
+    bufferData = false;
+    nagling = false;
+    ranges = false;
+    goaway = false;
+    cache = 0;
     user: unknown;
+    groups: UserGroup[] | undefined;
+    connection: unknown;
 
     constructor(ref: [unknown], name: string) {
         this.done = this.done.bind(this);
@@ -234,6 +264,45 @@ export class SystemHttpRequest implements Request {
         }        
     }
 
+    async jwt(options: JwtOptions | null) {
+        const { jwks = "", issuer, audience, skew, required, requireExpiry, group }: Partial<JwtOptions> = options || {};
+        await svc.http_request_jwt(this.handle(), jwks, issuer, audience, skew, required, requireExpiry, group);
+    }
+
+    stop() {
+        svc.http_request_stop(this.handle());
+    }
+
+    async drain(timeout = 30000) {
+        return await svc.http_request_drain(this.handle(), timeout) as number;
+    }
+
+    negotiate(offers: Partial<Record<AcceptKind, string[]>>) {
+        const { type = [], language = [], encoding = [], charset = [] } = offers;
+        svc.http_request_negotiate(this.handle(), type, language, encoding, charset);
+    }
+
+    cookieKeys(keys: CookieKeys | null) {
+        const handle = keys ? keys.handle() : undefined;
+        svc.http_request_cookie_keys(this.handle(), handle);
+    }
+
+    sessions(store: SessionStore | null, cookie = "sid", byUser = false) {
+        if (store) {
+            svc.http_request_sessions(this.handle(), store.handle(), cookie, byUser);
+        } else {
+            svc.http_request_sessions(this.handle());
+        }
+    }
+
+    certMap(rules: string[], required = false, untrusted = false) {
+        svc.http_request_cert_map(this.handle(), rules, required, untrusted);
+    }
+
+    async flush(url: string, recursive = false) {
+        return await svc.http_request_flush(this.handle(), url, recursive) as number;
+    }
+
     push(method: string, url: string, headers: Headers) {
         const path = url.replace(/\?.*/, "");
         const query = url.substring(path.length);
@@ -275,9 +344,30 @@ export class SystemHttpRequest implements Request {
         return 0;
     }
 
+    tcpInfo() {
+        const { id, ref } = this;
+        if (id[0] && ref[0]) {
+            return svc.http_request_tcp_info(ref[0], id[0]) as Record<string, number | boolean>;
+        }
+
+        return undefined;
+    }
+
+    async reset(code = 8) {
+        this.dropIdentity();
+
+        const { id, ref } = this;
+        if (id[0] && ref[0]) {
+            return await svc.http_request_reset(ref[0], id.pop(), code) as number;
+        }
+
+        return 0;
+    }
+
     resolveIdentity() {
-        const { user } = this;
+        const { user, groups } = this;
         this.user = undefined;
+        this.groups = undefined;
 
         if (user) {
             const result = svc.user_groups("viaToken", user) as UserGroup[];
@@ -296,12 +386,13 @@ export class SystemHttpRequest implements Request {
             return result;
         }
 
-        return [];
+        return groups || [];
     }
 
     dropIdentity() {
         const { user } = this;
         this.user = undefined;
+        this.groups = undefined;
 
         if (user) {
             svc.user_close(user);
@@ -309,12 +400,13 @@ export class SystemHttpRequest implements Request {
     }
 
     async receive(size = 0) {
-        const { knownHeaders, unknownHeaders, id, user, sockaddr, ...rest } = await svc.http_request_receive(this.handle(), size);
+        const { knownHeaders, unknownHeaders, id, connection, user, groups, identity, claims, negotiated, cookies, signedCookies, session, deferred, sockaddr, ...rest } = await svc.http_request_receive(this.handle(), size);
         if (rest.code !== 0) {
             return rest.code as number;
         }
 
         this.id[0] = id;
+        this.connection = connection;
 
         const { request, response } = this;
         request.method = rest.customVerb || mapper.verb(rest.verb) || "";
@@ -322,6 +414,25 @@ export class SystemHttpRequest implements Request {
         request.version = rest.version;
         request.speedy = !!rest.http2;
         request.userId = rest.user_sid || "";
+        request.timing = rest.timing || {};
+        request.identity = identity;
+        request.claims = claims ? JSON.parse(claims) : {};
+        request.negotiated = negotiated || {};
+        request.cookies = [];
+        request.signedCookies = {};
+
+        const list: string[] = cookies || [];
+        for (let i = 0; i < list.length; i += 2) {
+            request.cookies.push([list[i], list[i + 1]]);
+        }
+
+        request.session = toSession(session);
+        request.deferred = !!deferred;
+
+        const trusted: string[] = signedCookies || [];
+        for (let i = trusted.length - 2; i >= 0; i -= 2) {
+            request.signedCookies[trusted[i]] = trusted[i + 1];
+        }
         response.version = rest.version;
 
         if (Buffer.isBuffer(sockaddr)) {
@@ -369,6 +480,7 @@ export class SystemHttpRequest implements Request {
 
         this.readable = !!rest.body;
         this.user = user;
+        this.groups = groups;
         return true;
     }
 
@@ -387,6 +499,27 @@ export class SystemHttpRequest implements Request {
         return data.subarray(0, result.size);
     }
 
+    options() {
+        let result = 0;
+        if (this.bufferData) {
+            result |= 1;
+        }
+
+        if (this.nagling) {
+            result |= 2;
+        }
+
+        if (this.ranges) {
+            result |= 4;
+        }
+
+        if (this.goaway) {
+            result |= 8;
+        }
+
+        return result;
+    }
+
     async send(final = false) {
         if (final) {
             this.writable = false;
@@ -398,6 +531,8 @@ export class SystemHttpRequest implements Request {
             this.opaque,
             this.writable,
             !this.writable && this.disconnect,
+            this.options(),
+            this.writable ? 0 : this.cache,
             response.status,
             Number(major), Number(minor),
             response.reason,
@@ -410,6 +545,10 @@ export class SystemHttpRequest implements Request {
             addBlockHeader(block, name, value);
         }
 
+        for (const [name, value, options] of response.cookies.splice(0)) {
+            addBlockHeader(block, "Set-Cookie", serializeCookie(name, value, options));
+        }
+
         if (!this.writable) {
             for (const [name, value] of response.trailers.renderFlat()) {
                 addBlockHeader(block, name, value);
@@ -434,6 +573,7 @@ export class SystemHttpRequest implements Request {
             this.opaque,
             this.writable,
             !this.writable && this.disconnect,
+            this.options(),
         ];
 
         let hasTrailers = false;
@@ -22,6 +22,10 @@ export class SystemHttpSession {
         svc.http_session_config(this.handle(), ...args.flat());
     }
 
+    binding(url: string, ...flags: string[]) {
+        return svc.http_session_binding(this.handle(), url, ...flags) as number;
+    }
+
     handle() {
         const { ref } = this;
         if (ref[0]) {
@@ -38,13 +42,20 @@ export class SystemHttpSession {
         }
     }
 
-    listen(url: string) {
-        svc.http_session_listen(this.handle(), url);
+    listen(url: string, group?: string) {
+        svc.http_session_listen(this.handle(), url, group);
     }
 
     release(url: string) {
         svc.http_session_release(this.handle(), url);
     }
+
+    async waitForDemand() {
+        const code = await svc.http_session_wait_demand(this.handle()) as number;
+        if (code) {
+            throw new Error(`HttpWaitForDemandStart: ${code}`);
+        }
+    }
 }
 
 export default SystemHttpSession;
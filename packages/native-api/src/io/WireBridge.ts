@@ -0,0 +1,148 @@
+import { Server } from "http";
+
+import DuplexPair from "./DuplexPair";
+import { NodePlugin } from "../NodePlugin";
+
+let svc: any;
+
+interface WireRequest {
+    id: unknown;
+    head: Buffer;
+    chunked: boolean;
+    body: boolean;
+    upgrade: boolean;
+    verb: string;
+}
+
+export class WireBridge {
+    readonly ref: [unknown];
+    readonly server: Server;
+
+    constructor(ref: [unknown], server: Server) {
+        this.ref = ref;
+        this.server = server;
+    }
+
+    static create(name: string, server: Server) {
+        svc = NodePlugin.setup();
+
+        const ref = svc.http_request_create(name);
+        return new this([ref], server);
+    }
+
+    done() {
+        return !this.ref[0];
+    }
+
+    close() {
+        const { ref } = this;
+        if (ref[0]) {
+            svc.http_session_close(ref.pop());
+        }
+    }
+
+    // Relays run concurrently; the first one that fails rejects process once receiving stops.
+    async process(size = 0) {
+        const pending = new Set<Promise<number>>();
+        while (!this.done()) {
+            const result = await svc.http_wire_receive(this.ref[0], size);
+            if (result.code === 0) {
+                const relay = this.relay(result);
+                pending.add(relay);
+                relay.then(() => pending.delete(relay), () => undefined);
+            }
+        }
+
+        await Promise.all(pending);
+    }
+
+    private async pump(req: WireRequest, client: DuplexPair, chunked: boolean, end: boolean) {
+        const ref = this.ref[0];
+        while (true) {
+            const next = await svc.http_wire_read(ref, req.id, chunked, 16384);
+            if (next.data.byteLength > 0 && !client.destroyed) {
+                client.write(next.data);
+            }
+
+            if (next.code) {
+                client.destroy();
+                return next.code as number;
+            }
+
+            if (next.eof) {
+                if (end) {
+                    client.end();
+                }
+
+                return 0;
+            }
+        }
+    }
+
+    async relay(req: WireRequest) {
+        const ref = this.ref[0];
+        const responder = svc.http_wire_responder(ref, req.id, req.verb === "HEAD");
+        const [client, socket] = DuplexPair.create();
+        this.server.emit("connection", socket);
+
+        let aborted = false;
+        let upgraded: Promise<number> | undefined;
+        const reader = (async () => {
+            for await (const data of client) {
+                const code = await svc.http_wire_write(responder, data) as number;
+                if (code) {
+                    return code;
+                }
+
+                if (req.upgrade && !upgraded && svc.http_wire_upgraded(responder)) {
+                    upgraded = this.pump(req, client, false, true);
+                }
+            }
+
+            if (aborted) {
+                return 0;
+            }
+
+            return await svc.http_wire_end(responder) as number;
+        })();
+
+        const abort = async (code: number) => {
+            aborted = true;
+            client.destroy();
+            socket.destroy();
+            await reader.catch(() => 0);
+            await svc.http_request_cancel(ref, req.id);
+            return code;
+        };
+
+        try {
+            client.write(req.head);
+
+            if (req.body) {
+                const code = await this.pump(req, client, req.chunked, false);
+                if (code) {
+                    return await abort(code);
+                }
+            }
+
+            let code = await reader;
+            if (!code && upgraded) {
+                code = await upgraded;
+            }
+
+            if (code) {
+                await svc.http_request_cancel(ref, req.id);
+            }
+
+            return code;
+        } catch (err) {
+            await svc.http_request_cancel(ref, req.id);
+            throw err;
+        } finally {
+            client.destroy();
+            svc.http_wire_close(responder);
+        }
+    }
+}
+
+export default WireBridge;
@@ -0,0 +1,58 @@
+import FakePlugin from "../../FakePlugin";
+import DemandStart from "../DemandStart";
+import Handover from "../Handover";
+import type SystemHttpSession from "../SystemHttpSession";
+
+function session() {
+    let demands = 0;
+    const value = {
+        async waitForDemand() {
+            demands++;
+        },
+    };
+
+    return [value as unknown as SystemHttpSession, () => demands] as const;
+}
+
+describe("DemandStart", () => {
+    test("demand wakes a waiting worker without touching handover states", async () => {
+        new FakePlugin().install();
+        const [controller, demands] = session();
+
+        const attached = DemandStart.attach("worker", 1000);
+        expect(await DemandStart.once(controller, { signal: "worker" })).toBe(0);
+        expect(await attached).toBe(true);
+        expect(demands()).toBe(1);
+
+        expect(await Handover.wait("worker", "ready", 10)).toBe(false);
+        expect(await DemandStart.attach("worker", 10)).toBe(false);
+
+        Handover.signal("worker", "ready");
+        expect(await DemandStart.attach("worker", 10)).toBe(false);
+        expect(await Handover.wait("worker", "ready", 10)).toBe(true);
+    });
+
+    test("each demand releases one attach", async () => {
+        new FakePlugin().install();
+        const [controller] = session();
+
+        await DemandStart.once(controller, { signal: "pool" });
+        const first = DemandStart.attach("pool", 50);
+        const second = DemandStart.attach("pool", 50);
+        expect(await Promise.all([first, second])).toEqual([true, false]);
+    });
+
+    test("spawns the worker command and reports its exit code", async () => {
+        new FakePlugin().install();
+        const [controller, demands] = session();
+
+        const code = await DemandStart.once(controller, {
+            command: process.execPath,
+            args: ["-e", "process.exit(Number(process.env.EXIT_CODE))"],
+            env: { EXIT_CODE: "3" },
+        });
+
+        expect(code).toBe(3);
+        expect(demands()).toBe(1);
+    });
+});
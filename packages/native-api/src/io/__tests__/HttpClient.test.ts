@@ -0,0 +1,151 @@
+import { createServer, IncomingMessage, Server, ServerResponse } from "http";
+import { AddressInfo } from "net";
+
+import FakePlugin from "../../FakePlugin";
+import HttpClient from "../HttpClient";
+
+let server: Server;
+let other: Server;
+let base = "";
+let otherBase = "";
+
+async function echo(req: IncomingMessage, res: ServerResponse) {
+    const data: Buffer[] = [];
+    for await (const chunk of req) {
+        data.push(chunk);
+    }
+
+    if (req.url === "/away") {
+        res.writeHead(302, { Location: `${otherBase}/landed` });
+        res.end();
+        return;
+    }
+
+    if (req.url === "/moved") {
+        res.writeHead(303, { Location: "/x" });
+        res.end();
+        return;
+    }
+
+    res.setHeader("X-Method", req.method || "");
+    res.setHeader("X-Echo", String(req.headers["x-echo"] || ""));
+    res.setHeader("X-Authorization", String(req.headers["authorization"] || ""));
+    res.end(Buffer.concat(data));
+}
+
+async function listen(target: Server) {
+    await new Promise<void>(resolve => target.listen(0, "127.0.0.1", resolve));
+    return `http://127.0.0.1:${(target.address() as AddressInfo).port}`;
+}
+
+beforeAll(async () => {
+    server = createServer(echo);
+    other = createServer(echo);
+    base = await listen(server);
+    otherBase = await listen(other);
+});
+
+afterAll(async () => {
+    await new Promise(resolve => server.close(resolve));
+    await new Promise(resolve => other.close(resolve));
+});
+
+describe("HttpClient", () => {
+    test("get with headers", async () => {
+        const plugin = new FakePlugin().install();
+        const client = HttpClient.create();
+        const result = await client.text(`${base}/x`, { headers: { "X-Echo": "hi" } });
+
+        expect(result.status).toBe(200);
+        expect(result.headers).toContainEqual(["X-Echo", "hi"]);
+        expect(result.headers).toContainEqual(["X-Method", "GET"]);
+        expect(result.text).toBe("");
+        expect(plugin.clientRequests).toBe(0);
+        client.close();
+    });
+
+    test("post whole and streamed bodies", async () => {
+        new FakePlugin().install();
+        const client = HttpClient.create();
+        const whole = await client.text(`${base}/x`, { method: "POST", body: "hello" });
+        expect(whole.text).toBe("hello");
+
+        async function* parts() {
+            yield "hello ";
+            yield Buffer.from("world");
+        }
+
+        const streamed = await client.text(`${base}/x`, { method: "PUT", body: parts() });
+        expect(streamed.text).toBe("hello world");
+        client.close();
+    });
+
+    test("failed send closes the request", async () => {
+        const plugin = new FakePlugin().install();
+        plugin.fail("client_send", 12029);
+
+        const client = HttpClient.create();
+        await expect(client.request(`${base}/x`)).rejects.toThrow("http_client_send: 12029");
+        expect(plugin.clientOpens).toHaveLength(1);
+        expect(plugin.clientRequests).toBe(0);
+        client.close();
+    });
+
+    test("see other drops the body", async () => {
+        const plugin = new FakePlugin().install();
+        const client = HttpClient.create();
+        const result = await client.text(`${base}/moved`, { method: "POST", body: "hello", headers: { "Content-Type": "text/plain" } });
+
+        expect(result.headers).toContainEqual(["X-Method", "GET"]);
+        expect(result.text).toBe("");
+        expect(plugin.clientOpens[1].headers).toEqual([]);
+        expect(plugin.clientRequests).toBe(0);
+        client.close();
+    });
+
+    test("cross-origin redirect drops credentials", async () => {
+        const plugin = new FakePlugin().install();
+        const client = HttpClient.create();
+        const result = await client.text(`${base}/away`, { headers: { Authorization: "Basic c2VjcmV0", "X-Echo": "hi" } });
+
+        expect(result.headers).toContainEqual(["X-Authorization", ""]);
+        expect(result.headers).toContainEqual(["X-Echo", "hi"]);
+        expect(plugin.clientOpens.map(x => x.credentials)).toEqual([true, false]);
+        expect(plugin.clientRequests).toBe(0);
+        client.close();
+    });
+
+    test("manual redirect returns the response", async () => {
+        new FakePlugin().install();
+        const client = HttpClient.create({ redirect: "manual" });
+        const result = await client.text(`${base}/away`);
+
+        expect(result.status).toBe(302);
+        expect(result.headers).toContainEqual(["Location", `${otherBase}/landed`]);
+        client.close();
+    });
+
+    test("never policy returns the redirect response", async () => {
+        const plugin = new FakePlugin().install();
+        const client = HttpClient.create({ redirect: "never" });
+        const result = await client.text(`${base}/away`);
+
+        expect(result.status).toBe(302);
+        expect(plugin.clientOpens).toHaveLength(1);
+        client.close();
+    });
+
+    test("safe redirects refuse https downgrades", () => {
+        const svc = new FakePlugin().exports() as any;
+        const safe = svc.http_client_create(undefined, "follow");
+        const always = svc.http_client_create(undefined, "always");
+        const origin = "https://a.example/";
+
+        expect(svc.http_client_redirect(safe, origin, origin, "GET", 302, "http://a.example/x", 0)).toBeNull();
+        expect(svc.http_client_redirect(always, origin, origin, "GET", 302, "http://a.example/x", 0)).toEqual(
+            { url: "http://a.example/x", method: "GET", body: true, credentials: false });
+        expect(svc.http_client_redirect(safe, origin, origin, "GET", 302, "ftp://a.example/x", 0)).toBeNull();
+        expect(svc.http_client_redirect(safe, origin, origin, "HEAD", 303, "/x", 0)).toEqual(
+            { url: "https://a.example/x", method: "HEAD", body: true, credentials: true });
+    });
+});
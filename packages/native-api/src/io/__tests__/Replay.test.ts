@@ -0,0 +1,118 @@
+import { PassThrough } from "stream";
+
+import Recorder, { parseRecording, REDACTED, RecordingRequest } from "../Recording";
+import Request from "../Request";
+import ReplayRequest, { replay } from "../ReplayRequest";
+
+function source() {
+    const text = [
+        { type: "request", seq: 1, method: "POST", url: "/echo", version: "1.1", headers: [["Host", "local"]] },
+        { type: "request-data", seq: 1, data: Buffer.from("hello").toString("base64") },
+        { type: "request-end", seq: 1 },
+        { type: "response", seq: 1, status: 200, reason: "OK", version: "1.1", headers: [] },
+        { type: "response-end", seq: 1, trailers: [] },
+    ];
+
+    return parseRecording(text.map(x => JSON.stringify(x)).join("\n"));
+}
+
+async function echo(req: Request, suffix = "") {
+    const data: Buffer[] = [];
+    for (let chunk = await req.receiveData(); Buffer.isBuffer(chunk); chunk = await req.receiveData()) {
+        data.push(chunk);
+    }
+
+    req.response.status = 200;
+    req.response.reason = "OK";
+    req.response.headers.set("Content-Type", "text/plain");
+    await req.send();
+    await req.sendData([Buffer.concat(data), suffix], true);
+}
+
+describe("Replay", () => {
+    test("record then replay", async () => {
+        const sink = new PassThrough();
+        const lines: string[] = [];
+        sink.on("data", x => lines.push(x.toString()));
+
+        const recorder = new Recorder(sink);
+        const [exchange] = source();
+        const req = new RecordingRequest(new ReplayRequest(exchange), recorder);
+        await req.receive();
+        await echo(req);
+
+        const recorded = parseRecording(lines.join(""));
+        expect(recorded.length).toBe(1);
+        expect(recorded[0].request.body.map(x => x.toString())).toEqual(["hello"]);
+        expect(recorded[0].response.status).toBe(200);
+        expect(recorded[0].response.complete).toBe(true);
+
+        const same = await replay(recorded, x => echo(x), { chunks: true });
+        expect(same[0].diffs).toEqual([]);
+
+        const changed = await replay(recorded, x => echo(x, "!"));
+        expect(changed[0].diffs).toEqual(["body: differs at offset 5 (expected 5 bytes, got 6)"]);
+    });
+
+    test("headers are recorded once and closing waits for open exchanges", async () => {
+        const sink = new PassThrough();
+        const lines: string[] = [];
+        sink.on("data", x => lines.push(x.toString()));
+
+        const recorder = new Recorder(sink);
+        const [exchange] = source();
+        const req = new RecordingRequest(new ReplayRequest(exchange), recorder);
+        await req.receive();
+
+        let closed = false;
+        const closing = recorder.close().then(() => closed = true);
+        expect(recorder.retain()).toBe(false);
+
+        req.response.status = 204;
+        await req.send();
+        await req.send(true);
+        await new Promise(resolve => setImmediate(resolve));
+        expect(closed).toBe(false);
+
+        req.end();
+        await closing;
+
+        const types = lines.join("").trim().split("\n").map(x => JSON.parse(x).type);
+        expect(types).toEqual(["request", "response", "response-end"]);
+    });
+
+    test("credentials are redacted and not compared on replay", async () => {
+        const sink = new PassThrough();
+        const lines: string[] = [];
+        sink.on("data", x => lines.push(x.toString()));
+
+        const recorder = new Recorder(sink);
+        const [exchange] = source();
+        exchange.request.headers.push(["Authorization", "Bearer secret"], ["Cookie", "sid=1"]);
+
+        const login = async (req: Request, sid: string) => {
+            req.response.status = 204;
+            req.response.headers.set("Set-Cookie", `sid=${sid}`);
+            req.response.headers.set("X-Session", sid);
+            await req.send(true);
+        };
+
+        const req = new RecordingRequest(new ReplayRequest(exchange), recorder);
+        await req.receive();
+        await login(req, "1");
+        req.end();
+        await recorder.close();
+
+        const text = lines.join("");
+        expect(text).not.toContain("secret");
+        expect(text).not.toContain("sid=1");
+
+        const [recorded] = parseRecording(text);
+        expect(recorded.request.headers).toContainEqual(["Authorization", REDACTED]);
+        expect(recorded.request.headers).toContainEqual(["Cookie", REDACTED]);
+        expect(recorded.response.headers).toContainEqual(["Set-Cookie", REDACTED]);
+
+        const results = await replay([recorded], x => login(x, "2"));
+        expect(results[0].diffs).toEqual(["header missing: x-session: 1", "header unexpected: x-session: 2"]);
+    });
+});
@@ -0,0 +1,147 @@
+import FakePlugin, { ERROR_OPERATION_ABORTED } from "../../FakePlugin";
+import Handover from "../Handover";
+import SystemHttpRequest from "../SystemHttpRequest";
+
+describe("SystemHttpRequest", () => {
+    test("exchanges a request through the fake plugin", async () => {
+        const plugin = new FakePlugin().install();
+        const id = plugin.inject({
+            method: "POST",
+            url: "/echo?x=1",
+            headers: [["Host", "local"], ["Cookie", "a=1; b=2"], ["X-Custom", "yes"]],
+            body: ["hel", "lo"],
+        });
+
+        const req = SystemHttpRequest.create("fake");
+        expect(await req.receive()).toBe(true);
+        expect(req.request.method).toBe("POST");
+        expect(req.request.url).toBe("/echo?x=1");
+        expect(req.request.headers.get("X-Custom")).toBe("yes");
+        expect(req.request.cookies).toEqual([["a", "1"], ["b", "2"]]);
+        expect(req.connection).toBe(id);
+
+        const data: Buffer[] = [];
+        for (let chunk = await req.receiveData(); Buffer.isBuffer(chunk); chunk = await req.receiveData()) {
+            data.push(chunk);
+        }
+
+        expect(Buffer.concat(data).toString()).toBe("hello");
+
+        req.response.status = 200;
+        req.response.reason = "OK";
+        req.response.headers.set("Content-Type", "text/plain");
+        await req.send();
+        await req.sendData(["hello"], true);
+        req.close();
+
+        const [head, body] = plugin.callsFor(id);
+        expect(head.op).toBe("send");
+        expect(head.status).toBe(200);
+        expect(head.headers).toContainEqual(["Content-Type", "text/plain"]);
+        expect(body.op).toBe("send_data");
+        expect(Buffer.concat(body.data).toString()).toBe("hello");
+        expect(body.flags!.more).toBe(false);
+    });
+
+    test("reports identities, negotiation, signed cookies and sessions only when configured", async () => {
+        const plugin = new FakePlugin().install();
+        const init = {
+            url: "/me",
+            identity: { kind: "jwt" as const, subject: "alice", claims: { scope: "read" } },
+            negotiated: { type: "application/json" },
+            signedCookies: [["sid", "abc"]] as [string, string][],
+            session: { id: "abc", data: { n: 1 }, created: 1, expires: 2 },
+        };
+
+        const req = SystemHttpRequest.create("fake");
+        plugin.inject(init);
+        await req.receive();
+        expect(req.request.identity).toBeUndefined();
+        expect(req.request.negotiated).toEqual({});
+        expect(req.request.signedCookies).toEqual({});
+        expect(req.request.session).toBeUndefined();
+        await req.cancel();
+
+        await req.jwt({ jwks: "https://issuer/keys", required: true });
+        req.negotiate({ type: ["text/html", "application/json"], language: ["en"] });
+        req.cookieKeys({ handle: () => ({}) } as any);
+        req.sessions({ handle: () => ({}) } as any);
+
+        plugin.inject(init);
+        await req.receive();
+        expect(req.request.identity).toEqual({ kind: "jwt", subject: "alice" });
+        expect(req.request.claims).toEqual({ scope: "read" });
+        expect(req.request.negotiated).toEqual({ type: "application/json", language: "en" });
+        expect(req.request.signedCookies).toEqual({ sid: "abc" });
+        expect(req.request.session).toEqual(init.session);
+        await req.cancel();
+
+        const rejected = plugin.inject({ url: "/anonymous" });
+        const accepted = plugin.inject(init);
+        await req.receive();
+        expect(req.id[0]).toBe(accepted);
+        expect(plugin.callsFor(rejected)).toEqual([expect.objectContaining({ op: "reject", status: 401 })]);
+        await req.cancel();
+
+        await req.jwt(null);
+        req.certMap(["subject:CN=svc"], true, true);
+        const unsigned = plugin.inject({ url: "/anonymous" });
+        plugin.inject({ url: "/svc", identity: { kind: "cert", subject: "CN=svc" } });
+        await req.receive();
+        expect(req.request.identity).toEqual({ kind: "cert", subject: "CN=svc" });
+        expect(plugin.callsFor(unsigned)).toEqual([expect.objectContaining({ op: "reject", status: 403 })]);
+        await req.cancel();
+        req.close();
+    });
+
+    test("keys jwt settings by url group and refuses plain http key sets", async () => {
+        const plugin = new FakePlugin().install();
+        const req = SystemHttpRequest.create("fake");
+        await expect(req.jwt({ jwks: "http://issuer/keys" })).rejects.toThrow(TypeError);
+        await req.jwt({ jwks: "https://issuer/keys", required: true, group: "api" });
+
+        plugin.inject({ url: "/public" });
+        await req.receive();
+        expect(req.request.url).toBe("/public");
+        await req.cancel();
+
+        const rejected = plugin.inject({ url: "/api", group: "api" });
+        plugin.inject({ url: "/api/me", group: "api", identity: { kind: "jwt", subject: "alice" } });
+        await req.receive();
+        expect(req.request.identity).toEqual({ kind: "jwt", subject: "alice" });
+        expect(plugin.callsFor(rejected)).toEqual([expect.objectContaining({ op: "reject", status: 401 })]);
+        await req.cancel();
+        req.close();
+    });
+
+    test("stop ends receiving and drain waits for open requests", async () => {
+        const plugin = new FakePlugin().install();
+        const req = SystemHttpRequest.create("fake");
+        plugin.inject({ url: "/slow" });
+        await req.receive();
+
+        const other = req.clone();
+        const waiting = other.receive();
+        req.stop();
+        expect(await waiting).toBe(ERROR_OPERATION_ABORTED);
+        expect(await req.drain(50)).toBe(1);
+
+        const draining = req.drain(1000);
+        req.response.status = 204;
+        await req.send(true);
+        expect(await draining).toBe(0);
+        req.close();
+    });
+
+    test("handover events release one wait per signal", async () => {
+        new FakePlugin().install();
+        Handover.signal("worker", "ready");
+        Handover.signal("worker", "ready");
+        expect(await Handover.wait("worker", "ready", 10)).toBe(true);
+        expect(await Handover.wait("worker", "ready", 10)).toBe(false);
+
+        const waiting = Handover.wait("worker", "released", 1000);
+        Handover.signal("worker", "released");
+        expect(await waiting).toBe(true);
+    });
+});
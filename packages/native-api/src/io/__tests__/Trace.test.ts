@@ -0,0 +1,50 @@
+import FakePlugin from "../../FakePlugin";
+import TraceAPI, { TraceLevel } from "../../TraceAPI";
+import SystemHttpRequest from "../SystemHttpRequest";
+
+describe("Trace", () => {
+    test("fake plugin traces request operations", async () => {
+        const plugin = new FakePlugin().install();
+        const lines: [TraceLevel, string][] = [];
+        const trace = TraceAPI.create();
+        trace.toCallback("trace", (level, line) => lines.push([level, line]));
+
+        const id = plugin.inject({ url: "/x", body: "hi" });
+        const req = SystemHttpRequest.create("trace");
+        await req.receive();
+        await req.receiveData();
+
+        req.response.status = 204;
+        req.response.reason = "No Content";
+        await req.send();
+        await req.sendData([], true);
+
+        trace.off();
+        req.close();
+
+        expect(lines.map(x => x[0])).toEqual(lines.map(() => "trace"));
+        expect(lines.map(x => x[1])).toEqual([
+            `TRACE fake receive{id=${id}}: complete err=0`,
+            expect.stringMatching(new RegExp(`^TRACE fake receive_data\\{id=${id} len=\\d+\\}: issue err=0$`)),
+            `TRACE fake send{id=${id} more=true status=204}: issue err=0`,
+            expect.stringMatching(new RegExp(`^TRACE fake send_data\\{id=${id} more=false chunks=\\d+\\}: issue err=0$`)),
+        ]);
+    });
+
+    test("levels below trace and off are silent", async () => {
+        const plugin = new FakePlugin().install();
+        const lines: string[] = [];
+        const trace = TraceAPI.create();
+        trace.toCallback("debug", (_, line) => lines.push(line));
+
+        plugin.inject({ url: "/x" });
+        const req = SystemHttpRequest.create("trace");
+        await req.receive();
+        trace.off();
+        await req.cancel();
+        req.close();
+
+        expect(lines).toEqual([]);
+        expect(() => trace.toCallback("loud" as TraceLevel, () => {})).toThrow("Unknown trace level: loud");
+    });
+});
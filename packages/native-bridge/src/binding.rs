@@ -0,0 +1,262 @@
+use neon::prelude::*;
+
+use super::http::*;
+use super::support::*;
+use super::win32::*;
+
+use windows::core::PCSTR;
+use windows::core::PWSTR;
+use windows::Win32::Foundation::*;
+use windows::Win32::Networking::HttpServer::*;
+use windows::Win32::Networking::WinSock::AF_INET;
+use windows::Win32::Networking::WinSock::AF_INET6;
+use windows::Win32::Networking::WinSock::SOCKADDR;
+use windows::Win32::Networking::WinSock::SOCKADDR_STORAGE;
+use windows::Win32::System::IO::OVERLAPPED;
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::System::LibraryLoader::GetProcAddress;
+
+use std::ffi::c_void;
+use std::mem::size_of;
+use std::mem::transmute;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+
+// HttpUpdateServiceConfiguration only exists from Windows Server 2022 on, so it is resolved at
+// runtime instead of being linked.
+type UpdateServiceConfiguration = unsafe extern "system" fn(HANDLE, HTTP_SERVICE_CONFIG_ID, *const c_void, u32, *mut OVERLAPPED) -> u32;
+
+#[derive(Debug, PartialEq)]
+pub enum Endpoint {
+    Ip(SocketAddr),
+    Sni(String, u16),
+}
+
+impl Endpoint {
+    pub fn from_prefix(url: &str) -> Option<Endpoint> {
+        let rest = url.get(..8).filter(|x| x.eq_ignore_ascii_case("https://")).map(|_| &url[8..])?;
+        let authority = rest.split('/').next()?;
+        let (host, port) = match authority.rfind(':') {
+            Some(at) if !authority[at..].contains(']') => (&authority[..at], authority[at + 1..].parse::<u16>().ok()?),
+            _ => (authority, 443),
+        };
+
+        if host == "+" || host == "*" {
+            return Some(Endpoint::Ip(SocketAddr::new(IpAddr::from([0, 0, 0, 0]), port)));
+        }
+
+        let bare = host.strip_prefix('[').and_then(|x| x.strip_suffix(']')).unwrap_or(host);
+        if let Ok(ip) = bare.parse::<IpAddr>() {
+            return Some(Endpoint::Ip(SocketAddr::new(ip, port)));
+        }
+
+        if host.is_empty() || host.contains(['[', ']']) {
+            return None;
+        }
+
+        Some(Endpoint::Sni(host.to_ascii_lowercase(), port))
+    }
+}
+
+pub fn parse_flag(value: &str) -> Option<(u32, bool)> {
+    match value {
+        "http2" => Some((HTTP_SERVICE_CONFIG_SSL_FLAG_DISABLE_HTTP2, false)),
+        "no-http2" => Some((HTTP_SERVICE_CONFIG_SSL_FLAG_DISABLE_HTTP2, true)),
+        "legacy-tls" => Some((HTTP_SERVICE_CONFIG_SSL_FLAG_DISABLE_LEGACY_TLS, false)),
+        "no-legacy-tls" => Some((HTTP_SERVICE_CONFIG_SSL_FLAG_DISABLE_LEGACY_TLS, true)),
+        _ => None,
+    }
+}
+
+pub fn parse_flags(flags: &[String]) -> Result<(u32, u32), String> {
+    let mut set = 0;
+    let mut clear = 0;
+    for flag in flags {
+        match parse_flag(flag) {
+            Some((mask, true)) => set |= mask,
+            Some((mask, false)) => clear |= mask,
+            None => return Err(format!("Unknown binding flag: {}", flag)),
+        }
+    }
+
+    if set & clear != 0 {
+        return Err("Conflicting binding flags".to_string());
+    }
+
+    Ok((set, clear))
+}
+
+fn sockaddr_storage(addr: &SocketAddr) -> SOCKADDR_STORAGE {
+    let mut storage = SOCKADDR_STORAGE::default();
+    let bytes = unsafe { &mut *(&mut storage as *mut SOCKADDR_STORAGE as *mut [u8; size_of::<SOCKADDR_STORAGE>()]) };
+    bytes[2..4].copy_from_slice(&addr.port().to_be_bytes());
+    match addr {
+        SocketAddr::V4(v4) => {
+            bytes[0..2].copy_from_slice(&(AF_INET.0 as u16).to_le_bytes());
+            bytes[4..8].copy_from_slice(&v4.ip().octets());
+        },
+        SocketAddr::V6(v6) => {
+            bytes[0..2].copy_from_slice(&(AF_INET6.0 as u16).to_le_bytes());
+            bytes[8..24].copy_from_slice(&v6.ip().octets());
+            bytes[24..28].copy_from_slice(&v6.scope_id().to_le_bytes());
+        },
+    }
+
+    storage
+}
+
+fn update_function() -> Option<UpdateServiceConfiguration> {
+    unsafe {
+        let name = wide("httpapi.dll");
+        let module = GetModuleHandleW(wide_ptr(&name)).ok()?;
+        let proc = GetProcAddress(module, PCSTR(b"HttpUpdateServiceConfiguration\0".as_ptr()))?;
+        Some(transmute::<unsafe extern "system" fn() -> isize, UpdateServiceConfiguration>(proc))
+    }
+}
+
+pub fn ssl_binding_flags(endpoint: &Endpoint, set: u32, clear: u32) -> Result<u32, (&'static str, u32)> {
+    unsafe {
+        let err = HttpInitialize(ver_init, HTTP_INITIALIZE_CONFIG, None);
+        if err != 0 {
+            return Err(("HttpInitialize", err));
+        }
+
+        let result = update_binding(endpoint, set, clear);
+        HttpTerminate(HTTP_INITIALIZE_CONFIG, None);
+        result
+    }
+}
+
+unsafe fn query_binding(id: HTTP_SERVICE_CONFIG_ID, query: *const c_void, query_size: u32) -> Result<Vec<u64>, (&'static str, u32)> {
+    let mut size = 0u32;
+    let err = HttpQueryServiceConfiguration(HANDLE(0), id, Some(query), query_size, None, 0, Some(&mut size), None);
+    if err != ERROR_INSUFFICIENT_BUFFER.0 {
+        return Err(("HttpQueryServiceConfiguration", err));
+    }
+
+    let mut buf = vec![0u64; (size as usize + 7) / 8];
+    let buf_ptr = buf.as_mut_ptr() as *mut c_void;
+    let err = HttpQueryServiceConfiguration(HANDLE(0), id, Some(query), query_size, Some(buf_ptr), size, Some(&mut size), None);
+    if err != 0 {
+        return Err(("HttpQueryServiceConfiguration", err));
+    }
+
+    Ok(buf)
+}
+
+// The flags are changed with a single atomic update of the existing binding. Where the system has
+// no atomic update the binding is left alone and the call fails.
+unsafe fn update_binding(endpoint: &Endpoint, set: u32, clear: u32) -> Result<u32, (&'static str, u32)> {
+    let (id, mut buf, info_size) = match endpoint {
+        Endpoint::Ip(addr) => {
+            let mut storage = sockaddr_storage(addr);
+            let mut query = HTTP_SERVICE_CONFIG_SSL_QUERY::default();
+            query.QueryDesc = HttpServiceConfigQueryExact;
+            query.KeyDesc.pIpPort = &mut storage as *mut SOCKADDR_STORAGE as *mut SOCKADDR;
+
+            let query_ptr = &query as *const HTTP_SERVICE_CONFIG_SSL_QUERY as *const c_void;
+            let query_size = size_of::<HTTP_SERVICE_CONFIG_SSL_QUERY>() as u32;
+            let buf = query_binding(HttpServiceConfigSSLCertInfo, query_ptr, query_size)?;
+            (HttpServiceConfigSSLCertInfo, buf, size_of::<HTTP_SERVICE_CONFIG_SSL_SET>())
+        },
+        Endpoint::Sni(host, port) => {
+            let mut host_wide = wide(host);
+            let mut query = HTTP_SERVICE_CONFIG_SSL_SNI_QUERY::default();
+            query.QueryDesc = HttpServiceConfigQueryExact;
+            query.KeyDesc.IpPort = sockaddr_storage(&SocketAddr::new(IpAddr::from([0, 0, 0, 0]), *port));
+            query.KeyDesc.Host = PWSTR(host_wide.as_mut_ptr());
+
+            let query_ptr = &query as *const HTTP_SERVICE_CONFIG_SSL_SNI_QUERY as *const c_void;
+            let query_size = size_of::<HTTP_SERVICE_CONFIG_SSL_SNI_QUERY>() as u32;
+            let buf = query_binding(HttpServiceConfigSslSniCertInfo, query_ptr, query_size)?;
+            (HttpServiceConfigSslSniCertInfo, buf, size_of::<HTTP_SERVICE_CONFIG_SSL_SNI_SET>())
+        },
+    };
+
+    let info_ptr = buf.as_mut_ptr() as *mut c_void;
+    let param = match endpoint {
+        Endpoint::Ip(_) => &mut (*(info_ptr as *mut HTTP_SERVICE_CONFIG_SSL_SET)).ParamDesc,
+        Endpoint::Sni(..) => &mut (*(info_ptr as *mut HTTP_SERVICE_CONFIG_SSL_SNI_SET)).ParamDesc,
+    };
+
+    let after = (param.DefaultFlags & !clear) | set;
+    if param.DefaultFlags == after {
+        return Ok(after);
+    }
+
+    let update = match update_function() {
+        Some(update) => update,
+        None => return Err(("HttpUpdateServiceConfiguration", ERROR_NOT_SUPPORTED.0)),
+    };
+
+    param.DefaultFlags = after;
+    let err = update(HANDLE(0), id, info_ptr, info_size as u32, std::ptr::null_mut());
+    if err != 0 {
+        return Err(("HttpUpdateServiceConfiguration", err));
+    }
+
+    Ok(after)
+}
+
+fn http_session_binding(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let mut i = 0;
+    let arc = cx.import::<Session>(&mut i)?;
+    let url = cx.arg_string(&mut i)?;
+    let mut flags = Vec::<String>::new();
+    while i < cx.len() {
+        flags.push(cx.arg_string(&mut i)?);
+    }
+
+    let (set, clear) = match parse_flags(&flags) {
+        Ok(x) => x,
+        Err(err) => return cx.throw_type_error(err),
+    };
+
+    let endpoint = match Endpoint::from_prefix(&url) {
+        Some(x) if arc.registered(&url) => x,
+        Some(_) => return cx.throw_type_error(format!("Not registered on this session: {}", url)),
+        None => return cx.throw_type_error(format!("Invalid https prefix: {}", url)),
+    };
+
+    match ssl_binding_flags(&endpoint, set, clear) {
+        Ok(flags) => Ok(cx.number(flags)),
+        Err((hint, err)) => cx.throw_type_error(format!("{}: {}", hint, err))
+    }
+}
+
+pub fn binding_bind(cx: &mut ModuleContext) -> NeonResult<()> {
+    cx.export_function("http_session_binding", http_session_binding)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn flag_values() {
+        assert_eq!(HTTP_SERVICE_CONFIG_SSL_FLAG_DISABLE_HTTP2, 16);
+        assert_eq!(HTTP_SERVICE_CONFIG_SSL_FLAG_DISABLE_LEGACY_TLS, 1024);
+        assert_eq!(parse_flags(&strings(&["no-http2", "legacy-tls"])), Ok((16, 1024)));
+        assert!(parse_flags(&strings(&["http2", "no-http2"])).is_err());
+        assert!(parse_flags(&strings(&["tls13"])).is_err());
+    }
+
+    #[test]
+    fn endpoints() {
+        let any = |port| Endpoint::Ip(SocketAddr::new(IpAddr::from([0, 0, 0, 0]), port));
+        assert_eq!(Endpoint::from_prefix("https://+:8443/app/"), Some(any(8443)));
+        assert_eq!(Endpoint::from_prefix("https://*/"), Some(any(443)));
+        assert_eq!(Endpoint::from_prefix("https://127.0.0.1:9000/"), Some(Endpoint::Ip("127.0.0.1:9000".parse().unwrap())));
+        assert_eq!(Endpoint::from_prefix("https://[::1]:9000/"), Some(Endpoint::Ip("[::1]:9000".parse().unwrap())));
+        assert_eq!(Endpoint::from_prefix("https://[::1]/"), Some(Endpoint::Ip("[::1]:443".parse().unwrap())));
+        assert_eq!(Endpoint::from_prefix("HTTPS://Example.com:443/"), Some(Endpoint::Sni("example.com".into(), 443)));
+        assert_eq!(Endpoint::from_prefix("http://example.com/"), None);
+        assert_eq!(Endpoint::from_prefix("https://example.com:x/"), None);
+    }
+}
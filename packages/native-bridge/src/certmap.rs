@@ -0,0 +1,302 @@
+use super::cors::glob;
+use super::jwt::Identity;
+use super::message::*;
+
+use ring::digest;
+use serde_json::json;
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::*;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::RwLock;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CertField {
+    Subject,
+    Issuer,
+    San,
+    Thumbprint,
+}
+
+pub enum CertTarget {
+    Sid(String),
+    Role(String),
+}
+
+pub struct CertRule {
+    field: CertField,
+    pattern: String,
+    target: CertTarget,
+}
+
+impl CertRule {
+    // Rules are written as "<field>:<pattern> => <sid|role>:<value>".
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid certificate rule: {}", value);
+        let (from, to) = value.split_once("=>").ok_or_else(invalid)?;
+        let (field, pattern) = from.trim().split_once(':').ok_or_else(invalid)?;
+        let (kind, target) = to.trim().split_once(':').ok_or_else(invalid)?;
+        let field = match field.trim() {
+            "subject" => CertField::Subject,
+            "issuer" => CertField::Issuer,
+            "san" => CertField::San,
+            "thumbprint" => CertField::Thumbprint,
+            _ => return Err(invalid()),
+        };
+
+        let target = match kind.trim() {
+            "sid" => CertTarget::Sid(String::from(target.trim())),
+            "role" => CertTarget::Role(String::from(target.trim())),
+            _ => return Err(invalid()),
+        };
+
+        let mut pattern = pattern.trim().to_ascii_lowercase();
+        if field == CertField::Thumbprint {
+            pattern.retain(|x| x.is_ascii_hexdigit());
+        }
+
+        Ok(Self { field, pattern, target })
+    }
+
+    fn matches(&self, cert: &CertInfo) -> bool {
+        let test = |value: &str| glob(self.pattern.as_bytes(), value.to_ascii_lowercase().as_bytes());
+        match self.field {
+            CertField::Subject => test(&cert.subject),
+            CertField::Issuer => test(&cert.issuer),
+            CertField::San => cert.san.iter().any(|x| test(x)),
+            CertField::Thumbprint => self.pattern == cert.thumbprint,
+        }
+    }
+}
+
+pub struct CertInfo {
+    pub subject: String,
+    pub issuer: String,
+    pub san: Vec<String>,
+    pub thumbprint: String,
+}
+
+impl CertInfo {
+    pub fn parse(der: &[u8]) -> Option<Self> {
+        let (_, cert) = parse_x509_certificate(der).ok()?;
+        let mut san = Vec::new();
+        if let Ok(Some(ext)) = cert.subject_alternative_name() {
+            for name in ext.value.general_names.iter() {
+                match name {
+                    GeneralName::DNSName(x) => san.push(format!("dns:{}", x)),
+                    GeneralName::RFC822Name(x) => san.push(format!("email:{}", x)),
+                    GeneralName::URI(x) => san.push(format!("uri:{}", x)),
+                    _ => (),
+                }
+            }
+        }
+
+        let hash = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, der);
+        let thumbprint = hash.as_ref().iter().map(|x| format!("{:02x}", x)).collect();
+
+        Some(Self {
+            subject: cert.subject().to_string(),
+            issuer: cert.issuer().to_string(),
+            san,
+            thumbprint,
+        })
+    }
+}
+
+pub struct CertMapConfig {
+    pub rules: Vec<CertRule>,
+    pub required: bool,
+    pub untrusted: bool,
+}
+
+impl CertMapConfig {
+    // Rules only see names, so a certificate whose chain HTTP.sys could not validate (nonzero CertFlags)
+    // is never mapped unless untrusted certificates were explicitly allowed.
+    pub fn resolve(&self, flags: u32, der: &[u8]) -> Option<Identity> {
+        if flags != 0 && !self.untrusted {
+            return None;
+        }
+
+        self.map(&CertInfo::parse(der)?)
+    }
+
+    pub fn map(&self, cert: &CertInfo) -> Option<Identity> {
+        let mut sid = None;
+        let mut roles = Vec::new();
+        for rule in self.rules.iter().filter(|x| x.matches(cert)) {
+            match &rule.target {
+                CertTarget::Sid(value) if sid.is_none() => sid = Some(value.clone()),
+                CertTarget::Role(value) => roles.push(value.clone()),
+                _ => (),
+            }
+        }
+
+        if sid.is_none() && roles.len() < 1 {
+            return None;
+        }
+
+        let mut groups = Vec::new();
+        if let Some(sid) = &sid {
+            groups.push(("user", sid.clone()));
+        }
+
+        groups.extend(roles.into_iter().map(|x| ("role", x)));
+
+        let claims = json!({
+            "subject": cert.subject,
+            "issuer": cert.issuer,
+            "san": cert.san,
+            "thumbprint": cert.thumbprint,
+        });
+
+        Some(Identity { kind: "cert", sub: cert.subject.clone(), sid, groups, claims: claims.to_string() })
+    }
+}
+
+pub struct CertMap {
+    config: RwLock<Option<Arc<CertMapConfig>>>,
+    pending: Mutex<HashMap<u64, Identity>>,
+}
+
+impl CertMap {
+    pub fn new() -> Self {
+        Self {
+            config: RwLock::new(None),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self, config: Option<CertMapConfig>) {
+        if let Ok(mut value) = self.config.write() {
+            *value = config.map(Arc::new);
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.config.read().map(|x| x.is_some()).unwrap_or(false)
+    }
+
+    pub fn admit(&self, id: u64, cert: Option<(u32, &[u8])>) -> Option<Reply> {
+        let config = self.config.read().ok()?.clone()?;
+        let identity = cert.and_then(|(flags, der)| config.resolve(flags, der));
+        match identity {
+            Some(identity) => {
+                if let Ok(mut map) = self.pending.lock() {
+                    map.insert(id, identity);
+                }
+
+                None
+            },
+            None if config.required => Some(Reply::new(403, "Forbidden").header("Content-Length", "0")),
+            None => None,
+        }
+    }
+
+    pub fn take(&self, id: u64) -> Option<Identity> {
+        self.pending.lock().ok()?.remove(&id)
+    }
+
+    pub fn forget(&self, id: u64) {
+        if let Ok(mut map) = self.pending.lock() {
+            map.remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: &[u8] = include_bytes!("../tests/fixtures/alice.der");
+    const BOB: &[u8] = include_bytes!("../tests/fixtures/bob.der");
+    const FORGED: &[u8] = include_bytes!("../tests/fixtures/forged.der");
+
+    // CERT_E_UNTRUSTEDROOT, as reported for a chain ending in a root the server does not trust.
+    const UNTRUSTED_ROOT: u32 = 0x800B0109;
+
+    fn config(rules: &[&str]) -> CertMapConfig {
+        let rules = rules.iter().map(|x| CertRule::parse(x).unwrap()).collect();
+        CertMapConfig { rules, required: true, untrusted: false }
+    }
+
+    #[test]
+    fn parses_fixture_names() {
+        let cert = CertInfo::parse(ALICE).unwrap();
+        assert_eq!(cert.subject, "O=Example, CN=alice");
+        assert_eq!(cert.issuer, "O=Example, CN=Test Root");
+        assert_eq!(cert.san, vec!["dns:alice.example.com", "email:alice@example.com"]);
+        assert_eq!(cert.thumbprint, "76ea10a59b71f6f6be938cca969ef962a6864f20");
+    }
+
+    #[test]
+    fn maps_one_to_one() {
+        let config = config(&["thumbprint:76:EA:10:A5:9B:71:F6:F6:BE:93:8C:CA:96:9E:F9:62:A6:86:4F:20 => sid:S-1-5-21-1-2-3-1001"]);
+        let identity = config.resolve(0, ALICE).unwrap();
+        assert_eq!(identity.kind, "cert");
+        assert_eq!(identity.sid.as_deref(), Some("S-1-5-21-1-2-3-1001"));
+        assert_eq!(identity.groups, vec![("user", String::from("S-1-5-21-1-2-3-1001"))]);
+        assert!(config.resolve(0, BOB).is_none());
+    }
+
+    #[test]
+    fn maps_many_to_one() {
+        let config = config(&[
+            "issuer:*cn=test root => sid:S-1-5-21-1-2-3-2000",
+            "san:dns:*.example.com => role:partner",
+            "san:email:alice@* => role:admin",
+        ]);
+
+        let alice = config.resolve(0, ALICE).unwrap();
+        assert_eq!(alice.sid.as_deref(), Some("S-1-5-21-1-2-3-2000"));
+        assert!(alice.groups.contains(&("role", String::from("partner"))));
+        assert!(alice.groups.contains(&("role", String::from("admin"))));
+
+        let bob = config.resolve(0, BOB).unwrap();
+        assert_eq!(bob.sid.as_deref(), Some("S-1-5-21-1-2-3-2000"));
+        assert!(!bob.groups.contains(&("role", String::from("admin"))));
+    }
+
+    #[test]
+    fn first_sid_rule_wins() {
+        let config = config(&["subject:*cn=alice => sid:S-1-5-21-1", "subject:* => sid:S-1-5-21-2"]);
+        assert_eq!(config.resolve(0, ALICE).unwrap().sid.as_deref(), Some("S-1-5-21-1"));
+        assert_eq!(config.resolve(0, BOB).unwrap().sid.as_deref(), Some("S-1-5-21-2"));
+    }
+
+    #[test]
+    fn role_only_mapping_has_no_sid() {
+        let config = config(&["subject:*cn=bob => role:reader"]);
+        let identity = config.resolve(0, BOB).unwrap();
+        assert_eq!(identity.sid, None);
+        assert_eq!(identity.sub, "O=Example, CN=bob");
+        assert_eq!(identity.groups, vec![("role", String::from("reader"))]);
+    }
+
+    #[test]
+    fn rejects_untrusted_chain() {
+        let rules = ["issuer:*cn=test root => sid:S-1-5-21-1-2-3-2000", "subject:*cn=alice => role:admin"];
+        let strict = config(&rules);
+        assert!(strict.resolve(0, FORGED).is_some());
+        assert!(strict.resolve(UNTRUSTED_ROOT, FORGED).is_none());
+        assert!(strict.resolve(UNTRUSTED_ROOT, ALICE).is_none());
+
+        let lenient = CertMapConfig { untrusted: true, ..config(&rules) };
+        assert!(lenient.resolve(UNTRUSTED_ROOT, FORGED).is_some());
+    }
+
+    #[test]
+    fn forged_certificate_has_own_thumbprint() {
+        let config = config(&["thumbprint:76ea10a59b71f6f6be938cca969ef962a6864f20 => sid:S-1-5-21-1"]);
+        assert!(config.resolve(0, FORGED).is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_rules() {
+        assert!(CertRule::parse("subject:*").is_err());
+        assert!(CertRule::parse("serial:1 => sid:S-1-5-18").is_err());
+        assert!(CertRule::parse("subject:* => user:S-1-5-18").is_err());
+        assert!(CertInfo::parse(b"not a certificate").is_none());
+    }
+}
@@ -0,0 +1,793 @@
+use neon::prelude::*;
+
+use super::support::*;
+use super::user::token_groups_internal;
+use super::win32::*;
+
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::*;
+use windows::Win32::Networking::WinHttp::*;
+use windows::Win32::Security::ImpersonateLoggedOnUser;
+use windows::Win32::Security::RevertToSelf;
+
+use core::ffi::c_void;
+use core::ptr::*;
+use std::collections::HashMap;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread::spawn;
+
+const MAX_POOLS: usize = 64;
+const LAST_CHUNK: &[u8] = b"0\r\n\r\n";
+
+pub struct Internet(*mut c_void);
+
+unsafe impl Send for Internet {
+
+}
+
+unsafe impl Sync for Internet {
+
+}
+
+impl Drop for Internet {
+    fn drop(&mut self) {
+        unsafe {
+            WinHttpCloseHandle(self.0);
+        }
+    }
+}
+
+fn internet(h: *mut c_void, hint: &'static str) -> Result<Internet, (&'static str, u32)> {
+    if h.is_null() {
+        return Err((hint, unsafe { GetLastError().0 }));
+    }
+
+    Ok(Internet(h))
+}
+
+fn check(ok: BOOL, hint: &'static str) -> Result<(), (&'static str, u32)> {
+    if !ok.as_bool() {
+        return Err((hint, unsafe { GetLastError().0 }));
+    }
+
+    Ok(())
+}
+
+fn set_option(h: *mut c_void, option: u32, value: u32) -> Result<(), (&'static str, u32)> {
+    let bytes = value.to_ne_bytes();
+    check(unsafe { WinHttpSetOption(Some(h), option, Some(&bytes)) }, "WinHttpSetOption")
+}
+
+fn query_string(h: *mut c_void, level: u32) -> Option<String> {
+    unsafe {
+        let mut len = 0u32;
+        WinHttpQueryHeaders(h, level, PCWSTR::null(), None, &mut len, null_mut());
+        if GetLastError() != ERROR_INSUFFICIENT_BUFFER {
+            return None;
+        }
+
+        let mut buf = vec![0u16; (len as usize + 1) / 2];
+        let ptr = buf.as_mut_ptr() as *mut c_void;
+        if !WinHttpQueryHeaders(h, level, PCWSTR::null(), Some(ptr), &mut len, null_mut()).as_bool() {
+            return None;
+        }
+
+        Some(String::from_utf16_lossy(&buf[..len as usize / 2]))
+    }
+}
+
+fn query_url(h: *mut c_void) -> Option<String> {
+    unsafe {
+        let mut len = 0u32;
+        WinHttpQueryOption(h, WINHTTP_OPTION_URL, None, &mut len);
+        if GetLastError() != ERROR_INSUFFICIENT_BUFFER {
+            return None;
+        }
+
+        let mut buf = vec![0u16; (len as usize + 1) / 2];
+        let ptr = buf.as_mut_ptr() as *mut c_void;
+        if !WinHttpQueryOption(h, WINHTTP_OPTION_URL, Some(ptr), &mut len).as_bool() {
+            return None;
+        }
+
+        let value = String::from_utf16_lossy(&buf[..len as usize / 2]);
+        Some(String::from(value.trim_end_matches('\0')))
+    }
+}
+
+// Reverts on drop so a failed call never leaves a pool thread running as the request user.
+struct Impersonation(bool);
+
+impl Impersonation {
+    fn new(token: &Option<Arc<HandleRef>>) -> Result<Self, (&'static str, u32)> {
+        match token {
+            Some(token) => {
+                check(unsafe { ImpersonateLoggedOnUser(token.0) }, "ImpersonateLoggedOnUser")?;
+                Ok(Self(true))
+            },
+            None => Ok(Self(false)),
+        }
+    }
+}
+
+impl Drop for Impersonation {
+    fn drop(&mut self) {
+        if self.0 {
+            unsafe {
+                RevertToSelf();
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Redirect {
+    Never,
+    Safe,
+    Always,
+}
+
+pub struct ClientOptions {
+    pub agent: String,
+    pub redirect: Redirect,
+    pub max_redirects: u32,
+    pub timeout: u32,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            agent: String::from("node-plugin"),
+            redirect: Redirect::Safe,
+            max_redirects: 10,
+            timeout: 0,
+        }
+    }
+}
+
+// Keeps the most recently used values; evicted pools stay alive until their open requests finish.
+struct Lru<V> {
+    max: usize,
+    tick: u64,
+    entries: HashMap<String, (V, u64)>,
+}
+
+impl<V: Clone> Lru<V> {
+    fn new(max: usize) -> Self {
+        Self { max, tick: 0, entries: HashMap::new() }
+    }
+
+    fn get_or_insert<E, F>(&mut self, key: &str, f: F) -> Result<V, E> where F: FnOnce() -> Result<V, E> {
+        self.tick += 1;
+        if let Some((value, used)) = self.entries.get_mut(key) {
+            *used = self.tick;
+            return Ok(value.clone());
+        }
+
+        let value = f()?;
+        if self.entries.len() >= self.max {
+            let oldest = self.entries.iter().min_by_key(|(_, (_, used))| *used).map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.entries.insert(String::from(key), (value.clone(), self.tick));
+        Ok(value)
+    }
+}
+
+pub struct RedirectStep {
+    pub url: String,
+    pub verb: String,
+    pub body: bool,
+    pub credentials: bool,
+}
+
+// Credentials (integrated auth and the caller's identity) only ever go to the origin the caller asked for;
+// a redirect anywhere else is followed anonymously or, with the never policy, handed back to the caller.
+pub fn next_redirect(options: &ClientOptions, origin: &str, url: &str, verb: &str, status: u32, location: &str, hops: u32) -> Option<RedirectStep> {
+    if options.redirect == Redirect::Never || hops >= options.max_redirects || !matches!(status, 301 | 302 | 303 | 307 | 308) {
+        return None;
+    }
+
+    let origin = url::Url::parse(origin).ok()?;
+    let current = url::Url::parse(url).ok()?;
+    let target = current.join(location).ok()?;
+    if target.scheme() != "https" && target.scheme() != "http" {
+        return None;
+    }
+
+    if options.redirect == Redirect::Safe && current.scheme() == "https" && target.scheme() == "http" {
+        return None;
+    }
+
+    let (verb, body) = match status {
+        303 if verb != "HEAD" => (String::from("GET"), false),
+        301 | 302 if verb == "POST" => (String::from("GET"), false),
+        _ => (String::from(verb), true),
+    };
+
+    let credentials = target.origin() == origin.origin();
+    Some(RedirectStep { url: target.to_string(), verb, body, credentials })
+}
+
+pub fn frame_chunk(data: &[u8]) -> Vec<u8> {
+    let mut result = format!("{:x}\r\n", data.len()).into_bytes();
+    result.extend_from_slice(data);
+    result.extend_from_slice(b"\r\n");
+    result
+}
+
+// WinHTTP takes the total as a DWORD; longer bodies announce their length in a header instead.
+pub fn total_length(length: i64) -> (u32, Option<String>) {
+    if length < 0 {
+        return (WINHTTP_IGNORE_REQUEST_TOTAL_LENGTH, Some(String::from("Transfer-Encoding: chunked")));
+    }
+
+    match u32::try_from(length) {
+        Ok(total) => (total, None),
+        Err(_) => (WINHTTP_IGNORE_REQUEST_TOTAL_LENGTH, Some(format!("Content-Length: {}", length))),
+    }
+}
+
+struct Pool {
+    session: Internet,
+    connects: Mutex<HashMap<(String, u16), Arc<Internet>>>,
+}
+
+impl Pool {
+    fn connect(&self, host: &str, port: u16) -> Result<Arc<Internet>, (&'static str, u32)> {
+        let mut map = self.connects.lock().map_err(|_| ("Mutex::lock", ERROR_INVALID_STATE.0))?;
+        let key = (host.to_ascii_lowercase(), port);
+        if let Some(connect) = map.get(&key) {
+            return Ok(connect.clone());
+        }
+
+        let host_wide = wide(host);
+        let h = unsafe { WinHttpConnect(self.session.0, wide_ptr(&host_wide), INTERNET_PORT(port as u32), 0) };
+        let connect = Arc::new(internet(h, "WinHttpConnect")?);
+        map.insert(key, connect.clone());
+
+        Ok(connect)
+    }
+}
+
+// WinHTTP pools sockets per session, and NTLM authenticates the socket rather than the request,
+// so every identity (and anonymous use) gets a session of its own and never reuses another identity's connections.
+pub struct Client {
+    pub options: ClientOptions,
+    pools: Mutex<Lru<Arc<Pool>>>,
+}
+
+impl Finalize for Client {}
+
+impl Client {
+    pub fn new(options: ClientOptions) -> Self {
+        Self { options, pools: Mutex::new(Lru::new(MAX_POOLS)) }
+    }
+
+    fn pool(&self, key: &str) -> Result<Arc<Pool>, (&'static str, u32)> {
+        let mut pools = self.pools.lock().map_err(|_| ("Mutex::lock", ERROR_INVALID_STATE.0))?;
+        pools.get_or_insert(key, || self.open_pool())
+    }
+
+    fn open_pool(&self) -> Result<Arc<Pool>, (&'static str, u32)> {
+        let agent = wide(&self.options.agent);
+        let h = unsafe { WinHttpOpen(wide_ptr(&agent), WINHTTP_ACCESS_TYPE_AUTOMATIC_PROXY, PCWSTR::null(), PCWSTR::null(), 0) };
+        let session = internet(h, "WinHttpOpen")?;
+        if self.options.timeout > 0 {
+            let timeout = self.options.timeout as i32;
+            check(unsafe { WinHttpSetTimeouts(session.0, timeout, timeout, timeout, timeout) }, "WinHttpSetTimeouts")?;
+        }
+
+        Ok(Arc::new(Pool { session, connects: Mutex::new(HashMap::new()) }))
+    }
+
+    pub fn open(&self, verb: &str, url: &str, headers: &[(String, String)], token: Option<Arc<HandleRef>>, credentials: bool) -> Result<ClientRequest, (&'static str, u32)> {
+        let parsed = url::Url::parse(url).map_err(|_| ("Url::parse", ERROR_INVALID_PARAMETER.0))?;
+        let secure = match parsed.scheme() {
+            "https" => true,
+            "http" => false,
+            _ => return Err(("Url::scheme", ERROR_INVALID_PARAMETER.0)),
+        };
+
+        let host = parsed.host_str().ok_or(("Url::host", ERROR_INVALID_PARAMETER.0))?;
+        let port = parsed.port_or_known_default().unwrap_or(if secure { 443 } else { 80 });
+        let mut path = String::from(parsed.path());
+        if let Some(query) = parsed.query() {
+            path.push('?');
+            path.push_str(query);
+        }
+
+        let token = token.filter(|_| credentials);
+        let key = match &token {
+            Some(token) => token_groups_internal(token.0, true).pop().map(|(_, sid)| sid).unwrap_or_default(),
+            None if credentials => String::from("process"),
+            None => String::from("anonymous"),
+        };
+
+        let pool = self.pool(&key)?;
+        let connect = pool.connect(host, port)?;
+
+        unsafe {
+            let verb = wide(verb);
+            let path = wide(&path);
+            let flags = if secure { WINHTTP_FLAG_SECURE } else { WINHTTP_OPEN_REQUEST_FLAGS(0) };
+            let h = WinHttpOpenRequest(connect.0, wide_ptr(&verb), wide_ptr(&path), PCWSTR::null(), PCWSTR::null(), null_mut(), flags);
+            let request = internet(h, "WinHttpOpenRequest")?;
+
+            // Redirects are followed by the caller through next_redirect, which decides whether credentials go along.
+            let autologon = if credentials { WINHTTP_AUTOLOGON_SECURITY_LEVEL_MEDIUM } else { WINHTTP_AUTOLOGON_SECURITY_LEVEL_HIGH };
+            set_option(request.0, WINHTTP_OPTION_REDIRECT_POLICY, WINHTTP_OPTION_REDIRECT_POLICY_NEVER)?;
+            set_option(request.0, WINHTTP_OPTION_AUTOLOGON_POLICY, autologon)?;
+
+            for (name, value) in headers {
+                let line = format!("{}: {}", name, value).encode_utf16().collect::<Vec<u16>>();
+                check(WinHttpAddRequestHeaders(request.0, &line, WINHTTP_ADDREQ_FLAG_ADD), "WinHttpAddRequestHeaders")?;
+            }
+
+            Ok(ClientRequest {
+                _pool: pool,
+                _connect: connect,
+                request: Mutex::new(request),
+                token,
+                chunked: AtomicBool::new(false),
+            })
+        }
+    }
+}
+
+pub struct ClientResponse {
+    pub status: u32,
+    pub reason: String,
+    pub version: String,
+    pub headers: Vec<(String, String)>,
+    pub url: String,
+}
+
+pub struct ClientRequest {
+    _pool: Arc<Pool>,
+    _connect: Arc<Internet>,
+    request: Mutex<Internet>,
+    token: Option<Arc<HandleRef>>,
+    chunked: AtomicBool,
+}
+
+impl Finalize for ClientRequest {}
+
+impl ClientRequest {
+    fn with<T, F>(&self, f: F) -> Result<T, (&'static str, u32)> where F: FnOnce(*mut c_void) -> Result<T, (&'static str, u32)> {
+        let request = self.request.lock().map_err(|_| ("Mutex::lock", ERROR_INVALID_STATE.0))?;
+        let _user = Impersonation::new(&self.token)?;
+        f(request.0)
+    }
+
+    fn write_all(h: *mut c_void, mut data: &[u8]) -> Result<(), (&'static str, u32)> {
+        while data.len() > 0 {
+            let mut written = 0u32;
+            let ptr = data.as_ptr() as *const c_void;
+            check(unsafe { WinHttpWriteData(h, Some(ptr), data.len() as u32, &mut written) }, "WinHttpWriteData")?;
+            data = &data[written as usize..];
+        }
+
+        Ok(())
+    }
+
+    // A negative length streams the body with chunked transfer coding, framed here since WinHTTP leaves that to the caller.
+    pub fn send(&self, length: i64) -> Result<(), (&'static str, u32)> {
+        self.with(|h| unsafe {
+            let (total, header) = total_length(length);
+            if let Some(header) = header {
+                let line = header.encode_utf16().collect::<Vec<u16>>();
+                check(WinHttpAddRequestHeaders(h, &line, WINHTTP_ADDREQ_FLAG_ADD | WINHTTP_ADDREQ_FLAG_REPLACE), "WinHttpAddRequestHeaders")?;
+            }
+
+            self.chunked.store(length < 0, Relaxed);
+            check(WinHttpSendRequest(h, None, None, 0, total, 0), "WinHttpSendRequest")
+        })
+    }
+
+    pub fn write(&self, data: &[u8]) -> Result<(), (&'static str, u32)> {
+        self.with(|h| {
+            if !self.chunked.load(Relaxed) {
+                return Self::write_all(h, data);
+            }
+
+            if data.len() > 0 {
+                Self::write_all(h, &frame_chunk(data))?;
+            }
+
+            Ok(())
+        })
+    }
+
+    pub fn response(&self) -> Result<ClientResponse, (&'static str, u32)> {
+        self.with(|h| unsafe {
+            if self.chunked.swap(false, Relaxed) {
+                Self::write_all(h, LAST_CHUNK)?;
+            }
+
+            check(WinHttpReceiveResponse(h, null_mut()), "WinHttpReceiveResponse")?;
+
+            let mut status = 0u32;
+            let mut len = 4u32;
+            let query = WINHTTP_QUERY_STATUS_CODE | WINHTTP_QUERY_FLAG_NUMBER;
+            let status_ptr = &mut status as *mut u32 as *mut c_void;
+            check(WinHttpQueryHeaders(h, query, PCWSTR::null(), Some(status_ptr), &mut len, null_mut()), "WinHttpQueryHeaders")?;
+
+            let reason = query_string(h, WINHTTP_QUERY_STATUS_TEXT).unwrap_or_default();
+            let version = query_string(h, WINHTTP_QUERY_VERSION).unwrap_or_default();
+            let version = String::from(version.trim_start_matches("HTTP/"));
+            let raw = query_string(h, WINHTTP_QUERY_RAW_HEADERS_CRLF).unwrap_or_default();
+            let headers = raw.split("\r\n")
+                .skip(1)
+                .filter_map(|x| x.split_once(':'))
+                .map(|(name, value)| (String::from(name.trim()), String::from(value.trim())))
+                .collect();
+
+            let url = query_url(h).unwrap_or_default();
+            Ok(ClientResponse { status, reason, version, headers, url })
+        })
+    }
+
+    pub fn read(&self, size: u32) -> Result<Vec<u8>, (&'static str, u32)> {
+        self.with(|h| unsafe {
+            let mut buf = vec![0u8; size as usize];
+            let mut read = 0u32;
+            let ptr = buf.as_mut_ptr() as *mut c_void;
+            check(WinHttpReadData(h, ptr, size, &mut read), "WinHttpReadData")?;
+            buf.truncate(read as usize);
+
+            Ok(buf)
+        })
+    }
+}
+
+pub fn fetch_url(url: &str) -> Result<Vec<u8>, (&'static str, u32)> {
+    let client = Client::new(ClientOptions::default());
+    let request = client.open("GET", url, &[], None, false)?;
+    request.send(0)?;
+
+    let response = request.response()?;
+    if response.status != 200 {
+        return Err(("HTTP status", response.status));
+    }
+
+    let mut body = Vec::new();
+    loop {
+        let data = request.read(8192)?;
+        if data.len() < 1 {
+            break;
+        }
+
+        body.extend_from_slice(&data);
+    }
+
+    Ok(body)
+}
+
+fn pairs(list: Vec<String>) -> Vec<(String, String)> {
+    let mut result = Vec::with_capacity(list.len() / 2);
+    let mut iter = list.into_iter();
+    while let (Some(name), Some(value)) = (iter.next(), iter.next()) {
+        result.push((name, value));
+    }
+
+    result
+}
+
+fn http_client_create(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let mut i = 0;
+    let mut options = ClientOptions::default();
+    if cx.arg_opt(&mut i) {
+        options.agent = cx.arg_string(&mut i)?;
+    }
+
+    if cx.arg_opt(&mut i) {
+        let redirect = cx.arg_string(&mut i)?;
+        options.redirect = match redirect.as_str() {
+            "never" | "manual" => Redirect::Never,
+            "safe" | "follow" => Redirect::Safe,
+            "always" => Redirect::Always,
+            _ => return cx.throw_type_error(format!("Unknown redirect policy: {}", redirect)),
+        };
+    }
+
+    if cx.arg_opt(&mut i) {
+        options.max_redirects = cx.arg_u32(&mut i)?;
+    }
+
+    if cx.arg_opt(&mut i) {
+        options.timeout = cx.arg_u32(&mut i)?;
+    }
+
+    Ok(cx.export(Client::new(options)))
+}
+
+fn http_client_open(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let mut i = 0;
+    let client = cx.import::<Client>(&mut i)?;
+    let verb = cx.arg_string(&mut i)?;
+    let url = cx.arg_string(&mut i)?;
+    let headers = pairs(cx.arg_strings(&mut i)?);
+    let mut token = None;
+    let mut credentials = true;
+    if cx.arg_opt(&mut i) {
+        token = Some(cx.import::<HandleRef>(&mut i)?);
+    }
+
+    if cx.arg_opt(&mut i) {
+        credentials = cx.arg_bool(&mut i)?;
+    }
+
+    match client.open(&verb, &url, &headers, token, credentials) {
+        Ok(request) => Ok(cx.export(request)),
+        Err((hint, err)) => cx.throw_type_error(format!("{}: {}", hint, err)),
+    }
+}
+
+fn http_client_redirect(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let mut i = 0;
+    let client = cx.import::<Client>(&mut i)?;
+    let origin = cx.arg_string(&mut i)?;
+    let url = cx.arg_string(&mut i)?;
+    let verb = cx.arg_string(&mut i)?;
+    let status = cx.arg_u32(&mut i)?;
+    let location = cx.arg_string(&mut i)?;
+    let hops = cx.arg_u32(&mut i)?;
+    let step = match next_redirect(&client.options, &origin, &url, &verb, status, &location, hops) {
+        Some(step) => step,
+        None => return Ok(cx.null().upcast()),
+    };
+
+    let obj = cx.empty_object();
+    let js_url = cx.string(step.url);
+    obj.set(&mut cx, "url", js_url)?;
+
+    let js_verb = cx.string(step.verb);
+    obj.set(&mut cx, "method", js_verb)?;
+
+    let js_body = cx.boolean(step.body);
+    obj.set(&mut cx, "body", js_body)?;
+
+    let js_credentials = cx.boolean(step.credentials);
+    obj.set(&mut cx, "credentials", js_credentials)?;
+
+    Ok(obj.upcast())
+}
+
+fn http_client_send(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let request = cx.import::<ClientRequest>(&mut i)?;
+    let length = cx.arg_f64(&mut i)? as i64;
+    let tx = cx.channel();
+    let (def, promise) = cx.promise();
+    spawn(move || {
+        let err = request.send(length).err().map(|(_, err)| err).unwrap_or(0);
+        def.settle_with(&tx, move |mut cx| {
+            Ok(cx.number(err))
+        });
+    });
+
+    Ok(promise)
+}
+
+fn http_client_write(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let request = cx.import::<ClientRequest>(&mut i)?;
+    let block = cx.arg_buffer(&mut i)?;
+    let data = block.as_slice(&cx).to_vec();
+    let tx = cx.channel();
+    let (def, promise) = cx.promise();
+    spawn(move || {
+        let err = request.write(&data).err().map(|(_, err)| err).unwrap_or(0);
+        def.settle_with(&tx, move |mut cx| {
+            Ok(cx.number(err))
+        });
+    });
+
+    Ok(promise)
+}
+
+fn http_client_response(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let request = cx.import::<ClientRequest>(&mut i)?;
+    let tx = cx.channel();
+    let (def, promise) = cx.promise();
+    spawn(move || {
+        let result = request.response();
+        def.settle_with(&tx, move |mut cx| {
+            let obj = cx.empty_object();
+            let response = match result {
+                Ok(response) => response,
+                Err((_, err)) => {
+                    let js_err = cx.number(err);
+                    obj.set(&mut cx, "code", js_err)?;
+                    return Ok(obj);
+                }
+            };
+
+            let js_err = cx.number(0);
+            obj.set(&mut cx, "code", js_err)?;
+
+            let js_status = cx.number(response.status);
+            obj.set(&mut cx, "status", js_status)?;
+
+            let js_reason = cx.string(response.reason);
+            obj.set(&mut cx, "reason", js_reason)?;
+
+            let js_version = cx.string(response.version);
+            obj.set(&mut cx, "version", js_version)?;
+
+            let js_url = cx.string(response.url);
+            obj.set(&mut cx, "url", js_url)?;
+
+            let js_headers = cx.empty_array();
+            for (j, (name, value)) in response.headers.iter().enumerate() {
+                let js_name = cx.string(name);
+                js_headers.set(&mut cx, 2 * j as u32, js_name)?;
+
+                let js_value = cx.string(value);
+                js_headers.set(&mut cx, 2 * j as u32 + 1, js_value)?;
+            }
+
+            obj.set(&mut cx, "headers", js_headers)?;
+
+            Ok(obj)
+        });
+    });
+
+    Ok(promise)
+}
+
+fn http_client_read(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let request = cx.import::<ClientRequest>(&mut i)?;
+    let mut size = 16384;
+    if cx.arg_opt(&mut i) {
+        size = cx.arg_u32(&mut i)?;
+    }
+
+    let tx = cx.channel();
+    let (def, promise) = cx.promise();
+    spawn(move || {
+        let result = request.read(size);
+        def.settle_with(&tx, move |mut cx| {
+            let obj = cx.empty_object();
+            match result {
+                Ok(data) => {
+                    let js_err = cx.number(0);
+                    obj.set(&mut cx, "code", js_err)?;
+
+                    let mut js_data = cx.buffer(data.len())?;
+                    js_data.as_mut_slice(&mut cx).copy_from_slice(&data);
+                    obj.set(&mut cx, "data", js_data)?;
+                },
+                Err((_, err)) => {
+                    let js_err = cx.number(err);
+                    obj.set(&mut cx, "code", js_err)?;
+                }
+            }
+
+            Ok(obj)
+        });
+    });
+
+    Ok(promise)
+}
+
+fn http_client_close(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    cx.dispose::<ClientRequest>(0)?;
+    Ok(cx.undefined())
+}
+
+fn http_client_destroy(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    cx.dispose::<Client>(0)?;
+    Ok(cx.undefined())
+}
+
+pub fn client_bind(cx: &mut ModuleContext) -> NeonResult<()> {
+    cx.export_function("http_client_create", http_client_create)?;
+    cx.export_function("http_client_open", http_client_open)?;
+    cx.export_function("http_client_redirect", http_client_redirect)?;
+    cx.export_function("http_client_send", http_client_send)?;
+    cx.export_function("http_client_write", http_client_write)?;
+    cx.export_function("http_client_response", http_client_response)?;
+    cx.export_function("http_client_read", http_client_read)?;
+    cx.export_function("http_client_close", http_client_close)?;
+    cx.export_function("http_client_destroy", http_client_destroy)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn follow(redirect: Redirect, url: &str, verb: &str, status: u32, location: &str) -> Option<RedirectStep> {
+        let options = ClientOptions { redirect, ..ClientOptions::default() };
+        next_redirect(&options, "https://intranet.example/app", url, verb, status, location, 0)
+    }
+
+    #[test]
+    fn frames_chunks() {
+        assert_eq!(frame_chunk(b"hello"), b"5\r\nhello\r\n");
+        assert_eq!(frame_chunk(&[0u8; 26])[..4], *b"1a\r\n");
+        assert_eq!(LAST_CHUNK, b"0\r\n\r\n");
+    }
+
+    #[test]
+    fn announces_long_bodies_in_header() {
+        assert_eq!(total_length(0), (0, None));
+        assert_eq!(total_length(1234), (1234, None));
+        assert_eq!(total_length(u32::MAX as i64), (u32::MAX, None));
+        assert_eq!(total_length(5_000_000_000), (WINHTTP_IGNORE_REQUEST_TOTAL_LENGTH, Some(String::from("Content-Length: 5000000000"))));
+        assert_eq!(total_length(-1), (WINHTTP_IGNORE_REQUEST_TOTAL_LENGTH, Some(String::from("Transfer-Encoding: chunked"))));
+    }
+
+    #[test]
+    fn evicts_least_recently_used_pool() {
+        let mut lru = Lru::<Arc<u32>>::new(2);
+        let a = lru.get_or_insert::<(), _>("a", || Ok(Arc::new(1))).unwrap();
+        lru.get_or_insert::<(), _>("b", || Ok(Arc::new(2))).unwrap();
+        lru.get_or_insert::<(), _>("a", || panic!("cached")).unwrap();
+        lru.get_or_insert::<(), _>("c", || Ok(Arc::new(3))).unwrap();
+
+        assert_eq!(lru.entries.len(), 2);
+        assert!(lru.entries.contains_key("a"));
+        assert!(!lru.entries.contains_key("b"));
+        assert_eq!(*a, 1);
+
+        assert!(lru.get_or_insert("d", || Err("failed")).is_err());
+        assert_eq!(lru.entries.len(), 2);
+    }
+
+    #[test]
+    fn keeps_credentials_on_same_origin() {
+        let step = follow(Redirect::Safe, "https://intranet.example/app", "GET", 302, "/login?next=1").unwrap();
+        assert_eq!(step.url, "https://intranet.example/login?next=1");
+        assert!(step.credentials);
+        assert!(step.body);
+    }
+
+    #[test]
+    fn drops_credentials_across_origins() {
+        let step = follow(Redirect::Safe, "https://intranet.example/app", "GET", 302, "https://evil.example/").unwrap();
+        assert!(!step.credentials);
+
+        let port = follow(Redirect::Safe, "https://intranet.example/app", "GET", 307, "https://intranet.example:8443/app").unwrap();
+        assert!(!port.credentials);
+
+        let back = follow(Redirect::Safe, "https://evil.example/", "GET", 302, "https://intranet.example/app").unwrap();
+        assert!(back.credentials);
+    }
+
+    #[test]
+    fn honors_redirect_policy() {
+        assert!(follow(Redirect::Never, "https://intranet.example/app", "GET", 302, "/x").is_none());
+        assert!(follow(Redirect::Safe, "https://intranet.example/app", "GET", 302, "http://intranet.example/app").is_none());
+        assert!(follow(Redirect::Always, "https://intranet.example/app", "GET", 302, "http://intranet.example/app").is_some());
+        assert!(follow(Redirect::Always, "https://intranet.example/app", "GET", 302, "file:///etc/passwd").is_none());
+        assert!(follow(Redirect::Always, "https://intranet.example/app", "GET", 304, "/x").is_none());
+
+        let options = ClientOptions { max_redirects: 2, ..ClientOptions::default() };
+        assert!(next_redirect(&options, "https://a.example/", "https://a.example/", "GET", 302, "/x", 1).is_some());
+        assert!(next_redirect(&options, "https://a.example/", "https://a.example/", "GET", 302, "/x", 2).is_none());
+    }
+
+    #[test]
+    fn rewrites_method_like_browsers() {
+        let see_other = follow(Redirect::Safe, "https://intranet.example/app", "PUT", 303, "/done").unwrap();
+        assert_eq!((see_other.verb.as_str(), see_other.body), ("GET", false));
+
+        let found = follow(Redirect::Safe, "https://intranet.example/app", "POST", 302, "/done").unwrap();
+        assert_eq!((found.verb.as_str(), found.body), ("GET", false));
+
+        let temporary = follow(Redirect::Safe, "https://intranet.example/app", "POST", 307, "/retry").unwrap();
+        assert_eq!((temporary.verb.as_str(), temporary.body), ("POST", true));
+    }
+}
@@ -0,0 +1,524 @@
+use super::message::*;
+
+pub const LAST_CHUNK: &[u8] = b"0\r\n\r\n";
+pub const MAX_HEAD: usize = 65536;
+
+pub fn fold_headers(headers: &[(String, String)]) -> Vec<(String, String)> {
+    let mut result = Vec::<(String, String)>::new();
+    for (name, value) in headers.iter() {
+        if let Some(entry) = result.iter_mut().find(|x| x.0.eq_ignore_ascii_case(name)) {
+            let sep = if name.eq_ignore_ascii_case("Cookie") { "; " } else { ", " };
+            entry.1.push_str(sep);
+            entry.1.push_str(value);
+            continue;
+        }
+
+        result.push((name.clone(), value.clone()));
+    }
+
+    result
+}
+
+pub fn is_upgrade(info: &RequestInfo) -> bool {
+    let connection = info.header("Connection").unwrap_or("");
+    info.header("Upgrade").is_some() && connection.split(',').any(|x| x.trim().eq_ignore_ascii_case("upgrade"))
+}
+
+pub fn encode_request_head(info: &RequestInfo) -> (Vec<u8>, bool) {
+    let mut head = format!("{} {} HTTP/1.1\r\n", info.verb, info.target());
+    let mut length = false;
+    let upgrade = is_upgrade(info);
+    for (name, value) in fold_headers(&info.headers) {
+        if name.eq_ignore_ascii_case("Transfer-Encoding") || name.eq_ignore_ascii_case("Connection") || name.eq_ignore_ascii_case("Keep-Alive") {
+            continue;
+        }
+
+        if name.eq_ignore_ascii_case("Upgrade") && !upgrade {
+            continue;
+        }
+
+        if name.eq_ignore_ascii_case("Content-Length") {
+            length = true;
+        }
+
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+
+    let chunked = info.body && !length;
+    if chunked {
+        head.push_str("Transfer-Encoding: chunked\r\n");
+    }
+
+    head.push_str(if upgrade { "Connection: Upgrade\r\n\r\n" } else { "Connection: close\r\n\r\n" });
+    (head.into_bytes(), chunked)
+}
+
+pub fn encode_chunk(data: &[u8]) -> Vec<u8> {
+    let mut result = format!("{:x}\r\n", data.len()).into_bytes();
+    result.extend_from_slice(data);
+    result.extend_from_slice(b"\r\n");
+    result
+}
+
+pub fn parse_response_head(text: &str) -> Result<Reply, String> {
+    let mut lines = text.lines();
+    let line = lines.next().unwrap_or("");
+    let mut parts = line.trim_end().splitn(3, ' ');
+    let version = parts.next().unwrap_or("");
+    let status = parts.next().unwrap_or("").parse::<u16>().unwrap_or(0);
+    let reason = parts.next().unwrap_or("");
+    if !version.starts_with("HTTP/") || status < 100 {
+        return Err(String::from("Malformed status line."));
+    }
+
+    let mut reply = Reply::new(status, reason);
+    for line in lines {
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some(last) = reply.headers.last_mut() {
+                last.1.push(' ');
+                last.1.push_str(line.trim());
+            }
+
+            continue;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            reply = reply.header(name.trim(), value.trim());
+        }
+    }
+
+    Ok(reply)
+}
+
+fn take_line(buf: &mut Vec<u8>) -> Option<String> {
+    let i = buf.iter().position(|x| *x == b'\n')?;
+    let line = buf.drain(..=i).collect::<Vec<u8>>();
+    Some(String::from_utf8_lossy(&line).trim_end_matches(|x| x == '\r' || x == '\n').to_string())
+}
+
+// Scanning resumes where the previous push left off, so a head that trickles in is read once.
+fn head_end(buf: &[u8], from: usize) -> Option<usize> {
+    for i in from..buf.len() {
+        if buf[i] == b'\n' {
+            if buf.get(i + 1) == Some(&b'\n') {
+                return Some(i + 2);
+            }
+
+            if buf.get(i + 1) == Some(&b'\r') && buf.get(i + 2) == Some(&b'\n') {
+                return Some(i + 3);
+            }
+        }
+    }
+
+    None
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Framing {
+    Empty,
+    Length(u64),
+    Chunked,
+    Close,
+    Opaque,
+}
+
+enum Stage {
+    Head,
+    Body,
+    ChunkSize,
+    ChunkData(u64),
+    ChunkEnd,
+    Trailers,
+    Done,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum WireEvent {
+    Head(Reply),
+    Data(Vec<u8>),
+    End(Vec<(String, String)>),
+}
+
+pub struct ResponseDecoder {
+    buf: Vec<u8>,
+    scanned: usize,
+    stage: Stage,
+    framing: Framing,
+    head_only: bool,
+    trailers: Vec<(String, String)>,
+}
+
+impl ResponseDecoder {
+    pub fn new(head_only: bool) -> Self {
+        Self {
+            buf: Vec::new(),
+            scanned: 0,
+            stage: Stage::Head,
+            framing: Framing::Empty,
+            head_only,
+            trailers: Vec::new(),
+        }
+    }
+
+    pub fn started(&self) -> bool {
+        !matches!(self.stage, Stage::Head)
+    }
+
+    pub fn empty(&self) -> bool {
+        self.framing == Framing::Empty
+    }
+
+    pub fn chunked(&self) -> bool {
+        self.framing == Framing::Chunked
+    }
+
+    pub fn delimited(&self) -> bool {
+        self.framing == Framing::Close || self.framing == Framing::Opaque
+    }
+
+    pub fn opaque(&self) -> bool {
+        self.framing == Framing::Opaque
+    }
+
+    fn head(&mut self, events: &mut Vec<WireEvent>) -> Result<bool, String> {
+        let end = match head_end(&self.buf, self.scanned) {
+            Some(end) if end <= MAX_HEAD => end,
+            None if self.buf.len() <= MAX_HEAD => {
+                self.scanned = self.buf.len().saturating_sub(2);
+                return Ok(false);
+            },
+            _ => return Err(String::from("Response head too large.")),
+        };
+
+        self.scanned = 0;
+
+        let data = self.buf.drain(..end).collect::<Vec<u8>>();
+        let mut reply = parse_response_head(&String::from_utf8_lossy(&data))?;
+        if reply.status == 101 {
+            // The connection becomes an opaque stream in both directions once the 101 is sent.
+            self.framing = Framing::Opaque;
+            self.stage = Stage::Body;
+            events.push(WireEvent::Head(reply));
+            return Ok(true);
+        }
+
+        // HTTP.sys answers 100-continue itself and cannot send other interim responses.
+        if reply.status < 200 {
+            return Ok(true);
+        }
+
+        let mut framing = Framing::Close;
+        for (name, value) in reply.headers.iter() {
+            if name.eq_ignore_ascii_case("Transfer-Encoding") && value.to_ascii_lowercase().contains("chunked") {
+                framing = Framing::Chunked;
+            }
+
+            if name.eq_ignore_ascii_case("Content-Length") && framing != Framing::Chunked {
+                let length = value.trim().parse::<u64>().map_err(|_| String::from("Invalid Content-Length."))?;
+                framing = Framing::Length(length);
+            }
+        }
+
+        if self.head_only || reply.status == 204 || reply.status == 304 || framing == Framing::Length(0) {
+            framing = Framing::Empty;
+        }
+
+        // Headers named by Connection only apply to the upstream hop.
+        let connection = reply.headers.iter()
+            .filter(|x| x.0.eq_ignore_ascii_case("Connection"))
+            .map(|x| x.1.to_ascii_lowercase())
+            .collect::<Vec<String>>()
+            .join(",");
+
+        reply.headers.retain(|x| {
+            let name = x.0.to_ascii_lowercase();
+            name != "connection" && name != "keep-alive" && !connection.split(',').any(|x| x.trim() == name)
+        });
+
+        self.framing = framing;
+        events.push(WireEvent::Head(reply));
+
+        match framing {
+            Framing::Empty => {
+                self.stage = Stage::Done;
+                events.push(WireEvent::End(Vec::new()));
+            },
+            Framing::Chunked => self.stage = Stage::ChunkSize,
+            _ => self.stage = Stage::Body,
+        }
+
+        Ok(true)
+    }
+
+    fn step(&mut self, events: &mut Vec<WireEvent>) -> Result<bool, String> {
+        match self.stage {
+            Stage::Head => self.head(events),
+            Stage::Body => {
+                if self.buf.len() < 1 {
+                    return Ok(false);
+                }
+
+                let mut size = self.buf.len();
+                if let Framing::Length(remaining) = self.framing {
+                    size = size.min(remaining as usize);
+                    self.framing = Framing::Length(remaining - size as u64);
+                }
+
+                events.push(WireEvent::Data(self.buf.drain(..size).collect()));
+                if self.framing == Framing::Length(0) {
+                    self.stage = Stage::Done;
+                    events.push(WireEvent::End(Vec::new()));
+                }
+
+                Ok(true)
+            },
+            Stage::ChunkSize => {
+                let line = match take_line(&mut self.buf) {
+                    Some(line) => line,
+                    None => return Ok(false),
+                };
+
+                let hex = line.split(';').next().unwrap_or("").trim();
+                let size = u64::from_str_radix(hex, 16).map_err(|_| String::from("Invalid chunk size."))?;
+                self.stage = if size > 0 { Stage::ChunkData(size) } else { Stage::Trailers };
+                Ok(true)
+            },
+            Stage::ChunkData(remaining) => {
+                if self.buf.len() < 1 {
+                    return Ok(false);
+                }
+
+                let size = self.buf.len().min(remaining as usize);
+                events.push(WireEvent::Data(self.buf.drain(..size).collect()));
+                self.stage = if remaining > size as u64 { Stage::ChunkData(remaining - size as u64) } else { Stage::ChunkEnd };
+                Ok(true)
+            },
+            Stage::ChunkEnd => {
+                match take_line(&mut self.buf) {
+                    Some(line) if line.len() < 1 => {
+                        self.stage = Stage::ChunkSize;
+                        Ok(true)
+                    },
+                    Some(_) => Err(String::from("Missing chunk terminator.")),
+                    None => Ok(false),
+                }
+            },
+            Stage::Trailers => {
+                let line = match take_line(&mut self.buf) {
+                    Some(line) => line,
+                    None => return Ok(false),
+                };
+
+                if line.len() < 1 {
+                    self.stage = Stage::Done;
+                    events.push(WireEvent::End(self.trailers.split_off(0)));
+                } else if let Some((name, value)) = line.split_once(':') {
+                    self.trailers.push((name.trim().to_string(), value.trim().to_string()));
+                }
+
+                Ok(true)
+            },
+            Stage::Done => {
+                self.buf.clear();
+                Ok(false)
+            },
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<WireEvent>, String> {
+        let mut events = Vec::new();
+        self.buf.extend_from_slice(data);
+        while self.step(&mut events)? {
+
+        }
+
+        Ok(events)
+    }
+
+    pub fn finish(&mut self) -> Result<Vec<WireEvent>, String> {
+        let mut events = Vec::new();
+        match self.stage {
+            Stage::Done => (),
+            Stage::Body if self.delimited() => {
+                self.stage = Stage::Done;
+                events.push(WireEvent::End(Vec::new()));
+            },
+            _ => return Err(String::from("Response ended early.")),
+        }
+
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(verb: &str, headers: &[(&str, &str)], body: bool) -> RequestInfo {
+        RequestInfo {
+            id: 1,
+            group: 0,
+            verb: verb.to_string(),
+            version: (1, 1),
+            url: String::from("http://localhost:80/a/b?c=d"),
+            headers: headers.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            body,
+            http2: false,
+            secure: false,
+            local: None,
+            remote: None,
+        }
+    }
+
+    fn decode(decoder: &mut ResponseDecoder, parts: &[&[u8]]) -> Vec<WireEvent> {
+        let mut events = Vec::new();
+        for part in parts {
+            events.extend(decoder.push(part).unwrap());
+        }
+
+        events
+    }
+
+    fn head(event: &WireEvent) -> (u16, Vec<(String, String)>) {
+        match event {
+            WireEvent::Head(reply) => (reply.status, reply.headers.clone()),
+            _ => panic!("Expected a head."),
+        }
+    }
+
+    #[test]
+    fn request_head_with_length() {
+        let (head, chunked) = encode_request_head(&info("POST", &[("Host", "localhost"), ("Content-Length", "5"), ("Connection", "keep-alive"), ("Cookie", "a=1"), ("cookie", "b=2")], true));
+        assert!(!chunked);
+        assert_eq!(String::from_utf8(head).unwrap(), "POST /a/b?c=d HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nCookie: a=1; b=2\r\nConnection: close\r\n\r\n");
+    }
+
+    #[test]
+    fn request_head_chunked_without_length() {
+        let (head, chunked) = encode_request_head(&info("PUT", &[("Transfer-Encoding", "chunked"), ("Accept", "a"), ("Accept", "b")], true));
+        assert!(chunked);
+        assert_eq!(String::from_utf8(head).unwrap(), "PUT /a/b?c=d HTTP/1.1\r\nAccept: a, b\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n");
+    }
+
+    #[test]
+    fn request_head_upgrade() {
+        let upgrade = info("GET", &[("Connection", "keep-alive, Upgrade"), ("Upgrade", "websocket")], false);
+        assert!(is_upgrade(&upgrade));
+        let (head, _) = encode_request_head(&upgrade);
+        assert_eq!(String::from_utf8(head).unwrap(), "GET /a/b?c=d HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n");
+
+        let plain = info("GET", &[("Upgrade", "h2c")], false);
+        assert!(!is_upgrade(&plain));
+        let (head, _) = encode_request_head(&plain);
+        assert_eq!(String::from_utf8(head).unwrap(), "GET /a/b?c=d HTTP/1.1\r\nConnection: close\r\n\r\n");
+    }
+
+    #[test]
+    fn content_length_split() {
+        let mut decoder = ResponseDecoder::new(false);
+        let events = decode(&mut decoder, &[b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n", b"\r\nhel", b"lo extra"]);
+        assert_eq!(events.len(), 4);
+        assert_eq!(head(&events[0]), (200, vec![(String::from("Content-Length"), String::from("5"))]));
+        assert_eq!(events[1], WireEvent::Data(b"hel".to_vec()));
+        assert_eq!(events[2], WireEvent::Data(b"lo".to_vec()));
+        assert_eq!(events[3], WireEvent::End(Vec::new()));
+        assert!(decoder.finish().unwrap().is_empty());
+    }
+
+    #[test]
+    fn chunked_with_trailers() {
+        let mut decoder = ResponseDecoder::new(false);
+        let events = decode(&mut decoder, &[b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3;x=y\r\nabc\r", b"\n0\r\nGrpc-Status: 0\r\n\r\n"]);
+        assert!(decoder.chunked());
+        assert_eq!(events[1], WireEvent::Data(b"abc".to_vec()));
+        assert_eq!(events[2], WireEvent::End(vec![(String::from("Grpc-Status"), String::from("0"))]));
+    }
+
+    #[test]
+    fn chunk_errors() {
+        let mut decoder = ResponseDecoder::new(false);
+        assert!(decoder.push(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n").is_err());
+
+        let mut decoder = ResponseDecoder::new(false);
+        assert!(decoder.push(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n1\r\nab\r\n").is_err());
+
+        let mut decoder = ResponseDecoder::new(false);
+        decoder.push(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n1\r\n").unwrap();
+        assert!(decoder.finish().is_err());
+    }
+
+    #[test]
+    fn close_delimited() {
+        let mut decoder = ResponseDecoder::new(false);
+        let events = decode(&mut decoder, &[b"HTTP/1.0 200 OK\n\nbody"]);
+        assert!(decoder.delimited());
+        assert_eq!(events[1], WireEvent::Data(b"body".to_vec()));
+        assert_eq!(decoder.finish().unwrap(), vec![WireEvent::End(Vec::new())]);
+    }
+
+    #[test]
+    fn empty_bodies() {
+        let cases = [
+            (true, &b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n"[..]),
+            (false, b"HTTP/1.1 204 No Content\r\n\r\n"),
+            (false, b"HTTP/1.1 304 Not Modified\r\n\r\n"),
+            (false, b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"),
+        ];
+
+        for (head_only, text) in cases {
+            let mut decoder = ResponseDecoder::new(head_only);
+            let events = decoder.push(text).unwrap();
+            assert_eq!(events.len(), 2);
+            assert_eq!(events[1], WireEvent::End(Vec::new()));
+        }
+    }
+
+    #[test]
+    fn interim_and_upgrade() {
+        let mut decoder = ResponseDecoder::new(false);
+        let events = decode(&mut decoder, &[b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"]);
+        assert_eq!(head(&events[0]).0, 200);
+
+        let mut decoder = ResponseDecoder::new(false);
+        let events = decode(&mut decoder, &[b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n\x81\x00", b"HTTP/1.1 200"]);
+        assert!(decoder.opaque());
+        assert_eq!(head(&events[0]).0, 101);
+        assert_eq!(events[1], WireEvent::Data(b"\x81\x00".to_vec()));
+        assert_eq!(events[2], WireEvent::Data(b"HTTP/1.1 200".to_vec()));
+        assert_eq!(decoder.finish().unwrap(), vec![WireEvent::End(Vec::new())]);
+    }
+
+    #[test]
+    fn bounds_heads() {
+        let mut decoder = ResponseDecoder::new(false);
+        let head = b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n";
+        let mut events = Vec::new();
+        for byte in head.iter() {
+            events.extend(decoder.push(&[*byte]).unwrap());
+        }
+
+        assert_eq!(head_end(head, 0), Some(head.len()));
+        assert_eq!(events.len(), 2);
+
+        let mut decoder = ResponseDecoder::new(false);
+        decoder.push(b"HTTP/1.1 200 OK\r\n").unwrap();
+        let filler = format!("X-Fill: {}\r\n", "a".repeat(1000));
+        for _ in 0..64 {
+            decoder.push(filler.as_bytes()).unwrap();
+        }
+
+        assert_eq!(decoder.push(filler.as_bytes()).err(), Some(String::from("Response head too large.")));
+
+        let mut big = b"HTTP/1.1 200 OK\r\n".to_vec();
+        big.extend(filler.repeat(70).into_bytes());
+        big.extend(b"\r\n");
+        assert!(ResponseDecoder::new(false).push(&big).is_err());
+        assert!(!decoder.started());
+    }
+
+    #[test]
+    fn malformed_heads() {
+        assert!(ResponseDecoder::new(false).push(b"HTTP/1.1 abc\r\n\r\n").is_err());
+        assert!(ResponseDecoder::new(false).push(b"HTTP/1.1 200 OK\r\nContent-Length: x\r\n\r\n").is_err());
+    }
+}
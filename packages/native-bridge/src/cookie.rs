@@ -0,0 +1,615 @@
+#[cfg(windows)]
+use neon::prelude::*;
+
+#[cfg(windows)]
+use super::support::*;
+
+use ring::aead;
+use ring::hkdf;
+use ring::hmac;
+use ring::rand::SecureRandom;
+use ring::rand::SystemRandom;
+
+use std::sync::Arc;
+use std::sync::RwLock;
+
+const MIN_SECRET: usize = 32;
+const NONCE_LEN: usize = 12;
+
+pub fn parse_cookies(header: &str) -> Vec<(String, String)> {
+    let mut result = Vec::new();
+    for part in header.split(';') {
+        let (name, value) = match part.split_once('=') {
+            Some(pair) => pair,
+            None => continue,
+        };
+
+        let name = name.trim();
+        if name.len() < 1 {
+            continue;
+        }
+
+        let value = value.trim();
+        let value = if value.len() > 1 && value.starts_with('"') && value.ends_with('"') { &value[1..value.len() - 1] } else { value };
+        result.push((String::from(name), String::from(value)));
+    }
+
+    result
+}
+
+fn is_token(value: &str) -> bool {
+    value.len() > 0 && value.bytes().all(|x| x.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&x))
+}
+
+// cookie-octet excludes controls, whitespace, DQUOTE, comma, semicolon and backslash.
+fn is_cookie_value(value: &str) -> bool {
+    let value = if value.len() > 1 && value.starts_with('"') && value.ends_with('"') { &value[1..value.len() - 1] } else { value };
+    value.bytes().all(|x| x > 0x20 && x < 0x7f && x != b'"' && x != b',' && x != b';' && x != b'\\')
+}
+
+fn is_attribute(value: &str) -> bool {
+    value.bytes().all(|x| x >= 0x20 && x < 0x7f && x != b';')
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+#[derive(Default)]
+pub struct SetCookie {
+    pub name: String,
+    pub value: String,
+    pub path: Option<String>,
+    pub domain: Option<String>,
+    pub max_age: Option<i64>,
+    pub expires: Option<i64>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<SameSite>,
+    pub partitioned: bool,
+}
+
+// IMF-fixdate from milliseconds since the epoch, using the days-to-civil conversion from Howard Hinnant.
+pub fn http_date(ms: i64) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+    let secs = ms.div_euclid(1000);
+    let days = secs.div_euclid(86400);
+    let rem = secs.rem_euclid(86400);
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[days.rem_euclid(7) as usize], day, MONTHS[month as usize - 1], year,
+        rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+impl SetCookie {
+    // Reads a Set-Cookie value back so handlers that write the header themselves get the same checks as render.
+    pub fn parse(header: &str) -> Result<Self, String> {
+        let mut parts = header.split(';');
+        let (name, value) = match parts.next().and_then(|x| x.split_once('=')) {
+            Some(pair) => pair,
+            None => return Err(format!("Invalid Set-Cookie: {}", header)),
+        };
+
+        let mut result = Self {
+            name: String::from(name.trim()),
+            value: String::from(value.trim()),
+            ..Self::default()
+        };
+
+        for part in parts {
+            let (key, value) = part.split_once('=').unwrap_or((part, ""));
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "path" => result.path = Some(String::from(value)),
+                "domain" => result.domain = Some(String::from(value)),
+                "max-age" => result.max_age = value.parse().ok(),
+                "secure" => result.secure = true,
+                "httponly" => result.http_only = true,
+                "partitioned" => result.partitioned = true,
+                "samesite" => result.same_site = match value.to_ascii_lowercase().as_str() {
+                    "strict" => Some(SameSite::Strict),
+                    "lax" => Some(SameSite::Lax),
+                    "none" => Some(SameSite::None),
+                    _ => return Err(format!("Unknown SameSite value: {}", value)),
+                },
+                _ => (),
+            }
+        }
+
+        Ok(result)
+    }
+
+    pub fn render(&self) -> Result<String, String> {
+        if !is_token(&self.name) {
+            return Err(format!("Invalid cookie name: {}", self.name));
+        }
+
+        if !is_cookie_value(&self.value) {
+            return Err(format!("Invalid cookie value for {}", self.name));
+        }
+
+        let host = self.name.starts_with("__Host-");
+        if (host || self.name.starts_with("__Secure-")) && !self.secure {
+            return Err(format!("{} requires Secure", self.name));
+        }
+
+        if host && (self.domain.is_some() || self.path.as_deref() != Some("/")) {
+            return Err(format!("{} requires Path=/ and no Domain", self.name));
+        }
+
+        if self.partitioned && !self.secure {
+            return Err(String::from("Partitioned requires Secure"));
+        }
+
+        if self.same_site == Some(SameSite::None) && !self.secure {
+            return Err(String::from("SameSite=None requires Secure"));
+        }
+
+        let mut result = format!("{}={}", self.name, self.value);
+        if let Some(path) = &self.path {
+            if !is_attribute(path) {
+                return Err(format!("Invalid cookie path: {}", path));
+            }
+
+            result.push_str(&format!("; Path={}", path));
+        }
+
+        if let Some(domain) = &self.domain {
+            if !is_attribute(domain) {
+                return Err(format!("Invalid cookie domain: {}", domain));
+            }
+
+            result.push_str(&format!("; Domain={}", domain));
+        }
+
+        if let Some(max_age) = self.max_age {
+            result.push_str(&format!("; Max-Age={}", max_age));
+        }
+
+        if let Some(expires) = self.expires {
+            result.push_str(&format!("; Expires={}", http_date(expires)));
+        }
+
+        if self.secure {
+            result.push_str("; Secure");
+        }
+
+        if self.http_only {
+            result.push_str("; HttpOnly");
+        }
+
+        match self.same_site {
+            Some(SameSite::Strict) => result.push_str("; SameSite=Strict"),
+            Some(SameSite::Lax) => result.push_str("; SameSite=Lax"),
+            Some(SameSite::None) => result.push_str("; SameSite=None"),
+            None => (),
+        }
+
+        if self.partitioned {
+            result.push_str("; Partitioned");
+        }
+
+        Ok(result)
+    }
+}
+
+fn b64(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+fn unb64(value: &str) -> Option<Vec<u8>> {
+    base64::decode_config(value, base64::URL_SAFE_NO_PAD).ok()
+}
+
+struct Len(usize);
+
+impl hkdf::KeyType for Len {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
+
+struct CookieKey {
+    id: String,
+    mac: hmac::Key,
+    seal: aead::LessSafeKey,
+}
+
+impl CookieKey {
+    // Signing and sealing use separate subkeys derived from the one secret.
+    fn new(secret: &[u8]) -> Result<Self, String> {
+        if secret.len() < MIN_SECRET {
+            return Err(format!("Cookie secrets need at least {} bytes", MIN_SECRET));
+        }
+
+        let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, b"http-native-cookie").extract(secret);
+        let invalid = |_| String::from("Cookie key derivation failed");
+        let mac: hmac::Key = prk.expand(&[&b"sign"[..]], hmac::HMAC_SHA256).map_err(invalid)?.into();
+        let seal: aead::UnboundKey = prk.expand(&[&b"seal"[..]], &aead::AES_256_GCM).map_err(invalid)?.into();
+
+        let mut id = [0u8; 6];
+        prk.expand(&[&b"id"[..]], Len(id.len())).map_err(invalid)?.fill(&mut id).map_err(invalid)?;
+
+        Ok(Self { id: b64(&id), mac, seal: aead::LessSafeKey::new(seal) })
+    }
+}
+
+// The first key signs and seals; the rest are only accepted so older cookies survive a rotation.
+pub struct CookieKeys {
+    keys: Vec<CookieKey>,
+    rng: SystemRandom,
+}
+
+#[cfg(windows)]
+impl Finalize for CookieKeys {}
+
+impl CookieKeys {
+    pub fn new(secrets: &[Vec<u8>]) -> Result<Self, String> {
+        if secrets.len() < 1 {
+            return Err(String::from("At least one cookie secret is required"));
+        }
+
+        let keys = secrets.iter().map(|x| CookieKey::new(x)).collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { keys, rng: SystemRandom::new() })
+    }
+
+    fn find(&self, id: &str) -> Option<&CookieKey> {
+        self.keys.iter().find(|x| x.id == id)
+    }
+
+    // s:<value>.<kid>.<mac>, the name is covered by the MAC so a value cannot be moved to another cookie.
+    pub fn sign(&self, name: &str, value: &str) -> String {
+        let key = &self.keys[0];
+        let payload = b64(value.as_bytes());
+        let tag = hmac::sign(&key.mac, format!("{}={}.{}", name, payload, key.id).as_bytes());
+        format!("s:{}.{}.{}", payload, key.id, b64(tag.as_ref()))
+    }
+
+    // e:<kid>.<nonce+ciphertext>, with the name as associated data.
+    pub fn seal(&self, name: &str, value: &str) -> Result<String, String> {
+        let key = &self.keys[0];
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng.fill(&mut nonce).map_err(|_| String::from("Random nonce failed"))?;
+
+        let mut data = value.as_bytes().to_vec();
+        let aad = aead::Aad::from(name.as_bytes());
+        key.seal.seal_in_place_append_tag(aead::Nonce::assume_unique_for_key(nonce), aad, &mut data)
+            .map_err(|_| String::from("Cookie seal failed"))?;
+
+        let mut out = nonce.to_vec();
+        out.extend_from_slice(&data);
+        Ok(format!("e:{}.{}", key.id, b64(&out)))
+    }
+
+    pub fn open(&self, name: &str, value: &str) -> Option<String> {
+        if let Some(rest) = value.strip_prefix("s:") {
+            let mut parts = rest.splitn(3, '.');
+            let (payload, id, tag) = (parts.next()?, parts.next()?, parts.next()?);
+            let key = self.find(id)?;
+            hmac::verify(&key.mac, format!("{}={}.{}", name, payload, id).as_bytes(), &unb64(tag)?).ok()?;
+            return String::from_utf8(unb64(payload)?).ok();
+        }
+
+        if let Some(rest) = value.strip_prefix("e:") {
+            let (id, data) = rest.split_once('.')?;
+            let key = self.find(id)?;
+            let mut data = unb64(data)?;
+            if data.len() < NONCE_LEN {
+                return None;
+            }
+
+            let mut body = data.split_off(NONCE_LEN);
+            let nonce = aead::Nonce::try_assume_unique_for_key(&data).ok()?;
+            let plain = key.seal.open_in_place(nonce, aead::Aad::from(name.as_bytes()), &mut body).ok()?;
+            return String::from_utf8(plain.to_vec()).ok();
+        }
+
+        None
+    }
+}
+
+pub struct Cookies {
+    keys: RwLock<Option<Arc<CookieKeys>>>,
+}
+
+impl Cookies {
+    pub fn new() -> Self {
+        Self { keys: RwLock::new(None) }
+    }
+
+    pub fn config(&self, keys: Option<Arc<CookieKeys>>) {
+        if let Ok(mut value) = self.keys.write() {
+            *value = keys;
+        }
+    }
+
+    // Only cookies that carry a valid signature or seal under a known key are returned.
+    pub fn trusted(&self, cookies: &[(String, String)]) -> Option<Vec<(String, String)>> {
+        let keys = self.keys.read().ok()?.clone()?;
+        let result = cookies.iter()
+            .filter_map(|(name, value)| keys.open(name, value).map(|x| (name.clone(), x)))
+            .collect();
+
+        Some(result)
+    }
+}
+
+#[cfg(windows)]
+pub fn pairs_to_js<'a, T>(cx: &mut T, pairs: &[(String, String)]) -> JsResult<'a, JsArray> where T: Context<'a> {
+    let result = cx.empty_array();
+    for (i, (name, value)) in pairs.iter().enumerate() {
+        let js_name = cx.string(name);
+        result.set(cx, 2 * i as u32, js_name)?;
+
+        let js_value = cx.string(value);
+        result.set(cx, 2 * i as u32 + 1, js_value)?;
+    }
+
+    Ok(result)
+}
+
+#[cfg(windows)]
+fn cookie_parse(mut cx: FunctionContext) -> JsResult<JsArray> {
+    let mut i = 0;
+    let header = cx.arg_string(&mut i)?;
+    pairs_to_js(&mut cx, &parse_cookies(&header))
+}
+
+#[cfg(windows)]
+fn cookie_serialize(mut cx: FunctionContext) -> JsResult<JsString> {
+    let mut i = 0;
+    let mut cookie = SetCookie::default();
+    cookie.name = cx.arg_string(&mut i)?;
+    cookie.value = cx.arg_string(&mut i)?;
+    if cx.arg_opt(&mut i) {
+        cookie.path = Some(cx.arg_string(&mut i)?);
+    }
+
+    if cx.arg_opt(&mut i) {
+        cookie.domain = Some(cx.arg_string(&mut i)?);
+    }
+
+    if cx.arg_opt(&mut i) {
+        cookie.max_age = Some(cx.arg_f64(&mut i)? as i64);
+    }
+
+    if cx.arg_opt(&mut i) {
+        cookie.expires = Some(cx.arg_f64(&mut i)? as i64);
+    }
+
+    if cx.arg_opt(&mut i) {
+        cookie.secure = cx.arg_bool(&mut i)?;
+    }
+
+    if cx.arg_opt(&mut i) {
+        cookie.http_only = cx.arg_bool(&mut i)?;
+    }
+
+    if cx.arg_opt(&mut i) {
+        let value = cx.arg_string(&mut i)?;
+        cookie.same_site = match value.to_ascii_lowercase().as_str() {
+            "strict" => Some(SameSite::Strict),
+            "lax" => Some(SameSite::Lax),
+            "none" => Some(SameSite::None),
+            _ => return cx.throw_type_error(format!("Unknown SameSite value: {}", value)),
+        };
+    }
+
+    if cx.arg_opt(&mut i) {
+        cookie.partitioned = cx.arg_bool(&mut i)?;
+    }
+
+    match cookie.render() {
+        Ok(value) => Ok(cx.string(value)),
+        Err(err) => cx.throw_type_error(err),
+    }
+}
+
+#[cfg(windows)]
+fn cookie_keys_create(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let mut i = 0;
+    let secrets = cx.arg_strings(&mut i)?;
+    let mut decoded = Vec::with_capacity(secrets.len());
+    for secret in secrets.iter() {
+        match base64::decode(secret).or_else(|_| base64::decode_config(secret, base64::URL_SAFE_NO_PAD)) {
+            Ok(value) => decoded.push(value),
+            Err(_) => return cx.throw_type_error("Cookie secrets must be base64"),
+        }
+    }
+
+    match CookieKeys::new(&decoded) {
+        Ok(keys) => Ok(cx.export(keys)),
+        Err(err) => cx.throw_type_error(err),
+    }
+}
+
+#[cfg(windows)]
+fn cookie_sign(mut cx: FunctionContext) -> JsResult<JsString> {
+    let mut i = 0;
+    let keys = cx.import::<CookieKeys>(&mut i)?;
+    let name = cx.arg_string(&mut i)?;
+    let value = cx.arg_string(&mut i)?;
+    Ok(cx.string(keys.sign(&name, &value)))
+}
+
+#[cfg(windows)]
+fn cookie_seal(mut cx: FunctionContext) -> JsResult<JsString> {
+    let mut i = 0;
+    let keys = cx.import::<CookieKeys>(&mut i)?;
+    let name = cx.arg_string(&mut i)?;
+    let value = cx.arg_string(&mut i)?;
+    match keys.seal(&name, &value) {
+        Ok(value) => Ok(cx.string(value)),
+        Err(err) => cx.throw_type_error(err),
+    }
+}
+
+#[cfg(windows)]
+fn cookie_open(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let mut i = 0;
+    let keys = cx.import::<CookieKeys>(&mut i)?;
+    let name = cx.arg_string(&mut i)?;
+    let value = cx.arg_string(&mut i)?;
+    match keys.open(&name, &value) {
+        Some(value) => Ok(cx.string(value).upcast()),
+        None => Ok(cx.null().upcast()),
+    }
+}
+
+#[cfg(windows)]
+fn cookie_keys_close(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    cx.dispose::<CookieKeys>(0)?;
+    Ok(cx.undefined())
+}
+
+#[cfg(windows)]
+pub fn cookie_bind(cx: &mut ModuleContext) -> NeonResult<()> {
+    cx.export_function("cookie_parse", cookie_parse)?;
+    cx.export_function("cookie_serialize", cookie_serialize)?;
+    cx.export_function("cookie_keys_create", cookie_keys_create)?;
+    cx.export_function("cookie_sign", cookie_sign)?;
+    cx.export_function("cookie_seal", cookie_seal)?;
+    cx.export_function("cookie_open", cookie_open)?;
+    cx.export_function("cookie_keys_close", cookie_keys_close)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(secrets: &[u8]) -> CookieKeys {
+        let list: Vec<Vec<u8>> = secrets.iter().map(|x| vec![*x; MIN_SECRET]).collect();
+        CookieKeys::new(&list).ok().unwrap()
+    }
+
+    fn cookie(name: &str) -> SetCookie {
+        SetCookie {
+            name: String::from(name),
+            value: String::from("v"),
+            ..SetCookie::default()
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let keys = keys(&[1]);
+        let signed = keys.sign("sid", "a=b; c");
+        assert!(signed.starts_with("s:"));
+        assert!(is_cookie_value(&signed));
+        assert_eq!(keys.open("sid", &signed).as_deref(), Some("a=b; c"));
+
+        let sealed = keys.seal("sid", "secret").unwrap();
+        assert!(sealed.starts_with("e:"));
+        assert!(is_cookie_value(&sealed));
+        assert!(!sealed.contains("secret"));
+        assert_eq!(keys.open("sid", &sealed).as_deref(), Some("secret"));
+        assert_ne!(keys.seal("sid", "secret").unwrap(), sealed);
+
+        assert_eq!(keys.open("sid", "plain"), None);
+        assert!(CookieKeys::new(&[vec![0; MIN_SECRET - 1]]).is_err());
+        assert!(CookieKeys::new(&[]).is_err());
+    }
+
+    #[test]
+    fn rotates_keys() {
+        let old = keys(&[1]);
+        let signed = old.sign("sid", "x");
+        let sealed = old.seal("sid", "y").unwrap();
+
+        let rotated = keys(&[2, 1]);
+        assert_eq!(rotated.open("sid", &signed).as_deref(), Some("x"));
+        assert_eq!(rotated.open("sid", &sealed).as_deref(), Some("y"));
+        assert_ne!(rotated.sign("sid", "x"), signed);
+
+        let retired = keys(&[2]);
+        assert_eq!(retired.open("sid", &signed), None);
+        assert_eq!(retired.open("sid", &sealed), None);
+        assert_eq!(old.open("sid", &rotated.sign("sid", "x")), None);
+    }
+
+    #[test]
+    fn rejects_tampering() {
+        let keys = keys(&[1]);
+        let signed = keys.sign("sid", "user");
+        let sealed = keys.seal("sid", "user").unwrap();
+        assert_eq!(keys.open("other", &signed), None);
+        assert_eq!(keys.open("other", &sealed), None);
+
+        let forged = signed.replacen(&b64(b"user"), &b64(b"root"), 1);
+        assert_eq!(keys.open("sid", &forged), None);
+
+        let (head, tag) = signed.rsplit_once('.').unwrap();
+        let tag = unb64(tag).unwrap();
+        for i in 0..tag.len() {
+            let mut changed = tag.clone();
+            changed[i] ^= 0x80;
+            assert_eq!(keys.open("sid", &format!("{}.{}", head, b64(&changed))), None);
+        }
+
+        let (head, data) = sealed.split_once('.').unwrap();
+        let data = unb64(data).unwrap();
+        for i in 0..data.len() {
+            let mut changed = data.clone();
+            changed[i] ^= 0x80;
+            assert_eq!(keys.open("sid", &format!("{}.{}", head, b64(&changed))), None);
+        }
+
+        assert_eq!(keys.open("sid", &signed[..signed.len() - 2]), None);
+        assert_eq!(keys.open("sid", "e:"), None);
+        assert_eq!(keys.open("sid", "s:a.b"), None);
+    }
+
+    #[test]
+    fn enforces_prefixes() {
+        assert!(cookie("__Secure-a").render().is_err());
+        assert!(SetCookie { secure: true, ..cookie("__Secure-a") }.render().is_ok());
+
+        assert!(SetCookie { secure: true, ..cookie("__Host-a") }.render().is_err());
+        assert!(SetCookie { secure: true, path: Some(String::from("/x")), ..cookie("__Host-a") }.render().is_err());
+        let domain = SetCookie { secure: true, path: Some(String::from("/")), domain: Some(String::from("a.example")), ..cookie("__Host-a") };
+        assert!(domain.render().is_err());
+        let host = SetCookie { secure: true, path: Some(String::from("/")), ..cookie("__Host-a") };
+        assert_eq!(host.render().unwrap(), "__Host-a=v; Path=/; Secure");
+
+        assert!(SetCookie { same_site: Some(SameSite::None), ..cookie("a") }.render().is_err());
+        assert!(SetCookie { partitioned: true, ..cookie("a") }.render().is_err());
+        assert!(cookie("a b").render().is_err());
+        assert!(SetCookie { value: String::from("x;y"), ..cookie("a") }.render().is_err());
+    }
+
+    #[test]
+    fn checks_set_cookie_headers() {
+        let check = |x: &str| SetCookie::parse(x).and_then(|x| x.render());
+        assert!(check("__Host-sid=1; Path=/; Secure; HttpOnly; SameSite=Lax").is_ok());
+        assert!(check("__Host-sid=1; Path=/; HttpOnly").is_err());
+        assert!(check("__Host-sid=1; path=/; secure; domain=a.example").is_err());
+        assert!(check("__Secure-sid=1; Secure; Expires=Wed, 21 Oct 2015 07:28:00 GMT").is_ok());
+        assert!(check("a=1; SameSite=None").is_err());
+        assert!(check("a=1; SameSite=Sometimes; Secure").is_err());
+        assert!(check("novalue").is_err());
+        assert_eq!(check("a=1; Max-Age=60; Secure").unwrap(), "a=1; Max-Age=60; Secure");
+
+        assert_eq!(http_date(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(http_date(1445412480000), "Wed, 21 Oct 2015 07:28:00 GMT");
+        assert_eq!(parse_cookies("a=1; b=\"2\"; =x; c"), vec![
+            (String::from("a"), String::from("1")),
+            (String::from("b"), String::from("2")),
+        ]);
+    }
+}
@@ -0,0 +1,386 @@
+use super::message::*;
+
+use regex::Regex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::RwLock;
+
+pub enum OriginRule {
+    Any,
+    Exact(String),
+    Wildcard(String),
+    Pattern(Regex),
+}
+
+impl OriginRule {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        if value == "*" {
+            return Ok(Self::Any);
+        }
+
+        // Anchored so a pattern for one origin cannot match it as a prefix of an attacker's host.
+        if let Some(pattern) = value.strip_prefix("regex:") {
+            return match Regex::new(&format!("^(?:{})$", pattern)) {
+                Ok(regex) => Ok(Self::Pattern(regex)),
+                Err(err) => Err(format!("Invalid origin pattern: {}", err)),
+            };
+        }
+
+        if value.contains('*') {
+            return Ok(Self::Wildcard(value.to_ascii_lowercase()));
+        }
+
+        Ok(Self::Exact(value.to_ascii_lowercase()))
+    }
+
+    pub fn matches(&self, origin: &str) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Exact(value) => value.eq_ignore_ascii_case(origin),
+            Self::Wildcard(value) => glob(value.as_bytes(), origin.to_ascii_lowercase().as_bytes()),
+            Self::Pattern(regex) => regex.is_match(origin),
+        }
+    }
+}
+
+pub fn glob(pattern: &[u8], value: &[u8]) -> bool {
+    let (mut p, mut v) = (0, 0);
+    let mut star = None;
+    while v < value.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, v));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == value[v] {
+            p += 1;
+            v += 1;
+        } else if let Some((sp, sv)) = star {
+            p = sp + 1;
+            v = sv + 1;
+            star = Some((sp, sv + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|x| *x == b'*')
+}
+
+// With credentials "*" is a literal name, and it never covers Authorization (Fetch standard, CORS-preflight fetch).
+fn list_contains(list: &[String], value: &str, wildcard: bool) -> bool {
+    let starred = wildcard && !value.eq_ignore_ascii_case("Authorization");
+    list.iter().any(|x| (starred && x == "*") || x.eq_ignore_ascii_case(value))
+}
+
+fn list_has(list: &str, value: &str) -> bool {
+    list.split(',').any(|x| x.trim() == "*" || x.trim().eq_ignore_ascii_case(value))
+}
+
+// Returns the combined Vary value, or None when the existing one already covers the new entry.
+pub fn append_vary(existing: &str, value: &str) -> Option<String> {
+    if list_has(existing, value) {
+        return None;
+    }
+
+    if existing.trim().len() < 1 {
+        return Some(String::from(value));
+    }
+
+    Some(format!("{}, {}", existing, value))
+}
+
+// Adds CORS headers to a response without overriding what the handler set; Vary is merged instead.
+pub fn merge_headers(headers: &mut Vec<(String, String)>, cors: Vec<(String, String)>) {
+    for (name, value) in cors {
+        let found = headers.iter_mut().rev().find(|(x, _)| x.eq_ignore_ascii_case(&name));
+        match found {
+            Some((_, existing)) if name.eq_ignore_ascii_case("Vary") => {
+                if let Some(merged) = append_vary(existing, &value) {
+                    *existing = merged;
+                }
+            },
+            Some(_) => (),
+            None => headers.push((name, value)),
+        }
+    }
+}
+
+pub struct CorsPolicy {
+    pub origins: Vec<OriginRule>,
+    pub methods: Vec<String>,
+    pub headers: Vec<String>,
+    pub expose: Vec<String>,
+    pub credentials: bool,
+    pub max_age: Option<u32>,
+}
+
+impl CorsPolicy {
+    // A credentialed response must name its origin, and echoing every origin back would let any
+    // site read it, so "*" only ever allows anonymous requests.
+    pub fn check(&self) -> Result<(), String> {
+        if self.credentials && self.origins.iter().any(|x| matches!(x, OriginRule::Any)) {
+            return Err(String::from("Credentials cannot be allowed for any origin; list the origins instead"));
+        }
+
+        Ok(())
+    }
+
+    fn allow_origin(&self, origin: &str) -> Option<Vec<(String, String)>> {
+        let rule = self.origins.iter().find(|x| x.matches(origin))?;
+        let mut headers = Vec::new();
+        if matches!(rule, OriginRule::Any) {
+            headers.push((String::from("Access-Control-Allow-Origin"), String::from("*")));
+            return Some(headers);
+        }
+
+        headers.push((String::from("Access-Control-Allow-Origin"), String::from(origin)));
+        headers.push((String::from("Vary"), String::from("Origin")));
+
+        // Sandboxed frames and local files all send "null", so it never identifies a trusted site.
+        if self.credentials && origin != "null" {
+            headers.push((String::from("Access-Control-Allow-Credentials"), String::from("true")));
+        }
+
+        Some(headers)
+    }
+
+    pub fn preflight(&self, origin: &str, method: &str, requested: Option<&str>) -> Option<Vec<(String, String)>> {
+        let mut headers = self.allow_origin(origin)?;
+        let simple = ["GET", "HEAD", "POST"].contains(&method);
+        let wildcard = !self.credentials;
+        if !simple && !list_contains(&self.methods, method, wildcard) {
+            return None;
+        }
+
+        let requested: Vec<&str> = requested
+            .map(|x| x.split(',').map(|x| x.trim()).filter(|x| x.len() > 0).collect())
+            .unwrap_or_default();
+
+        if requested.iter().any(|x| !list_contains(&self.headers, x, wildcard)) {
+            return None;
+        }
+
+        headers.push((String::from("Access-Control-Allow-Methods"), String::from(method)));
+        if requested.len() > 0 {
+            headers.push((String::from("Access-Control-Allow-Headers"), requested.join(", ")));
+        }
+
+        if let Some(age) = self.max_age {
+            headers.push((String::from("Access-Control-Max-Age"), age.to_string()));
+        }
+
+        Some(headers)
+    }
+
+    pub fn actual(&self, origin: &str) -> Option<Vec<(String, String)>> {
+        let mut headers = self.allow_origin(origin)?;
+        if self.expose.len() > 0 {
+            headers.push((String::from("Access-Control-Expose-Headers"), self.expose.join(", ")));
+        }
+
+        Some(headers)
+    }
+}
+
+// Policies are keyed by URL group; group 0 is the queue default for groups without their own.
+pub struct Cors {
+    policies: RwLock<HashMap<u64, Arc<CorsPolicy>>>,
+    pending: Mutex<HashMap<u64, Vec<(String, String)>>>,
+}
+
+impl Cors {
+    pub fn new() -> Self {
+        Self {
+            policies: RwLock::new(HashMap::new()),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self, group: u64, policy: Option<CorsPolicy>) {
+        if let Ok(mut map) = self.policies.write() {
+            match policy {
+                Some(policy) => map.insert(group, Arc::new(policy)),
+                None => map.remove(&group),
+            };
+        }
+    }
+
+    fn current(&self, group: u64) -> Option<Arc<CorsPolicy>> {
+        let map = self.policies.read().ok()?;
+        map.get(&group).or_else(|| map.get(&0)).cloned()
+    }
+
+    pub fn active(&self) -> bool {
+        self.policies.read().map(|x| x.len() > 0).unwrap_or(false)
+    }
+
+    pub fn admit(&self, info: &RequestInfo) -> Option<Reply> {
+        let policy = self.current(info.group)?;
+        let origin = info.header("Origin")?;
+        if let Some(method) = info.header("Access-Control-Request-Method").filter(|_| info.verb == "OPTIONS") {
+            let requested = info.header("Access-Control-Request-Headers");
+            let reply = match policy.preflight(origin, method, requested) {
+                Some(headers) => headers.iter().fold(Reply::new(204, "No Content"), |r, (k, v)| r.header(k, v)),
+                None => Reply::new(403, "Forbidden"),
+            };
+
+            return Some(reply.header("Content-Length", "0"));
+        }
+
+        if let Some(headers) = policy.actual(origin) {
+            if let Ok(mut map) = self.pending.lock() {
+                map.insert(info.id, headers);
+            }
+        }
+
+        None
+    }
+
+    pub fn take(&self, id: u64) -> Vec<(String, String)> {
+        match self.pending.lock() {
+            Ok(mut map) => map.remove(&id).unwrap_or_default(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    pub fn forget(&self, id: u64) {
+        if let Ok(mut map) = self.pending.lock() {
+            map.remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(origins: &[&str], methods: &[&str], headers: &[&str], credentials: bool) -> CorsPolicy {
+        CorsPolicy {
+            origins: origins.iter().map(|x| OriginRule::parse(x).unwrap()).collect(),
+            methods: methods.iter().map(|x| String::from(*x)).collect(),
+            headers: headers.iter().map(|x| String::from(*x)).collect(),
+            expose: Vec::new(),
+            credentials,
+            max_age: None,
+        }
+    }
+
+    fn value<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        headers.iter().find(|(x, _)| x == name).map(|(_, x)| x.as_str())
+    }
+
+    #[test]
+    fn anchors_patterns() {
+        let rule = OriginRule::parse(r"regex:https://a\.com").unwrap();
+        assert!(rule.matches("https://a.com"));
+        assert!(!rule.matches("https://a.com.evil.net"));
+        assert!(!rule.matches("https://evil.net/?https://a.com"));
+
+        let alternatives = OriginRule::parse(r"regex:https://a\.com|https://b\.com").unwrap();
+        assert!(alternatives.matches("https://b.com"));
+        assert!(!alternatives.matches("https://a.com.evil.net"));
+        assert!(OriginRule::parse("regex:(").is_err());
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        let rule = OriginRule::parse("https://*.Example.com").unwrap();
+        assert!(rule.matches("https://app.example.com"));
+        assert!(!rule.matches("https://example.com"));
+        assert!(!rule.matches("http://app.example.com"));
+        assert!(OriginRule::parse("https://app.example.com").unwrap().matches("HTTPS://APP.EXAMPLE.COM"));
+        assert!(glob(b"a*b*c", b"aXXbYc"));
+        assert!(!glob(b"a*b", b"aXXbY"));
+    }
+
+    #[test]
+    fn echoes_origin_with_credentials() {
+        let open = policy(&["*"], &[], &[], false).actual("https://x.example").unwrap();
+        assert_eq!(value(&open, "Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(value(&open, "Vary"), None);
+
+        let closed = policy(&["https://*.example"], &[], &[], true).actual("https://x.example").unwrap();
+        assert_eq!(value(&closed, "Access-Control-Allow-Origin"), Some("https://x.example"));
+        assert_eq!(value(&closed, "Access-Control-Allow-Credentials"), Some("true"));
+        assert_eq!(value(&closed, "Vary"), Some("Origin"));
+
+        assert!(policy(&["https://a.example"], &[], &[], false).actual("https://b.example").is_none());
+    }
+
+    #[test]
+    fn refuses_wildcard_credentials() {
+        assert!(policy(&["*"], &[], &[], true).check().is_err());
+        assert!(policy(&["https://a.example", "*"], &[], &[], true).check().is_err());
+        assert!(policy(&["*"], &[], &[], false).check().is_ok());
+        assert!(policy(&["https://a.example"], &[], &[], true).check().is_ok());
+
+        let null = policy(&[r"regex:null|https://a\.example"], &[], &[], true).actual("null").unwrap();
+        assert_eq!(value(&null, "Access-Control-Allow-Origin"), Some("null"));
+        assert_eq!(value(&null, "Access-Control-Allow-Credentials"), None);
+    }
+
+    #[test]
+    fn checks_preflight_lists() {
+        let open = policy(&["*"], &["*"], &["*"], false);
+        assert!(open.preflight("https://x.example", "DELETE", Some("X-Custom, Content-Type")).is_some());
+        assert!(open.preflight("https://x.example", "GET", Some("Authorization")).is_none());
+
+        let closed = policy(&["https://x.example"], &["*"], &["*"], true);
+        assert!(closed.preflight("https://x.example", "DELETE", None).is_none());
+        assert!(closed.preflight("https://x.example", "GET", Some("X-Custom")).is_none());
+        assert!(closed.preflight("https://x.example", "GET", None).is_some());
+
+        let named = policy(&["https://x.example"], &["PUT"], &["Authorization", "X-Custom"], true);
+        let headers = named.preflight("https://x.example", "PUT", Some("authorization, x-custom")).unwrap();
+        assert_eq!(value(&headers, "Access-Control-Allow-Methods"), Some("PUT"));
+        assert_eq!(value(&headers, "Access-Control-Allow-Headers"), Some("authorization, x-custom"));
+        assert!(named.preflight("https://x.example", "PATCH", None).is_none());
+    }
+
+    #[test]
+    fn keys_policies_by_group() {
+        let cors = Cors::new();
+        let api = url_group("api");
+        let request = |id, group| RequestInfo::fake(id, group, "GET", "/", &[("Origin", "https://x.example")]);
+        cors.config(0, Some(policy(&["*"], &[], &[], false)));
+        cors.config(api, Some(policy(&["https://a.example"], &[], &[], false)));
+
+        assert!(cors.admit(&request(1, 0)).is_none());
+        assert_eq!(value(&cors.take(1), "Access-Control-Allow-Origin"), Some("*"));
+        assert!(cors.admit(&request(2, api)).is_none());
+        assert!(cors.take(2).is_empty());
+        assert!(cors.admit(&request(3, url_group("static"))).is_none());
+        assert_eq!(value(&cors.take(3), "Access-Control-Allow-Origin"), Some("*"));
+
+        cors.config(api, None);
+        assert!(cors.admit(&request(4, api)).is_none());
+        assert_eq!(value(&cors.take(4), "Access-Control-Allow-Origin"), Some("*"));
+        cors.config(0, None);
+        assert!(!cors.active());
+    }
+
+    #[test]
+    fn merges_vary() {
+        assert_eq!(append_vary("Accept-Encoding", "Origin"), Some(String::from("Accept-Encoding, Origin")));
+        assert_eq!(append_vary("accept-encoding, origin", "Origin"), None);
+        assert_eq!(append_vary("*", "Origin"), None);
+        assert_eq!(append_vary("", "Origin"), Some(String::from("Origin")));
+
+        let mut headers = vec![
+            (String::from("Vary"), String::from("Accept")),
+            (String::from("Access-Control-Allow-Origin"), String::from("https://mine.example")),
+        ];
+
+        merge_headers(&mut headers, vec![
+            (String::from("Access-Control-Allow-Origin"), String::from("https://x.example")),
+            (String::from("Vary"), String::from("Origin")),
+            (String::from("Access-Control-Allow-Credentials"), String::from("true")),
+        ]);
+
+        assert_eq!(headers, vec![
+            (String::from("Vary"), String::from("Accept, Origin")),
+            (String::from("Access-Control-Allow-Origin"), String::from("https://mine.example")),
+            (String::from("Access-Control-Allow-Credentials"), String::from("true")),
+        ]);
+    }
+}
@@ -0,0 +1,911 @@
+use neon::prelude::*;
+use neon::types::buffer::*;
+use neon::types::Deferred;
+
+use super::http::*;
+use super::message::*;
+use super::support::*;
+
+use windows::Win32::Foundation::*;
+use windows::Win32::Networking::HttpServer::*;
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::remove_file;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::thread::spawn;
+
+// Events a stream holds for JS before the parser stops reading the body.
+const STREAM_EVENTS: usize = 16;
+
+#[allow(non_upper_case_globals)]
+static temp_counter: AtomicU64 = AtomicU64::new(0);
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.len() > data.len() {
+        return None;
+    }
+
+    (0..=data.len() - needle.len()).find(|i| &data[*i..*i + needle.len()] == needle)
+}
+
+fn param(value: &str, key: &str) -> Option<String> {
+    for part in value.split(';').skip(1) {
+        if let Some((name, value)) = part.split_once('=') {
+            if name.trim().eq_ignore_ascii_case(key) {
+                let value = value.trim();
+                let value = value.strip_prefix('"').and_then(|x| x.strip_suffix('"')).unwrap_or(value);
+                return Some(value.replace("\\\"", "\""));
+            }
+        }
+    }
+
+    None
+}
+
+pub enum FormError {
+    Client(u32),
+    Status(u16, &'static str),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Storage {
+    Temp,
+    Memory,
+    Stream,
+}
+
+pub struct FormLimits {
+    pub part: u64,
+    pub total: u64,
+    pub storage: Storage,
+    pub dir: PathBuf,
+}
+
+pub struct FormFile {
+    pub name: String,
+    pub filename: String,
+    pub content_type: String,
+    pub size: u64,
+    pub path: Option<PathBuf>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Default)]
+pub struct FormData {
+    pub fields: Vec<(String, String)>,
+    pub files: Vec<FormFile>,
+}
+
+impl FormData {
+    fn discard(&self) {
+        for file in self.files.iter() {
+            if let Some(path) = file.path.as_ref() {
+                remove_file(path).ok();
+            }
+        }
+    }
+}
+
+pub enum FormEvent {
+    Part { name: String, filename: Option<String>, content_type: String },
+    Data(Vec<u8>),
+    End,
+    Done,
+    Failed(FormError),
+}
+
+enum Sink {
+    Field(String, Vec<u8>),
+    Memory(FormFile),
+    Temp(FormFile, File),
+    Stream,
+}
+
+#[derive(PartialEq)]
+enum State {
+    Preamble,
+    Delimiter,
+    Headers,
+    Body,
+    Done,
+}
+
+pub struct Multipart<'a> {
+    limits: &'a FormLimits,
+    delimiter: Vec<u8>,
+    buf: Vec<u8>,
+    state: State,
+    sink: Option<Sink>,
+    part: u64,
+    total: u64,
+    pub result: FormData,
+    pub events: Vec<FormEvent>,
+}
+
+impl<'a> Multipart<'a> {
+    pub fn new(boundary: &str, limits: &'a FormLimits) -> Self {
+        Self {
+            limits,
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            buf: b"\r\n".to_vec(),
+            state: State::Preamble,
+            sink: None,
+            part: 0,
+            total: 0,
+            result: FormData::default(),
+            events: Vec::new(),
+        }
+    }
+
+    fn start(&mut self, head: &[u8]) -> Result<(), FormError> {
+        let text = String::from_utf8_lossy(head);
+        let mut disposition = String::new();
+        let mut content_type = String::new();
+        for line in text.split("\r\n") {
+            if let Some((name, value)) = line.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("Content-Disposition") {
+                    disposition = String::from(value.trim());
+                }
+
+                if name.trim().eq_ignore_ascii_case("Content-Type") {
+                    content_type = String::from(value.trim());
+                }
+            }
+        }
+
+        let name = match param(&disposition, "name") {
+            Some(name) => name,
+            None => return Err(FormError::Status(400, "Part is missing a name.")),
+        };
+
+        self.part = 0;
+        if self.limits.storage == Storage::Stream {
+            let filename = param(&disposition, "filename");
+            self.events.push(FormEvent::Part { name, filename, content_type });
+            self.sink = Some(Sink::Stream);
+            return Ok(());
+        }
+
+        self.sink = match param(&disposition, "filename") {
+            None => Some(Sink::Field(name, Vec::new())),
+            Some(filename) => {
+                let file = FormFile {
+                    name,
+                    filename,
+                    content_type,
+                    size: 0,
+                    path: None,
+                    data: Vec::new(),
+                };
+
+                if self.limits.storage == Storage::Memory {
+                    Some(Sink::Memory(file))
+                } else {
+                    let n = temp_counter.fetch_add(1, Relaxed);
+                    let path = self.limits.dir.join(format!("form-{}-{}.tmp", std::process::id(), n));
+                    match File::create(&path) {
+                        Ok(handle) => Some(Sink::Temp(FormFile { path: Some(path), ..file }, handle)),
+                        Err(_) => return Err(FormError::Status(500, "Could not create a temporary file.")),
+                    }
+                }
+            }
+        };
+
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), FormError> {
+        if data.len() < 1 {
+            return Ok(());
+        }
+
+        self.part += data.len() as u64;
+        if self.part > self.limits.part {
+            return Err(FormError::Status(413, "Part exceeds the size limit."));
+        }
+
+        match self.sink.as_mut() {
+            Some(Sink::Field(_, value)) => value.extend_from_slice(data),
+            Some(Sink::Memory(file)) => file.data.extend_from_slice(data),
+            Some(Sink::Temp(_, handle)) => {
+                if handle.write_all(data).is_err() {
+                    return Err(FormError::Status(500, "Could not write a temporary file."));
+                }
+            },
+            Some(Sink::Stream) => self.events.push(FormEvent::Data(data.to_vec())),
+            None => (),
+        }
+
+        Ok(())
+    }
+
+    fn end(&mut self) -> Result<(), FormError> {
+        let size = self.part;
+        match self.sink.take() {
+            Some(Sink::Field(name, value)) => match String::from_utf8(value) {
+                Ok(value) => self.result.fields.push((name, value)),
+                Err(_) => return Err(FormError::Status(400, "Field is not valid UTF-8.")),
+            },
+            Some(Sink::Memory(file)) => self.result.files.push(FormFile { size, ..file }),
+            Some(Sink::Temp(file, handle)) => {
+                drop(handle);
+                self.result.files.push(FormFile { size, ..file });
+            },
+            Some(Sink::Stream) => self.events.push(FormEvent::End),
+            None => (),
+        }
+
+        Ok(())
+    }
+
+    pub fn push(&mut self, data: &[u8]) -> Result<(), FormError> {
+        self.total += data.len() as u64;
+        if self.total > self.limits.total {
+            return Err(FormError::Status(413, "Body exceeds the size limit."));
+        }
+
+        self.buf.extend_from_slice(data);
+        loop {
+            match self.state {
+                State::Preamble | State::Body => {
+                    let keep = self.delimiter.len() - 1;
+                    match find(&self.buf, &self.delimiter) {
+                        Some(i) => {
+                            if self.state == State::Body {
+                                let chunk = self.buf[..i].to_vec();
+                                self.write(&chunk)?;
+                                self.end()?;
+                            }
+
+                            self.buf.drain(..i + self.delimiter.len());
+                            self.state = State::Delimiter;
+                        },
+                        None => {
+                            if self.buf.len() > keep {
+                                let cut = self.buf.len() - keep;
+                                if self.state == State::Body {
+                                    let chunk = self.buf[..cut].to_vec();
+                                    self.write(&chunk)?;
+                                }
+
+                                self.buf.drain(..cut);
+                            }
+
+                            return Ok(());
+                        }
+                    }
+                },
+                State::Delimiter => {
+                    // A delimiter line may end in transport padding (RFC 2046, section 5.1.1).
+                    let pad = self.buf.iter().take_while(|x| **x == b' ' || **x == b'\t').count();
+                    if self.buf.len() < pad + 2 {
+                        if pad > 16384 {
+                            return Err(FormError::Status(400, "Malformed multipart delimiter."));
+                        }
+
+                        return Ok(());
+                    }
+
+                    if pad < 1 && self.buf.starts_with(b"--") {
+                        self.buf.clear();
+                        self.state = State::Done;
+                    } else if self.buf[pad..].starts_with(b"\r\n") {
+                        self.buf.drain(..pad + 2);
+                        self.state = State::Headers;
+                    } else {
+                        return Err(FormError::Status(400, "Malformed multipart delimiter."));
+                    }
+                },
+                State::Headers => {
+                    match find(&self.buf, b"\r\n\r\n") {
+                        Some(i) => {
+                            let head = self.buf[..i].to_vec();
+                            self.buf.drain(..i + 4);
+                            self.start(&head)?;
+                            self.state = State::Body;
+                        },
+                        None => {
+                            if self.buf.len() > 16384 {
+                                return Err(FormError::Status(431, "Part headers are too large."));
+                            }
+
+                            return Ok(());
+                        }
+                    }
+                },
+                State::Done => {
+                    self.buf.clear();
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> Result<FormData, FormError> {
+        if self.state != State::Done {
+            self.end().ok();
+            self.result.discard();
+            return Err(FormError::Status(400, "Truncated multipart body."));
+        }
+
+        Ok(self.result)
+    }
+
+    pub fn discard(mut self) {
+        self.end().ok();
+        self.result.discard();
+    }
+}
+
+fn receive_all<F>(req: &Arc<Request>, id: u64, mut f: F) -> Result<(), FormError> where F: FnMut(&[u8]) -> Result<(), FormError> {
+    let mut buf = vec![0u8; 16384];
+    loop {
+        let (err, size) = block_on(|done| {
+            req.receive_data(id, Slice::new(&mut buf), move |err, size| done((err, size)));
+        }).unwrap_or((ERROR_OPERATION_ABORTED.0, 0));
+
+        if err == ERROR_HANDLE_EOF.0 || (err == 0 && size == 0) {
+            return Ok(());
+        }
+
+        if err != 0 {
+            return Err(FormError::Client(err));
+        }
+
+        f(&buf[..size as usize])?;
+    }
+}
+
+// Reads the body through `receive`; with Storage::Stream every part is handed to `emit` as it arrives.
+pub fn parse_form<R, E>(mut receive: R, content_type: &str, limits: &FormLimits, mut emit: E) -> Result<FormData, FormError>
+    where R: FnMut(&mut dyn FnMut(&[u8]) -> Result<(), FormError>) -> Result<(), FormError>,
+          E: FnMut(FormEvent) -> Result<(), FormError> {
+    let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    if mime == "application/x-www-form-urlencoded" {
+        let mut body = Vec::new();
+        receive(&mut |data| {
+            if body.len() as u64 + data.len() as u64 > limits.total {
+                return Err(FormError::Status(413, "Body exceeds the size limit."));
+            }
+
+            body.extend_from_slice(data);
+            Ok(())
+        })?;
+
+        let mut result = FormData::default();
+        for (name, value) in url::form_urlencoded::parse(&body) {
+            if value.len() as u64 > limits.part {
+                return Err(FormError::Status(413, "Field exceeds the size limit."));
+            }
+
+            if limits.storage == Storage::Stream {
+                emit(FormEvent::Part { name: name.into_owned(), filename: None, content_type: String::new() })?;
+                emit(FormEvent::Data(value.into_owned().into_bytes()))?;
+                emit(FormEvent::End)?;
+            } else {
+                result.fields.push((name.into_owned(), value.into_owned()));
+            }
+        }
+
+        return Ok(result);
+    }
+
+    if mime != "multipart/form-data" {
+        return Err(FormError::Status(415, "Unsupported form content type."));
+    }
+
+    let boundary = match param(content_type, "boundary") {
+        Some(boundary) if boundary.len() > 0 && boundary.len() <= 70 => boundary,
+        _ => return Err(FormError::Status(400, "Missing multipart boundary.")),
+    };
+
+    let mut parser = Multipart::new(&boundary, limits);
+    let result = receive(&mut |data| {
+        parser.push(data)?;
+        for event in parser.events.drain(..) {
+            emit(event)?;
+        }
+
+        Ok(())
+    });
+
+    match result {
+        Ok(()) => parser.finish(),
+        Err(err) => {
+            parser.discard();
+            Err(err)
+        }
+    }
+}
+
+struct StreamState {
+    events: VecDeque<FormEvent>,
+    waiters: VecDeque<(Channel, Deferred)>,
+    closed: bool,
+}
+
+pub struct FormStream {
+    state: Mutex<StreamState>,
+    ready: Condvar,
+}
+
+impl Finalize for FormStream {}
+
+impl FormStream {
+    fn new() -> Self {
+        Self {
+            state: Mutex::new(StreamState {
+                events: VecDeque::new(),
+                waiters: VecDeque::new(),
+                closed: false,
+            }),
+            ready: Condvar::new(),
+        }
+    }
+
+    // Blocks the parser while JS is behind, so a slow reader holds the upload back instead of buffering it.
+    fn emit(&self, event: FormEvent) -> Result<(), FormError> {
+        let mut state = self.state.lock().map_err(|_| FormError::Client(ERROR_OPERATION_ABORTED.0))?;
+        loop {
+            if state.closed {
+                return Err(FormError::Client(ERROR_OPERATION_ABORTED.0));
+            }
+
+            if let Some((tx, def)) = state.waiters.pop_front() {
+                drop(state);
+                settle_event(tx, def, event);
+                return Ok(());
+            }
+
+            if state.events.len() < STREAM_EVENTS {
+                state.events.push_back(event);
+                return Ok(());
+            }
+
+            state = self.ready.wait(state).map_err(|_| FormError::Client(ERROR_OPERATION_ABORTED.0))?;
+        }
+    }
+
+    fn end(&self, event: FormEvent) {
+        if let Ok(mut state) = self.state.lock() {
+            state.closed = true;
+            match state.waiters.pop_front() {
+                Some((tx, def)) => {
+                    drop(state);
+                    settle_event(tx, def, event);
+                },
+                None => state.events.push_back(event),
+            }
+        }
+    }
+
+    fn next(&self, tx: Channel, def: Deferred) {
+        if let Ok(mut state) = self.state.lock() {
+            if let Some(event) = state.events.pop_front() {
+                self.ready.notify_all();
+                drop(state);
+                settle_event(tx, def, event);
+                return;
+            }
+
+            if !state.closed {
+                state.waiters.push_back((tx, def));
+                return;
+            }
+        }
+
+        settle_event(tx, def, FormEvent::Done);
+    }
+
+    fn close(&self) {
+        let mut waiters = VecDeque::new();
+        if let Ok(mut state) = self.state.lock() {
+            state.closed = true;
+            state.events.clear();
+            waiters.append(&mut state.waiters);
+        }
+
+        self.ready.notify_all();
+        for (tx, def) in waiters {
+            settle_event(tx, def, FormEvent::Done);
+        }
+    }
+}
+
+fn settle_event(tx: Channel, def: Deferred, event: FormEvent) {
+    def.settle_with(&tx, move |mut cx| {
+        let obj = cx.empty_object();
+        let kind = match event {
+            FormEvent::Part { name, filename, content_type } => {
+                let js_name = cx.string(name);
+                obj.set(&mut cx, "name", js_name)?;
+
+                if let Some(filename) = filename {
+                    let js_filename = cx.string(filename);
+                    obj.set(&mut cx, "filename", js_filename)?;
+                }
+
+                let js_type = cx.string(content_type);
+                obj.set(&mut cx, "contentType", js_type)?;
+                "part"
+            },
+            FormEvent::Data(data) => {
+                let mut js_data = cx.buffer(data.len())?;
+                js_data.as_mut_slice(&mut cx).copy_from_slice(&data);
+                obj.set(&mut cx, "data", js_data)?;
+                "data"
+            },
+            FormEvent::End => "end",
+            FormEvent::Done => "done",
+            FormEvent::Failed(FormError::Client(err)) => {
+                let js_err = cx.number(err);
+                obj.set(&mut cx, "code", js_err)?;
+                "error"
+            },
+            FormEvent::Failed(FormError::Status(status, message)) => {
+                let js_status = cx.number(status);
+                obj.set(&mut cx, "status", js_status)?;
+
+                let js_message = cx.string(message);
+                obj.set(&mut cx, "error", js_message)?;
+                "error"
+            },
+        };
+
+        let js_kind = cx.string(kind);
+        obj.set(&mut cx, "type", js_kind)?;
+        Ok(obj)
+    });
+}
+
+pub fn reject(req: &Arc<Request>, id: u64, status: u16, message: &str) {
+    let reason = match status {
+        400 => "Bad Request",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        431 => "Request Header Fields Too Large",
+        _ => "Internal Server Error",
+    };
+
+    let reply = Reply::new(status, reason)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .header("Content-Length", &message.len().to_string())
+        .header("Connection", "close")
+        .body(message.as_bytes().to_vec());
+
+    block_on(|done| {
+        reply.send(req, id, HTTP_SEND_RESPONSE_FLAG_DISCONNECT, move |err, size| done((err, size)));
+    }).ok();
+}
+
+fn arg_limits(cx: &mut FunctionContext, i: &mut i32) -> NeonResult<FormLimits> {
+    let mut limits = FormLimits {
+        part: 16 * 1024 * 1024,
+        total: 64 * 1024 * 1024,
+        storage: Storage::Temp,
+        dir: std::env::temp_dir(),
+    };
+
+    if cx.arg_opt(i) {
+        limits.part = cx.arg_u32(i)? as u64;
+    }
+
+    if cx.arg_opt(i) {
+        limits.total = cx.arg_u32(i)? as u64;
+    }
+
+    if cx.arg_opt(i) {
+        limits.storage = match cx.arg_string(i)?.as_str() {
+            "temp" => Storage::Temp,
+            "memory" => Storage::Memory,
+            "stream" => Storage::Stream,
+            other => return cx.throw_type_error(format!("Unknown form storage: {}", other)),
+        };
+    }
+
+    if cx.arg_opt(i) {
+        limits.dir = PathBuf::from(cx.arg_string(i)?);
+    }
+
+    Ok(limits)
+}
+
+fn http_form_parse(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let arc = cx.import::<Request>(&mut i)?;
+    let id = cx.arg_u64(&mut i)?;
+    let content_type = cx.arg_string(&mut i)?;
+    let limits = arg_limits(&mut cx, &mut i)?;
+    if limits.storage == Storage::Stream {
+        return cx.throw_type_error("Streamed forms are read with http_form_stream.");
+    }
+
+    let tx = cx.channel();
+    let (def, promise) = cx.promise();
+    spawn(move || {
+        let result = parse_form(|f| receive_all(&arc, id, f), &content_type, &limits, |_| Ok(()));
+        if let Err(FormError::Status(status, message)) = &result {
+            reject(&arc, id, *status, message);
+        }
+
+        def.settle_with(&tx, move |mut cx| {
+            let obj = cx.empty_object();
+            let data = match result {
+                Ok(data) => data,
+                Err(FormError::Client(err)) => {
+                    let js_err = cx.number(err);
+                    obj.set(&mut cx, "code", js_err)?;
+                    return Ok(obj);
+                },
+                Err(FormError::Status(status, message)) => {
+                    let js_err = cx.number(0);
+                    obj.set(&mut cx, "code", js_err)?;
+
+                    let js_status = cx.number(status);
+                    obj.set(&mut cx, "status", js_status)?;
+
+                    let js_message = cx.string(message);
+                    obj.set(&mut cx, "error", js_message)?;
+                    return Ok(obj);
+                }
+            };
+
+            let js_err = cx.number(0);
+            obj.set(&mut cx, "code", js_err)?;
+
+            let js_fields = cx.empty_array();
+            obj.set(&mut cx, "fields", js_fields)?;
+
+            for (i, (name, value)) in data.fields.iter().enumerate() {
+                let pair = cx.empty_array();
+                let js_name = cx.string(name);
+                pair.set(&mut cx, 0, js_name)?;
+
+                let js_value = cx.string(value);
+                pair.set(&mut cx, 1, js_value)?;
+                js_fields.set(&mut cx, i as u32, pair)?;
+            }
+
+            let js_files = cx.empty_array();
+            obj.set(&mut cx, "files", js_files)?;
+
+            for (i, file) in data.files.iter().enumerate() {
+                let js_file = cx.empty_object();
+                let js_name = cx.string(&file.name);
+                js_file.set(&mut cx, "name", js_name)?;
+
+                let js_filename = cx.string(&file.filename);
+                js_file.set(&mut cx, "filename", js_filename)?;
+
+                let js_type = cx.string(&file.content_type);
+                js_file.set(&mut cx, "contentType", js_type)?;
+
+                let js_size = cx.number(file.size as f64);
+                js_file.set(&mut cx, "size", js_size)?;
+
+                if let Some(path) = file.path.as_ref() {
+                    let js_path = cx.string(path.to_string_lossy());
+                    js_file.set(&mut cx, "path", js_path)?;
+                } else {
+                    let mut js_data = cx.buffer(file.data.len())?;
+                    js_data.as_mut_slice(&mut cx).copy_from_slice(&file.data);
+                    js_file.set(&mut cx, "data", js_data)?;
+                }
+
+                js_files.set(&mut cx, i as u32, js_file)?;
+            }
+
+            Ok(obj)
+        });
+    });
+
+    Ok(promise)
+}
+
+fn http_form_stream(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let mut i = 0;
+    let arc = cx.import::<Request>(&mut i)?;
+    let id = cx.arg_u64(&mut i)?;
+    let content_type = cx.arg_string(&mut i)?;
+    let mut limits = arg_limits(&mut cx, &mut i)?;
+    limits.storage = Storage::Stream;
+
+    let stream = Arc::new(FormStream::new());
+    let result = stream.clone();
+    spawn(move || {
+        let result = parse_form(|f| receive_all(&arc, id, f), &content_type, &limits, |event| stream.emit(event));
+        match result {
+            Ok(_) => stream.end(FormEvent::Done),
+            Err(FormError::Status(status, message)) => {
+                reject(&arc, id, status, message);
+                stream.end(FormEvent::Failed(FormError::Status(status, message)));
+            },
+            Err(err) => stream.end(FormEvent::Failed(err)),
+        }
+    });
+
+    let result = cx.boxed(RefCell::new(Some(result)));
+    Ok(result.upcast())
+}
+
+fn http_form_next(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let arc = cx.import::<FormStream>(&mut i)?;
+    let tx = cx.channel();
+    let (def, promise) = cx.promise();
+    arc.next(tx, def);
+
+    Ok(promise)
+}
+
+fn http_form_close(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let mut i = 0;
+    let arc = cx.import::<FormStream>(&mut i)?;
+    arc.close();
+    cx.dispose::<FormStream>(0)?;
+
+    Ok(cx.undefined())
+}
+
+pub fn form_bind(cx: &mut ModuleContext) -> NeonResult<()> {
+    cx.export_function("http_form_parse", http_form_parse)?;
+    cx.export_function("http_form_stream", http_form_stream)?;
+    cx.export_function("http_form_next", http_form_next)?;
+    cx.export_function("http_form_close", http_form_close)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(storage: Storage) -> FormLimits {
+        FormLimits {
+            part: 1024,
+            total: 4096,
+            storage,
+            dir: std::env::temp_dir(),
+        }
+    }
+
+    fn body(padding: &str) -> Vec<u8> {
+        let text = format!(concat!(
+            "preamble\r\n",
+            "--xyz{0}\r\n",
+            "Content-Disposition: form-data; name=\"title\"\r\n",
+            "\r\n",
+            "hello\r\nworld\r\n",
+            "--xyz{0}\r\n",
+            "Content-Disposition: form-data; name=\"doc\"; filename=\"a \\\"b\\\".txt\"\r\n",
+            "Content-Type: text/plain\r\n",
+            "\r\n",
+            "--xy not a boundary\r\n",
+            "--xyz--{0}\r\n",
+            "epilogue",
+        ), padding);
+
+        text.into_bytes()
+    }
+
+    fn parse(data: &[u8], size: usize, limits: &FormLimits, events: &mut Vec<FormEvent>) -> Result<FormData, FormError> {
+        let content_type = "multipart/form-data; boundary=\"xyz\"";
+        let receive = |f: &mut dyn FnMut(&[u8]) -> Result<(), FormError>| {
+            for chunk in data.chunks(size) {
+                f(chunk)?;
+            }
+
+            Ok(())
+        };
+
+        parse_form(receive, content_type, limits, |event| {
+            events.push(event);
+            Ok(())
+        })
+    }
+
+    fn status(result: Result<FormData, FormError>) -> u16 {
+        match result {
+            Err(FormError::Status(status, _)) => status,
+            Err(FormError::Client(_)) => 0,
+            Ok(_) => 200,
+        }
+    }
+
+    #[test]
+    fn parses_any_chunking() {
+        let data = body("");
+        let limits = limits(Storage::Memory);
+        for size in 1..data.len() {
+            let form = parse(&data, size, &limits, &mut Vec::new()).ok().unwrap();
+            assert_eq!(form.fields, vec![(String::from("title"), String::from("hello\r\nworld"))]);
+            assert_eq!(form.files.len(), 1);
+            assert_eq!(form.files[0].filename, "a \"b\".txt");
+            assert_eq!(form.files[0].content_type, "text/plain");
+            assert_eq!(form.files[0].data, b"--xy not a boundary");
+            assert_eq!(form.files[0].size, 19);
+        }
+    }
+
+    #[test]
+    fn accepts_transport_padding() {
+        let data = body(" \t ");
+        for size in [1, 3, data.len()] {
+            let form = parse(&data, size, &limits(Storage::Memory), &mut Vec::new()).ok().unwrap();
+            assert_eq!(form.fields.len(), 1);
+            assert_eq!(form.files.len(), 1);
+        }
+
+        let bad = String::from_utf8(body("")).unwrap().replacen("--xyz\r\n", "--xyz x\r\n", 1);
+        assert_eq!(status(parse(bad.as_bytes(), 7, &limits(Storage::Memory), &mut Vec::new())), 400);
+    }
+
+    #[test]
+    fn streams_parts() {
+        let data = body("");
+        let mut events = Vec::new();
+        let form = parse(&data, 5, &limits(Storage::Stream), &mut events).ok().unwrap();
+        assert_eq!(form.fields.len(), 0);
+        assert_eq!(form.files.len(), 0);
+
+        let mut parts = Vec::new();
+        for event in events {
+            match event {
+                FormEvent::Part { name, filename, .. } => parts.push((name, filename, Vec::new(), false)),
+                FormEvent::Data(data) => parts.last_mut().unwrap().2.extend_from_slice(&data),
+                FormEvent::End => parts.last_mut().unwrap().3 = true,
+                _ => panic!("unexpected event"),
+            }
+        }
+
+        assert_eq!(parts, vec![
+            (String::from("title"), None, b"hello\r\nworld".to_vec(), true),
+            (String::from("doc"), Some(String::from("a \"b\".txt")), b"--xy not a boundary".to_vec(), true),
+        ]);
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let data = body("");
+        let mut small = limits(Storage::Memory);
+        small.part = 10;
+        assert_eq!(status(parse(&data, 16, &small, &mut Vec::new())), 413);
+
+        let mut short = limits(Storage::Memory);
+        short.total = 64;
+        assert_eq!(status(parse(&data, 16, &short, &mut Vec::new())), 413);
+
+        let truncated = &data[..data.len() - 20];
+        assert_eq!(status(parse(truncated, 16, &limits(Storage::Memory), &mut Vec::new())), 400);
+
+        let unnamed = String::from_utf8(data.clone()).unwrap().replace("name=\"title\"", "x=1");
+        assert_eq!(status(parse(unnamed.as_bytes(), 16, &limits(Storage::Memory), &mut Vec::new())), 400);
+
+        let receive = |_: &mut dyn FnMut(&[u8]) -> Result<(), FormError>| Ok(());
+        assert_eq!(status(parse_form(receive, "text/plain", &small, |_| Ok(()))), 415);
+        assert_eq!(status(parse_form(receive, "multipart/form-data", &small, |_| Ok(()))), 400);
+    }
+
+    #[test]
+    fn discards_temp_files() {
+        let data = body("");
+        let form = parse(&data, 64, &limits(Storage::Temp), &mut Vec::new()).ok().unwrap();
+        let path = form.files[0].path.clone().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"--xy not a boundary");
+        form.discard();
+        assert!(!path.exists());
+
+        let mut events = Vec::new();
+        let truncated = &data[..data.len() - 20];
+        assert_eq!(status(parse(truncated, 64, &limits(Storage::Temp), &mut events)), 400);
+        assert!(events.is_empty());
+    }
+}
@@ -0,0 +1,761 @@
+use neon::prelude::*;
+use neon::types::buffer::*;
+
+use super::http::*;
+use super::message::*;
+use super::support::*;
+
+use windows::Win32::Foundation::*;
+use windows::Win32::Networking::HttpServer::*;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::mpsc::channel;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::RwLock;
+use std::thread::park_timeout;
+use std::thread::spawn;
+use std::thread::Thread;
+use std::time::Duration;
+use std::time::Instant;
+
+pub const GRPC_CANCELLED: u32 = 1;
+pub const GRPC_DEADLINE_EXCEEDED: u32 = 4;
+pub const GRPC_RESOURCE_EXHAUSTED: u32 = 8;
+pub const GRPC_UNIMPLEMENTED: u32 = 12;
+pub const GRPC_INTERNAL: u32 = 13;
+
+pub fn encode_message(data: &[u8], compressed: bool) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len() + 5);
+    result.push(compressed as u8);
+    result.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    result.extend_from_slice(data);
+    result
+}
+
+pub struct Decoder {
+    buf: Vec<u8>,
+    limit: usize,
+}
+
+impl Decoder {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            limit,
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<(bool, Vec<u8>)>, (u32, &'static str)> {
+        self.buf.extend_from_slice(data);
+
+        let mut result = Vec::new();
+        let mut off = 0;
+        while self.buf.len() - off >= 5 {
+            let head = &self.buf[off..off + 5];
+            if head[0] > 1 {
+                return Err((GRPC_INTERNAL, "Invalid compressed flag."));
+            }
+
+            let len = u32::from_be_bytes([head[1], head[2], head[3], head[4]]) as usize;
+            if len > self.limit {
+                return Err((GRPC_RESOURCE_EXHAUSTED, "Message exceeds the size limit."));
+            }
+
+            if self.buf.len() - off - 5 < len {
+                break;
+            }
+
+            let compressed = head[0] == 1;
+            result.push((compressed, self.buf[off + 5..off + 5 + len].to_vec()));
+            off += 5 + len;
+        }
+
+        self.buf.drain(..off);
+        Ok(result)
+    }
+
+    pub fn pending(&self) -> bool {
+        self.buf.len() > 0
+    }
+}
+
+pub fn parse_timeout(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if value.len() < 2 || value.len() > 9 {
+        return None;
+    }
+
+    let (digits, unit) = value.split_at(value.len() - 1);
+    let amount = digits.parse::<u64>().ok()?;
+    match unit {
+        "H" => Some(Duration::from_secs(amount * 3600)),
+        "M" => Some(Duration::from_secs(amount * 60)),
+        "S" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_millis(amount)),
+        "u" => Some(Duration::from_micros(amount)),
+        "n" => Some(Duration::from_nanos(amount)),
+        _ => None,
+    }
+}
+
+pub fn encode_grpc_message(value: &str) -> String {
+    let mut result = String::new();
+    for b in value.bytes() {
+        if b < 0x20 || b > 0x7e || b == b'%' {
+            result.push_str(&format!("%{:02X}", b));
+        } else {
+            result.push(b as char);
+        }
+    }
+
+    result
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CallKind {
+    Unary,
+    ClientStream,
+    ServerStream,
+    Bidi,
+}
+
+impl CallKind {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "unary" => Some(CallKind::Unary),
+            "client-stream" => Some(CallKind::ClientStream),
+            "server-stream" => Some(CallKind::ServerStream),
+            "bidi" => Some(CallKind::Bidi),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            CallKind::Unary => "unary",
+            CallKind::ClientStream => "client-stream",
+            CallKind::ServerStream => "server-stream",
+            CallKind::Bidi => "bidi",
+        }
+    }
+
+    fn single_request(&self) -> bool {
+        *self == CallKind::Unary || *self == CallKind::ServerStream
+    }
+
+    fn single_response(&self) -> bool {
+        *self == CallKind::Unary || *self == CallKind::ClientStream
+    }
+}
+
+pub struct GrpcServer {
+    methods: RwLock<HashMap<String, CallKind>>,
+    limit: usize,
+}
+
+impl Finalize for GrpcServer {}
+
+impl GrpcServer {
+    pub fn new(limit: u32) -> Self {
+        let mut limit = limit as usize;
+        if limit < 1 {
+            limit = 4 * 1024 * 1024;
+        }
+
+        Self {
+            methods: RwLock::new(HashMap::new()),
+            limit,
+        }
+    }
+
+    pub fn register(&self, path: &str, kind: CallKind) {
+        if let Ok(mut map) = self.methods.write() {
+            map.insert(String::from(path), kind);
+        }
+    }
+
+    pub fn find(&self, path: &str) -> Option<CallKind> {
+        match self.methods.read() {
+            Ok(map) => map.get(path).copied(),
+            Err(_) => None,
+        }
+    }
+}
+
+fn trailers(status: u32, message: &str, metadata: Vec<(String, String)>) -> Vec<(String, String)> {
+    let mut result = vec![
+        (String::from("grpc-status"), status.to_string()),
+        (String::from("grpc-message"), encode_grpc_message(message)),
+    ];
+
+    result.extend(metadata);
+    result
+}
+
+fn trailers_only_reply(status: u32, message: &str) -> Reply {
+    let mut reply = Reply::new(200, "OK").header("Content-Type", "application/grpc");
+    for (name, value) in trailers(status, message, Vec::new()) {
+        reply = reply.header(&name, &value);
+    }
+
+    reply
+}
+
+fn trailers_only(req: &Arc<Request>, id: u64, status: u32, message: &str) {
+    trailers_only_reply(status, message).send(req, id, 0, |_, _| {});
+}
+
+type CallJob = Box<dyn FnOnce() + Send>;
+
+// Runs one side of a call in order on its own thread; the thread exits once the call is closed
+// and the queue drains.
+struct CallWorker {
+    jobs: Mutex<Sender<CallJob>>,
+}
+
+impl CallWorker {
+    fn new() -> Self {
+        let (tx, rx) = channel::<CallJob>();
+        spawn(move || {
+            for job in rx {
+                job();
+            }
+        });
+
+        Self { jobs: Mutex::new(tx) }
+    }
+
+    fn run<F>(&self, f: F) -> bool where F: FnOnce() + Send + 'static {
+        match self.jobs.lock() {
+            Ok(jobs) => jobs.send(Box::new(f)).is_ok(),
+            Err(_) => false,
+        }
+    }
+}
+
+struct CallState {
+    decoder: Decoder,
+    queue: VecDeque<(bool, Vec<u8>)>,
+    metadata: Vec<(String, String)>,
+    eof: bool,
+    headers: bool,
+    finished: bool,
+    sent: u32,
+}
+
+pub struct GrpcCall {
+    req: Arc<Request>,
+    id: u64,
+    kind: CallKind,
+    state: Mutex<CallState>,
+    send: Mutex<()>,
+    timer: Mutex<Option<Thread>>,
+    reader: CallWorker,
+    writer: CallWorker,
+}
+
+impl Finalize for GrpcCall {}
+
+impl GrpcCall {
+    fn new(req: Arc<Request>, id: u64, kind: CallKind, limit: usize) -> Self {
+        Self {
+            req,
+            id,
+            kind,
+            state: Mutex::new(CallState {
+                decoder: Decoder::new(limit),
+                queue: VecDeque::new(),
+                metadata: Vec::new(),
+                eof: false,
+                headers: false,
+                finished: false,
+                sent: 0,
+            }),
+            send: Mutex::new(()),
+            timer: Mutex::new(None),
+            reader: CallWorker::new(),
+            writer: CallWorker::new(),
+        }
+    }
+
+    fn deadline(self: &Arc<Self>, timeout: Duration) {
+        let call = self.clone();
+        let end = Instant::now() + timeout;
+        let handle = spawn(move || {
+            loop {
+                if call.finished() {
+                    return;
+                }
+
+                let now = Instant::now();
+                if now >= end {
+                    break;
+                }
+
+                park_timeout(end - now);
+            }
+
+            // The trailers could not be sent, so the client would never see the deadline.
+            let err = call.finish(GRPC_DEADLINE_EXCEEDED, "Deadline exceeded.", Vec::new());
+            if err != 0 && err != ERROR_INVALID_HANDLE.0 {
+                block_on(|done| call.req.cancel(call.id, move |err| done(err))).ok();
+            }
+        });
+
+        if let Ok(mut timer) = self.timer.lock() {
+            *timer = Some(handle.thread().clone());
+        }
+    }
+
+    fn finished(&self) -> bool {
+        match self.state.lock() {
+            Ok(state) => state.finished,
+            Err(_) => true,
+        }
+    }
+
+    fn next(&self) -> Result<Option<(bool, Vec<u8>)>, (u32, &'static str)> {
+        let mut buf = vec![0u8; 16384];
+        loop {
+            if let Ok(mut state) = self.state.lock() {
+                if let Some(message) = state.queue.pop_front() {
+                    return Ok(Some(message));
+                }
+
+                if state.eof {
+                    if state.decoder.pending() {
+                        return Err((GRPC_INTERNAL, "Truncated message."));
+                    }
+
+                    return Ok(None);
+                }
+            }
+
+            let (err, size) = block_on(|done| {
+                self.req.receive_data(self.id, Slice::new(&mut buf), move |err, size| done((err, size)));
+            }).unwrap_or((ERROR_OPERATION_ABORTED.0, 0));
+
+            if let Ok(mut state) = self.state.lock() {
+                if err == ERROR_HANDLE_EOF.0 || (err == 0 && size == 0) {
+                    state.eof = true;
+                    continue;
+                }
+
+                if err != 0 {
+                    return Err((GRPC_CANCELLED, "Receive failed."));
+                }
+
+                let messages = state.decoder.push(&buf[..size as usize])?;
+                state.queue.extend(messages);
+            }
+        }
+    }
+
+    pub fn read(&self) -> Result<Option<(bool, Vec<u8>)>, (u32, &'static str)> {
+        if !self.kind.single_request() {
+            return self.next();
+        }
+
+        let first = self.next()?;
+        if first.is_none() {
+            return Ok(None);
+        }
+
+        if self.next()?.is_some() {
+            return Err((GRPC_INTERNAL, "Expected a single request message."));
+        }
+
+        Ok(first)
+    }
+
+    fn take_headers(state: &mut CallState) -> Reply {
+        let mut reply = Reply::new(200, "OK").header("Content-Type", "application/grpc");
+        for (name, value) in state.metadata.drain(..) {
+            reply = reply.header(&name, &value);
+        }
+
+        state.headers = true;
+        reply
+    }
+
+    // Called without the state lock; `send` keeps writers ordered.
+    fn send_headers(&self, reply: Reply, flags: u32) -> u32 {
+        let (err, _) = block_on(|done| {
+            reply.send(&self.req, self.id, flags, move |err, size| done((err, size)));
+        }).unwrap_or((ERROR_OPERATION_ABORTED.0, 0));
+
+        err
+    }
+
+    pub fn metadata(&self, name: &str, value: &str) -> bool {
+        if let Ok(mut state) = self.state.lock() {
+            if !state.headers {
+                state.metadata.push((String::from(name), String::from(value)));
+                return true;
+            }
+        }
+
+        false
+    }
+
+    pub fn write(&self, data: &[u8], compressed: bool) -> Result<u32, &'static str> {
+        let _guard = self.send.lock();
+        let mut headers = None;
+        if let Ok(mut state) = self.state.lock() {
+            if state.finished {
+                return Err("Call already finished.");
+            }
+
+            if self.kind.single_response() && state.sent > 0 {
+                return Err("Call only allows a single response message.");
+            }
+
+            state.sent += 1;
+
+            if !state.headers {
+                headers = Some(Self::take_headers(&mut state));
+            }
+        }
+
+        if let Some(reply) = headers {
+            let err = self.send_headers(reply, HTTP_SEND_RESPONSE_FLAG_MORE_DATA);
+            if err != 0 {
+                return Ok(err);
+            }
+        }
+
+        let data = vec![encode_message(data, compressed)];
+        let (err, _) = block_on(|done| {
+            send_body(&self.req, self.id, HTTP_SEND_RESPONSE_FLAG_MORE_DATA, data, Vec::new(), move |err, size| done((err, size)));
+        }).unwrap_or((ERROR_OPERATION_ABORTED.0, 0));
+
+        Ok(err)
+    }
+
+    pub fn finish(&self, status: u32, message: &str, metadata: Vec<(String, String)>) -> u32 {
+        let _guard = self.send.lock();
+        let mut headers = None;
+        let mut metadata = trailers(status, message, metadata);
+        if let Ok(mut state) = self.state.lock() {
+            if state.finished {
+                return ERROR_INVALID_HANDLE.0;
+            }
+
+            state.finished = true;
+
+            if !state.headers {
+                state.metadata.append(&mut metadata);
+                headers = Some(Self::take_headers(&mut state));
+            }
+        }
+
+        if let Ok(timer) = self.timer.lock() {
+            if let Some(thread) = timer.as_ref() {
+                thread.unpark();
+            }
+        }
+
+        if let Some(reply) = headers {
+            return self.send_headers(reply, 0);
+        }
+
+        let (err, _) = block_on(|done| {
+            send_body(&self.req, self.id, 0, Vec::new(), metadata, move |err, size| done((err, size)));
+        }).unwrap_or((ERROR_OPERATION_ABORTED.0, 0));
+
+        err
+    }
+}
+
+fn http_grpc_create(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let mut i = 0;
+    let mut limit = 0;
+    if cx.arg_opt(&mut i) {
+        limit = cx.arg_u32(&mut i)?;
+    }
+
+    Ok(cx.export(GrpcServer::new(limit)))
+}
+
+fn http_grpc_register(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let mut i = 0;
+    let arc = cx.import::<GrpcServer>(&mut i)?;
+    let path = cx.arg_string(&mut i)?;
+    let kind = cx.arg_string(&mut i)?;
+    match CallKind::parse(&kind) {
+        Some(kind) => arc.register(&path, kind),
+        None => return cx.throw_type_error(format!("Unknown call kind: {}", kind)),
+    }
+
+    Ok(cx.undefined())
+}
+
+fn http_grpc_accept(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let mut i = 0;
+    let server = cx.import::<GrpcServer>(&mut i)?;
+    let req = cx.import::<Request>(&mut i)?;
+    let id = cx.arg_u64(&mut i)?;
+    let http2 = cx.arg_bool(&mut i)?;
+    let path = cx.arg_string(&mut i)?;
+    let content_type = cx.arg_string(&mut i)?;
+    let mut timeout = None;
+    if cx.arg_opt(&mut i) {
+        let value = cx.arg_string(&mut i)?;
+        timeout = parse_timeout(&value);
+    }
+
+    if !http2 {
+        let reply = Reply::new(505, "HTTP Version Not Supported").header("Content-Length", "0");
+        reply.send(&req, id, 0, |_, _| {});
+        return Ok(cx.undefined().upcast());
+    }
+
+    if !content_type.starts_with("application/grpc") {
+        let reply = Reply::new(415, "Unsupported Media Type").header("Content-Length", "0");
+        reply.send(&req, id, 0, |_, _| {});
+        return Ok(cx.undefined().upcast());
+    }
+
+    let kind = match server.find(&path) {
+        Some(kind) => kind,
+        None => {
+            trailers_only(&req, id, GRPC_UNIMPLEMENTED, &format!("Method not found: {}", path));
+            return Ok(cx.undefined().upcast());
+        }
+    };
+
+    let call = Arc::new(GrpcCall::new(req, id, kind, server.limit));
+    if let Some(timeout) = timeout {
+        call.deadline(timeout);
+    }
+
+    let obj = cx.empty_object();
+    let js_kind = cx.string(kind.name());
+    obj.set(&mut cx, "kind", js_kind)?;
+
+    let js_call = cx.boxed(RefCell::new(Some(call)));
+    obj.set(&mut cx, "call", js_call)?;
+
+    Ok(obj.upcast())
+}
+
+fn http_grpc_metadata(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+    let mut i = 0;
+    let call = cx.import::<GrpcCall>(&mut i)?;
+    let mut result = true;
+    while i < cx.len() {
+        let name = cx.arg_string(&mut i)?;
+        let value = cx.arg_string(&mut i)?;
+        result = call.metadata(&name, &value) && result;
+    }
+
+    Ok(cx.boolean(result))
+}
+
+fn http_grpc_read(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let call = cx.import::<GrpcCall>(&mut i)?;
+    let tx = cx.channel();
+    let (def, promise) = cx.promise();
+    let reader = call.clone();
+    let queued = call.reader.run(move || {
+        let result = reader.read();
+        def.settle_with(&tx, move |mut cx| {
+            let obj = cx.empty_object();
+            match result {
+                Ok(Some((compressed, data))) => {
+                    let mut js_data = cx.buffer(data.len())?;
+                    js_data.as_mut_slice(&mut cx).copy_from_slice(&data);
+                    obj.set(&mut cx, "data", js_data)?;
+
+                    let js_compressed = cx.boolean(compressed);
+                    obj.set(&mut cx, "compressed", js_compressed)?;
+                },
+                Ok(None) => {
+                    let js_done = cx.boolean(true);
+                    obj.set(&mut cx, "done", js_done)?;
+                },
+                Err((status, message)) => {
+                    let js_status = cx.number(status);
+                    obj.set(&mut cx, "status", js_status)?;
+
+                    let js_message = cx.string(message);
+                    obj.set(&mut cx, "message", js_message)?;
+                }
+            }
+
+            Ok(obj)
+        });
+    });
+
+    if !queued {
+        return cx.throw_type_error("Call closed.");
+    }
+
+    Ok(promise)
+}
+
+fn http_grpc_write(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let call = cx.import::<GrpcCall>(&mut i)?;
+    let block = cx.arg_buffer(&mut i)?;
+    let data = block.as_slice(&cx).to_vec();
+    let mut compressed = false;
+    if cx.arg_opt(&mut i) {
+        compressed = cx.arg_bool(&mut i)?;
+    }
+
+    let tx = cx.channel();
+    let (def, promise) = cx.promise();
+    let writer = call.clone();
+    let queued = call.writer.run(move || {
+        let result = writer.write(&data, compressed);
+        def.settle_with(&tx, move |mut cx| {
+            match result {
+                Ok(err) => Ok(cx.number(err)),
+                Err(message) => cx.throw_type_error(message),
+            }
+        });
+    });
+
+    if !queued {
+        return cx.throw_type_error("Call closed.");
+    }
+
+    Ok(promise)
+}
+
+fn http_grpc_finish(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let call = cx.import::<GrpcCall>(&mut i)?;
+    let status = cx.arg_u32(&mut i)?;
+    let mut message = String::new();
+    let mut metadata = Vec::new();
+    if cx.arg_opt(&mut i) {
+        message = cx.arg_string(&mut i)?;
+    }
+
+    while i < cx.len() {
+        let name = cx.arg_string(&mut i)?;
+        let value = cx.arg_string(&mut i)?;
+        metadata.push((name, value));
+    }
+
+    let tx = cx.channel();
+    let (def, promise) = cx.promise();
+    let writer = call.clone();
+    let queued = call.writer.run(move || {
+        let err = writer.finish(status, &message, metadata);
+        def.settle_with(&tx, move |mut cx| {
+            Ok(cx.number(err))
+        });
+    });
+
+    if !queued {
+        return cx.throw_type_error("Call closed.");
+    }
+
+    Ok(promise)
+}
+
+fn http_grpc_close(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    cx.dispose::<GrpcCall>(0)?;
+    Ok(cx.undefined())
+}
+
+fn http_grpc_server_close(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    cx.dispose::<GrpcServer>(0)?;
+    Ok(cx.undefined())
+}
+
+pub fn grpc_bind(cx: &mut ModuleContext) -> NeonResult<()> {
+    cx.export_function("http_grpc_create", http_grpc_create)?;
+    cx.export_function("http_grpc_register", http_grpc_register)?;
+    cx.export_function("http_grpc_server_close", http_grpc_server_close)?;
+
+    cx.export_function("http_grpc_accept", http_grpc_accept)?;
+    cx.export_function("http_grpc_metadata", http_grpc_metadata)?;
+    cx.export_function("http_grpc_read", http_grpc_read)?;
+    cx.export_function("http_grpc_write", http_grpc_write)?;
+    cx.export_function("http_grpc_finish", http_grpc_finish)?;
+    cx.export_function("http_grpc_close", http_grpc_close)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(name: &str, value: &str) -> (String, String) {
+        (String::from(name), String::from(value))
+    }
+
+    #[test]
+    fn framing() {
+        let mut data = encode_message(b"hello", false);
+        data.extend(encode_message(b"", false));
+        data.extend(encode_message(b"world", false));
+        assert_eq!(&data[..5], &[0, 0, 0, 0, 5]);
+
+        let mut decoder = Decoder::new(16);
+        let mut messages = Vec::new();
+        for part in data.chunks(3) {
+            messages.extend(decoder.push(part).unwrap());
+        }
+
+        assert!(!decoder.pending());
+        assert_eq!(messages, vec![(false, b"hello".to_vec()), (false, Vec::new()), (false, b"world".to_vec())]);
+
+        let mut decoder = Decoder::new(16);
+        assert_eq!(decoder.push(&encode_message(b"hello", false)[..7]), Ok(Vec::new()));
+        assert!(decoder.pending());
+
+        let mut decoder = Decoder::new(4);
+        assert_eq!(decoder.push(&encode_message(b"hello", false)), Err((GRPC_RESOURCE_EXHAUSTED, "Message exceeds the size limit.")));
+    }
+
+    #[test]
+    fn compressed_flag() {
+        let data = encode_message(b"zip", true);
+        assert_eq!(data[0], 1);
+
+        let mut decoder = Decoder::new(16);
+        assert_eq!(decoder.push(&data), Ok(vec![(true, b"zip".to_vec())]));
+
+        let mut decoder = Decoder::new(16);
+        assert_eq!(decoder.push(&[2, 0, 0, 0, 0]), Err((GRPC_INTERNAL, "Invalid compressed flag.")));
+    }
+
+    #[test]
+    fn trailer_values() {
+        assert_eq!(
+            trailers(GRPC_DEADLINE_EXCEEDED, "50% done\n", vec![pair("x-id", "7")]),
+            vec![pair("grpc-status", "4"), pair("grpc-message", "50%25 done%0A"), pair("x-id", "7")]
+        );
+
+        assert_eq!(
+            trailers_only_reply(GRPC_UNIMPLEMENTED, "Method not found: /a.B/C"),
+            Reply::new(200, "OK")
+                .header("Content-Type", "application/grpc")
+                .header("grpc-status", "12")
+                .header("grpc-message", "Method not found: /a.B/C")
+        );
+
+        assert_eq!(encode_grpc_message("caf\u{e9}"), "caf%C3%A9");
+    }
+
+    #[test]
+    fn timeouts() {
+        assert_eq!(parse_timeout("1H"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse_timeout("250m"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_timeout("10n"), Some(Duration::from_nanos(10)));
+        assert_eq!(parse_timeout("S"), None);
+        assert_eq!(parse_timeout("123456789S"), None);
+        assert_eq!(parse_timeout("5x"), None);
+    }
+}
@@ -0,0 +1,159 @@
+use neon::prelude::*;
+
+use super::http::*;
+use super::support::*;
+use super::win32::*;
+
+use windows::Win32::Foundation::*;
+use windows::Win32::System::Threading::*;
+
+use std::sync::Mutex;
+use std::thread::sleep;
+use std::thread::spawn;
+use std::time::Duration;
+use std::time::Instant;
+
+// One handle per signaled name stays open, so a signal raised before the peer starts waiting is not lost.
+static EVENTS: Mutex<Vec<(String, isize)>> = Mutex::new(Vec::new());
+
+// Handover events are auto reset: each signal releases exactly one wait, so a key can be handed over again.
+fn open_event(name: &str) -> Result<HANDLE, (&'static str, u32)> {
+    unsafe {
+        let name_wide = wide(&format!("Local\\http-handover-{}", name));
+        match CreateEventW(None, false, false, wide_ptr(&name_wide)) {
+            Ok(h) => Ok(h),
+            Err(err) => Err(("CreateEventW", err.code().0 as u32)),
+        }
+    }
+}
+
+fn signal_event(name: &str) -> Result<(), (&'static str, u32)> {
+    let mut events = EVENTS.lock().map_err(|_| ("Mutex", 0))?;
+    let h = match events.iter().find(|(x, _)| x == name) {
+        Some((_, h)) => HANDLE(*h),
+        None => {
+            let h = open_event(name)?;
+            events.push((String::from(name), h.0));
+            h
+        },
+    };
+
+    unsafe {
+        if !SetEvent(h).as_bool() {
+            return Err(("SetEvent", GetLastError().0));
+        }
+    }
+
+    Ok(())
+}
+
+fn handover_signal(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let mut i = 0;
+    let name = cx.arg_string(&mut i)?;
+    if let Err((hint, err)) = signal_event(&name) {
+        return cx.throw_type_error(format!("{}: {}", hint, err));
+    }
+
+    Ok(cx.undefined())
+}
+
+fn handover_wait(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let name = cx.arg_string(&mut i)?;
+    let timeout = cx.arg_u32(&mut i)?;
+    let h = match open_event(&name) {
+        Ok(h) => h,
+        Err((hint, err)) => return cx.throw_type_error(format!("{}: {}", hint, err)),
+    };
+
+    let h = h.0;
+    let builder = cx.task(move || unsafe {
+        let h = HANDLE(h);
+        let result = WaitForSingleObject(h, timeout);
+        CloseHandle(h);
+
+        result == WAIT_OBJECT_0
+    });
+
+    let promise = builder.promise(move |mut cx, value| {
+        Ok(cx.boolean(value))
+    });
+
+    Ok(promise)
+}
+
+fn http_request_stop(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let mut i = 0;
+    let arc = cx.import::<Request>(&mut i)?;
+    arc.stop();
+
+    Ok(cx.undefined())
+}
+
+fn http_request_drain(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let arc = cx.import::<Request>(&mut i)?;
+    let timeout = Duration::from_millis(cx.arg_u32(&mut i)? as u64);
+    let tx = cx.channel();
+    let (def, promise) = cx.promise();
+    spawn(move || {
+        let start = Instant::now();
+        let mut remaining = arc.metrics.in_flight();
+        while remaining > 0 && start.elapsed() < timeout {
+            sleep(Duration::from_millis(20));
+            remaining = arc.metrics.in_flight();
+        }
+
+        def.settle_with(&tx, move |mut cx| {
+            Ok(cx.number(remaining as f64))
+        });
+    });
+
+    Ok(promise)
+}
+
+pub fn handover_bind(cx: &mut ModuleContext) -> NeonResult<()> {
+    cx.export_function("handover_signal", handover_signal)?;
+    cx.export_function("handover_wait", handover_wait)?;
+    cx.export_function("http_request_stop", http_request_stop)?;
+    cx.export_function("http_request_drain", http_request_drain)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wait(name: &str, timeout: u32) -> bool {
+        unsafe {
+            let h = open_event(name).ok().unwrap();
+            let result = WaitForSingleObject(h, timeout);
+            CloseHandle(h);
+
+            result == WAIT_OBJECT_0
+        }
+    }
+
+    #[test]
+    fn signals_before_waits() {
+        let name = format!("test-{}-ready", std::process::id());
+        signal_event(&name).ok().unwrap();
+        signal_event(&name).ok().unwrap();
+        assert!(wait(&name, 0));
+        assert!(!wait(&name, 0));
+
+        signal_event(&name).ok().unwrap();
+        assert!(wait(&name, 1000));
+    }
+
+    #[test]
+    fn keeps_names_apart() {
+        let pid = std::process::id();
+        let ready = format!("test-{}-a-ready", pid);
+        let demand = format!("test-{}-a-demand", pid);
+        signal_event(&demand).ok().unwrap();
+        assert!(!wait(&ready, 0));
+        assert!(wait(&demand, 0));
+    }
+}
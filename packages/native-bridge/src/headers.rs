@@ -0,0 +1,173 @@
+pub const HEADERS_OFF: u8 = 0;
+pub const HEADERS_STRICT: u8 = 1;
+pub const HEADERS_LENIENT: u8 = 2;
+
+pub fn parse_header_policy(value: &str) -> Option<u8> {
+    match value {
+        "off" => Some(HEADERS_OFF),
+        "strict" => Some(HEADERS_STRICT),
+        "lenient" => Some(HEADERS_LENIENT),
+        _ => None,
+    }
+}
+
+pub fn is_tchar(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+fn is_bad_value(b: u8) -> bool {
+    (b < 0x20 && b != b'\t') || b == 0x7f
+}
+
+// HTTP.sys takes 16-bit lengths, so anything longer would be cut short on the wire.
+fn length_fault(kind: &str, data: &[u8]) -> Option<String> {
+    if data.len() > u16::MAX as usize {
+        return Some(format!("{} is {} bytes, limit is {}", kind, data.len(), u16::MAX));
+    }
+
+    None
+}
+
+pub fn name_fault(name: &[u8]) -> Option<String> {
+    if name.len() < 1 {
+        return Some(String::from("name is empty"));
+    }
+
+    if let Some(fault) = length_fault("name", name) {
+        return Some(fault);
+    }
+
+    let i = name.iter().position(|x| !is_tchar(*x))?;
+    Some(format!("name has invalid character 0x{:02x} at offset {}", name[i], i))
+}
+
+pub fn value_fault(value: &[u8]) -> Option<String> {
+    if let Some(fault) = length_fault("value", value) {
+        return Some(fault);
+    }
+
+    let i = value.iter().position(|x| is_bad_value(*x))?;
+    Some(format!("value has invalid character 0x{:02x} at offset {}", value[i], i))
+}
+
+pub fn sanitize_value(value: &[u8]) -> Vec<u8> {
+    value.iter().map(|x| if is_bad_value(*x) { b' ' } else { *x }).collect()
+}
+
+#[derive(Debug, PartialEq)]
+pub enum HeaderCheck {
+    Keep,
+    Skip,
+    Sanitized(Vec<u8>),
+}
+
+// Never writes to `value`: it usually points into a buffer JS still owns.
+pub fn check_header(policy: u8, label: &str, name: Option<&[u8]>, value: &[u8]) -> Result<HeaderCheck, String> {
+    if policy == HEADERS_OFF {
+        let fault = name.and_then(|x| length_fault("name", x)).or_else(|| length_fault("value", value));
+        return match fault {
+            Some(fault) => Err(format!("Invalid header {:?}: {}", label, fault)),
+            None => Ok(HeaderCheck::Keep),
+        };
+    }
+
+    if let Some(fault) = name.and_then(name_fault) {
+        if policy == HEADERS_STRICT {
+            return Err(format!("Invalid header {:?}: {}", label, fault));
+        }
+
+        return Ok(HeaderCheck::Skip);
+    }
+
+    if let Some(fault) = value_fault(value) {
+        if policy == HEADERS_STRICT {
+            return Err(format!("Invalid header {:?}: {}", label, fault));
+        }
+
+        if value.len() > u16::MAX as usize {
+            return Ok(HeaderCheck::Skip);
+        }
+
+        return Ok(HeaderCheck::Sanitized(sanitize_value(value)));
+    }
+
+    Ok(HeaderCheck::Keep)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn bytes(&mut self) -> Vec<u8> {
+            let len = match self.next() % 16 {
+                0 => u16::MAX as usize + 1 + (self.next() % 4) as usize,
+                _ => (self.next() % 24) as usize,
+            };
+
+            let pool = b"aZ09-_ \t:;\"\x00\x01\x0a\x0d\x7f\x80\xff";
+            (0..len).map(|_| pool[(self.next() % pool.len() as u64) as usize]).collect()
+        }
+    }
+
+    #[test]
+    fn policies() {
+        assert_eq!(parse_header_policy("lenient"), Some(HEADERS_LENIENT));
+        assert_eq!(parse_header_policy("loose"), None);
+        assert_eq!(check_header(HEADERS_STRICT, "X-A", Some(b"X-A"), b"one\ttwo"), Ok(HeaderCheck::Keep));
+        assert_eq!(check_header(HEADERS_LENIENT, "X-A", Some(b"X-A"), b"a\r\nb"), Ok(HeaderCheck::Sanitized(b"a  b".to_vec())));
+        assert_eq!(check_header(HEADERS_LENIENT, "X A", Some(b"X A"), b"a"), Ok(HeaderCheck::Skip));
+        assert_eq!(
+            check_header(HEADERS_STRICT, "X-A", Some(b"X-A"), b"a\nb"),
+            Err(String::from("Invalid header \"X-A\": value has invalid character 0x0a at offset 1"))
+        );
+
+        let long = vec![b'a'; u16::MAX as usize + 1];
+        assert_eq!(check_header(HEADERS_OFF, "X-A", Some(b"X-A"), b"a\nb"), Ok(HeaderCheck::Keep));
+        assert_eq!(
+            check_header(HEADERS_OFF, "X-A", Some(b"X-A"), &long),
+            Err(String::from("Invalid header \"X-A\": value is 65536 bytes, limit is 65535"))
+        );
+    }
+
+    #[test]
+    fn property_checks() {
+        let mut rng = Rng(0x2545f4914f6cdd1d);
+        for _ in 0..2000 {
+            let name = rng.bytes();
+            let value = rng.bytes();
+            let fault = name_fault(&name).or(value_fault(&value));
+
+            let long = name.len() > u16::MAX as usize || value.len() > u16::MAX as usize;
+            assert_eq!(check_header(HEADERS_OFF, "x", Some(&name), &value).is_err(), long);
+
+            match check_header(HEADERS_STRICT, "x", Some(&name), &value) {
+                Ok(result) => {
+                    assert!(fault.is_none());
+                    assert_eq!(result, HeaderCheck::Keep);
+                },
+                Err(_) => assert!(fault.is_some()),
+            }
+
+            match check_header(HEADERS_LENIENT, "x", Some(&name), &value).unwrap() {
+                HeaderCheck::Keep => assert!(fault.is_none()),
+                HeaderCheck::Skip => assert!(name_fault(&name).is_some() || value.len() > u16::MAX as usize),
+                HeaderCheck::Sanitized(copy) => {
+                    assert!(name_fault(&name).is_none());
+                    assert_eq!(copy.len(), value.len());
+                    assert_eq!(value_fault(&copy), None);
+                    assert!(copy.iter().zip(value.iter()).all(|(a, b)| a == b || *a == b' '));
+                },
+            }
+        }
+    }
+}
@@ -1,11 +1,29 @@
+use super::certmap::*;
+use super::cookie::*;
+use super::cors::*;
+use super::headers::*;
+use super::jwt::*;
+use super::limits::*;
+use super::message::*;
+use super::metrics::*;
+use super::negotiate::*;
+use super::ratelimit::*;
+use super::session::*;
 use super::support::*;
-use crate::user::user_groups_internal;
+use super::tcpinfo::*;
+use super::user::groups_to_js;
+use super::user::token_groups_internal;
+use super::user::user_groups_internal;
 use super::win32::*;
 
 use neon::prelude::*;
+use tracing::debug_span;
+use tracing::field::Empty;
+use tracing::trace;
 use neon::types::buffer::*;
 use windows::Win32::Networking::WinSock::AF_INET;
 use windows::Win32::Networking::WinSock::AF_INET6;
+use windows::Win32::Networking::WinSock::SOCKADDR;
 use windows::Win32::Networking::WinSock::SOCKADDR_IN;
 use windows::Win32::Networking::WinSock::SOCKADDR_IN6;
 
@@ -18,20 +36,407 @@ use windows::core::PCWSTR;
 use windows::Win32::Foundation::*;
 use windows::Win32::Networking::HttpServer::*;
 use windows::Win32::Security::Authentication::Identity::*;
+use windows::Win32::System::IO::CancelIoEx;
+use windows::Win32::System::IO::OVERLAPPED;
 
 use std::ffi::*;
 use std::mem::size_of;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
 use std::slice::from_raw_parts;
 use std::slice::from_raw_parts_mut;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU8;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread::spawn;
 
 #[allow(non_upper_case_globals)]
-static ver_init: HTTPAPI_VERSION = HTTPAPI_VERSION {
+pub static ver_init: HTTPAPI_VERSION = HTTPAPI_VERSION {
     HttpApiMajorVersion: 2,
     HttpApiMinorVersion: 0,
 };
 
-fn find_user_token(req: &HTTP_REQUEST_V2) -> Option<Arc<HandleRef>> {
+pub const SEND_BUFFER_DATA: u32 = 1;
+pub const SEND_NAGLING: u32 = 2;
+pub const SEND_RANGES: u32 = 4;
+pub const SEND_GOAWAY: u32 = 8;
+
+pub fn send_options(opaque: bool, more: bool, disconnect: bool, extra: u32) -> u32 {
+    let mut flags = 0;
+    if opaque {
+        flags |= HTTP_SEND_RESPONSE_FLAG_OPAQUE;
+    }
+
+    if more {
+        flags |= HTTP_SEND_RESPONSE_FLAG_MORE_DATA;
+    }
+
+    if disconnect {
+        flags |= HTTP_SEND_RESPONSE_FLAG_DISCONNECT;
+    }
+
+    if extra & SEND_BUFFER_DATA != 0 {
+        flags |= HTTP_SEND_RESPONSE_FLAG_BUFFER_DATA;
+    }
+
+    if extra & SEND_NAGLING != 0 {
+        flags |= HTTP_SEND_RESPONSE_FLAG_ENABLE_NAGLING;
+    }
+
+    if extra & SEND_RANGES != 0 {
+        flags |= HTTP_SEND_RESPONSE_FLAG_PROCESS_RANGES;
+    }
+
+    if extra & SEND_GOAWAY != 0 {
+        flags |= HTTP_SEND_RESPONSE_FLAG_GOAWAY;
+    }
+
+    flags
+}
+
+pub fn cache_policy(ttl: i32) -> Option<HTTP_CACHE_POLICY> {
+    if ttl > 0 {
+        return Some(HTTP_CACHE_POLICY { Policy: HttpCachePolicyTimeToLive, SecondsToLive: ttl as u32 });
+    }
+
+    if ttl < 0 {
+        return Some(HTTP_CACHE_POLICY { Policy: HttpCachePolicyUserInvalidates, SecondsToLive: 0 });
+    }
+
+    None
+}
+
+// Sanitized values are copied into `copies`, which must outlive the request that points at them.
+fn check_slice(policy: u8, label: &str, name: Option<Slice>, value: Slice, copies: &mut Vec<Vec<u8>>) -> Result<Option<Slice>, String> {
+    let result = unsafe {
+        check_header(policy, label, name.map(|x| x.bytes()), value.bytes())?
+    };
+
+    match result {
+        HeaderCheck::Keep => Ok(Some(value)),
+        HeaderCheck::Skip => Ok(None),
+        HeaderCheck::Sanitized(mut copy) => {
+            let slice = Slice::new(&mut copy);
+            copies.push(copy);
+            Ok(Some(slice))
+        }
+    }
+}
+
+// Cookies are only validated when header checks are on, whichever way JS named the header.
+fn check_set_cookie(policy: u8, value: Slice) -> Result<(), String> {
+    if policy == HEADERS_OFF {
+        return Ok(());
+    }
+
+    SetCookie::parse(&label_slice(value)).and_then(|x| x.render()).map(|_| ())
+}
+
+fn label_slice(name: Slice) -> String {
+    unsafe {
+        String::from_utf8_lossy(name.bytes()).into_owned()
+    }
+}
+
+unsafe fn sockaddr(ptr: *const SOCKADDR) -> Option<SocketAddr> {
+    if ptr.is_null() {
+        return None;
+    }
+
+    let addr = &*ptr;
+    if addr.sa_family == AF_INET.0 as u16 {
+        let data = from_raw_parts(ptr as *const u8, size_of::<SOCKADDR_IN>());
+        let port = u16::from_be_bytes([data[2], data[3]]);
+        let ip = Ipv4Addr::new(data[4], data[5], data[6], data[7]);
+        return Some(SocketAddr::new(IpAddr::V4(ip), port));
+    }
+
+    if addr.sa_family == AF_INET6.0 as u16 {
+        let data = from_raw_parts(ptr as *const u8, size_of::<SOCKADDR_IN6>());
+        let port = u16::from_be_bytes([data[2], data[3]]);
+        let mut octets = [0u8; 16];
+        octets.copy_from_slice(&data[8..24]);
+        return Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port));
+    }
+
+    None
+}
+
+fn accept_header_id(kind: AcceptKind) -> HTTP_HEADER_ID {
+    match kind {
+        AcceptKind::Type => HttpHeaderAccept,
+        AcceptKind::Language => HttpHeaderAcceptLanguage,
+        AcceptKind::Encoding => HttpHeaderAcceptEncoding,
+        AcceptKind::Charset => HttpHeaderAcceptCharset,
+    }
+}
+
+fn known_header(info: &HTTP_REQUEST_V1, id: HTTP_HEADER_ID) -> Option<&str> {
+    let header = &info.Headers.KnownHeaders[id.0 as usize];
+    if header.RawValueLength < 1 || header.pRawValue.is_null() {
+        return None;
+    }
+
+    unsafe {
+        let slice = from_raw_parts(header.pRawValue.0, header.RawValueLength as usize);
+        std::str::from_utf8(slice).ok()
+    }
+}
+
+pub fn request_verb(info: &HTTP_REQUEST_V1) -> String {
+    let mut verb = String::new();
+    if info.UnknownVerbLength > 0 {
+        if let Ok(value) = unsafe { info.pUnknownVerb.to_string() } {
+            verb = value;
+        }
+    } else if let Some(name) = VERB_NAMES.get(info.Verb.0 as usize) {
+        verb.push_str(name);
+    }
+
+    verb
+}
+
+impl RequestInfo {
+    pub fn from(req: &HTTP_REQUEST_V2) -> Self {
+        unsafe {
+            let info = &req.Base;
+            let verb = request_verb(info);
+            let mut url = String::new();
+            if info.RawUrlLength > 0 {
+                if let Ok(value) = info.pRawUrl.to_string() {
+                    url = value;
+                }
+            }
+
+            let mut headers = Vec::new();
+            let known = &info.Headers.KnownHeaders;
+            for i in 0..known.len() {
+                let header = &known[i];
+                if header.RawValueLength > 0 {
+                    if let Ok(value) = header.pRawValue.to_string() {
+                        headers.push((String::from(REQUEST_HEADER_NAMES[i]), value));
+                    }
+                }
+            }
+
+            let mut next = info.Headers.pUnknownHeaders;
+            let last = next.add(info.Headers.UnknownHeaderCount as usize);
+            while next < last {
+                let header = &*next;
+                next = next.add(1);
+
+                if header.NameLength > 0 {
+                    if let Ok(key) = header.pName.to_string() {
+                        if let Ok(value) = header.pRawValue.to_string() {
+                            headers.push((key, value));
+                        }
+                    }
+                }
+            }
+
+            Self {
+                id: info.RequestId,
+                group: info.UrlContext,
+                verb,
+                version: (info.Version.MajorVersion, info.Version.MinorVersion),
+                url,
+                headers,
+                body: (info.Flags & HTTP_REQUEST_FLAG_MORE_ENTITY_BODY_EXISTS) != 0,
+                http2: (info.Flags & HTTP_REQUEST_FLAG_HTTP2) != 0,
+                secure: !info.pSslInfo.is_null(),
+                local: sockaddr(info.Address.pLocalAddress),
+                remote: sockaddr(info.Address.pRemoteAddress),
+            }
+        }
+    }
+}
+
+fn new_response() -> Box<HTTP_RESPONSE_V2> {
+    Box::new(HTTP_RESPONSE_V2 {
+        Base: HTTP_RESPONSE_V1 {
+            Flags: 0,
+            Version: HTTP_VERSION {
+                MajorVersion: 0,
+                MinorVersion: 0,
+            },
+            StatusCode: 0,
+            ReasonLength: 0,
+            pReason: PCSTR::null(),
+            Headers: HTTP_RESPONSE_HEADERS {
+                UnknownHeaderCount: 0,
+                pUnknownHeaders: null_mut(),
+                TrailerCount: 0,
+                pTrailers: null_mut(),
+                KnownHeaders: [HTTP_KNOWN_HEADER { RawValueLength: 0, pRawValue: PCSTR::null() }; HttpHeaderResponseMaximum.0 as usize]
+            },
+            EntityChunkCount: 0,
+            pEntityChunks: null_mut(),
+        },
+        ResponseInfoCount: 0,
+        pResponseInfo: null_mut(),
+    })
+}
+
+fn memory_chunk(data: &[u8]) -> HTTP_DATA_CHUNK {
+    HTTP_DATA_CHUNK {
+        DataChunkType: HttpDataChunkFromMemory,
+        Anonymous: HTTP_DATA_CHUNK_0 {
+            FromMemory: HTTP_DATA_CHUNK_0_3 {
+                BufferLength: data.len() as u32,
+                pBuffer: data.as_ptr() as *mut c_void
+            }
+        }
+    }
+}
+
+fn unknown_header(name: &str, value: &str) -> HTTP_UNKNOWN_HEADER {
+    HTTP_UNKNOWN_HEADER {
+        NameLength: name.len() as u16,
+        pName: PCSTR(name.as_ptr()),
+        RawValueLength: value.len() as u16,
+        pRawValue: PCSTR(value.as_ptr())
+    }
+}
+
+fn header_named(header: &HTTP_UNKNOWN_HEADER, name: &str) -> bool {
+    unsafe {
+        let slice = from_raw_parts(header.pName.0, header.NameLength as usize);
+        slice.eq_ignore_ascii_case(name.as_bytes())
+    }
+}
+
+impl Reply {
+    pub fn send<F>(self, req: &Arc<Request>, id: u64, flags: u32, f: F) where F: FnOnce(u32, u32) + Send + 'static {
+        if !self.fits() {
+            f(ERROR_INVALID_PARAMETER.0, 0);
+            return;
+        }
+
+        let Reply { status, reason, mut headers, body } = self;
+        merge_headers(&mut headers, req.cors.take(id));
+
+        let mut response = new_response();
+        let mut unknown = Vec::<HTTP_UNKNOWN_HEADER>::new();
+        let mut extra = Vec::<(i32, Vec<HTTP_KNOWN_HEADER>)>::new();
+        let mut multiple = Vec::<HTTP_MULTIPLE_KNOWN_HEADERS>::new();
+        let mut infos = Vec::<HTTP_RESPONSE_INFO>::new();
+        let mut chunks = Vec::<HTTP_DATA_CHUNK>::new();
+        let base = &mut response.as_mut().Base;
+        base.StatusCode = status;
+        base.Version = HTTP_VERSION {
+            MajorVersion: 1,
+            MinorVersion: 1,
+        };
+
+        base.ReasonLength = reason.len() as u16;
+        base.pReason = PCSTR(reason.as_ptr());
+
+        for (name, value) in headers.iter() {
+            let id = response_header_id(name);
+            if id < 0 {
+                unknown.push(unknown_header(name, value));
+                continue;
+            }
+
+            let next = HTTP_KNOWN_HEADER {
+                RawValueLength: value.len() as u16,
+                pRawValue: PCSTR(value.as_ptr())
+            };
+
+            let first = &mut base.Headers.KnownHeaders[id as usize];
+            if first.RawValueLength == 0 {
+                *first = next;
+            } else if let Some(group) = extra.iter_mut().find(|x| x.0 == id) {
+                group.1.push(next);
+            } else {
+                extra.push((id, vec![*first, next]));
+            }
+        }
+
+        for (id, list) in extra.iter_mut() {
+            base.Headers.KnownHeaders[*id as usize] = HTTP_KNOWN_HEADER::default();
+            multiple.push(HTTP_MULTIPLE_KNOWN_HEADERS {
+                HeaderId: HTTP_HEADER_ID(*id),
+                Flags: 0,
+                KnownHeaderCount: list.len() as u16,
+                KnownHeaders: list.as_mut_ptr()
+            });
+        }
+
+        for i in multiple.iter_mut() {
+            infos.push(HTTP_RESPONSE_INFO {
+                Type: HttpResponseInfoTypeMultipleKnownHeaders,
+                Length: size_of::<HTTP_MULTIPLE_KNOWN_HEADERS>() as u32,
+                pInfo: i as *mut HTTP_MULTIPLE_KNOWN_HEADERS as *mut c_void
+            });
+        }
+
+        if unknown.len() > 0 {
+            base.Headers.UnknownHeaderCount = unknown.len() as u16;
+            base.Headers.pUnknownHeaders = unknown.as_mut_ptr();
+        }
+
+        if body.len() > 0 {
+            chunks.push(memory_chunk(&body));
+            base.EntityChunkCount = 1;
+            base.pEntityChunks = chunks.as_mut_ptr();
+        }
+
+        if infos.len() > 0 {
+            response.ResponseInfoCount = infos.len() as u16;
+            response.pResponseInfo = infos.as_mut_ptr();
+        }
+
+        let ptr = response.as_mut() as *mut HTTP_RESPONSE_V2;
+        let transfer = SendRef((response, reason, headers, body, chunks, unknown, extra, multiple, infos));
+        req.send(id, flags, unsafe { &mut *ptr }, None, move |err, size| {
+            drop(transfer);
+            f(err, size);
+        });
+    }
+}
+
+pub fn send_body<F>(req: &Arc<Request>, id: u64, flags: u32, data: Vec<Vec<u8>>, trailers: Vec<(String, String)>, f: F) where F: FnOnce(u32, u32) + Send + 'static {
+    let mut chunks = Vec::<HTTP_DATA_CHUNK>::new();
+    let mut unknown = Vec::<HTTP_UNKNOWN_HEADER>::new();
+    for block in data.iter() {
+        if block.len() > 0 {
+            chunks.push(memory_chunk(block));
+        }
+    }
+
+    for (name, value) in trailers.iter() {
+        unknown.push(unknown_header(name, value));
+    }
+
+    if unknown.len() > 0 {
+        chunks.push(HTTP_DATA_CHUNK {
+            DataChunkType: HttpDataChunkTrailers,
+            Anonymous: HTTP_DATA_CHUNK_0 {
+                Trailers: HTTP_DATA_CHUNK_0_4 {
+                    TrailerCount: unknown.len() as u16,
+                    pTrailers: unknown.as_mut_ptr()
+                }
+            }
+        });
+    }
+
+    let ptr = chunks.as_mut_ptr();
+    let count = chunks.len();
+    let slice = unsafe { from_raw_parts_mut(ptr, count) };
+    let transfer = SendRef((chunks, unknown, data, trailers));
+    req.send_data(id, flags, slice, move |err, size| {
+        drop(transfer);
+        f(err, size);
+    });
+}
+
+pub fn find_user_token(req: &HTTP_REQUEST_V2) -> Option<Arc<HandleRef>> {
     unsafe {
         let slice = from_raw_parts(req.pRequestInfo, req.RequestInfoCount  as usize);    
         for info in slice {
@@ -47,10 +452,45 @@ fn find_user_token(req: &HTTP_REQUEST_V2) -> Option<Arc<HandleRef>> {
     None
 }
 
-struct Session {
-    queue: HANDLE,
+fn client_cert(req: &HTTP_REQUEST_V2) -> Option<(u32, &[u8])> {
+    unsafe {
+        let ssl = req.Base.pSslInfo;
+        if ssl.is_null() || (*ssl).pClientCertInfo.is_null() {
+            return None;
+        }
+
+        let cert = &*(*ssl).pClientCertInfo;
+        if cert.pCertEncoded.is_null() {
+            return None;
+        }
+
+        Some((cert.CertFlags, from_raw_parts(cert.pCertEncoded, cert.CertEncodedSize as usize)))
+    }
+}
+
+fn request_user_sid(req: &HTTP_REQUEST_V2) -> Option<String> {
+    unsafe {
+        let slice = from_raw_parts(req.pRequestInfo, req.RequestInfoCount  as usize);
+        for info in slice {
+            if info.InfoType == HttpRequestInfoTypeAuth {
+                let auth = &*(info.pInfo as *const HTTP_REQUEST_AUTH_INFO);
+                if auth.AccessToken.is_invalid() {
+                    return None;
+                }
+
+                return token_groups_internal(auth.AccessToken, true).pop().map(|(_, sid)| sid);
+            }
+        }
+    }
+
+    None
+}
+
+pub struct Session {
+    queue: Arc<HandleRef>,
     session: u64,
     urls: u64,
+    prefixes: Mutex<Vec<String>>,
 }
 
 impl Finalize for Session {}
@@ -104,8 +544,16 @@ impl Session {
                 CloseHandle(queue);
                 return Err(("HttpSetUrlGroupProperty", err));
             }
+
+            if !bind_io(queue) {
+                let err = GetLastError().0;
+                HttpCloseUrlGroup(urls);
+                HttpCloseServerSession(session);
+                CloseHandle(queue);
+                return Err(("BindIoCompletionCallback", err));
+            }
    
-            Ok(Self { queue, session, urls })
+            Ok(Self { queue: HandleRef::new(queue), session, urls, prefixes: Mutex::new(Vec::new()) })
         }
     }
 
@@ -155,13 +603,18 @@ impl Session {
         }
     }
 
-    pub fn listen(self: &Arc<Self>, url: &str) -> Result<(), (&str, u32)> {
+    pub fn listen(self: &Arc<Self>, url: &str, group: Option<&str>) -> Result<(), (&str, u32)> {
         unsafe {
             let url_wide = wide(url);
-            let err = HttpAddUrlToUrlGroup(self.urls, wide_ptr(&url_wide), 0, 0);
+            let context = group.map_or(0, url_group);
+            let err = HttpAddUrlToUrlGroup(self.urls, wide_ptr(&url_wide), context, 0);
             if err != 0 {
                 return Err(("HttpAddUrlToUrlGroup", err));
             }
+
+            if let Ok(mut prefixes) = self.prefixes.lock() {
+                prefixes.push(url.to_string());
+            }
     
             Ok(())
         }
@@ -181,24 +634,54 @@ impl Session {
             if err != 0 {
                 return Err(("HttpAddUrlToUrlGroup", err));
             }
+
+            if let Ok(mut prefixes) = self.prefixes.lock() {
+                prefixes.retain(|x| url != "all" && x != url);
+            }
     
             Ok(())
         }
     }
+
+    pub fn registered(&self, url: &str) -> bool {
+        self.prefixes.lock().map(|x| x.iter().any(|x| x == url)).unwrap_or(false)
+    }
+
+    pub fn wait_demand<F>(self: &Arc<Self>, f: F) where F: FnOnce(u32) + Send + 'static {
+        unsafe {
+            let h = &self.queue;
+            let o = h.wrap(move |err, _| f(err));
+            let err = HttpWaitForDemandStart(h.0, o);
+            h.cleanup(o, err);
+        }
+    }
 }
 
 impl Drop for Session {
     fn drop(&mut self) {
+        self.queue.cancel();
+
         unsafe {
             HttpCloseUrlGroup(self.urls);
             HttpCloseServerSession(self.session);
-            CloseHandle(self.queue);
         }
     }
 }
 
-struct Request {
+pub struct Request {
     arc: Arc<HandleRef>,
+    pub limits: BodyLimits,
+    pub rates: RateLimiter,
+    pub cors: Cors,
+    pub metrics: Metrics,
+    pub jwt: JwtAuth,
+    pub certs: CertMap,
+    pub negotiate: Negotiation,
+    pub cookies: Cookies,
+    pub sessions: Sessions,
+    stopped: AtomicBool,
+    receiving: Mutex<Vec<Arc<AtomicUsize>>>,
+    pub headers: AtomicU8,
 }
 
 impl Finalize for Request {}
@@ -227,15 +710,57 @@ impl Request {
                 return Err(("BindIoCompletionCallback", err));                
             }
 
-            Ok(Self { arc: HandleRef::new(queue) })
+            Ok(Self {
+                arc: HandleRef::new(queue),
+                limits: BodyLimits::new(),
+                rates: RateLimiter::new(),
+                cors: Cors::new(),
+                metrics: Metrics::new(name),
+                jwt: JwtAuth::new(),
+                certs: CertMap::new(),
+                negotiate: Negotiation::new(),
+                cookies: Cookies::new(),
+                sessions: Sessions::new(),
+                stopped: AtomicBool::new(false),
+                receiving: Mutex::new(Vec::new()),
+                headers: AtomicU8::new(HEADERS_STRICT),
+            })
         }
     }
 
+    // Drops the state kept for a request, however it ended.
+    pub fn forget(&self, id: u64) {
+        self.limits.forget(id);
+        self.cors.forget(id);
+        self.jwt.forget(id);
+        self.certs.forget(id);
+        self.metrics.forget(id);
+    }
+
     pub fn cancel<F>(self: &Arc<Self>, id: u64, f: F) where F: FnOnce(u32) + Send + 'static {
+        self.forget(id);
+
+        let span = debug_span!(parent: None, "cancel", id);
+        let _guard = span.enter();
+
         unsafe {
             let h = &self.arc;
             let o = h.wrap(move |err, _| f(err));
             let err = HttpCancelHttpRequest(h.0, id, o);
+            trace!(err, "issue");
+            h.cleanup(o, err);
+        }
+    }
+
+    pub fn wait_disconnect<F>(self: &Arc<Self>, connection: u64, f: F) where F: FnOnce(u32) + Send + 'static {
+        let span = debug_span!(parent: None, "wait_disconnect", connection);
+        let _guard = span.enter();
+
+        unsafe {
+            let h = &self.arc;
+            let o = h.wrap(move |err, _| f(err));
+            let err = HttpWaitForDisconnect(h.0, connection, o);
+            trace!(err, "issue");
             h.cleanup(o, err);
         }
     }
@@ -250,13 +775,47 @@ impl Request {
                 size = 2048;
             }
 
+            let req = self.clone();
+            let f = move |err: u32, vec: Vec<u8>, result: &'static SendRef<HTTP_REQUEST_V2>| {
+                if err == 0 {
+                    let base = &result.0.Base;
+                    req.metrics.received(base.RequestId, base.UrlContext, &request_verb(base));
+                } else {
+                    req.metrics.error(err);
+                }
+
+                f(err, vec, result);
+            };
+
+            let span = debug_span!(parent: None, "receive", size, id = Empty);
+            let _guard = span.enter();
+
             let h0 = &self.arc;
             let h1 = h0.clone();
             let vec = Vec::<u8>::with_capacity(size as usize);
             let ptr = vec.as_ptr() as *mut HTTP_REQUEST_V2;
             let flags = HTTP_RECEIVE_HTTP_REQUEST_FLAGS(0);
+            let slot = Arc::new(AtomicUsize::new(0));
+            if !self.track(&slot) {
+                let result = &*(vec.as_ptr() as *const SendRef<HTTP_REQUEST_V2>);
+                f(ERROR_OPERATION_ABORTED.0, vec, result);
+                return;
+            }
+
+            let req = self.clone();
+            let tracked = slot.clone();
+            let span = span.clone();
             let o = h0.wrap(move |err, size| {
+                req.untrack(&tracked);
+
                 let result = &*(vec.as_ptr() as *const SendRef<HTTP_REQUEST_V2>);
+                if err == 0 || err == ERROR_MORE_DATA.0 {
+                    span.record("id", &result.0.Base.RequestId);
+                }
+
+                let _guard = span.enter();
+                trace!(err, size, "complete");
+
                 if err == ERROR_MORE_DATA.0 {
                     // Make sure we close the handle
                     find_user_token(&result.0);
@@ -264,63 +823,304 @@ impl Request {
                     let id = result.0.Base.RequestId;
                     let vec = Vec::<u8>::with_capacity(size as usize);
                     let ptr = vec.as_ptr() as *mut HTTP_REQUEST_V2;
+                    let slot = Arc::new(AtomicUsize::new(0));
+                    if !req.track(&slot) {
+                        // The request is already ours, so a stopped receiver has to cancel it rather than leave it queued.
+                        HttpCancelHttpRequest(h1.0, id, None);
+                        let result = &*(vec.as_ptr() as *const SendRef<HTTP_REQUEST_V2>);
+                        f(ERROR_OPERATION_ABORTED.0, vec, result);
+                        return;
+                    }
+
+                    let owner = req.clone();
+                    let tracked = slot.clone();
+                    let h2 = h1.clone();
+                    let more = debug_span!(parent: None, "receive", size, id);
                     let o = h1.wrap(move |err, _| {
+                        let _guard = more.enter();
+                        trace!(err, "complete more");
+
+                        req.untrack(&tracked);
+                        if err == ERROR_OPERATION_ABORTED.0 {
+                            HttpCancelHttpRequest(h2.0, id, None);
+                        }
+
                         let result = &*(vec.as_ptr() as *const SendRef<HTTP_REQUEST_V2>);
                         f(err, vec, result);
                     });
 
+                    slot.store(o as usize, Relaxed);
+
                     let err = HttpReceiveHttpRequest(h1.0, id, flags, ptr, size, None, o);
+                    trace!(err, id, size, "issue more");
                     h1.cleanup(o, err);
+
+                    if owner.stopped() {
+                        owner.stop();
+                    }
                 } else {
                     f(err, vec, result);
                 }
             });
 
+            slot.store(o as usize, Relaxed);
+
             let err = HttpReceiveHttpRequest(h0.0, 0, flags, ptr, size, None, o);
+            trace!(err, "issue");
             h0.cleanup(o, err);
+
+            if self.stopped.load(Relaxed) {
+                self.stop();
+            }
+        }
+    }
+
+    fn track(&self, slot: &Arc<AtomicUsize>) -> bool {
+        match self.receiving.lock() {
+            Ok(mut list) if !self.stopped.load(Relaxed) => {
+                list.push(slot.clone());
+                true
+            },
+            _ => false,
         }
     }
 
-    pub fn receive_data<F>(self: &Arc<Self>, id: u64, slice: &mut [u8], f: F) where F: FnOnce(u32, u32) + Send + 'static {
+    fn untrack(&self, slot: &Arc<AtomicUsize>) {
+        if let Ok(mut list) = self.receiving.lock() {
+            list.retain(|x| !Arc::ptr_eq(x, slot));
+        }
+    }
+
+    pub fn stop(&self) {
+        if let Ok(list) = self.receiving.lock() {
+            self.stopped.store(true, Relaxed);
+            for slot in list.iter() {
+                let ptr = slot.load(Relaxed) as *const OVERLAPPED;
+                if !ptr.is_null() {
+                    unsafe {
+                        CancelIoEx(self.arc.0, Some(ptr));
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.stopped.load(Relaxed)
+    }
+
+    fn admit(&self, req: &HTTP_REQUEST_V2) -> Option<(u64, Reply)> {
+        if !self.limits.active() && !self.rates.active() && !self.cors.active() && !self.metrics.active() && !self.jwt.active() && !self.certs.active() {
+            return None;
+        }
+
+        let info = RequestInfo::from(req);
+        if self.rates.active() {
+            let user = if self.rates.wants_user() { request_user_sid(req) } else { None };
+            if let Some(reply) = self.rates.admit(&info, user.as_deref()) {
+                return Some((info.id, reply));
+            }
+        }
+
+        if info.verb == "GET" && self.metrics.serves(info.path(), info.remote) {
+            let text = self.metrics.snapshot(pending_io(), &self.limits.stats).prometheus();
+            let reply = Reply::new(200, "OK")
+                .header("Content-Type", "text/plain; version=0.0.4")
+                .header("Content-Length", &text.len().to_string())
+                .body(text.into_bytes());
+
+            return Some((info.id, reply));
+        }
+
+        if let Some(reply) = self.cors.admit(&info) {
+            return Some((info.id, reply));
+        }
+
+        if let Some(reply) = self.jwt.admit(&info) {
+            return Some((info.id, reply));
+        }
+
+        if let Some(reply) = self.certs.admit(info.id, client_cert(req)) {
+            return Some((info.id, reply));
+        }
+
+        self.limits.admit(&info).map(|reply| (info.id, reply))
+    }
+
+    pub fn receive_admitted<F>(self: &Arc<Self>, size: u32, f: F) where F: FnOnce(u32, Vec<u8>, &'static SendRef<HTTP_REQUEST_V2>) + Send + 'static {
+        let req = self.clone();
+        self.receive(size, move |err, vec, result| {
+            if err == 0 {
+                if let Some((id, reply)) = req.admit(&result.0) {
+                    // Make sure we close the handle
+                    find_user_token(&result.0);
+                    drop(vec);
+
+                    let flags = if reply.status < 400 { 0 } else { HTTP_SEND_RESPONSE_FLAG_DISCONNECT };
+                    reply.send(&req, id, flags, |_, _| {});
+                    req.receive_admitted(size, f);
+                    return;
+                }
+            }
+
+            f(err, vec, result);
+        });
+    }
+
+    // The slice must stay valid until `f` runs; HTTP.sys writes into it after this returns.
+    pub fn receive_data<F>(self: &Arc<Self>, id: u64, slice: Slice, f: F) where F: FnOnce(u32, u32) + Send + 'static {
         unsafe {
+            let mut len = slice.len();
+            if let Some(remaining) = self.limits.remaining(id) {
+                len = len.min((remaining as usize).saturating_add(1));
+            }
+
+            let span = debug_span!(parent: None, "receive_data", id, len);
+            let _guard = span.enter();
+
+            let req = self.clone();
             let h = &self.arc;
-            let o = h.wrap(f);
-            let err = HttpReceiveRequestEntityBody(h.0, id, 0, slice.as_mut_ptr() as *mut c_void, slice.len() as u32, None, o);
+            let o = h.wrap(move |err, size| {
+                req.metrics.read(id, size);
+                if err != 0 && err != ERROR_HANDLE_EOF.0 {
+                    req.forget(id);
+                }
+
+                if err == 0 && !req.limits.consume(id, size as u64) {
+                    too_large().send(&req, id, HTTP_SEND_RESPONSE_FLAG_DISCONNECT, |_, _| {});
+                    f(ERROR_FILE_TOO_LARGE.0, 0);
+                    return;
+                }
+
+                f(err, size);
+            });
+
+            let err = HttpReceiveRequestEntityBody(h.0, id, 0, slice.mut_ptr() as *mut c_void, len as u32, None, o);
+            trace!(err, "issue");
             h.cleanup(o, err);
         }
     }
 
-    pub fn send<F>(self: &Arc<Self>, id: u64, flags: u32, response: &mut HTTP_RESPONSE_V2, f: F) where F: FnOnce(u32, u32) + Send + 'static {
+    pub fn send<F>(self: &Arc<Self>, id: u64, flags: u32, response: &mut HTTP_RESPONSE_V2, mut cache: Option<HTTP_CACHE_POLICY>, f: F) where F: FnOnce(u32, u32) + Send + 'static {
+        let last = flags & HTTP_SEND_RESPONSE_FLAG_MORE_DATA == 0;
+
+        let status = response.Base.StatusCode;
+        let span = debug_span!(parent: None, "send", id, flags, status);
+        let _guard = span.enter();
+
         unsafe {
             let h = &self.arc;
             let mut size = Box::new(0u32);
             let size_ptr = size.as_mut() as *mut u32;
-            let o = h.wrap(move |err, _| f(err, *size));
-            let err = HttpSendHttpResponse(h.0, id, flags, response, null_mut(), size_ptr, None, 0, o, null_mut());
+            let cache_ptr = cache.as_mut().map_or(null_mut(), |x| x as *mut _);
+            let req = self.clone();
+            let o = h.wrap(move |err, _| {
+                req.metrics.wrote(id, *size);
+                if err != 0 {
+                    req.forget(id);
+                } else {
+                    req.metrics.status(id, status);
+                    if last {
+                        req.metrics.finish(id);
+                        req.forget(id);
+                    }
+                }
+
+                f(err, *size)
+            });
+
+            let err = HttpSendHttpResponse(h.0, id, flags, response, cache_ptr, size_ptr, None, 0, o, null_mut());
+            trace!(err, "issue");
             h.cleanup(o, err);
         }
     }
 
     pub fn send_data<F>(self: &Arc<Self>, id: u64, flags: u32, chunks: &mut [HTTP_DATA_CHUNK], f: F) where F: FnOnce(u32, u32) + Send + 'static {
+        let last = flags & HTTP_SEND_RESPONSE_FLAG_MORE_DATA == 0;
+
+        let chunks_len = chunks.len();
+        let span = debug_span!(parent: None, "send_data", id, flags, chunks = chunks_len);
+        let _guard = span.enter();
+
         unsafe {
             let h = &self.arc;
             let mut size = Box::new(0u32);
             let size_ptr = size.as_mut() as *mut u32;
-            let o = h.wrap(move |err, _| f(err, *size));
+            let req = self.clone();
+            let o = h.wrap(move |err, _| {
+                req.metrics.wrote(id, *size);
+                if err != 0 {
+                    req.forget(id);
+                } else if last {
+                    req.metrics.finish(id);
+                    req.forget(id);
+                }
+
+                f(err, *size)
+            });
+
             let err = HttpSendResponseEntityBody(h.0, id, flags, Some(chunks), size_ptr, None, 0, o, null_mut());
+            trace!(err, "issue");
             h.cleanup(o, err);
         }
     }
 
-    pub fn push(&self, id: u64, verb: i32, path: *const u16, query: *const u8, headers: *const HTTP_REQUEST_HEADERS ) -> Result<(), (&str, u32)> {
+    pub fn reset<F>(self: &Arc<Self>, id: u64, code: u32, f: F) where F: FnOnce(u32) + Send + 'static {
+        self.forget(id);
+
         unsafe {
-            let arc = self.arc.clone();
-            let mut query_opt: Option<PCSTR> = None;
-            let tail = query.add(1);
-            if *tail != 0 {
-                query_opt = Some(PCSTR(tail))
+            let h = &self.arc;
+            let info = Box::new(HTTP_REQUEST_PROPERTY_STREAM_ERROR { ErrorCode: code });
+            let ptr = info.as_ref() as *const HTTP_REQUEST_PROPERTY_STREAM_ERROR as *const c_void;
+            let size = size_of::<HTTP_REQUEST_PROPERTY_STREAM_ERROR>() as u32;
+            let o = h.wrap(move |err, _| {
+                drop(info);
+                f(err);
+            });
+
+            let err = HttpSetRequestProperty(h.0, id, HttpRequestPropertyStreamError, Some(ptr), size, o);
+            h.cleanup(o, err);
+        }
+    }
+
+    pub fn query<T: Default>(&self, id: u64, prop: HTTP_REQUEST_PROPERTY) -> Result<T, (&'static str, u32)> {
+        unsafe {
+            let mut result = T::default();
+            let ptr = &mut result as *mut T as *mut c_void;
+            let size = size_of::<T>() as u32;
+            let err = HttpQueryRequestProperty(self.arc.0, id, prop, null(), 0, ptr, size, null_mut(), null_mut());
+            if err != 0 {
+                return Err(("HttpQueryRequestProperty", err));
             }
 
+            Ok(result)
+        }
+    }
+
+    pub fn flush<F>(self: &Arc<Self>, url: &str, recursive: bool, f: F) where F: FnOnce(u32) + Send + 'static {
+        unsafe {
+            let h = &self.arc;
+            let url_wide = wide(url);
+            let url_ptr = wide_ptr(&url_wide);
+            let mut flags = 0;
+            if recursive {
+                flags |= HTTP_FLUSH_RESPONSE_FLAG_RECURSIVE;
+            }
+
+            let o = h.wrap(move |err, _| {
+                drop(url_wide);
+                f(err);
+            });
+
+            let err = HttpFlushResponseCache(h.0, url_ptr, flags, o);
+            h.cleanup(o, err);
+        }
+    }
+
+    pub fn push(&self, id: u64, verb: i32, path: *const u16, query: Option<*const u8>, headers: *const HTTP_REQUEST_HEADERS ) -> Result<(), (&str, u32)> {
+        unsafe {
+            let arc = self.arc.clone();
+            let query_opt = query.map(|x| PCSTR(x));
             let err = HttpDeclarePush(arc.0, id, HTTP_VERB(verb), PCWSTR(path), query_opt, Some(headers));
             if err != 0 {
                 return Err(("HttpDeclarePush", err));
@@ -365,7 +1165,12 @@ fn http_session_listen(mut cx: FunctionContext) -> JsResult<JsUndefined> {
     let mut i = 0;
     let arc = cx.import::<Session>(&mut i)?;
     let url = cx.arg_string(&mut i)?;
-    match arc.listen(&url) {
+    let mut group = None;
+    if cx.arg_opt(&mut i) {
+        group = Some(cx.arg_string(&mut i)?);
+    }
+
+    match arc.listen(&url, group.as_deref()) {
         Ok(()) => Ok(cx.undefined()),
         Err((hint, err)) => cx.throw_type_error(format!("{}: {}", hint, err))
     } 
@@ -381,6 +1186,20 @@ fn http_session_release(mut cx: FunctionContext) -> JsResult<JsUndefined> {
     } 
 }
 
+fn http_session_wait_demand(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let arc = cx.import::<Session>(&mut i)?;
+    let tx = cx.channel();
+    let (def, promise) = cx.promise();
+    arc.wait_demand(move |err| {
+        def.settle_with(&tx, move |mut cx| {
+            Ok(cx.number(err))
+        });
+    });
+
+    Ok(promise)
+}
+
 fn http_session_close(mut cx: FunctionContext) -> JsResult<JsUndefined> {
     cx.dispose::<Session>(0)?;
     Ok(cx.undefined())
@@ -401,166 +1220,724 @@ fn http_request_cancel(mut cx: FunctionContext) -> JsResult<JsPromise> {
     Ok(promise)
 }
 
+fn http_request_reset(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let arc = cx.import::<Request>(&mut i)?;
+    let id = cx.arg_u64(&mut i)?;
+    let code = cx.arg_u32(&mut i)?;
+    let tx = cx.channel();
+    let (def, promise) = cx.promise();
+    arc.reset(id, code, move |err| {
+        def.settle_with(&tx, move |mut cx| {
+            Ok(cx.number(err))
+        });
+    });
+
+    Ok(promise)
+}
+
+fn http_request_flush(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let arc = cx.import::<Request>(&mut i)?;
+    let url = cx.arg_string(&mut i)?;
+    let recursive = cx.arg_bool(&mut i)?;
+    let tx = cx.channel();
+    let (def, promise) = cx.promise();
+    arc.flush(&url, recursive, move |err| {
+        def.settle_with(&tx, move |mut cx| {
+            Ok(cx.number(err))
+        });
+    });
+
+    Ok(promise)
+}
+
 fn http_request_receive(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let mut i = 0;
     let arc = cx.import::<Request>(&mut i)?;
     let size = cx.arg_u32(&mut i)?;
     let tx = cx.channel();
     let (def, promise) = cx.promise();
-    arc.receive(size, move |err, vec, result| {
+    let req = arc.clone();
+    arc.receive_admitted(size, move |err, vec, result| {
         let mut user_opt: Option<Arc<HandleRef>> = None;
+        let mut identity_opt = None;
+        let mut negotiated_opt = None;
+        let mut cookies = Vec::new();
+        let mut trusted_opt = None;
+        let mut binding = None;
+        let mut deferred = false;
         if err == 0 || err == ERROR_MORE_DATA.0 {
             user_opt = find_user_token(&result.0);
         }
 
-        def.settle_with(&tx, move |mut cx| {
+        if err == 0 {
+            let id = result.0.Base.RequestId;
+            deferred = req.limits.deferred(id);
+            identity_opt = req.jwt.take(id).or_else(|| req.certs.take(id));
+            negotiated_opt = req.negotiate.select(|kind| known_header(&result.0.Base, accept_header_id(kind)));
+            if let Some(header) = known_header(&result.0.Base, HttpHeaderCookie) {
+                cookies = parse_cookies(header);
+                trusted_opt = req.cookies.trusted(&cookies);
+            }
+
+            if req.sessions.active() {
+                let mut user = None;
+                if req.sessions.wants_user() {
+                    let sid = request_user_sid(&result.0).or_else(|| identity_opt.as_ref().and_then(|x| x.sid.clone()));
+                    user = match (sid, &identity_opt) {
+                        (Some(sid), _) => Some(format!("sid:{}", sid)),
+                        (None, Some(identity)) => Some(format!("{}:{}", identity.kind, identity.sub)),
+                        (None, None) => None,
+                    };
+                }
+
+                binding = Some(user);
+            }
+        }
+
+        let settle = move |session_opt: Option<(String, SessionRecord)>, cookies: Vec<(String, String)>, trusted_opt: Option<Vec<(String, String)>>| def.settle_with(&tx, move |mut cx| {
             let info = &result.0.Base;
             let obj = cx.empty_object();
             let js_err = cx.number(err);
             obj.set(&mut cx, "code", js_err)?;
 
-            if err != 0 && err != ERROR_MORE_DATA.0 {
-                return Ok(obj);
-            }
+            if err != 0 && err != ERROR_MORE_DATA.0 {
+                return Ok(obj);
+            }
+
+            let js_id = cx.boxed(info.RequestId);
+            obj.set(&mut cx, "id", js_id)?;
+
+            if err != 0 {
+                return Ok(obj);
+            }
+
+            let js_connection = cx.boxed(info.ConnectionId);
+            obj.set(&mut cx, "connection", js_connection)?;
+
+            let js_verb = cx.number(info.Verb.0);
+            obj.set(&mut cx, "verb", js_verb)?;
+
+            let ver = &info.Version;
+            let version_str = format!("{}.{}", ver.MajorVersion, ver.MinorVersion);
+            let js_version = cx.string(version_str);
+            obj.set(&mut cx, "version", js_version)?;
+
+            let body = (info.Flags & HTTP_REQUEST_FLAG_MORE_ENTITY_BODY_EXISTS) != 0;
+            let js_body = cx.boolean(body);
+            obj.set(&mut cx, "body", js_body)?;
+
+            let http2 = (info.Flags & HTTP_REQUEST_FLAG_HTTP2) != 0;
+            let js_http2 = cx.boolean(http2);
+            obj.set(&mut cx, "http2", js_http2)?;
+           
+            unsafe {
+                if info.UnknownVerbLength > 0 {
+                    if let Ok(value) = info.pUnknownVerb.to_string() {
+                        let js_custom_verb = cx.string(value);
+                        obj.set(&mut cx, "customVerb", js_custom_verb)?;            
+                    }
+                }
+
+                if info.RawUrlLength > 0 {
+                    if let Ok(value) = info.pRawUrl.to_string() {
+                        let js_url = cx.string(value);
+                        obj.set(&mut cx, "url", js_url)?;            
+                    }
+                }
+
+                let js_known = cx.empty_array();
+                obj.set(&mut cx, "knownHeaders", js_known)?;
+
+                let known = &info.Headers.KnownHeaders;
+                for i in 0..known.len() {
+                    let header = &known[i];
+                    if header.RawValueLength > 0 {
+                        if let Ok(value) = header.pRawValue.to_string() {
+                            let key = format!("{}", i);
+                            let js_value = cx.string(value);
+                            js_known.set(&mut cx, key.as_str(), js_value)?;
+                        }
+                    }
+                }
+
+                let mut i = 0;
+                let js_unknown = cx.empty_array();
+                obj.set(&mut cx, "unknownHeaders", js_unknown)?;
+                
+                let mut next = info.Headers.pUnknownHeaders;
+                let last = next.add(info.Headers.UnknownHeaderCount as usize);
+                while next < last {
+                    let header = &*next;
+                    next = next.add(1);
+
+                    if header.NameLength > 0 && header.RawValueLength > 0 {
+                        if let Ok(key) = header.pName.to_string() {
+                            if let Ok(value) = header.pRawValue.to_string() {
+                                let js_key = cx.string(key);
+                                js_unknown.set(&mut cx, i, js_key)?;
+                                i += 1;
+
+                                let js_value = cx.string(value);
+                                js_unknown.set(&mut cx, i, js_value)?;
+                                i += 1;
+                            }
+                        }
+                    }
+                }
+
+                let addr = &*info.Address.pLocalAddress;
+                let addr_ptr = info.Address.pLocalAddress as *const u8;
+                if addr.sa_family == AF_INET.0 as u16 {
+                    let size = size_of::<SOCKADDR_IN>();
+                    let mut js_addr = cx.buffer(size)?;
+                    copy(addr_ptr, js_addr.as_mut_slice(&mut cx).as_mut_ptr(), size);
+                    obj.set(&mut cx, "sockaddr", js_addr)?;
+                }
+
+                if addr.sa_family == AF_INET6.0 as u16 {
+                    let size = size_of::<SOCKADDR_IN6>();
+                    let mut js_addr = cx.buffer(size)?;
+                    copy(addr_ptr, js_addr.as_mut_slice(&mut cx).as_mut_ptr(), size);
+                    obj.set(&mut cx, "sockaddr", js_addr)?;
+                }
+            }
+
+            if let Some(timing) = request_timing(&result.0) {
+                let js_timing = timing_to_js(&mut cx, &timing)?;
+                obj.set(&mut cx, "timing", js_timing)?;
+            }
+
+            if let Some(user) = user_opt {
+                let js_user_sid = user_groups_internal(&mut cx, user.0, true)?;
+                let js_user = cx.boxed(RefCell::new(Some(user)));
+                obj.set(&mut cx, "user", js_user)?;
+                obj.set(&mut cx, "user_sid", js_user_sid)?;
+            } else if let Some(identity) = identity_opt {
+                if let Some(sid) = &identity.sid {
+                    let js_user_sid = cx.string(sid);
+                    obj.set(&mut cx, "user_sid", js_user_sid)?;
+                }
+
+                let js_identity = cx.empty_object();
+                let js_kind = cx.string(identity.kind);
+                js_identity.set(&mut cx, "kind", js_kind)?;
+
+                let js_subject = cx.string(&identity.sub);
+                js_identity.set(&mut cx, "subject", js_subject)?;
+                obj.set(&mut cx, "identity", js_identity)?;
+
+                let js_groups = groups_to_js(&mut cx, &identity.groups)?;
+                obj.set(&mut cx, "groups", js_groups)?;
+
+                let js_claims = cx.string(&identity.claims);
+                obj.set(&mut cx, "claims", js_claims)?;
+            }
+
+            if let Some(negotiated) = negotiated_opt {
+                let js_negotiated = negotiated_to_js(&mut cx, &negotiated)?;
+                obj.set(&mut cx, "negotiated", js_negotiated)?;
+            }
+
+            if cookies.len() > 0 {
+                let js_cookies = pairs_to_js(&mut cx, &cookies)?;
+                obj.set(&mut cx, "cookies", js_cookies)?;
+            }
+
+            if let Some(trusted) = trusted_opt {
+                let js_trusted = pairs_to_js(&mut cx, &trusted)?;
+                obj.set(&mut cx, "signedCookies", js_trusted)?;
+            }
+
+            if let Some((id, record)) = session_opt {
+                let js_session = session_to_js(&mut cx, &id, &record)?;
+                obj.set(&mut cx, "session", js_session)?;
+            }
+
+            if deferred {
+                let js_deferred = cx.boolean(true);
+                obj.set(&mut cx, "deferred", js_deferred)?;
+            }
+
+            drop(vec);
+            Ok(obj)
+        });
+
+        // Session files can be held by another process for a while, so they are never read on the
+        // completion thread.
+        match binding {
+            Some(user) => {
+                let req = req.clone();
+                spawn(move || {
+                    let session_opt = req.sessions.bind(&cookies, trusted_opt.as_deref(), user.as_deref());
+                    settle(session_opt, cookies, trusted_opt);
+                });
+            },
+            None => settle(None, cookies, trusted_opt),
+        }
+    });
+    
+    Ok(promise)
+}
+
+fn http_request_receive_data(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let arc = cx.import::<Request>(&mut i)?;
+    let id = cx.arg_u64(&mut i)?;
+    let block = cx.arg_block(&mut i)?;
+    let tx = cx.channel();
+    let (def, promise) = cx.promise();
+    arc.receive_data(id, block.whole(), move |err, size| {
+        def.settle_with(&tx, move |mut cx| {
+            block.release(&mut cx);
+
+            let obj = cx.empty_object();
+            let js_err = cx.number(err);
+            obj.set(&mut cx, "code", js_err)?;
+
+            let js_size = cx.number(size);
+            obj.set(&mut cx, "size", js_size)?;
+
+            let js_eof = cx.boolean(err == ERROR_HANDLE_EOF.0);
+            obj.set(&mut cx, "eof", js_eof)?;
+
+            let js_limit = cx.boolean(err == ERROR_FILE_TOO_LARGE.0);
+            obj.set(&mut cx, "limit", js_limit)?;
+
+            Ok(obj)
+        });
+    });
+
+    Ok(promise)
+}
+
+fn http_request_limits(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let mut i = 0;
+    let arc = cx.import::<Request>(&mut i)?;
+    let max = cx.arg_u32(&mut i)? as u64;
+    let mut expect = EXPECT_ACCEPT;
+    let mut group = 0;
+    if cx.arg_opt(&mut i) {
+        let value = cx.arg_string(&mut i)?;
+        expect = match parse_expect(&value) {
+            Some(expect) => expect,
+            None => return cx.throw_type_error(format!("Unknown expect policy: {}", value)),
+        };
+    }
+
+    if cx.arg_opt(&mut i) {
+        group = url_group(&cx.arg_string(&mut i)?);
+    }
+
+    arc.limits.config(group, max, expect);
+    Ok(cx.undefined())
+}
+
+fn http_request_limit(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let mut i = 0;
+    let arc = cx.import::<Request>(&mut i)?;
+    let id = cx.arg_u64(&mut i)?;
+    let max = cx.arg_u32(&mut i)? as u64;
+    arc.limits.limit(id, max);
+
+    Ok(cx.undefined())
+}
+
+fn http_request_rate_limit(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let mut i = 0;
+    let arc = cx.import::<Request>(&mut i)?;
+    let rate = cx.arg_f64(&mut i)?;
+    let burst = cx.arg_f64(&mut i)?;
+    let mut key = RateKey::Ip;
+    if cx.arg_opt(&mut i) {
+        let value = cx.arg_string(&mut i)?;
+        key = match parse_rate_key(&value) {
+            Some(key) => key,
+            None => return cx.throw_type_error(format!("Unknown rate limit key: {}", value)),
+        };
+    }
+
+    let mut group = 0;
+    if cx.arg_opt(&mut i) {
+        group = url_group(&cx.arg_string(&mut i)?);
+    }
+
+    arc.rates.config(group, rate, burst, key);
+    Ok(cx.undefined())
+}
+
+fn http_request_rate_lists(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let mut i = 0;
+    let arc = cx.import::<Request>(&mut i)?;
+    let allow = cx.arg_strings(&mut i)?;
+    let deny = cx.arg_strings(&mut i)?;
+    let allow = match parse_cidrs(&allow) {
+        Ok(list) => list,
+        Err(err) => return cx.throw_type_error(err),
+    };
+
+    let deny = match parse_cidrs(&deny) {
+        Ok(list) => list,
+        Err(err) => return cx.throw_type_error(err),
+    };
+
+    arc.rates.lists(allow, deny);
+    Ok(cx.undefined())
+}
+
+fn http_request_rate_stats(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let mut i = 0;
+    let arc = cx.import::<Request>(&mut i)?;
+    let stats = &arc.rates.stats;
+    let obj = cx.empty_object();
+    let js_allowed = cx.number(stats.allowed.load(Relaxed) as f64);
+    obj.set(&mut cx, "allowed", js_allowed)?;
+
+    let js_limited = cx.number(stats.limited.load(Relaxed) as f64);
+    obj.set(&mut cx, "limited", js_limited)?;
+
+    let js_denied = cx.number(stats.denied.load(Relaxed) as f64);
+    obj.set(&mut cx, "denied", js_denied)?;
+
+    let js_clients = cx.number(arc.rates.clients() as f64);
+    obj.set(&mut cx, "clients", js_clients)?;
+
+    Ok(obj)
+}
+
+fn http_request_cors(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let mut i = 0;
+    let arc = cx.import::<Request>(&mut i)?;
+    let origins = cx.arg_strings(&mut i)?;
+    let methods = cx.arg_strings(&mut i)?;
+    let headers = cx.arg_strings(&mut i)?;
+    let expose = cx.arg_strings(&mut i)?;
+    let credentials = cx.arg_bool(&mut i)?;
+    let max_age = cx.arg_i32(&mut i)?;
+    let mut group = 0;
+    if cx.arg_opt(&mut i) {
+        group = url_group(&cx.arg_string(&mut i)?);
+    }
+
+    if origins.len() < 1 {
+        arc.cors.config(group, None);
+        return Ok(cx.undefined());
+    }
+
+    let origins = match origins.iter().map(|x| OriginRule::parse(x)).collect::<Result<Vec<_>, _>>() {
+        Ok(origins) => origins,
+        Err(err) => return cx.throw_type_error(err),
+    };
+
+    let policy = CorsPolicy {
+        origins,
+        methods,
+        headers,
+        expose,
+        credentials,
+        max_age: if max_age < 0 { None } else { Some(max_age as u32) },
+    };
+
+    if let Err(err) = policy.check() {
+        return cx.throw_type_error(err);
+    }
+
+    arc.cors.config(group, Some(policy));
+    Ok(cx.undefined())
+}
+
+fn http_request_metrics(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let mut i = 0;
+    let arc = cx.import::<Request>(&mut i)?;
+    let snapshot = arc.metrics.snapshot(pending_io(), &arc.limits.stats);
+    let obj = cx.empty_object();
+    let js_queue = cx.string(&snapshot.queue);
+    obj.set(&mut cx, "queue", js_queue)?;
+
+    let js_groups = cx.empty_array();
+    for (n, group) in snapshot.groups.iter().enumerate() {
+        let js_group = cx.empty_object();
+        let js_name = cx.string(&group.group);
+        js_group.set(&mut cx, "group", js_name)?;
+
+        let js_received = cx.empty_object();
+        for (verb, count) in group.received.iter() {
+            let js_count = cx.number(*count as f64);
+            js_received.set(&mut cx, verb.as_str(), js_count)?;
+        }
+
+        js_group.set(&mut cx, "received", js_received)?;
+
+        let js_responses = cx.empty_object();
+        for (class, count) in STATUS_CLASSES.iter().zip(group.responses.iter()) {
+            let js_count = cx.number(*count as f64);
+            js_responses.set(&mut cx, *class, js_count)?;
+        }
+
+        js_group.set(&mut cx, "responses", js_responses)?;
+
+        let js_bytes_in = cx.number(group.bytes_in as f64);
+        js_group.set(&mut cx, "bytesIn", js_bytes_in)?;
+
+        let js_bytes_out = cx.number(group.bytes_out as f64);
+        js_group.set(&mut cx, "bytesOut", js_bytes_out)?;
+
+        let js_in_flight = cx.number(group.in_flight as f64);
+        js_group.set(&mut cx, "inFlight", js_in_flight)?;
+
+        let js_latency = cx.empty_object();
+        let js_buckets = cx.empty_array();
+        for (n, (bound, count)) in group.latency.iter().enumerate() {
+            let js_pair = cx.empty_array();
+            let js_bound = cx.number(*bound);
+            js_pair.set(&mut cx, 0, js_bound)?;
+
+            let js_count = cx.number(*count as f64);
+            js_pair.set(&mut cx, 1, js_count)?;
+            js_buckets.set(&mut cx, n as u32, js_pair)?;
+        }
+
+        js_latency.set(&mut cx, "buckets", js_buckets)?;
+
+        let js_count = cx.number(group.latency_count as f64);
+        js_latency.set(&mut cx, "count", js_count)?;
+
+        let js_sum = cx.number(group.latency_sum);
+        js_latency.set(&mut cx, "sum", js_sum)?;
+        js_group.set(&mut cx, "latency", js_latency)?;
+        js_groups.set(&mut cx, n as u32, js_group)?;
+    }
+
+    obj.set(&mut cx, "groups", js_groups)?;
+
+    let js_in_flight = cx.number(snapshot.in_flight as f64);
+    obj.set(&mut cx, "inFlight", js_in_flight)?;
+
+    let js_pending_io = cx.number(snapshot.pending_io as f64);
+    obj.set(&mut cx, "pendingIo", js_pending_io)?;
+
+    let js_errors = cx.empty_object();
+    for (code, count) in snapshot.errors.iter() {
+        let js_count = cx.number(*count as f64);
+        js_errors.set(&mut cx, code.to_string().as_str(), js_count)?;
+    }
+
+    obj.set(&mut cx, "errors", js_errors)?;
+
+    let js_limits = cx.empty_object();
+    for (reason, count) in snapshot.limits.iter() {
+        let js_count = cx.number(*count as f64);
+        js_limits.set(&mut cx, *reason, js_count)?;
+    }
+
+    obj.set(&mut cx, "limits", js_limits)?;
+
+    Ok(obj)
+}
+
+fn http_request_metrics_text(mut cx: FunctionContext) -> JsResult<JsString> {
+    let mut i = 0;
+    let arc = cx.import::<Request>(&mut i)?;
+    let text = arc.metrics.snapshot(pending_io(), &arc.limits.stats).prometheus();
+    Ok(cx.string(text))
+}
+
+fn http_request_metrics_url(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let mut i = 0;
+    let arc = cx.import::<Request>(&mut i)?;
+    let mut url = None;
+    if cx.arg_opt(&mut i) {
+        url = Some(cx.arg_string(&mut i)?);
+    }
+
+    let mut allow = Vec::new();
+    if cx.arg_opt(&mut i) {
+        let list = cx.arg_strings(&mut i)?;
+        allow = match parse_cidrs(&list) {
+            Ok(list) => list,
+            Err(err) => return cx.throw_type_error(err),
+        };
+    }
+
+    arc.metrics.serve(url, allow);
+    Ok(cx.undefined())
+}
+
+fn http_request_jwt(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let arc = cx.import::<Request>(&mut i)?;
+    let source = cx.arg_string(&mut i)?;
+    let mut issuer = None;
+    let mut audience = None;
+    let mut skew = 60;
+    let mut required = false;
+    let mut expiry = true;
+    if cx.arg_opt(&mut i) {
+        issuer = Some(cx.arg_string(&mut i)?);
+    }
+
+    if cx.arg_opt(&mut i) {
+        audience = Some(cx.arg_string(&mut i)?);
+    }
+
+    if cx.arg_opt(&mut i) {
+        skew = cx.arg_u32(&mut i)? as u64;
+    }
+
+    if cx.arg_opt(&mut i) {
+        required = cx.arg_bool(&mut i)?;
+    }
+
+    if cx.arg_opt(&mut i) {
+        expiry = cx.arg_bool(&mut i)?;
+    }
+
+    let mut group = 0;
+    if cx.arg_opt(&mut i) {
+        group = url_group(&cx.arg_string(&mut i)?);
+    }
+
+    let builder = cx.task(move || {
+        if source.len() < 1 {
+            arc.jwt.config(group, None);
+            return Ok(());
+        }
 
-            let js_id = cx.boxed(info.RequestId);
-            obj.set(&mut cx, "id", js_id)?;
+        let keys = load_jwks(&source)?;
+        let url = Some(source).filter(|x| is_remote(x));
+        let mut config = JwtConfig::new(keys, url);
+        config.issuer = issuer;
+        config.audience = audience;
+        config.skew = skew;
+        config.required = required;
+        config.expiry = expiry;
+        arc.jwt.config(group, Some(config));
+        Ok(())
+    });
 
-            if err != 0 {
-                return Ok(obj);
-            }
+    let promise = builder.promise(move |mut cx, value: Result<(), String>| {
+        match value {
+            Ok(()) => Ok(cx.undefined()),
+            Err(msg) => cx.throw_type_error(msg),
+        }
+    });
 
-            let js_verb = cx.number(info.Verb.0);
-            obj.set(&mut cx, "verb", js_verb)?;
+    Ok(promise)
+}
 
-            let ver = &info.Version;
-            let version_str = format!("{}.{}", ver.MajorVersion, ver.MinorVersion);
-            let js_version = cx.string(version_str);
-            obj.set(&mut cx, "version", js_version)?;
+fn http_request_cert_map(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let mut i = 0;
+    let arc = cx.import::<Request>(&mut i)?;
+    let rules = cx.arg_strings(&mut i)?;
+    let mut required = false;
+    let mut untrusted = false;
+    if cx.arg_opt(&mut i) {
+        required = cx.arg_bool(&mut i)?;
+    }
 
-            let body = (info.Flags & HTTP_REQUEST_FLAG_MORE_ENTITY_BODY_EXISTS) != 0;
-            let js_body = cx.boolean(body);
-            obj.set(&mut cx, "body", js_body)?;
+    if cx.arg_opt(&mut i) {
+        untrusted = cx.arg_bool(&mut i)?;
+    }
 
-            let http2 = (info.Flags & HTTP_REQUEST_FLAG_HTTP2) != 0;
-            let js_http2 = cx.boolean(http2);
-            obj.set(&mut cx, "http2", js_http2)?;
-           
-            unsafe {
-                if info.UnknownVerbLength > 0 {
-                    if let Ok(value) = info.pUnknownVerb.to_string() {
-                        let js_custom_verb = cx.string(value);
-                        obj.set(&mut cx, "customVerb", js_custom_verb)?;            
-                    }
-                }
+    if rules.len() < 1 {
+        arc.certs.config(None);
+        return Ok(cx.undefined());
+    }
 
-                if info.RawUrlLength > 0 {
-                    if let Ok(value) = info.pRawUrl.to_string() {
-                        let js_url = cx.string(value);
-                        obj.set(&mut cx, "url", js_url)?;            
-                    }
-                }
+    let rules = match rules.iter().map(|x| CertRule::parse(x)).collect::<Result<Vec<_>, _>>() {
+        Ok(rules) => rules,
+        Err(err) => return cx.throw_type_error(err),
+    };
 
-                let js_known = cx.empty_array();
-                obj.set(&mut cx, "knownHeaders", js_known)?;
+    arc.certs.config(Some(CertMapConfig { rules, required, untrusted }));
+    Ok(cx.undefined())
+}
 
-                let known = &info.Headers.KnownHeaders;
-                for i in 0..known.len() {
-                    let header = &known[i];
-                    if header.RawValueLength > 0 {
-                        if let Ok(value) = header.pRawValue.to_string() {
-                            let key = format!("{}", i);
-                            let js_value = cx.string(value);
-                            js_known.set(&mut cx, key.as_str(), js_value)?;
-                        }
-                    }
-                }
+fn http_request_negotiate(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let mut i = 0;
+    let arc = cx.import::<Request>(&mut i)?;
+    let types = cx.arg_strings(&mut i)?;
+    let languages = cx.arg_strings(&mut i)?;
+    let encodings = cx.arg_strings(&mut i)?;
+    let charsets = cx.arg_strings(&mut i)?;
+    if types.len() < 1 && languages.len() < 1 && encodings.len() < 1 && charsets.len() < 1 {
+        arc.negotiate.config(None);
+    } else {
+        arc.negotiate.config(Some(Offers { types, languages, encodings, charsets }));
+    }
 
-                let mut i = 0;
-                let js_unknown = cx.empty_array();
-                obj.set(&mut cx, "unknownHeaders", js_unknown)?;
-                
-                let mut next = info.Headers.pUnknownHeaders;
-                let last = next.add(info.Headers.UnknownHeaderCount as usize);
-                while next < last {
-                    let header = &*next;
-                    next = next.add(1);
+    Ok(cx.undefined())
+}
 
-                    if header.NameLength > 0 && header.RawValueLength > 0 {
-                        if let Ok(key) = header.pName.to_string() {
-                            if let Ok(value) = header.pRawValue.to_string() {
-                                let js_key = cx.string(key);
-                                js_unknown.set(&mut cx, i, js_key)?;
-                                i += 1;
+fn http_request_cookie_keys(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let mut i = 0;
+    let arc = cx.import::<Request>(&mut i)?;
+    let mut keys = None;
+    if cx.arg_opt(&mut i) {
+        keys = Some(cx.import::<CookieKeys>(&mut i)?);
+    }
 
-                                let js_value = cx.string(value);
-                                js_unknown.set(&mut cx, i, js_value)?;
-                                i += 1;
-                            }
-                        }
-                    }
-                }
+    arc.cookies.config(keys);
+    Ok(cx.undefined())
+}
 
-                let addr = &*info.Address.pLocalAddress;
-                let addr_ptr = info.Address.pLocalAddress as *const u8;
-                if addr.sa_family == AF_INET.0 as u16 {
-                    let size = size_of::<SOCKADDR_IN>();
-                    let mut js_addr = cx.buffer(size)?;
-                    copy(addr_ptr, js_addr.as_mut_slice(&mut cx).as_mut_ptr(), size);
-                    obj.set(&mut cx, "sockaddr", js_addr)?;
-                }
+fn http_request_sessions(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let mut i = 0;
+    let arc = cx.import::<Request>(&mut i)?;
+    if !cx.arg_opt(&mut i) {
+        arc.sessions.config(None);
+        return Ok(cx.undefined());
+    }
 
-                if addr.sa_family == AF_INET6.0 as u16 {
-                    let size = size_of::<SOCKADDR_IN6>();
-                    let mut js_addr = cx.buffer(size)?;
-                    copy(addr_ptr, js_addr.as_mut_slice(&mut cx).as_mut_ptr(), size);
-                    obj.set(&mut cx, "sockaddr", js_addr)?;
-                }
-            }
+    let store = cx.import::<SessionStore>(&mut i)?;
+    let mut cookie = String::from("sid");
+    let mut by_user = false;
+    if cx.arg_opt(&mut i) {
+        cookie = cx.arg_string(&mut i)?;
+    }
 
-            if let Some(user) = user_opt {
-                let js_user_sid = user_groups_internal(&mut cx, user.0, true)?;
-                let js_user = cx.boxed(RefCell::new(Some(user)));
-                obj.set(&mut cx, "user", js_user)?;
-                obj.set(&mut cx, "user_sid", js_user_sid)?;
-            }
+    if cx.arg_opt(&mut i) {
+        by_user = cx.arg_bool(&mut i)?;
+    }
 
-            drop(vec);
-            Ok(obj)
-        });
-    });
-    
-    Ok(promise)
+    arc.sessions.config(Some(SessionBinding { store, cookie, by_user }));
+    Ok(cx.undefined())
 }
 
-fn http_request_receive_data(mut cx: FunctionContext) -> JsResult<JsPromise> {
+fn http_request_header_policy(mut cx: FunctionContext) -> JsResult<JsUndefined> {
     let mut i = 0;
     let arc = cx.import::<Request>(&mut i)?;
-    let id = cx.arg_u64(&mut i)?;
-    let mut buf = cx.arg_buffer(&mut i)?;
-    let root = buf.root(&mut cx);
-    let tx = cx.channel();
-    let (def, promise) = cx.promise();
-    let slice = buf.as_mut_slice(&mut cx);
-    arc.receive_data(id, slice, move |err, size| {
-        drop(root);
-        
-        def.settle_with(&tx, move |mut cx| {
-            let obj = cx.empty_object();
-            let js_err = cx.number(err);
-            obj.set(&mut cx, "code", js_err)?;
+    let value = cx.arg_string(&mut i)?;
+    match parse_header_policy(&value) {
+        Some(policy) => arc.headers.store(policy, Relaxed),
+        None => return cx.throw_type_error(format!("Unknown header policy: {}", value)),
+    }
 
-            let js_size = cx.number(size);
-            obj.set(&mut cx, "size", js_size)?;
+    Ok(cx.undefined())
+}
 
-            let js_eof = cx.boolean(err == ERROR_HANDLE_EOF.0);
-            obj.set(&mut cx, "eof", js_eof)?;
+fn http_request_limits_stats(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let mut i = 0;
+    let arc = cx.import::<Request>(&mut i)?;
+    let stats = &arc.limits.stats;
+    let obj = cx.empty_object();
+    let js_length = cx.number(stats.length.load(Relaxed) as f64);
+    obj.set(&mut cx, "length", js_length)?;
 
-            Ok(obj)
-        });
-    });
+    let js_body = cx.number(stats.body.load(Relaxed) as f64);
+    obj.set(&mut cx, "body", js_body)?;
 
-    Ok(promise)
+    let js_expect = cx.number(stats.expect.load(Relaxed) as f64);
+    obj.set(&mut cx, "expect", js_expect)?;
+
+    let js_deferred = cx.number(stats.deferred.load(Relaxed) as f64);
+    obj.set(&mut cx, "deferred", js_deferred)?;
+
+    let js_tracked = cx.number(arc.limits.tracked() as f64);
+    obj.set(&mut cx, "tracked", js_tracked)?;
+
+    Ok(obj)
 }
 
 fn http_request_send(mut cx: FunctionContext) -> JsResult<JsPromise> {
@@ -571,36 +1948,19 @@ fn http_request_send(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let mut known = Vec::<HTTP_KNOWN_HEADER>::new();
     let mut multiple = Vec::<HTTP_MULTIPLE_KNOWN_HEADERS>::new();
     let mut unknown = Vec::<HTTP_UNKNOWN_HEADER>::new();
-    let mut response = Box::new(HTTP_RESPONSE_V2 {
-        Base: HTTP_RESPONSE_V1 {
-            Flags: 0,
-            Version: HTTP_VERSION {
-                MajorVersion: 0,
-                MinorVersion: 0,
-            },
-            StatusCode: 0,
-            ReasonLength: 0,
-            pReason: PCSTR::null(),
-            Headers: HTTP_RESPONSE_HEADERS {
-                UnknownHeaderCount: 0,
-                pUnknownHeaders: null_mut(),
-                TrailerCount: 0,
-                pTrailers: null_mut(),
-                KnownHeaders: [HTTP_KNOWN_HEADER { RawValueLength: 0, pRawValue: PCSTR::null() }; HttpHeaderResponseMaximum.0 as usize]
-            },
-            EntityChunkCount: 0,
-            pEntityChunks: null_mut(),
-        },
-        ResponseInfoCount: 0,
-        pResponseInfo: null_mut(),
-    });
+    let mut response = new_response();
 
-    let block = cx.arg_buffer(&mut i)?;
-    let root = block.root(&mut cx);
+    let block = cx.arg_block(&mut i)?;
     let base = &mut response.as_mut().Base;
     let opaque = cx.arg_bool(&mut i)?;
     let more = cx.arg_bool(&mut i)?;
     let disconnect = cx.arg_bool(&mut i)?;
+    let extra = cx.arg_u32(&mut i)?;
+    let cache = cache_policy(cx.arg_i32(&mut i)?);
+    if more && cache.is_some() {
+        return cx.throw_type_error("Cache policy requires a complete response.");
+    }
+
     let status = cx.arg_u16(&mut i)?;
     let major = cx.arg_u16(&mut i)?;
     let minor = cx.arg_u16(&mut i)?;
@@ -610,27 +1970,68 @@ fn http_request_send(mut cx: FunctionContext) -> JsResult<JsPromise> {
         MinorVersion: minor,
     };
 
-    let reason = cx.arg_ptr(&mut i, &block)?;
-    base.ReasonLength = reason.1 as u16;
-    base.pReason = PCSTR(reason.0);
+    let policy = arc.headers.load(Relaxed);
+    let mut copies = Vec::<Vec<u8>>::new();
+    let mut reason = cx.arg_slice(&mut i, &block)?;
+    if reason.len() > u16::MAX as usize {
+        return cx.throw_range_error(format!("Reason too long: {} bytes, limit is {}", reason.len(), u16::MAX));
+    }
+
+    match check_slice(policy, ":reason", None, reason, &mut copies) {
+        Ok(Some(value)) => reason = value,
+        Ok(None) => (),
+        Err(msg) => return cx.throw_type_error(msg),
+    }
+
+    base.ReasonLength = reason.len() as u16;
+    base.pReason = PCSTR(reason.ptr());
 
     while i < cx.len() {
         let id = cx.arg_i32(&mut i)?;
-        let value = cx.arg_ptr(&mut i, &block)?;
+        let value = cx.arg_slice(&mut i, &block)?;
+        if id >= HttpHeaderResponseMaximum.0 {
+            return cx.throw_range_error(format!("Invalid header id: {}", id));
+        }
+
         if id < 0 {
-            let name = cx.arg_ptr(&mut i, &block)?;
+            let name = cx.arg_slice(&mut i, &block)?;
+            let label = label_slice(name);
+            let value = match check_slice(policy, &label, Some(name), value, &mut copies) {
+                Ok(Some(value)) => value,
+                Ok(None) => continue,
+                Err(msg) => return cx.throw_type_error(msg),
+            };
+
+            if label.eq_ignore_ascii_case("Set-Cookie") {
+                if let Err(msg) = check_set_cookie(policy, value) {
+                    return cx.throw_type_error(msg);
+                }
+            }
+
             unknown.push(HTTP_UNKNOWN_HEADER {
-                NameLength: name.1 as u16,
-                pName: PCSTR(name.0),
-                RawValueLength: value.1 as u16,
-                pRawValue: PCSTR(value.0)
+                NameLength: name.len() as u16,
+                pName: PCSTR(name.ptr()),
+                RawValueLength: value.len() as u16,
+                pRawValue: PCSTR(value.ptr())
             });
         } else {
+            let value = match check_slice(policy, response_header_name(id), None, value, &mut copies) {
+                Ok(Some(value)) => value,
+                Ok(None) => continue,
+                Err(msg) => return cx.throw_type_error(msg),
+            };
+
+            if id == HttpHeaderSetCookie.0 {
+                if let Err(msg) = check_set_cookie(policy, value) {
+                    return cx.throw_type_error(msg);
+                }
+            }
+
             let mut assign = true;
             let first = &mut base.Headers.KnownHeaders[id as usize];
             let next = HTTP_KNOWN_HEADER {
-                RawValueLength: value.1 as u16,
-                pRawValue: PCSTR(value.0)    
+                RawValueLength: value.len() as u16,
+                pRawValue: PCSTR(value.ptr())    
             };
             
             if let Some(last) = multiple.last_mut() {
@@ -665,6 +2066,49 @@ fn http_request_send(mut cx: FunctionContext) -> JsResult<JsPromise> {
         }
     }
 
+    let cors = arc.cors.take(id);
+    let mut merged = Vec::<String>::new();
+    for (name, value) in cors.iter() {
+        let id = response_header_id(name);
+        if id >= 0 {
+            // A header JS sent several times lives in `known`, grouped in the order the groups were created.
+            let mut slot = &mut base.Headers.KnownHeaders[id as usize];
+            let mut offset = 0;
+            for group in multiple.iter() {
+                let count = group.KnownHeaderCount as usize;
+                if group.HeaderId.0 == id {
+                    slot = &mut known[offset + count - 1];
+                    break;
+                }
+
+                offset += count;
+            }
+
+            if slot.RawValueLength == 0 {
+                *slot = HTTP_KNOWN_HEADER {
+                    RawValueLength: value.len() as u16,
+                    pRawValue: PCSTR(value.as_ptr())
+                };
+            } else if name.eq_ignore_ascii_case("Vary") {
+                let existing = unsafe { from_raw_parts(slot.pRawValue.0, slot.RawValueLength as usize) };
+                if let Some(value) = append_vary(&String::from_utf8_lossy(existing), value) {
+                    *slot = HTTP_KNOWN_HEADER {
+                        RawValueLength: value.len() as u16,
+                        pRawValue: PCSTR(value.as_ptr())
+                    };
+
+                    merged.push(value);
+                }
+            }
+        } else if !unknown.iter().any(|x| header_named(x, name)) {
+            unknown.push(unknown_header(name, value));
+        }
+    }
+
+    if unknown.len() > u16::MAX as usize {
+        return cx.throw_range_error(format!("Too many headers: {}", unknown.len()));
+    }
+
     if unknown.len() > 0 {
         base.Headers.UnknownHeaderCount = unknown.len() as u16;
         base.Headers.pUnknownHeaders = unknown.as_mut_ptr();            
@@ -690,27 +2134,18 @@ fn http_request_send(mut cx: FunctionContext) -> JsResult<JsPromise> {
         response.pResponseInfo = infos.as_mut_ptr();  
     }
 
-    let mut flags = 0;
-    if opaque {
-        flags |= HTTP_SEND_RESPONSE_FLAG_OPAQUE;
-    }
-
-    if more {
-        flags |= HTTP_SEND_RESPONSE_FLAG_MORE_DATA;
-    }
-
-    if disconnect {
-        flags |= HTTP_SEND_RESPONSE_FLAG_DISCONNECT;
-    }
+    let flags = send_options(opaque, more, disconnect, extra);
     
     let ptr = response.as_mut() as *mut HTTP_RESPONSE_V2;
-    let transfer = SendRef((root, response, infos, multiple, known, unknown));
+    let transfer = SendRef((response, infos, multiple, known, unknown, cors, merged, copies));
     let tx = cx.channel();
     let (def, promise) = cx.promise();
-    arc.send(id, flags, unsafe { &mut *ptr }, move |err, size| {
+    arc.send(id, flags, unsafe { &mut *ptr }, cache, move |err, size| {
         drop(transfer);
 
         def.settle_with(&tx, move |mut cx| {
+            block.release(&mut cx);
+
             let obj = cx.empty_object();
             let js_err = cx.number(err);
             obj.set(&mut cx, "code", js_err)?;
@@ -735,44 +2170,59 @@ fn http_request_send_data(mut cx: FunctionContext) -> JsResult<JsPromise> {
     let mut count = cx.arg_u16(&mut i)?;
     let mut chunks = Vec::<HTTP_DATA_CHUNK>::new();
     let mut unknown = Vec::<HTTP_UNKNOWN_HEADER>::new();
-    let mut roots = Vec::<Root<JsBuffer>>::new();
+    let mut blocks = Vec::<Block>::new();
     while count > 0 {
-        let mut block = cx.arg_buffer(&mut i)?;
-        roots.push(block.root(&mut cx));
+        let block = cx.arg_block(&mut i)?;
+        let slice = block.whole();
+        if slice.len() > u32::MAX as usize {
+            return cx.throw_range_error(format!("Chunk too large: {}", slice.len()));
+        }
 
-        let slice = block.as_mut_slice(&mut cx);
         if slice.len() > 0 {
             chunks.push(HTTP_DATA_CHUNK {
                 DataChunkType: HttpDataChunkFromMemory,
                 Anonymous: HTTP_DATA_CHUNK_0 {
                     FromMemory: HTTP_DATA_CHUNK_0_3 {
                         BufferLength: slice.len() as u32,
-                        pBuffer: slice.as_mut_ptr() as *mut c_void
+                        pBuffer: slice.mut_ptr() as *mut c_void
                     }
                 }
             });            
         }
 
+        blocks.push(block);
         count -= 1;
     }
 
-    let block = cx.arg_buffer(&mut i)?;
-    roots.push(block.root(&mut cx));
+    let block = cx.arg_block(&mut i)?;
 
     let opaque = cx.arg_bool(&mut i)?;
     let more = cx.arg_bool(&mut i)?;
     let disconnect = cx.arg_bool(&mut i)?;
+    let extra = cx.arg_u32(&mut i)?;
+    let policy = arc.headers.load(Relaxed);
+    let mut copies = Vec::<Vec<u8>>::new();
     while i < cx.len() {
-        let name = cx.arg_ptr(&mut i, &block)?;
-        let value = cx.arg_ptr(&mut i, &block)?;
+        let name = cx.arg_slice(&mut i, &block)?;
+        let value = cx.arg_slice(&mut i, &block)?;
+        let value = match check_slice(policy, &label_slice(name), Some(name), value, &mut copies) {
+            Ok(Some(value)) => value,
+            Ok(None) => continue,
+            Err(msg) => return cx.throw_type_error(msg),
+        };
+
         unknown.push(HTTP_UNKNOWN_HEADER {
-            NameLength: name.1 as u16,
-            pName: PCSTR(name.0),
-            RawValueLength: value.1 as u16,
-            pRawValue: PCSTR(value.0)
+            NameLength: name.len() as u16,
+            pName: PCSTR(name.ptr()),
+            RawValueLength: value.len() as u16,
+            pRawValue: PCSTR(value.ptr())
         });
     }
 
+    if unknown.len() > u16::MAX as usize {
+        return cx.throw_range_error(format!("Too many trailers: {}", unknown.len()));
+    }
+
     if unknown.len() > 0 {
         chunks.push(HTTP_DATA_CHUNK {
             DataChunkType: HttpDataChunkTrailers,
@@ -785,29 +2235,24 @@ fn http_request_send_data(mut cx: FunctionContext) -> JsResult<JsPromise> {
         });
     }
 
-    let mut flags = 0;
-    if opaque {
-        flags |= HTTP_SEND_RESPONSE_FLAG_OPAQUE;
-    }
-
-    if more {
-        flags |= HTTP_SEND_RESPONSE_FLAG_MORE_DATA;
-    }
-
-    if disconnect {
-        flags |= HTTP_SEND_RESPONSE_FLAG_DISCONNECT;
-    }
+    let flags = send_options(opaque, more, disconnect, extra);
 
     let ptr = chunks.as_mut_ptr();
     let count = chunks.len();
     let slice = unsafe { from_raw_parts_mut(ptr, count) };
-    let transfer = SendRef((chunks, roots, unknown));
+    let transfer = SendRef((chunks, unknown, copies));
     let tx = cx.channel();
     let (def, promise) = cx.promise();
     arc.send_data(id, flags, slice, move |err, size|  {
         drop(transfer);
 
         def.settle_with(&tx, move |mut cx| {
+            for chunk in blocks {
+                chunk.release(&mut cx);
+            }
+
+            block.release(&mut cx);
+
             let obj = cx.empty_object();
             let js_err = cx.number(err);
             obj.set(&mut cx, "code", js_err)?;
@@ -835,36 +2280,67 @@ fn http_request_push(mut cx: FunctionContext) -> JsResult<JsUndefined> {
         KnownHeaders: [HTTP_KNOWN_HEADER { RawValueLength: 0, pRawValue: PCSTR::null() }; HttpHeaderRequestMaximum.0 as usize]
     });
 
-    let block = cx.arg_buffer(&mut i)?;
+    let block = cx.arg_block(&mut i)?;
     let base = headers.as_mut();
     let verb = cx.arg_i32(&mut i)?;
-    let path = cx.arg_ptr(&mut i, &block)?;
-    let query = cx.arg_ptr(&mut i, &block)?;
+    let path = cx.arg_cstr(&mut i, &block, 2)?;
+    let query = cx.arg_cstr(&mut i, &block, 1)?;
+    let policy = arc.headers.load(Relaxed);
+    let mut copies = Vec::<Vec<u8>>::new();
     while i < cx.len() {
         let id = cx.arg_i32(&mut i)?;
-        let value = cx.arg_ptr(&mut i, &block)?;
+        let value = cx.arg_slice(&mut i, &block)?;
+        if id >= HttpHeaderRequestMaximum.0 {
+            return cx.throw_range_error(format!("Invalid header id: {}", id));
+        }
+
         if id < 0 {
-            let name = cx.arg_ptr(&mut i, &block)?;
+            let name = cx.arg_slice(&mut i, &block)?;
+            let value = match check_slice(policy, &label_slice(name), Some(name), value, &mut copies) {
+                Ok(Some(value)) => value,
+                Ok(None) => continue,
+                Err(msg) => return cx.throw_type_error(msg),
+            };
+
             unknown.push(HTTP_UNKNOWN_HEADER {
-                NameLength: name.1 as u16,
-                pName: PCSTR(name.0),
-                RawValueLength: value.1 as u16,
-                pRawValue: PCSTR(value.0)
+                NameLength: name.len() as u16,
+                pName: PCSTR(name.ptr()),
+                RawValueLength: value.len() as u16,
+                pRawValue: PCSTR(value.ptr())
             });
         } else {
+            let value = match check_slice(policy, REQUEST_HEADER_NAMES[id as usize], None, value, &mut copies) {
+                Ok(Some(value)) => value,
+                Ok(None) => continue,
+                Err(msg) => return cx.throw_type_error(msg),
+            };
+
             base.KnownHeaders[id as usize] = HTTP_KNOWN_HEADER {
-                RawValueLength: value.1 as u16,
-                pRawValue: PCSTR(value.0)
+                RawValueLength: value.len() as u16,
+                pRawValue: PCSTR(value.ptr())
             };
         }
     }
 
+    if unknown.len() > u16::MAX as usize {
+        return cx.throw_range_error(format!("Too many headers: {}", unknown.len()));
+    }
+
     if unknown.len() > 0 {
         base.UnknownHeaderCount = unknown.len() as u16;
         base.pUnknownHeaders = unknown.as_mut_ptr();
     }
 
-    match arc.push(id, verb, path.0 as *const u16, query.0, headers.as_ref()) {
+    // The query includes its '?'; `cstr` checked the terminator, so the byte after it is readable.
+    let mut tail = None;
+    if query.len() > 0 && unsafe { *query.ptr().add(1) } != 0 {
+        tail = Some(unsafe { query.ptr().add(1) });
+    }
+
+    let result = arc.push(id, verb, path.ptr() as *const u16, tail, headers.as_ref());
+    block.release(&mut cx);
+
+    match result {
         Ok(_) => Ok(cx.undefined()),
         Err((hint, err)) => cx.throw_type_error(format!("{}: {}", hint, err))
     }
@@ -889,16 +2365,83 @@ pub fn http_bind(cx: &mut ModuleContext) -> NeonResult<()> {
     cx.export_function("http_session_config", http_session_config)?;
     cx.export_function("http_session_listen", http_session_listen)?;
     cx.export_function("http_session_release", http_session_release)?;
+    cx.export_function("http_session_wait_demand", http_session_wait_demand)?;
     cx.export_function("http_session_close", http_session_close)?;
 
     cx.export_function("http_request_create", http_request_create)?;
     cx.export_function("http_request_cancel", http_request_cancel)?;
+    cx.export_function("http_request_flush", http_request_flush)?;
+    cx.export_function("http_request_reset", http_request_reset)?;
     cx.export_function("http_request_receive", http_request_receive)?;
     cx.export_function("http_request_receive_data", http_request_receive_data)?;
     cx.export_function("http_request_send", http_request_send)?;
     cx.export_function("http_request_send_data", http_request_send_data)?;
     cx.export_function("http_request_push", http_request_push)?;
+    cx.export_function("http_request_limits", http_request_limits)?;
+    cx.export_function("http_request_limit", http_request_limit)?;
+    cx.export_function("http_request_limits_stats", http_request_limits_stats)?;
+    cx.export_function("http_request_rate_limit", http_request_rate_limit)?;
+    cx.export_function("http_request_rate_lists", http_request_rate_lists)?;
+    cx.export_function("http_request_rate_stats", http_request_rate_stats)?;
+    cx.export_function("http_request_cors", http_request_cors)?;
+    cx.export_function("http_request_metrics", http_request_metrics)?;
+    cx.export_function("http_request_metrics_text", http_request_metrics_text)?;
+    cx.export_function("http_request_metrics_url", http_request_metrics_url)?;
+    cx.export_function("http_request_jwt", http_request_jwt)?;
+    cx.export_function("http_request_cert_map", http_request_cert_map)?;
+    cx.export_function("http_request_negotiate", http_request_negotiate)?;
+    cx.export_function("http_request_cookie_keys", http_request_cookie_keys)?;
+    cx.export_function("http_request_sessions", http_request_sessions)?;
+    cx.export_function("http_request_header_policy", http_request_header_policy)?;
     cx.export_function("http_request_close", http_request_close)?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_ids() {
+        assert_eq!(response_header_id("set-cookie"), HttpHeaderSetCookie.0);
+        assert_eq!(response_header_id("Content-Type"), HttpHeaderContentType.0);
+        assert_eq!(response_header_id("Host"), -1);
+        assert_eq!(response_header_id("X-Custom"), -1);
+        for id in 0..HttpHeaderResponseMaximum.0 {
+            assert_eq!(response_header_id(response_header_name(id)), id);
+        }
+
+        assert_eq!(response_header_name(HttpHeaderResponseMaximum.0), "");
+    }
+
+    #[test]
+    fn send_flags() {
+        assert_eq!(send_options(false, false, false, 0), 0);
+        assert_eq!(send_options(true, true, true, 0), HTTP_SEND_RESPONSE_FLAG_OPAQUE | HTTP_SEND_RESPONSE_FLAG_MORE_DATA | HTTP_SEND_RESPONSE_FLAG_DISCONNECT);
+        assert_eq!(send_options(false, false, false, SEND_BUFFER_DATA | SEND_GOAWAY), HTTP_SEND_RESPONSE_FLAG_BUFFER_DATA | HTTP_SEND_RESPONSE_FLAG_GOAWAY);
+        assert_eq!(send_options(false, false, false, SEND_NAGLING | SEND_RANGES), HTTP_SEND_RESPONSE_FLAG_ENABLE_NAGLING | HTTP_SEND_RESPONSE_FLAG_PROCESS_RANGES);
+
+        assert!(cache_policy(0).is_none());
+        let ttl = cache_policy(30).unwrap();
+        assert_eq!((ttl.Policy, ttl.SecondsToLive), (HttpCachePolicyTimeToLive, 30));
+        assert_eq!(cache_policy(-1).unwrap().Policy, HttpCachePolicyUserInvalidates);
+    }
+
+    #[test]
+    fn unknown_headers() {
+        let header = unknown_header("X-Forwarded-For", "1.2.3.4");
+        assert!(header_named(&header, "x-forwarded-for"));
+        assert!(!header_named(&header, "X-Forwarded"));
+    }
+
+    #[test]
+    fn set_cookie_policy() {
+        let mut bad = b"id=1; SameSite=None".to_vec();
+        let mut good = b"id=1; Secure; SameSite=None".to_vec();
+        assert!(check_set_cookie(HEADERS_STRICT, Slice::new(&mut bad)).is_err());
+        assert!(check_set_cookie(HEADERS_LENIENT, Slice::new(&mut bad)).is_err());
+        assert!(check_set_cookie(HEADERS_OFF, Slice::new(&mut bad)).is_ok());
+        assert!(check_set_cookie(HEADERS_STRICT, Slice::new(&mut good)).is_ok());
+    }
+}
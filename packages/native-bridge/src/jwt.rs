@@ -0,0 +1,552 @@
+#[cfg(windows)]
+use super::client::fetch_url;
+use super::message::*;
+
+use ring::hmac;
+use ring::signature;
+use serde_json::Value;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::RwLock;
+use std::thread::Thread;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+const JWKS_REFRESH: Duration = Duration::from_secs(3600);
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(60);
+
+enum KeyKind {
+    Rsa { n: Vec<u8>, e: Vec<u8> },
+    Ec { point: Vec<u8> },
+    Oct(Vec<u8>),
+}
+
+pub struct JwtKey {
+    kid: Option<String>,
+    alg: Option<String>,
+    kind: KeyKind,
+}
+
+fn b64(value: &str) -> Option<Vec<u8>> {
+    base64::decode_config(value, base64::URL_SAFE_NO_PAD).ok()
+}
+
+fn field<'a>(value: &'a Value, name: &str) -> Option<&'a str> {
+    value.get(name).and_then(|x| x.as_str())
+}
+
+impl JwtKey {
+    fn parse(jwk: &Value) -> Option<Self> {
+        let kind = match field(jwk, "kty")? {
+            "RSA" => KeyKind::Rsa { n: b64(field(jwk, "n")?)?, e: b64(field(jwk, "e")?)? },
+            "EC" if field(jwk, "crv") == Some("P-256") => {
+                let mut point = vec![4u8];
+                point.extend(b64(field(jwk, "x")?)?);
+                point.extend(b64(field(jwk, "y")?)?);
+                KeyKind::Ec { point }
+            },
+            "oct" => KeyKind::Oct(b64(field(jwk, "k")?)?),
+            _ => return None,
+        };
+
+        Some(Self {
+            kid: field(jwk, "kid").map(String::from),
+            alg: field(jwk, "alg").map(String::from),
+            kind,
+        })
+    }
+
+    fn verify(&self, alg: &str, message: &[u8], sig: &[u8]) -> bool {
+        if self.alg.as_deref().map(|x| x != alg).unwrap_or(false) {
+            return false;
+        }
+
+        match (alg, &self.kind) {
+            ("RS256", KeyKind::Rsa { n, e }) => {
+                let key = signature::RsaPublicKeyComponents { n, e };
+                key.verify(&signature::RSA_PKCS1_2048_8192_SHA256, message, sig).is_ok()
+            },
+            ("ES256", KeyKind::Ec { point }) => {
+                let key = signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, point);
+                key.verify(message, sig).is_ok()
+            },
+            ("HS256", KeyKind::Oct(secret)) => {
+                let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+                hmac::verify(&key, message, sig).is_ok()
+            },
+            _ => false,
+        }
+    }
+}
+
+pub fn parse_jwks(text: &str) -> Result<Vec<JwtKey>, String> {
+    let value: Value = serde_json::from_str(text).map_err(|err| format!("Invalid JWKS: {}", err))?;
+    let keys = match value.get("keys").and_then(|x| x.as_array()) {
+        Some(keys) => keys.iter().filter_map(JwtKey::parse).collect::<Vec<_>>(),
+        None => JwtKey::parse(&value).into_iter().collect(),
+    };
+
+    if keys.len() < 1 {
+        return Err(String::from("Invalid JWKS: no usable keys"));
+    }
+
+    Ok(keys)
+}
+
+pub struct Identity {
+    pub kind: &'static str,
+    pub sub: String,
+    pub sid: Option<String>,
+    pub groups: Vec<(&'static str, String)>,
+    pub claims: String,
+}
+
+pub struct JwtConfig {
+    pub keys: RwLock<Vec<JwtKey>>,
+    pub url: Option<String>,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+    pub skew: u64,
+    pub required: bool,
+    pub expiry: bool,
+    waker: Mutex<Option<(Thread, Instant)>>,
+}
+
+fn strings(value: Option<&Value>) -> Vec<String> {
+    match value {
+        Some(Value::String(x)) => vec![x.clone()],
+        Some(Value::Array(list)) => list.iter().filter_map(|x| x.as_str().map(String::from)).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn time(claims: &Value, name: &str) -> Result<Option<f64>, &'static str> {
+    match claims.get(name) {
+        None => Ok(None),
+        Some(value) => value.as_f64().filter(|x| x.is_finite()).map(Some).ok_or("invalid time claim"),
+    }
+}
+
+impl JwtConfig {
+    pub fn new(keys: Vec<JwtKey>, url: Option<String>) -> Self {
+        Self {
+            keys: RwLock::new(keys),
+            url,
+            issuer: None,
+            audience: None,
+            skew: 60,
+            required: false,
+            expiry: true,
+            waker: Mutex::new(None),
+        }
+    }
+
+    // Keys fetched from a URL are reloaded periodically, and early when a token names an unknown key id.
+    fn watch(self: &Arc<Self>) {
+        let url = match &self.url {
+            Some(url) => url.clone(),
+            None => return,
+        };
+
+        let weak = Arc::downgrade(self);
+        let handle = std::thread::spawn(move || loop {
+            std::thread::park_timeout(JWKS_REFRESH);
+            let config = match weak.upgrade() {
+                Some(config) => config,
+                None => return,
+            };
+
+            if let Ok(keys) = load_jwks(&url) {
+                if let Ok(mut value) = config.keys.write() {
+                    *value = keys;
+                }
+            }
+        });
+
+        if let Ok(mut waker) = self.waker.lock() {
+            *waker = Some((handle.thread().clone(), Instant::now()));
+        }
+    }
+
+    fn wake(&self) {
+        if let Ok(mut waker) = self.waker.lock() {
+            if let Some((thread, last)) = waker.as_mut() {
+                if last.elapsed() >= JWKS_MIN_REFRESH {
+                    *last = Instant::now();
+                    thread.unpark();
+                }
+            }
+        }
+    }
+
+    pub fn validate(&self, token: &str, now: u64) -> Result<Identity, &'static str> {
+        let mut parts = token.split('.');
+        let (head, body, sig) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(head), Some(body), Some(sig), None) => (head, body, sig),
+            _ => return Err("malformed token"),
+        };
+
+        let header: Value = b64(head).and_then(|x| serde_json::from_slice(&x).ok()).ok_or("malformed header")?;
+        let claims: Value = b64(body).and_then(|x| serde_json::from_slice(&x).ok()).ok_or("malformed claims")?;
+        let sig = b64(sig).ok_or("malformed signature")?;
+        let alg = field(&header, "alg").ok_or("missing algorithm")?;
+        let kid = field(&header, "kid");
+        let message = &token.as_bytes()[..head.len() + 1 + body.len()];
+
+        let keys = self.keys.read().map_err(|_| "key set unavailable")?;
+        let candidates = keys.iter().filter(|x| kid.is_none() || x.kid.as_deref() == kid).collect::<Vec<_>>();
+        if candidates.len() < 1 {
+            self.wake();
+        }
+
+        if !candidates.iter().any(|x| x.verify(alg, message, &sig)) {
+            return Err("invalid signature");
+        }
+
+        // NumericDate may carry a fraction, so times are compared as f64 rather than dropped when not integral.
+        let (now, skew) = (now as f64, self.skew as f64);
+        match time(&claims, "exp")? {
+            Some(exp) if now > exp + skew => return Err("token expired"),
+            None if self.expiry => return Err("missing expiry"),
+            _ => (),
+        }
+
+        if let Some(nbf) = time(&claims, "nbf")? {
+            if now + skew < nbf {
+                return Err("token not yet valid");
+            }
+        }
+
+        if let Some(issuer) = &self.issuer {
+            if field(&claims, "iss") != Some(issuer.as_str()) {
+                return Err("invalid issuer");
+            }
+        }
+
+        if let Some(audience) = &self.audience {
+            if !strings(claims.get("aud")).iter().any(|x| x == audience) {
+                return Err("invalid audience");
+            }
+        }
+
+        // Token values are issuer-controlled strings, so they live under their own kinds and never pass for Windows SIDs.
+        let sub = field(&claims, "sub").ok_or("missing subject")?.to_string();
+        let mut groups = vec![("jwt:user", sub.clone())];
+        for group in strings(claims.get("groups")) {
+            groups.push(("jwt:group", group));
+        }
+
+        for role in strings(claims.get("roles")) {
+            groups.push(("jwt:role", role));
+        }
+
+        if let Some(scope) = field(&claims, "scope") {
+            for scope in scope.split_whitespace() {
+                groups.push(("jwt:scope", String::from(scope)));
+            }
+        }
+
+        Ok(Identity { kind: "jwt", sub, sid: None, groups, claims: claims.to_string() })
+    }
+}
+
+// Configs are keyed by URL group; group 0 is the queue default for groups without their own.
+pub struct JwtAuth {
+    configs: RwLock<HashMap<u64, Arc<JwtConfig>>>,
+    pending: Mutex<HashMap<u64, Identity>>,
+}
+
+impl JwtAuth {
+    pub fn new() -> Self {
+        Self {
+            configs: RwLock::new(HashMap::new()),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self, group: u64, config: Option<JwtConfig>) {
+        let config = config.map(Arc::new);
+        if let Some(config) = &config {
+            config.watch();
+        }
+
+        if let Ok(mut map) = self.configs.write() {
+            match config {
+                Some(config) => map.insert(group, config),
+                None => map.remove(&group),
+            };
+        }
+    }
+
+    fn current(&self, group: u64) -> Option<Arc<JwtConfig>> {
+        let map = self.configs.read().ok()?;
+        map.get(&group).or_else(|| map.get(&0)).cloned()
+    }
+
+    pub fn active(&self) -> bool {
+        self.configs.read().map(|x| x.len() > 0).unwrap_or(false)
+    }
+
+    pub fn admit(&self, info: &RequestInfo) -> Option<Reply> {
+        let config = self.current(info.group)?;
+        let token = info.header("Authorization")
+            .filter(|x| x.get(..7).map(|x| x.eq_ignore_ascii_case("bearer ")).unwrap_or(false))
+            .map(|x| x[7..].trim());
+
+        let token = match token {
+            Some(token) => token,
+            None if config.required => return Some(unauthorized("Bearer")),
+            None => return None,
+        };
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or(0);
+        match config.validate(token, now) {
+            Ok(identity) => {
+                if let Ok(mut map) = self.pending.lock() {
+                    map.insert(info.id, identity);
+                }
+
+                None
+            },
+            Err(msg) => Some(unauthorized(&format!("Bearer error=\"invalid_token\", error_description=\"{}\"", msg))),
+        }
+    }
+
+    pub fn take(&self, id: u64) -> Option<Identity> {
+        self.pending.lock().ok()?.remove(&id)
+    }
+
+    pub fn forget(&self, id: u64) {
+        if let Ok(mut map) = self.pending.lock() {
+            map.remove(&id);
+        }
+    }
+}
+
+impl Drop for JwtConfig {
+    fn drop(&mut self) {
+        if let Ok(waker) = self.waker.get_mut() {
+            if let Some((thread, _)) = waker.take() {
+                thread.unpark();
+            }
+        }
+    }
+}
+
+fn unauthorized(challenge: &str) -> Reply {
+    Reply::new(401, "Unauthorized")
+        .header("WWW-Authenticate", challenge)
+        .header("Content-Length", "0")
+}
+
+// Remote key sets are fetched with WinHTTP, which only the Windows build has.
+#[cfg(not(windows))]
+fn fetch_url(_: &str) -> Result<Vec<u8>, (&'static str, u32)> {
+    Err(("Not supported", 50))
+}
+
+pub fn is_remote(source: &str) -> bool {
+    source.get(..8).map(|x| x.eq_ignore_ascii_case("https://")).unwrap_or(false)
+}
+
+pub fn load_jwks(source: &str) -> Result<Vec<JwtKey>, String> {
+    if source.trim_start().starts_with('{') {
+        return parse_jwks(source);
+    }
+
+    // Keys decide who gets in, so they are never taken from a connection anyone on the path can rewrite.
+    if source.get(..7).map(|x| x.eq_ignore_ascii_case("http://")).unwrap_or(false) {
+        return Err(format!("Key sets must be fetched over https: {}", source));
+    }
+
+    let data = if is_remote(source) {
+        fetch_url(source).map_err(|(hint, err)| format!("{}: {}", hint, err))?
+    } else {
+        std::fs::read(source).map_err(|err| format!("{}: {}", source, err))?
+    };
+
+    parse_jwks(&String::from_utf8_lossy(&data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ring::rand::SystemRandom;
+    use serde_json::json;
+
+    const NOW: u64 = 1_700_000_000;
+    const SECRET: &str = "c2VjcmV0LWtleS1mb3ItdGVzdGluZy1vbmx5LTAxMjM0NTY";
+    const RSA_PKCS8: &[u8] = include_bytes!("../tests/fixtures/rsa.pk8");
+    const RSA_JWK: &str = include_str!("../tests/fixtures/rsa.jwk.json");
+
+    fn encode(value: &Value) -> String {
+        base64::encode_config(value.to_string(), base64::URL_SAFE_NO_PAD)
+    }
+
+    fn token<F>(header: Value, claims: Value, sign: F) -> String where F: Fn(&[u8]) -> Vec<u8> {
+        let message = format!("{}.{}", encode(&header), encode(&claims));
+        let sig = sign(message.as_bytes());
+        format!("{}.{}", message, base64::encode_config(sig, base64::URL_SAFE_NO_PAD))
+    }
+
+    fn hs256(message: &[u8]) -> Vec<u8> {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, &b64(SECRET).unwrap());
+        hmac::sign(&key, message).as_ref().to_vec()
+    }
+
+    fn hs256_config() -> JwtConfig {
+        let jwks = json!({ "keys": [{ "kty": "oct", "kid": "hs-1", "k": SECRET }] });
+        JwtConfig::new(parse_jwks(&jwks.to_string()).unwrap(), None)
+    }
+
+    fn claims() -> Value {
+        json!({ "sub": "alice", "exp": NOW + 300, "iss": "https://issuer.example", "aud": ["api", "web"] })
+    }
+
+    fn header() -> Value {
+        json!({ "alg": "HS256", "kid": "hs-1" })
+    }
+
+    #[test]
+    fn accepts_hs256() {
+        let claims = json!({ "sub": "alice", "exp": NOW + 300, "groups": ["staff"], "roles": "admin", "scope": "read write" });
+        let identity = hs256_config().validate(&token(header(), claims, hs256), NOW).unwrap();
+        assert_eq!(identity.kind, "jwt");
+        assert_eq!(identity.sub, "alice");
+        assert_eq!(identity.sid, None);
+        assert_eq!(identity.groups, vec![
+            ("jwt:user", String::from("alice")),
+            ("jwt:group", String::from("staff")),
+            ("jwt:role", String::from("admin")),
+            ("jwt:scope", String::from("read")),
+            ("jwt:scope", String::from("write")),
+        ]);
+    }
+
+    #[test]
+    fn accepts_rs256_fixture() {
+        let rng = SystemRandom::new();
+        let pair = signature::RsaKeyPair::from_pkcs8(RSA_PKCS8).unwrap();
+        let sign = |message: &[u8]| {
+            let mut sig = vec![0u8; pair.public_modulus_len()];
+            pair.sign(&signature::RSA_PKCS1_SHA256, &rng, message, &mut sig).unwrap();
+            sig
+        };
+
+        let config = JwtConfig::new(parse_jwks(RSA_JWK).unwrap(), None);
+        let jwt = token(json!({ "alg": "RS256", "kid": "rsa-1" }), claims(), sign);
+        assert_eq!(config.validate(&jwt, NOW).unwrap().sub, "alice");
+
+        let confused = token(json!({ "alg": "HS256", "kid": "rsa-1" }), claims(), hs256);
+        assert_eq!(config.validate(&confused, NOW).err(), Some("invalid signature"));
+    }
+
+    #[test]
+    fn accepts_es256() {
+        let rng = SystemRandom::new();
+        let alg = &signature::ECDSA_P256_SHA256_FIXED_SIGNING;
+        let pkcs8 = signature::EcdsaKeyPair::generate_pkcs8(alg, &rng).unwrap();
+        let pair = signature::EcdsaKeyPair::from_pkcs8(alg, pkcs8.as_ref()).unwrap();
+        let point = signature::KeyPair::public_key(&pair).as_ref().to_vec();
+        let jwk = json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "x": base64::encode_config(&point[1..33], base64::URL_SAFE_NO_PAD),
+            "y": base64::encode_config(&point[33..], base64::URL_SAFE_NO_PAD),
+        });
+
+        let config = JwtConfig::new(parse_jwks(&jwk.to_string()).unwrap(), None);
+        let jwt = token(json!({ "alg": "ES256" }), claims(), |x| pair.sign(&rng, x).unwrap().as_ref().to_vec());
+        assert!(config.validate(&jwt, NOW).is_ok());
+    }
+
+    #[test]
+    fn rejects_tampering() {
+        let config = hs256_config();
+        let jwt = token(header(), claims(), hs256);
+        let mut parts = jwt.split('.').map(String::from).collect::<Vec<_>>();
+        parts[1] = encode(&json!({ "sub": "S-1-5-18", "exp": NOW + 300 }));
+        assert_eq!(config.validate(&parts.join("."), NOW).err(), Some("invalid signature"));
+        assert_eq!(config.validate("a.b", NOW).err(), Some("malformed token"));
+
+        let other = token(json!({ "alg": "HS256", "kid": "hs-2" }), claims(), hs256);
+        assert_eq!(config.validate(&other, NOW).err(), Some("invalid signature"));
+    }
+
+    #[test]
+    fn checks_expiry() {
+        let config = hs256_config();
+        let at = |exp: Value| token(header(), json!({ "sub": "alice", "exp": exp }), hs256);
+        assert!(config.validate(&at(json!(NOW - 30)), NOW).is_ok());
+        assert_eq!(config.validate(&at(json!(NOW - 61)), NOW).err(), Some("token expired"));
+        assert_eq!(config.validate(&at(json!(NOW as f64 - 60.5)), NOW).err(), Some("token expired"));
+        assert_eq!(config.validate(&at(json!(NOW.to_string())), NOW).err(), Some("invalid time claim"));
+
+        let forever = token(header(), json!({ "sub": "alice" }), hs256);
+        assert_eq!(config.validate(&forever, NOW).err(), Some("missing expiry"));
+
+        let mut lenient = hs256_config();
+        lenient.expiry = false;
+        assert!(lenient.validate(&forever, NOW).is_ok());
+    }
+
+    #[test]
+    fn checks_not_before() {
+        let config = hs256_config();
+        let at = |nbf: u64| token(header(), json!({ "sub": "alice", "exp": NOW + 300, "nbf": nbf }), hs256);
+        assert!(config.validate(&at(NOW + 60), NOW).is_ok());
+        assert_eq!(config.validate(&at(NOW + 61), NOW).err(), Some("token not yet valid"));
+    }
+
+    #[test]
+    fn checks_issuer_and_audience() {
+        let mut config = hs256_config();
+        config.issuer = Some(String::from("https://issuer.example"));
+        config.audience = Some(String::from("web"));
+        assert!(config.validate(&token(header(), claims(), hs256), NOW).is_ok());
+
+        config.audience = Some(String::from("admin"));
+        assert_eq!(config.validate(&token(header(), claims(), hs256), NOW).err(), Some("invalid audience"));
+
+        config.issuer = Some(String::from("https://other.example"));
+        assert_eq!(config.validate(&token(header(), claims(), hs256), NOW).err(), Some("invalid issuer"));
+    }
+
+    #[test]
+    fn keys_configs_by_group() {
+        let auth = JwtAuth::new();
+        let api = url_group("api");
+        let mut config = hs256_config();
+        config.required = true;
+        auth.config(api, Some(config));
+
+        let bearer = format!("Bearer {}", token(header(), json!({ "sub": "alice", "exp": 4102444800u64 }), hs256));
+        assert!(auth.admit(&RequestInfo::fake(1, 0, "GET", "/", &[])).is_none());
+        assert_eq!(auth.admit(&RequestInfo::fake(2, api, "GET", "/", &[])).map(|x| x.status), Some(401));
+        assert!(auth.admit(&RequestInfo::fake(3, api, "GET", "/", &[("Authorization", &bearer)])).is_none());
+        assert_eq!(auth.take(3).map(|x| x.sub), Some(String::from("alice")));
+
+        auth.config(api, None);
+        assert!(!auth.active());
+    }
+
+    #[test]
+    fn requires_https_key_sets() {
+        assert!(load_jwks("http://issuer.example/keys").err().unwrap().contains("https"));
+        assert!(load_jwks("HTTP://issuer.example/keys").is_err());
+        assert!(is_remote("https://issuer.example/keys"));
+        assert!(!is_remote("keys.json"));
+    }
+
+    #[test]
+    fn rejects_unusable_jwks() {
+        assert!(parse_jwks("{\"keys\": [{\"kty\": \"EC\", \"crv\": \"P-384\"}]}").is_err());
+        assert!(parse_jwks("not json").is_err());
+        assert_eq!(parse_jwks(RSA_JWK).unwrap().len(), 1);
+    }
+}
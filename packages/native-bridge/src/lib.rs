@@ -1,20 +1,73 @@
+// The codecs and policies build everywhere so their tests run on any host; the plugin itself,
+// the Neon bindings and everything that talks to HTTP.sys or Win32, is Windows only.
+#![cfg_attr(not(windows), allow(dead_code))]
+
+mod certmap;
+mod codec;
+mod cookie;
+mod cors;
+mod headers;
+mod jwt;
+mod limits;
+mod message;
+mod metrics;
+mod negotiate;
+mod proxy;
+mod ratelimit;
+
+#[cfg(windows)]
 mod support;
+#[cfg(windows)]
+mod binding;
+#[cfg(windows)]
+mod client;
+#[cfg(windows)]
+mod form;
+#[cfg(windows)]
+mod grpc;
+#[cfg(windows)]
+mod handover;
+#[cfg(windows)]
 mod http;
+#[cfg(windows)]
 mod service;
+#[cfg(windows)]
+mod session;
+#[cfg(windows)]
+mod sse;
+#[cfg(windows)]
+mod tcpinfo;
+#[cfg(windows)]
+mod trace;
+#[cfg(windows)]
 mod user;
+#[cfg(windows)]
 mod win32;
+#[cfg(windows)]
+mod wire;
 
-use http::*;
-use service::*;
-use user::*;
-
+#[cfg(windows)]
 use neon::prelude::*;
 
+#[cfg(windows)]
 #[neon::main]
 fn main(mut cx: ModuleContext) -> NeonResult<()> {
-    http_bind(&mut cx)?;
-    service_bind(&mut cx)?;
-    user_bind(&mut cx)?;
+    http::http_bind(&mut cx)?;
+    binding::binding_bind(&mut cx)?;
+    client::client_bind(&mut cx)?;
+    cookie::cookie_bind(&mut cx)?;
+    proxy::proxy_bind(&mut cx)?;
+    grpc::grpc_bind(&mut cx)?;
+    handover::handover_bind(&mut cx)?;
+    negotiate::negotiate_bind(&mut cx)?;
+    form::form_bind(&mut cx)?;
+    service::service_bind(&mut cx)?;
+    session::session_bind(&mut cx)?;
+    sse::sse_bind(&mut cx)?;
+    tcpinfo::tcpinfo_bind(&mut cx)?;
+    trace::trace_bind(&mut cx)?;
+    user::user_bind(&mut cx)?;
+    wire::wire_bind(&mut cx)?;
 
     Ok(())
 }
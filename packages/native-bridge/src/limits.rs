@@ -0,0 +1,253 @@
+use super::message::*;
+
+use std::collections::HashMap;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Mutex;
+use std::sync::RwLock;
+
+pub const EXPECT_ACCEPT: u8 = 0;
+pub const EXPECT_REJECT: u8 = 1;
+pub const EXPECT_DEFER: u8 = 2;
+
+pub fn parse_expect(value: &str) -> Option<u8> {
+    match value {
+        "accept" => Some(EXPECT_ACCEPT),
+        "reject" => Some(EXPECT_REJECT),
+        "defer" => Some(EXPECT_DEFER),
+        _ => None,
+    }
+}
+
+#[derive(Default)]
+pub struct LimitStats {
+    pub length: AtomicU64,
+    pub body: AtomicU64,
+    pub expect: AtomicU64,
+    pub deferred: AtomicU64,
+}
+
+impl LimitStats {
+    // 413s are split by whether the declared length or the body itself went over the limit.
+    pub fn counts(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("length", self.length.load(Relaxed)),
+            ("body", self.body.load(Relaxed)),
+            ("expect", self.expect.load(Relaxed)),
+            ("deferred", self.deferred.load(Relaxed)),
+        ]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Policy {
+    max: u64,
+    expect: u8,
+}
+
+const NO_POLICY: Policy = Policy { max: 0, expect: EXPECT_ACCEPT };
+
+#[derive(Default)]
+struct Usage {
+    used: u64,
+    max: u64,
+    deferred: bool,
+}
+
+// Policies are keyed by URL group; group 0 is the queue default for groups without their own.
+pub struct BodyLimits {
+    policies: RwLock<HashMap<u64, Policy>>,
+    sizes: Mutex<HashMap<u64, Usage>>,
+    pub stats: LimitStats,
+}
+
+impl BodyLimits {
+    pub fn new() -> Self {
+        Self {
+            policies: RwLock::new(HashMap::new()),
+            sizes: Mutex::new(HashMap::new()),
+            stats: LimitStats::default(),
+        }
+    }
+
+    pub fn config(&self, group: u64, max: u64, expect: u8) {
+        if let Ok(mut map) = self.policies.write() {
+            let policy = Policy { max, expect };
+            if policy == NO_POLICY && group == 0 {
+                map.remove(&group);
+            } else {
+                map.insert(group, policy);
+            }
+        }
+    }
+
+    fn policy(&self, group: u64) -> Policy {
+        match self.policies.read() {
+            Ok(map) => map.get(&group).or_else(|| map.get(&0)).copied().unwrap_or(NO_POLICY),
+            Err(_) => NO_POLICY,
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        match self.policies.read() {
+            Ok(map) => map.values().any(|x| *x != NO_POLICY),
+            Err(_) => false,
+        }
+    }
+
+    pub fn admit(&self, info: &RequestInfo) -> Option<Reply> {
+        let policy = self.policy(info.group);
+        let continues = info.header("Expect").map(|x| x.eq_ignore_ascii_case("100-continue")).unwrap_or(false);
+        if continues && policy.expect == EXPECT_REJECT {
+            self.stats.expect.fetch_add(1, Relaxed);
+            return Some(Reply::new(417, "Expectation Failed").header("Content-Length", "0"));
+        }
+
+        if policy.max > 0 {
+            if let Some(length) = info.header("Content-Length").and_then(|x| x.trim().parse::<u64>().ok()) {
+                if length > policy.max {
+                    self.stats.length.fetch_add(1, Relaxed);
+                    return Some(too_large());
+                }
+            }
+        }
+
+        let deferred = continues && policy.expect == EXPECT_DEFER;
+        if deferred {
+            self.stats.deferred.fetch_add(1, Relaxed);
+        }
+
+        if policy.max > 0 || deferred {
+            if let Ok(mut map) = self.sizes.lock() {
+                map.insert(info.id, Usage { used: 0, max: policy.max, deferred });
+            }
+        }
+
+        None
+    }
+
+    // Deferred requests wait for JS: reading the body sends the 100 Continue, a final response skips it.
+    pub fn deferred(&self, id: u64) -> bool {
+        match self.sizes.lock() {
+            Ok(map) => map.get(&id).map(|x| x.deferred).unwrap_or(false),
+            Err(_) => false,
+        }
+    }
+
+    pub fn limit(&self, id: u64, max: u64) {
+        if let Ok(mut map) = self.sizes.lock() {
+            map.entry(id).or_default().max = max;
+        }
+    }
+
+    pub fn remaining(&self, id: u64) -> Option<u64> {
+        let map = self.sizes.lock().ok()?;
+        let entry = map.get(&id)?;
+        if entry.max < 1 {
+            return None;
+        }
+
+        Some(entry.max.saturating_sub(entry.used))
+    }
+
+    pub fn consume(&self, id: u64, size: u64) -> bool {
+        if let Ok(mut map) = self.sizes.lock() {
+            if let Some(entry) = map.get_mut(&id) {
+                entry.used += size;
+                if entry.max > 0 && entry.used > entry.max {
+                    self.stats.body.fetch_add(1, Relaxed);
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
+    pub fn forget(&self, id: u64) {
+        if let Ok(mut map) = self.sizes.lock() {
+            map.remove(&id);
+        }
+    }
+
+    pub fn tracked(&self) -> usize {
+        self.sizes.lock().map(|x| x.len()).unwrap_or(0)
+    }
+}
+
+pub fn too_large() -> Reply {
+    Reply::new(413, "Payload Too Large")
+        .header("Content-Length", "0")
+        .header("Connection", "close")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(id: u64, group: u64, headers: &[(&str, &str)]) -> RequestInfo {
+        RequestInfo::fake(id, group, "POST", "/upload", headers)
+    }
+
+    #[test]
+    fn checks_length_before_deferring() {
+        let limits = BodyLimits::new();
+        limits.config(0, 10, EXPECT_DEFER);
+
+        let big = request(1, 0, &[("Expect", "100-continue"), ("Content-Length", "11")]);
+        assert_eq!(limits.admit(&big).map(|x| x.status), Some(413));
+        assert!(!limits.deferred(1));
+
+        let small = request(2, 0, &[("Expect", "100-Continue"), ("Content-Length", "4")]);
+        assert_eq!(limits.admit(&small), None);
+        assert!(limits.deferred(2));
+        assert_eq!(limits.stats.deferred.load(Relaxed), 1);
+    }
+
+    #[test]
+    fn rejects_expectations() {
+        let limits = BodyLimits::new();
+        limits.config(0, 0, EXPECT_REJECT);
+        assert!(limits.active());
+        assert_eq!(limits.admit(&request(1, 0, &[("Expect", "100-continue")])).map(|x| x.status), Some(417));
+        assert_eq!(limits.admit(&request(2, 0, &[])), None);
+    }
+
+    #[test]
+    fn limits_per_group() {
+        let limits = BodyLimits::new();
+        let api = url_group("limits-api");
+        let files = url_group("limits-files");
+        limits.config(0, 8, EXPECT_ACCEPT);
+        limits.config(files, 1024, EXPECT_ACCEPT);
+
+        let length = [("Content-Length", "100")];
+        assert_eq!(limits.admit(&request(1, api, &length)).map(|x| x.status), Some(413));
+        assert_eq!(limits.admit(&request(2, files, &length)), None);
+        assert_eq!(limits.remaining(2), Some(1024));
+
+        limits.config(0, 0, EXPECT_ACCEPT);
+        assert!(limits.active());
+        assert_eq!(limits.admit(&request(3, api, &length)), None);
+        assert_eq!(limits.remaining(3), None);
+    }
+
+    #[test]
+    fn consumes_and_forgets() {
+        let limits = BodyLimits::new();
+        limits.config(0, 10, EXPECT_ACCEPT);
+        assert_eq!(limits.admit(&request(1, 0, &[])), None);
+        assert!(limits.consume(1, 6));
+        assert_eq!(limits.remaining(1), Some(4));
+        assert!(!limits.consume(1, 6));
+        assert_eq!(limits.stats.body.load(Relaxed), 1);
+
+        limits.limit(1, 100);
+        assert_eq!(limits.remaining(1), Some(88));
+
+        assert!(limits.consume(7, 1000));
+        limits.forget(1);
+        assert_eq!(limits.tracked(), 0);
+        assert_eq!(limits.remaining(1), None);
+    }
+}
@@ -0,0 +1,237 @@
+use std::net::SocketAddr;
+use std::sync::Mutex;
+
+pub const REQUEST_HEADER_NAMES: [&str; 41] = [
+    "Cache-Control", "Connection", "Date", "Keep-Alive", "Pragma",
+    "Trailer", "Transfer-Encoding", "Upgrade", "Via", "Warning",
+    "Allow", "Content-Length", "Content-Type", "Content-Encoding", "Content-Language",
+    "Content-Location", "Content-MD5", "Content-Range", "Expires", "Last-Modified",
+    "Accept", "Accept-Charset", "Accept-Encoding", "Accept-Language", "Authorization",
+    "Cookie", "Expect", "From", "Host", "If-Match",
+    "If-Modified-Since", "If-None-Match", "If-Range", "If-Unmodified-Since", "Max-Forwards",
+    "Proxy-Authorization", "Referer", "Range", "TE", "Translate",
+    "User-Agent",
+];
+
+pub const RESPONSE_HEADER_NAMES: [&str; 10] = [
+    "Accept-Ranges", "Age", "ETag", "Location", "Proxy-Authenticate",
+    "Retry-After", "Server", "Set-Cookie", "Vary", "WWW-Authenticate",
+];
+
+pub const VERB_NAMES: [&str; 20] = [
+    "", "", "",
+    "OPTIONS", "GET", "HEAD",
+    "POST", "PUT", "DELETE",
+    "TRACE", "CONNECT", "TRACK",
+    "MOVE", "COPY",
+    "PROPFIND", "PROPPATCH", "MKCOL",
+    "LOCK", "UNLOCK", "SEARCH",
+];
+
+pub fn response_header_id(name: &str) -> i32 {
+    for (i, known) in RESPONSE_HEADER_NAMES.iter().enumerate() {
+        if known.eq_ignore_ascii_case(name) {
+            return (i + 20) as i32;
+        }
+    }
+
+    for (i, known) in REQUEST_HEADER_NAMES[0..20].iter().enumerate() {
+        if known.eq_ignore_ascii_case(name) {
+            return i as i32;
+        }
+    }
+
+    -1
+}
+
+// URL groups are told apart by the URL context they register their prefixes with. The context is
+// derived from the group name so a worker in another process can name the same group.
+static URL_GROUPS: Mutex<Vec<(u64, String)>> = Mutex::new(Vec::new());
+
+pub fn url_group(name: &str) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for byte in name.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    let context = hash.max(1);
+    if let Ok(mut list) = URL_GROUPS.lock() {
+        if !list.iter().any(|(x, _)| *x == context) {
+            list.push((context, name.to_string()));
+        }
+    }
+
+    context
+}
+
+pub fn url_group_name(context: u64) -> String {
+    if context == 0 {
+        return String::new();
+    }
+
+    URL_GROUPS.lock().ok()
+        .and_then(|list| list.iter().find(|(x, _)| *x == context).map(|(_, name)| name.clone()))
+        .unwrap_or_else(|| format!("{:016x}", context))
+}
+
+pub fn response_header_name(id: i32) -> &'static str {
+    if id >= 20 {
+        return RESPONSE_HEADER_NAMES.get(id as usize - 20).copied().unwrap_or("");
+    }
+
+    REQUEST_HEADER_NAMES.get(id as usize).copied().unwrap_or("")
+}
+
+pub struct RequestInfo {
+    pub id: u64,
+    pub group: u64,
+    pub verb: String,
+    pub version: (u16, u16),
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: bool,
+    pub http2: bool,
+    pub secure: bool,
+    pub local: Option<SocketAddr>,
+    pub remote: Option<SocketAddr>,
+}
+
+impl RequestInfo {
+    #[cfg(test)]
+    pub fn fake(id: u64, group: u64, verb: &str, url: &str, headers: &[(&str, &str)]) -> Self {
+        Self {
+            id,
+            group,
+            verb: String::from(verb),
+            version: (1, 1),
+            url: String::from(url),
+            headers: headers.iter().map(|(x, y)| (String::from(*x), String::from(*y))).collect(),
+            body: false,
+            http2: false,
+            secure: false,
+            local: None,
+            remote: None,
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        for (key, value) in self.headers.iter() {
+            if key.eq_ignore_ascii_case(name) {
+                return Some(value);
+            }
+        }
+
+        None
+    }
+
+    pub fn target(&self) -> &str {
+        let mut target = &self.url[..];
+        if let Some(i) = target.find("://") {
+            target = &target[i + 3..];
+            target = &target[target.find('/').unwrap_or(target.len())..];
+        }
+
+        if target.len() < 1 {
+            return "/";
+        }
+
+        target
+    }
+
+    pub fn path(&self) -> &str {
+        let target = self.target();
+        match target.find('?') {
+            Some(i) => &target[..i],
+            None => target,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Reply {
+    pub status: u16,
+    pub reason: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Reply {
+    pub fn new(status: u16, reason: &str) -> Self {
+        Self {
+            status,
+            reason: String::from(reason),
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((String::from(name), String::from(value)));
+        self
+    }
+
+    pub fn body(mut self, data: Vec<u8>) -> Self {
+        self.body = data;
+        self
+    }
+
+    // HTTP.sys lengths and counts are 16 bits wide, so larger values cannot be described to it.
+    pub fn fits(&self) -> bool {
+        let limit = u16::MAX as usize;
+        self.reason.len() <= limit && self.headers.len() <= limit && self.headers.iter().all(|(x, y)| x.len() <= limit && y.len() <= limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_names() {
+        assert_eq!(response_header_id("set-cookie"), 27);
+        assert_eq!(response_header_id("Content-Type"), 12);
+        assert_eq!(response_header_id("Host"), -1);
+        assert_eq!(response_header_id("X-Custom"), -1);
+        for id in 0..30 {
+            assert_eq!(response_header_id(response_header_name(id)), id);
+        }
+
+        assert_eq!(response_header_name(30), "");
+    }
+
+    #[test]
+    fn url_groups() {
+        let api = url_group("api");
+        assert_eq!(url_group("api"), api);
+        assert_ne!(url_group("static"), api);
+        assert_ne!(url_group(""), 0);
+        assert_eq!(url_group_name(api), "api");
+        assert_eq!(url_group_name(0), "");
+        assert_eq!(url_group_name(0x1234), "0000000000001234");
+    }
+
+    #[test]
+    fn request_targets() {
+        let info = RequestInfo::fake(1, 0, "GET", "http://localhost:8080/a/b?x=1", &[("Host", "localhost"), ("x-id", "7")]);
+        assert_eq!(info.target(), "/a/b?x=1");
+        assert_eq!(info.path(), "/a/b");
+        assert_eq!(info.header("X-ID"), Some("7"));
+        assert_eq!(info.header("Accept"), None);
+
+        assert_eq!(RequestInfo::fake(1, 0, "GET", "https://localhost", &[]).target(), "/");
+        assert_eq!(RequestInfo::fake(1, 0, "GET", "/only?q", &[]).path(), "/only");
+    }
+
+    #[test]
+    fn reply_limits() {
+        let reply = Reply::new(200, "OK").header("X-A", "1").body(b"hi".to_vec());
+        assert!(reply.fits());
+        assert_eq!(reply.headers, vec![(String::from("X-A"), String::from("1"))]);
+
+        let long = "x".repeat(u16::MAX as usize + 1);
+        assert!(!Reply::new(200, &long).fits());
+        assert!(!Reply::new(200, "OK").header("X-A", &long).fits());
+        assert!(!Reply::new(200, "OK").header(&long, "1").fits());
+    }
+}
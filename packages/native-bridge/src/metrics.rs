@@ -0,0 +1,399 @@
+use super::limits::*;
+use super::message::*;
+use super::ratelimit::*;
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::RwLock;
+use std::time::Instant;
+
+pub const LATENCY_BOUNDS: [f64; 12] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+pub const STATUS_CLASSES: [&str; 6] = ["other", "1xx", "2xx", "3xx", "4xx", "5xx"];
+
+pub struct Histogram {
+    buckets: [AtomicU64; LATENCY_BOUNDS.len()],
+    count: AtomicU64,
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Self {
+            buckets: Default::default(),
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, seconds: f64) {
+        for (i, bound) in LATENCY_BOUNDS.iter().enumerate() {
+            if seconds <= *bound {
+                self.buckets[i].fetch_add(1, Relaxed);
+            }
+        }
+
+        self.count.fetch_add(1, Relaxed);
+        self.sum.fetch_add((seconds * 1e6) as u64, Relaxed);
+    }
+}
+
+struct Counters {
+    received: Mutex<HashMap<String, u64>>,
+    responses: [AtomicU64; STATUS_CLASSES.len()],
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    latency: Histogram,
+}
+
+impl Counters {
+    fn new() -> Self {
+        Self {
+            received: Mutex::new(HashMap::new()),
+            responses: Default::default(),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            latency: Histogram::new(),
+        }
+    }
+}
+
+pub struct GroupSnapshot {
+    pub group: String,
+    pub received: BTreeMap<String, u64>,
+    pub responses: [u64; STATUS_CLASSES.len()],
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub in_flight: u64,
+    pub latency: Vec<(f64, u64)>,
+    pub latency_count: u64,
+    pub latency_sum: f64,
+}
+
+pub struct Snapshot {
+    pub queue: String,
+    pub groups: Vec<GroupSnapshot>,
+    pub in_flight: u64,
+    pub pending_io: i64,
+    pub errors: BTreeMap<u32, u64>,
+    pub limits: Vec<(&'static str, u64)>,
+}
+
+// Counters are kept per URL group, identified by the URL context HTTP.sys hands back with each
+// request. Receive errors happen before there is a request, so they are counted per queue.
+pub struct Metrics {
+    queue: String,
+    groups: Mutex<HashMap<u64, Arc<Counters>>>,
+    errors: Mutex<HashMap<u32, u64>>,
+    started: Mutex<HashMap<u64, (u64, Instant)>>,
+    url: RwLock<Option<String>>,
+    allow: RwLock<Vec<Cidr>>,
+}
+
+impl Metrics {
+    pub fn new(queue: &str) -> Self {
+        Self {
+            queue: String::from(queue),
+            groups: Mutex::new(HashMap::new()),
+            errors: Mutex::new(HashMap::new()),
+            started: Mutex::new(HashMap::new()),
+            url: RwLock::new(None),
+            allow: RwLock::new(Vec::new()),
+        }
+    }
+
+    fn group(&self, group: u64) -> Option<Arc<Counters>> {
+        let mut map = self.groups.lock().ok()?;
+        Some(map.entry(group).or_insert_with(|| Arc::new(Counters::new())).clone())
+    }
+
+    fn request(&self, id: u64) -> Option<Arc<Counters>> {
+        let group = self.started.lock().ok()?.get(&id)?.0;
+        self.group(group)
+    }
+
+    pub fn received(&self, id: u64, group: u64, verb: &str) {
+        if let Some(counters) = self.group(group) {
+            if let Ok(mut map) = counters.received.lock() {
+                *map.entry(String::from(verb)).or_insert(0) += 1;
+            }
+        }
+
+        if let Ok(mut map) = self.started.lock() {
+            map.insert(id, (group, Instant::now()));
+        }
+    }
+
+    pub fn error(&self, err: u32) {
+        if let Ok(mut map) = self.errors.lock() {
+            *map.entry(err).or_insert(0) += 1;
+        }
+    }
+
+    pub fn status(&self, id: u64, status: u16) {
+        let class = status as usize / 100;
+        let class = if class < STATUS_CLASSES.len() { class } else { 0 };
+        if let Some(counters) = self.request(id) {
+            counters.responses[class].fetch_add(1, Relaxed);
+        }
+    }
+
+    pub fn read(&self, id: u64, size: u32) {
+        if let Some(counters) = self.request(id) {
+            counters.bytes_in.fetch_add(size as u64, Relaxed);
+        }
+    }
+
+    pub fn wrote(&self, id: u64, size: u32) {
+        if let Some(counters) = self.request(id) {
+            counters.bytes_out.fetch_add(size as u64, Relaxed);
+        }
+    }
+
+    pub fn finish(&self, id: u64) {
+        let started = self.started.lock().ok().and_then(|mut x| x.remove(&id));
+        if let Some((group, start)) = started {
+            if let Some(counters) = self.group(group) {
+                counters.latency.observe(start.elapsed().as_secs_f64());
+            }
+        }
+    }
+
+    pub fn forget(&self, id: u64) {
+        if let Ok(mut map) = self.started.lock() {
+            map.remove(&id);
+        }
+    }
+
+    pub fn serve(&self, url: Option<String>, allow: Vec<Cidr>) {
+        if let Ok(mut value) = self.url.write() {
+            *value = url;
+        }
+
+        if let Ok(mut list) = self.allow.write() {
+            *list = allow;
+        }
+    }
+
+    // Without an allow list only loopback clients can read the endpoint.
+    pub fn serves(&self, path: &str, remote: Option<SocketAddr>) -> bool {
+        let matches = self.url.read().map(|x| x.as_deref() == Some(path)).unwrap_or(false);
+        let addr = match remote {
+            Some(addr) if matches => addr.ip(),
+            _ => return false,
+        };
+
+        match self.allow.read() {
+            Ok(list) if list.len() > 0 => list.iter().any(|x| x.contains(&addr)),
+            Ok(_) => addr.is_loopback() || Cidr::parse("::ffff:127.0.0.0/104").map_or(false, |x| x.contains(&addr)),
+            Err(_) => false,
+        }
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.started.lock().map(|x| x.len()).unwrap_or(0)
+    }
+
+    pub fn active(&self) -> bool {
+        self.url.read().map(|x| x.is_some()).unwrap_or(false)
+    }
+
+    pub fn snapshot(&self, pending_io: i64, limits: &LimitStats) -> Snapshot {
+        let errors = self.errors.lock().map(|x| x.iter().map(|(k, v)| (*k, *v)).collect()).unwrap_or_default();
+        let mut flights = HashMap::<u64, u64>::new();
+        if let Ok(map) = self.started.lock() {
+            for (group, _) in map.values() {
+                *flights.entry(*group).or_insert(0) += 1;
+            }
+        }
+
+        let list = self.groups.lock().map(|x| x.iter().map(|(k, v)| (*k, v.clone())).collect::<Vec<_>>()).unwrap_or_default();
+        let mut groups = Vec::new();
+        for (context, counters) in list {
+            let received = counters.received.lock().map(|x| x.iter().map(|(k, v)| (k.clone(), *v)).collect()).unwrap_or_default();
+            let mut responses = [0; STATUS_CLASSES.len()];
+            for (i, value) in counters.responses.iter().enumerate() {
+                responses[i] = value.load(Relaxed);
+            }
+
+            let latency = LATENCY_BOUNDS.iter()
+                .zip(counters.latency.buckets.iter())
+                .map(|(bound, count)| (*bound, count.load(Relaxed)))
+                .collect();
+
+            groups.push(GroupSnapshot {
+                group: url_group_name(context),
+                received,
+                responses,
+                bytes_in: counters.bytes_in.load(Relaxed),
+                bytes_out: counters.bytes_out.load(Relaxed),
+                in_flight: flights.get(&context).copied().unwrap_or(0),
+                latency,
+                latency_count: counters.latency.count.load(Relaxed),
+                latency_sum: counters.latency.sum.load(Relaxed) as f64 / 1e6,
+            });
+        }
+
+        groups.sort_by(|a, b| a.group.cmp(&b.group));
+        Snapshot {
+            queue: self.queue.clone(),
+            in_flight: groups.iter().map(|x| x.in_flight).sum(),
+            groups,
+            pending_io,
+            errors,
+            limits: limits.counts(),
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+impl Snapshot {
+    pub fn prometheus(&self) -> String {
+        let mut out = String::new();
+        let queue = escape(&self.queue);
+        let labels = self.groups.iter()
+            .map(|x| format!("queue=\"{}\",group=\"{}\"", queue, escape(&x.group)))
+            .collect::<Vec<_>>();
+
+        out.push_str("# HELP http_native_requests_total Requests received by verb.\n");
+        out.push_str("# TYPE http_native_requests_total counter\n");
+        for (group, labels) in self.groups.iter().zip(labels.iter()) {
+            for (verb, count) in group.received.iter() {
+                writeln!(out, "http_native_requests_total{{{},verb=\"{}\"}} {}", labels, escape(verb), count).ok();
+            }
+        }
+
+        out.push_str("# HELP http_native_responses_total Responses sent by status class.\n");
+        out.push_str("# TYPE http_native_responses_total counter\n");
+        for (group, labels) in self.groups.iter().zip(labels.iter()) {
+            for (class, count) in STATUS_CLASSES.iter().zip(group.responses.iter()) {
+                writeln!(out, "http_native_responses_total{{{},class=\"{}\"}} {}", labels, class, count).ok();
+            }
+        }
+
+        out.push_str("# HELP http_native_received_bytes_total Request body bytes received.\n");
+        out.push_str("# TYPE http_native_received_bytes_total counter\n");
+        for (group, labels) in self.groups.iter().zip(labels.iter()) {
+            writeln!(out, "http_native_received_bytes_total{{{}}} {}", labels, group.bytes_in).ok();
+        }
+
+        out.push_str("# HELP http_native_sent_bytes_total Response bytes sent.\n");
+        out.push_str("# TYPE http_native_sent_bytes_total counter\n");
+        for (group, labels) in self.groups.iter().zip(labels.iter()) {
+            writeln!(out, "http_native_sent_bytes_total{{{}}} {}", labels, group.bytes_out).ok();
+        }
+
+        out.push_str("# HELP http_native_request_duration_seconds Time from receive to final send.\n");
+        out.push_str("# TYPE http_native_request_duration_seconds histogram\n");
+        for (group, labels) in self.groups.iter().zip(labels.iter()) {
+            for (bound, count) in group.latency.iter() {
+                writeln!(out, "http_native_request_duration_seconds_bucket{{{},le=\"{}\"}} {}", labels, bound, count).ok();
+            }
+
+            writeln!(out, "http_native_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}", labels, group.latency_count).ok();
+            writeln!(out, "http_native_request_duration_seconds_sum{{{}}} {}", labels, group.latency_sum).ok();
+            writeln!(out, "http_native_request_duration_seconds_count{{{}}} {}", labels, group.latency_count).ok();
+        }
+
+        out.push_str("# HELP http_native_requests_in_flight Requests received without a final response.\n");
+        out.push_str("# TYPE http_native_requests_in_flight gauge\n");
+        for (group, labels) in self.groups.iter().zip(labels.iter()) {
+            writeln!(out, "http_native_requests_in_flight{{{}}} {}", labels, group.in_flight).ok();
+        }
+
+        out.push_str("# HELP http_native_pending_io Outstanding overlapped operations in the process.\n");
+        out.push_str("# TYPE http_native_pending_io gauge\n");
+        writeln!(out, "http_native_pending_io {}", self.pending_io).ok();
+
+        out.push_str("# HELP http_native_receive_errors_total Failed receives by Win32 error code.\n");
+        out.push_str("# TYPE http_native_receive_errors_total counter\n");
+        for (code, count) in self.errors.iter() {
+            writeln!(out, "http_native_receive_errors_total{{queue=\"{}\",code=\"{}\"}} {}", queue, code, count).ok();
+        }
+
+        out.push_str("# HELP http_native_body_limits_total Requests refused with 413 or 417, or deferred, by body limits.\n");
+        out.push_str("# TYPE http_native_body_limits_total counter\n");
+        for (reason, count) in self.limits.iter() {
+            writeln!(out, "http_native_body_limits_total{{queue=\"{}\",reason=\"{}\"}} {}", queue, reason, count).ok();
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_per_group() {
+        let metrics = Metrics::new("queue");
+        let api = url_group("metrics-api");
+        metrics.received(1, api, "GET");
+        metrics.received(2, 0, "POST");
+        metrics.read(2, 10);
+        metrics.status(1, 204);
+        metrics.wrote(1, 5);
+        metrics.finish(1);
+
+        let limits = LimitStats::default();
+        limits.expect.fetch_add(2, Relaxed);
+
+        let snapshot = metrics.snapshot(0, &limits);
+        assert_eq!(snapshot.in_flight, 1);
+        assert_eq!(snapshot.groups.len(), 2);
+        assert_eq!(snapshot.groups[0].group, "");
+        assert_eq!(snapshot.groups[0].bytes_in, 10);
+        assert_eq!(snapshot.groups[0].in_flight, 1);
+        assert_eq!(snapshot.groups[1].group, "metrics-api");
+        assert_eq!(snapshot.groups[1].responses[2], 1);
+        assert_eq!(snapshot.groups[1].bytes_out, 5);
+        assert_eq!(snapshot.groups[1].latency_count, 1);
+
+        let text = snapshot.prometheus();
+        assert!(text.contains("http_native_requests_total{queue=\"queue\",group=\"metrics-api\",verb=\"GET\"} 1"));
+        assert!(text.contains("http_native_responses_total{queue=\"queue\",group=\"metrics-api\",class=\"2xx\"} 1"));
+        assert!(text.contains("http_native_requests_in_flight{queue=\"queue\",group=\"\"} 1"));
+        assert!(text.contains("http_native_body_limits_total{queue=\"queue\",reason=\"expect\"} 2"));
+        assert!(text.contains("http_native_body_limits_total{queue=\"queue\",reason=\"length\"} 0"));
+    }
+
+    #[test]
+    fn forget_clears_in_flight() {
+        let metrics = Metrics::new("queue");
+        metrics.received(1, 0, "GET");
+        metrics.received(2, 0, "GET");
+        metrics.forget(1);
+        metrics.forget(2);
+
+        assert_eq!(metrics.in_flight(), 0);
+        assert_eq!(metrics.snapshot(0, &LimitStats::default()).groups[0].latency_count, 0);
+    }
+
+    #[test]
+    fn serves_allowed_clients() {
+        let metrics = Metrics::new("queue");
+        let local = "127.0.0.1:5000".parse().ok();
+        let remote = "10.1.2.3:5000".parse().ok();
+        assert!(!metrics.serves("/metrics", local));
+
+        metrics.serve(Some("/metrics".into()), Vec::new());
+        assert!(metrics.serves("/metrics", local));
+        assert!(metrics.serves("/metrics", "[::1]:5000".parse().ok()));
+        assert!(!metrics.serves("/metrics", remote));
+        assert!(!metrics.serves("/other", local));
+        assert!(!metrics.serves("/metrics", None));
+
+        metrics.serve(Some("/metrics".into()), vec![Cidr::parse("10.0.0.0/8").unwrap()]);
+        assert!(metrics.serves("/metrics", remote));
+        assert!(!metrics.serves("/metrics", local));
+    }
+}
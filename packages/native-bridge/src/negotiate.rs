@@ -0,0 +1,503 @@
+#[cfg(windows)]
+use neon::prelude::*;
+
+#[cfg(windows)]
+use super::support::*;
+
+use std::sync::Arc;
+use std::sync::RwLock;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum AcceptKind {
+    Type,
+    Language,
+    Encoding,
+    Charset,
+}
+
+impl AcceptKind {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "type" | "accept" => Some(Self::Type),
+            "language" | "accept-language" => Some(Self::Language),
+            "encoding" | "accept-encoding" => Some(Self::Encoding),
+            "charset" | "accept-charset" => Some(Self::Charset),
+            _ => None,
+        }
+    }
+}
+
+pub struct Preference {
+    pub value: String,
+    pub params: Vec<(String, String)>,
+    pub q: u16,
+}
+
+impl Preference {
+    pub fn render(&self) -> String {
+        let mut result = self.value.clone();
+        for (name, value) in self.params.iter() {
+            result.push_str(&format!(";{}={}", name, value));
+        }
+
+        result
+    }
+}
+
+// Splits on delimiters outside of quoted strings, honoring backslash escapes inside them.
+fn split_quoted(value: &str, delim: char) -> Vec<&str> {
+    let mut result = Vec::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in value.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if quoted && c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            quoted = !quoted;
+        } else if !quoted && c == delim {
+            result.push(&value[start..i]);
+            start = i + 1;
+        }
+    }
+
+    result.push(&value[start..]);
+    result
+}
+
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    if value.len() < 2 || !value.starts_with('"') || !value.ends_with('"') {
+        return String::from(value);
+    }
+
+    let mut result = String::new();
+    let mut escaped = false;
+    for c in value[1..value.len() - 1].chars() {
+        if !escaped && c == '\\' {
+            escaped = true;
+        } else {
+            escaped = false;
+            result.push(c);
+        }
+    }
+
+    result
+}
+
+// qvalue = ( "0" [ "." 0*3DIGIT ] ) / ( "1" [ "." 0*3("0") ] ), scaled to thousandths.
+pub fn parse_q(value: &str) -> Option<u16> {
+    let (int, frac) = value.split_once('.').unwrap_or((value, ""));
+    if frac.len() > 3 || !frac.bytes().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+
+    let scaled = format!("{:0<3}", frac).parse::<u16>().ok()?;
+    match int {
+        "0" => Some(scaled),
+        "1" if scaled == 0 => Some(1000),
+        _ => None,
+    }
+}
+
+// Elements with a malformed weight are dropped rather than guessed at.
+pub fn parse_accept(value: &str) -> Vec<Preference> {
+    let mut result = Vec::new();
+    for element in split_quoted(value, ',') {
+        let mut parts = split_quoted(element, ';').into_iter();
+        let main = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        if main.len() < 1 {
+            continue;
+        }
+
+        let mut q = Some(1000);
+        let mut params = Vec::new();
+        for part in parts {
+            let (name, value) = part.split_once('=').unwrap_or((part, ""));
+            let name = name.trim().to_ascii_lowercase();
+            if name == "q" {
+                q = parse_q(value.trim());
+                break;
+            }
+
+            if name.len() > 0 {
+                params.push((name, unquote(value)));
+            }
+        }
+
+        if let Some(q) = q {
+            result.push(Preference { value: main, params, q });
+        }
+    }
+
+    result
+}
+
+pub fn sorted(mut list: Vec<Preference>) -> Vec<Preference> {
+    list.sort_by(|a, b| b.q.cmp(&a.q));
+    list
+}
+
+fn media_specificity(range: &Preference, offer: &Preference) -> Option<usize> {
+    let (range_type, range_sub) = range.value.split_once('/')?;
+    let (offer_type, offer_sub) = offer.value.split_once('/')?;
+    if range_type == "*" {
+        return if range_sub == "*" { Some(0) } else { None };
+    }
+
+    if range_type != offer_type {
+        return None;
+    }
+
+    if range_sub == "*" {
+        return Some(1);
+    }
+
+    if range_sub != offer_sub {
+        return None;
+    }
+
+    for (name, value) in range.params.iter() {
+        let found = offer.params.iter().any(|(x, y)| x == name && (y == value || name == "charset" && y.eq_ignore_ascii_case(value)));
+        if !found {
+            return None;
+        }
+    }
+
+    Some(2 + range.params.len())
+}
+
+fn language_specificity(range: &str, offer: &str) -> Option<usize> {
+    if range == "*" {
+        return Some(0);
+    }
+
+    if offer == range || offer.starts_with(range) && offer.as_bytes().get(range.len()) == Some(&b'-') {
+        return Some(1 + range.split('-').count());
+    }
+
+    None
+}
+
+fn coding_alias(value: &str) -> &str {
+    match value {
+        "x-gzip" => "gzip",
+        "x-compress" => "compress",
+        _ => value,
+    }
+}
+
+fn weight(kind: AcceptKind, prefs: &[Preference], empty: bool, offer: &Preference) -> u16 {
+    if kind == AcceptKind::Encoding {
+        let name = coding_alias(&offer.value);
+        if empty {
+            return if name == "identity" { 1000 } else { 0 };
+        }
+
+        if let Some(pref) = prefs.iter().find(|x| coding_alias(&x.value) == name) {
+            return pref.q;
+        }
+
+        if let Some(pref) = prefs.iter().find(|x| x.value == "*") {
+            return pref.q;
+        }
+
+        // Identity stays acceptable unless excluded, but any coding the client asked for outranks it.
+        return if name == "identity" { 1 } else { 0 };
+    }
+
+    if kind == AcceptKind::Charset {
+        if let Some(pref) = prefs.iter().find(|x| x.value == offer.value) {
+            return pref.q;
+        }
+
+        return prefs.iter().find(|x| x.value == "*").map(|x| x.q).unwrap_or(0);
+    }
+
+    let mut best: Option<(usize, u16)> = None;
+    for pref in prefs {
+        let specificity = match kind {
+            AcceptKind::Type => media_specificity(pref, offer),
+            _ => language_specificity(&pref.value, &offer.value),
+        };
+
+        if let Some(specificity) = specificity {
+            if best.map(|(x, _)| specificity > x).unwrap_or(true) {
+                best = Some((specificity, pref.q));
+            }
+        }
+    }
+
+    best.map(|(_, q)| q).unwrap_or(0)
+}
+
+// Returns the index of the offer with the highest weight, preferring earlier offers on ties.
+pub fn best_match<T: AsRef<str>>(kind: AcceptKind, header: Option<&str>, offers: &[T]) -> Option<usize> {
+    let header = match header {
+        Some(header) => header,
+        None => return if offers.len() > 0 { Some(0) } else { None },
+    };
+
+    let empty = header.trim().len() < 1;
+    if empty && kind != AcceptKind::Encoding {
+        return if offers.len() > 0 { Some(0) } else { None };
+    }
+
+    let prefs = parse_accept(header);
+    let mut best: Option<(usize, u16)> = None;
+    for (i, offer) in offers.iter().enumerate() {
+        let offer = match parse_accept(offer.as_ref()).pop() {
+            Some(offer) => offer,
+            None => continue,
+        };
+
+        let q = weight(kind, &prefs, empty, &offer);
+        if q > 0 && best.map(|(_, x)| q > x).unwrap_or(true) {
+            best = Some((i, q));
+        }
+    }
+
+    best.map(|(i, _)| i)
+}
+
+pub struct Offers {
+    pub types: Vec<String>,
+    pub languages: Vec<String>,
+    pub encodings: Vec<String>,
+    pub charsets: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct Negotiated {
+    pub media_type: Option<Option<String>>,
+    pub language: Option<Option<String>>,
+    pub encoding: Option<Option<String>>,
+    pub charset: Option<Option<String>>,
+}
+
+pub struct Negotiation {
+    offers: RwLock<Option<Arc<Offers>>>,
+}
+
+impl Negotiation {
+    pub fn new() -> Self {
+        Self { offers: RwLock::new(None) }
+    }
+
+    pub fn config(&self, offers: Option<Offers>) {
+        if let Ok(mut value) = self.offers.write() {
+            *value = offers.map(Arc::new);
+        }
+    }
+
+    pub fn select<'a, F>(&self, header: F) -> Option<Negotiated> where F: Fn(AcceptKind) -> Option<&'a str> {
+        let offers = self.offers.read().ok()?.clone()?;
+        let pick = |kind: AcceptKind, list: &Vec<String>| {
+            if list.len() < 1 {
+                return None;
+            }
+
+            Some(best_match(kind, header(kind), list).map(|i| list[i].clone()))
+        };
+
+        Some(Negotiated {
+            media_type: pick(AcceptKind::Type, &offers.types),
+            language: pick(AcceptKind::Language, &offers.languages),
+            encoding: pick(AcceptKind::Encoding, &offers.encodings),
+            charset: pick(AcceptKind::Charset, &offers.charsets),
+        })
+    }
+}
+
+#[cfg(windows)]
+pub fn negotiated_to_js<'a, T>(cx: &mut T, value: &Negotiated) -> JsResult<'a, JsObject> where T: Context<'a> {
+    let obj = cx.empty_object();
+    let fields = [
+        ("type", &value.media_type),
+        ("language", &value.language),
+        ("encoding", &value.encoding),
+        ("charset", &value.charset),
+    ];
+
+    for (name, field) in fields {
+        match field {
+            Some(Some(x)) => {
+                let js_value = cx.string(x);
+                obj.set(cx, name, js_value)?;
+            },
+            Some(None) => {
+                let js_value = cx.null();
+                obj.set(cx, name, js_value)?;
+            },
+            None => (),
+        }
+    }
+
+    Ok(obj)
+}
+
+#[cfg(windows)]
+fn arg_kind(cx: &mut FunctionContext, i: &mut i32) -> NeonResult<AcceptKind> {
+    let kind = cx.arg_string(i)?;
+    match AcceptKind::parse(&kind.to_ascii_lowercase()) {
+        Some(kind) => Ok(kind),
+        None => cx.throw_type_error(format!("Unknown accept kind: {}", kind)),
+    }
+}
+
+#[cfg(windows)]
+fn arg_header(cx: &mut FunctionContext, i: &mut i32) -> NeonResult<Option<String>> {
+    let value = cx.argument::<JsValue>(*i)?;
+    if value.is_a::<JsString, _>(cx) {
+        return Ok(Some(cx.arg_string(i)?));
+    }
+
+    *i += 1;
+    Ok(None)
+}
+
+#[cfg(windows)]
+fn negotiate_parse(mut cx: FunctionContext) -> JsResult<JsArray> {
+    let mut i = 0;
+    let _ = arg_kind(&mut cx, &mut i)?;
+    let header = arg_header(&mut cx, &mut i)?.unwrap_or_default();
+    let list = sorted(parse_accept(&header));
+    let result = cx.empty_array();
+    for (j, pref) in list.iter().enumerate() {
+        let row = cx.empty_array();
+        let js_value = cx.string(pref.render());
+        row.set(&mut cx, 0, js_value)?;
+
+        let js_q = cx.number(pref.q as f64 / 1000.0);
+        row.set(&mut cx, 1, js_q)?;
+        result.set(&mut cx, j as u32, row)?;
+    }
+
+    Ok(result)
+}
+
+#[cfg(windows)]
+fn negotiate_best(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let mut i = 0;
+    let kind = arg_kind(&mut cx, &mut i)?;
+    let header = arg_header(&mut cx, &mut i)?;
+    let offers = cx.arg_strings(&mut i)?;
+    match best_match(kind, header.as_deref(), &offers) {
+        Some(index) => Ok(cx.string(&offers[index]).upcast()),
+        None => Ok(cx.null().upcast()),
+    }
+}
+
+#[cfg(windows)]
+pub fn negotiate_bind(cx: &mut ModuleContext) -> NeonResult<()> {
+    cx.export_function("negotiate_parse", negotiate_parse)?;
+    cx.export_function("negotiate_best", negotiate_best)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn q(kind: AcceptKind, header: &str, offer: &str) -> u16 {
+        let offer = parse_accept(offer).pop().unwrap();
+        weight(kind, &parse_accept(header), header.trim().len() < 1, &offer)
+    }
+
+    #[test]
+    fn parses_qvalues() {
+        assert_eq!(parse_q("1"), Some(1000));
+        assert_eq!(parse_q("1.000"), Some(1000));
+        assert_eq!(parse_q("0"), Some(0));
+        assert_eq!(parse_q("0."), Some(0));
+        assert_eq!(parse_q("0.5"), Some(500));
+        assert_eq!(parse_q("0.123"), Some(123));
+        assert_eq!(parse_q("1.001"), None);
+        assert_eq!(parse_q("0.1234"), None);
+        assert_eq!(parse_q("2"), None);
+        assert_eq!(parse_q(".5"), None);
+        assert_eq!(parse_q("0.5x"), None);
+
+        let list = parse_accept("a;q=2, b;q=0.5, c;p=\"x,y\";q=0.8, d");
+        let values: Vec<_> = sorted(list).iter().map(|x| (x.render(), x.q)).collect();
+        assert_eq!(values, vec![
+            (String::from("d"), 1000),
+            (String::from("c;p=x,y"), 800),
+            (String::from("b"), 500),
+        ]);
+    }
+
+    // The example from RFC 9110, section 12.5.1.
+    #[test]
+    fn prefers_specific_media_ranges() {
+        let header = "text/*;q=0.3, text/plain;q=0.7, text/plain;format=flowed, text/plain;format=fixed;q=0.4, */*;q=0.5";
+        assert_eq!(q(AcceptKind::Type, header, "text/plain;format=flowed"), 1000);
+        assert_eq!(q(AcceptKind::Type, header, "text/plain"), 700);
+        assert_eq!(q(AcceptKind::Type, header, "text/html"), 300);
+        assert_eq!(q(AcceptKind::Type, header, "image/jpeg"), 500);
+        assert_eq!(q(AcceptKind::Type, header, "text/plain;format=fixed"), 400);
+
+        let offers = ["text/html", "image/jpeg", "text/plain"];
+        assert_eq!(best_match(AcceptKind::Type, Some(header), &offers), Some(2));
+        assert_eq!(best_match(AcceptKind::Type, Some("text/html;q=0, */*"), &["text/html"]), None);
+        assert_eq!(best_match(AcceptKind::Type, Some("text/*;q=0.5, application/json"), &["text/html", "application/json"]), Some(1));
+        assert_eq!(best_match(AcceptKind::Type, Some("*/*"), &["text/html", "application/json"]), Some(0));
+        assert_eq!(best_match(AcceptKind::Type, Some("*/html"), &["text/html"]), None);
+        assert_eq!(best_match(AcceptKind::Type, None, &["text/html", "application/json"]), Some(0));
+        assert_eq!(best_match(AcceptKind::Type, Some(""), &["application/json"]), Some(0));
+    }
+
+    #[test]
+    fn negotiates_encodings() {
+        assert_eq!(best_match(AcceptKind::Encoding, Some("gzip, identity;q=0"), &["identity"]), None);
+        assert_eq!(best_match(AcceptKind::Encoding, Some("gzip, identity;q=0"), &["br", "identity"]), None);
+        assert_eq!(best_match(AcceptKind::Encoding, Some("*;q=0"), &["identity", "gzip"]), None);
+        assert_eq!(best_match(AcceptKind::Encoding, Some("br;q=0.2"), &["identity", "br"]), Some(1));
+        assert_eq!(best_match(AcceptKind::Encoding, Some("deflate"), &["identity", "br"]), Some(0));
+        assert_eq!(best_match(AcceptKind::Encoding, Some("x-gzip"), &["gzip"]), Some(0));
+        assert_eq!(best_match(AcceptKind::Encoding, Some("*, gzip;q=0"), &["gzip", "br"]), Some(1));
+        assert_eq!(best_match(AcceptKind::Encoding, Some(""), &["gzip", "identity"]), Some(1));
+        assert_eq!(best_match(AcceptKind::Encoding, Some(""), &["gzip"]), None);
+        assert_eq!(best_match(AcceptKind::Encoding, None, &["gzip", "identity"]), Some(0));
+    }
+
+    #[test]
+    fn negotiates_languages_and_charsets() {
+        assert_eq!(q(AcceptKind::Language, "en, en-us;q=0.5", "en-US"), 500);
+        assert_eq!(q(AcceptKind::Language, "en;q=0.5, *;q=0.1", "en-GB"), 500);
+        assert_eq!(q(AcceptKind::Language, "en;q=0.5, *;q=0.1", "fr"), 100);
+        assert_eq!(q(AcceptKind::Language, "en-us", "en"), 0);
+        assert_eq!(q(AcceptKind::Language, "en", "eng"), 0);
+        assert_eq!(best_match(AcceptKind::Language, Some("en-US, fr;q=0.8"), &["fr-CA", "en"]), Some(0));
+
+        assert_eq!(best_match(AcceptKind::Charset, Some("utf-8, *;q=0.1"), &["iso-8859-1", "utf-8"]), Some(1));
+        assert_eq!(best_match(AcceptKind::Charset, Some("UTF-8"), &["iso-8859-1"]), None);
+    }
+
+    #[test]
+    fn selects_configured_kinds() {
+        let negotiation = Negotiation::new();
+        assert!(negotiation.select(|_| None).is_none());
+
+        negotiation.config(Some(Offers {
+            types: vec![String::from("text/html"), String::from("application/json")],
+            languages: Vec::new(),
+            encodings: vec![String::from("gzip")],
+            charsets: Vec::new(),
+        }));
+
+        let result = negotiation.select(|kind| match kind {
+            AcceptKind::Type => Some("application/json"),
+            AcceptKind::Encoding => Some("br"),
+            _ => None,
+        }).unwrap();
+
+        assert_eq!(result.media_type, Some(Some(String::from("application/json"))));
+        assert_eq!(result.encoding, Some(None));
+        assert_eq!(result.language, None);
+        assert_eq!(result.charset, None);
+    }
+}
@@ -0,0 +1,670 @@
+#[cfg(windows)]
+use neon::prelude::*;
+
+use super::codec::*;
+use super::message::*;
+
+#[cfg(windows)]
+use super::http::*;
+#[cfg(windows)]
+use super::support::*;
+#[cfg(windows)]
+use super::user::*;
+#[cfg(windows)]
+use super::win32::*;
+
+#[cfg(windows)]
+use windows::Win32::Foundation::*;
+#[cfg(windows)]
+use windows::Win32::Networking::HttpServer::*;
+
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::sync::mpsc::channel;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread::spawn;
+use std::time::Duration;
+
+const HOP_HEADERS: [&str; 10] = [
+    "Connection", "Keep-Alive", "Proxy-Connection", "Proxy-Authenticate", "Proxy-Authorization",
+    "TE", "Trailer", "Transfer-Encoding", "Upgrade", "Expect",
+];
+
+fn is_hop(name: &str, connection: &str) -> bool {
+    if HOP_HEADERS.iter().any(|x| x.eq_ignore_ascii_case(name)) {
+        return true;
+    }
+
+    connection.split(',').any(|x| x.trim().eq_ignore_ascii_case(name))
+}
+
+enum Failure {
+    Client,
+    Upstream(std::io::Error),
+}
+
+impl From<std::io::Error> for Failure {
+    fn from(err: std::io::Error) -> Self {
+        Failure::Upstream(err)
+    }
+}
+
+impl Failure {
+    fn status(&self) -> u16 {
+        match self {
+            Failure::Client => 400,
+            Failure::Upstream(err) => match err.kind() {
+                ErrorKind::TimedOut | ErrorKind::WouldBlock => 504,
+                _ => 502,
+            }
+        }
+    }
+}
+
+// The client side of a forwarded request. Errors are Win32 codes; any of them ends the exchange
+// without a reply, since the client can no longer be answered.
+pub trait Downstream {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, u32>;
+    fn send_head(&self, reply: Reply, more: bool) -> u32;
+    fn send_data(&self, data: Vec<Vec<u8>>, trailers: Vec<(String, String)>, more: bool, disconnect: bool) -> u32;
+    fn cancel(&self);
+}
+
+type ProxyJob = Box<dyn FnOnce() + Send>;
+
+// Forwarded requests run on a fixed set of workers; the rest wait in `jobs` until one is free.
+pub struct Proxy {
+    upstream: String,
+    user_header: String,
+    timeout: Duration,
+    jobs: Mutex<Sender<ProxyJob>>,
+}
+
+#[cfg(windows)]
+impl Finalize for Proxy {}
+
+impl Proxy {
+    pub fn new(upstream: &str, user_header: &str, timeout: u32, workers: u32) -> Self {
+        let mut ms = timeout as u64;
+        if ms < 1 {
+            ms = 30000;
+        }
+
+        let mut workers = workers;
+        if workers < 1 {
+            workers = 16;
+        }
+
+        let (tx, rx) = channel::<ProxyJob>();
+        let rx = Arc::new(Mutex::new(rx));
+        for _ in 0..workers {
+            let rx = rx.clone();
+            spawn(move || loop {
+                let job = match rx.lock() {
+                    Ok(rx) => rx.recv(),
+                    Err(_) => return,
+                };
+
+                match job {
+                    Ok(job) => job(),
+                    Err(_) => return,
+                }
+            });
+        }
+
+        Self {
+            upstream: String::from(upstream),
+            user_header: String::from(user_header),
+            timeout: Duration::from_millis(ms),
+            jobs: Mutex::new(tx),
+        }
+    }
+
+    fn run<F>(&self, f: F) where F: FnOnce() + Send + 'static {
+        if let Ok(jobs) = self.jobs.lock() {
+            jobs.send(Box::new(f)).ok();
+        }
+    }
+
+    fn connect(&self) -> std::io::Result<TcpStream> {
+        let mut last = std::io::Error::new(ErrorKind::NotFound, "Upstream did not resolve.");
+        for addr in self.upstream.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, self.timeout) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(self.timeout))?;
+                    stream.set_write_timeout(Some(self.timeout))?;
+                    stream.set_nodelay(true)?;
+                    return Ok(stream);
+                },
+                Err(err) => last = err,
+            }
+        }
+
+        Err(last)
+    }
+
+    fn write_head(&self, stream: &mut TcpStream, info: &RequestInfo, user: Option<(String, String)>) -> std::io::Result<bool> {
+        let connection = info.header("Connection").unwrap_or("");
+        let sid_header = format!("{}-Sid", self.user_header);
+        let mut head = format!("{} {} HTTP/1.1\r\n", info.verb, info.target());
+        let mut forwarded = String::new();
+        let mut length = false;
+        for (name, value) in info.headers.iter() {
+            if is_hop(name, connection) {
+                continue;
+            }
+
+            if name.eq_ignore_ascii_case(&self.user_header) || name.eq_ignore_ascii_case(&sid_header) {
+                continue;
+            }
+
+            if name.eq_ignore_ascii_case("X-Forwarded-Proto") || name.eq_ignore_ascii_case("X-Forwarded-Host") {
+                continue;
+            }
+
+            if name.eq_ignore_ascii_case("X-Forwarded-For") {
+                forwarded = value.clone();
+                continue;
+            }
+
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = true;
+            }
+
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+
+        if let Some(addr) = info.remote {
+            if forwarded.len() > 0 {
+                forwarded.push_str(", ");
+            }
+
+            forwarded.push_str(&addr.ip().to_string());
+        }
+
+        if forwarded.len() > 0 {
+            head.push_str(&format!("X-Forwarded-For: {}\r\n", forwarded));
+        }
+
+        let proto = if info.secure { "https" } else { "http" };
+        head.push_str(&format!("X-Forwarded-Proto: {}\r\n", proto));
+
+        if let Some(host) = info.header("Host") {
+            head.push_str(&format!("X-Forwarded-Host: {}\r\n", host));
+        }
+
+        if let Some((name, sid)) = user {
+            head.push_str(&format!("{}: {}\r\n", self.user_header, name));
+            head.push_str(&format!("{}: {}\r\n", sid_header, sid));
+        }
+
+        let chunked = info.body && !length;
+        if chunked {
+            head.push_str("Transfer-Encoding: chunked\r\n");
+        }
+
+        head.push_str("Connection: close\r\n\r\n");
+        stream.write_all(head.as_bytes())?;
+
+        Ok(chunked)
+    }
+
+    fn write_body<D: Downstream>(&self, stream: &mut TcpStream, client: &D, chunked: bool) -> Result<(), Failure> {
+        let mut buf = vec![0u8; 16384];
+        loop {
+            let size = client.read(&mut buf).map_err(|_| Failure::Client)?;
+            if size < 1 {
+                break;
+            }
+
+            let data = &buf[..size];
+            if chunked {
+                stream.write_all(&encode_chunk(data))?;
+            } else {
+                stream.write_all(data)?;
+            }
+        }
+
+        if chunked {
+            stream.write_all(LAST_CHUNK)?;
+        }
+
+        stream.flush()?;
+        Ok(())
+    }
+
+    // HTTP.sys sends the body as given, so a chunked upstream body keeps its Transfer-Encoding and
+    // is re-encoded chunk by chunk; any other body without a length ends with the connection.
+    fn response(&self, head: Reply, chunked: bool) -> Reply {
+        let mut reply = Reply::new(head.status, &head.reason);
+        for (name, value) in head.headers.iter() {
+            if !is_hop(name, "") {
+                reply = reply.header(name, value);
+            }
+        }
+
+        if chunked {
+            reply = reply.header("Transfer-Encoding", "chunked");
+        }
+
+        reply
+    }
+
+    fn relay<D: Downstream>(&self, mut stream: TcpStream, client: &D, info: &RequestInfo) -> Result<u16, Failure> {
+        let mut decoder = ResponseDecoder::new(info.verb == "HEAD");
+        let mut buf = vec![0u8; 16384];
+        let mut status = 0;
+        loop {
+            let events = match stream.read(&mut buf) {
+                Ok(0) => decoder.finish(),
+                Ok(size) => decoder.push(&buf[..size]),
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            };
+
+            let events = events.map_err(|x| Failure::Upstream(Error::new(ErrorKind::InvalidData, x)))?;
+            for event in events {
+                let err = match event {
+                    WireEvent::Head(head) => {
+                        status = head.status;
+                        client.send_head(self.response(head, decoder.chunked()), true)
+                    },
+                    WireEvent::Data(data) => {
+                        let data = if decoder.chunked() { encode_chunk(&data) } else { data };
+                        client.send_data(vec![data], Vec::new(), true, false)
+                    },
+                    WireEvent::End(trailers) => {
+                        // Trailers can only follow a last chunk; other framings have no place for them.
+                        let (tail, trailers) = match decoder.chunked() {
+                            true if trailers.len() > 0 => (vec![b"0\r\n".to_vec()], trailers),
+                            true => (vec![LAST_CHUNK.to_vec()], Vec::new()),
+                            false => (Vec::new(), Vec::new()),
+                        };
+
+                        client.send_data(tail, trailers, false, decoder.delimited());
+                        return Ok(status);
+                    },
+                };
+
+                if err != 0 {
+                    return Err(Failure::Client);
+                }
+            }
+        }
+    }
+
+    fn exchange<D: Downstream>(&self, client: &D, info: &RequestInfo, user: Option<(String, String)>) -> Result<u16, Failure> {
+        let mut stream = self.connect()?;
+        let chunked = self.write_head(&mut stream, info, user)?;
+        if info.body {
+            self.write_body(&mut stream, client, chunked)?;
+        }
+
+        self.relay(stream, client, info)
+    }
+
+    pub fn forward<D: Downstream>(&self, client: &D, info: &RequestInfo, user: Option<(String, String)>) -> u16 {
+        match self.exchange(client, info, user) {
+            Ok(status) => status,
+            Err(Failure::Client) => {
+                client.cancel();
+                0
+            },
+            Err(failure) => {
+                let status = failure.status();
+                let reason = if status == 504 { "Gateway Timeout" } else { "Bad Gateway" };
+                let reply = Reply::new(status, reason).header("Content-Length", "0");
+                if client.send_head(reply, false) != 0 {
+                    // The error reply itself could not be sent, usually because the upstream failed after
+                    // its headers were relayed; all that is left is dropping the connection.
+                    client.send_data(Vec::new(), Vec::new(), false, true);
+                }
+
+                status
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+struct Incoming<'a> {
+    req: &'a Arc<Request>,
+    id: u64,
+}
+
+#[cfg(windows)]
+impl Downstream for Incoming<'_> {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, u32> {
+        let (err, size) = block_on(|done| {
+            self.req.receive_data(self.id, Slice::new(buf), move |err, size| done((err, size)));
+        }).unwrap_or((ERROR_OPERATION_ABORTED.0, 0));
+
+        match err {
+            0 => Ok(size as usize),
+            _ if err == ERROR_HANDLE_EOF.0 => Ok(0),
+            _ => Err(err),
+        }
+    }
+
+    fn send_head(&self, reply: Reply, more: bool) -> u32 {
+        let flags = if more { HTTP_SEND_RESPONSE_FLAG_MORE_DATA } else { 0 };
+        block_on(|done| {
+            reply.send(self.req, self.id, flags, move |err, size| done((err, size)));
+        }).unwrap_or((ERROR_OPERATION_ABORTED.0, 0)).0
+    }
+
+    fn send_data(&self, data: Vec<Vec<u8>>, trailers: Vec<(String, String)>, more: bool, disconnect: bool) -> u32 {
+        let flags = send_options(false, more, disconnect, 0);
+        block_on(|done| {
+            send_body(self.req, self.id, flags, data, trailers, move |err, size| done((err, size)));
+        }).unwrap_or((ERROR_OPERATION_ABORTED.0, 0)).0
+    }
+
+    fn cancel(&self) {
+        block_on(|done| self.req.cancel(self.id, move |err| done(err))).ok();
+    }
+}
+
+#[cfg(windows)]
+fn token_user(user: Option<Arc<HandleRef>>) -> Option<(String, String)> {
+    let token = user?;
+    let groups = token_groups_internal(token.0, true);
+    let (_, sid) = groups.first()?;
+    let name = lookup_sid_internal(sid).unwrap_or_else(|| sid.clone());
+
+    Some((name, sid.clone()))
+}
+
+#[cfg(windows)]
+fn http_proxy_create(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let mut i = 0;
+    let upstream = cx.arg_string(&mut i)?;
+    let mut user_header = String::from("X-Forwarded-User");
+    let mut timeout = 0;
+    if cx.arg_opt(&mut i) {
+        user_header = cx.arg_string(&mut i)?;
+    }
+
+    if cx.arg_opt(&mut i) {
+        timeout = cx.arg_u32(&mut i)?;
+    }
+
+    let mut workers = 0;
+    if cx.arg_opt(&mut i) {
+        workers = cx.arg_u32(&mut i)?;
+    }
+
+    Ok(cx.export(Proxy::new(&upstream, &user_header, timeout, workers)))
+}
+
+#[cfg(windows)]
+fn http_proxy_forward(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let proxy = cx.import::<Proxy>(&mut i)?;
+    let arc = cx.import::<Request>(&mut i)?;
+    let size = cx.arg_u32(&mut i)?;
+    let req = arc.clone();
+    let tx = cx.channel();
+    let (def, promise) = cx.promise();
+    arc.receive_admitted(size, move |err, vec, result| {
+        if err != 0 {
+            def.settle_with(&tx, move |mut cx| {
+                let obj = cx.empty_object();
+                let js_err = cx.number(err);
+                obj.set(&mut cx, "code", js_err)?;
+
+                Ok(obj)
+            });
+
+            return;
+        }
+
+        let info = RequestInfo::from(&result.0);
+        let user = find_user_token(&result.0);
+        drop(vec);
+
+        let pool = proxy.clone();
+        pool.run(move || {
+            let client = Incoming { req: &req, id: info.id };
+            let status = proxy.forward(&client, &info, token_user(user));
+            def.settle_with(&tx, move |mut cx| {
+                let obj = cx.empty_object();
+                let js_err = cx.number(0);
+                obj.set(&mut cx, "code", js_err)?;
+
+                let js_id = cx.boxed(info.id);
+                obj.set(&mut cx, "id", js_id)?;
+
+                let js_status = cx.number(status);
+                obj.set(&mut cx, "status", js_status)?;
+
+                Ok(obj)
+            });
+        });
+    });
+
+    Ok(promise)
+}
+
+#[cfg(windows)]
+fn http_proxy_close(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    cx.dispose::<Proxy>(0)?;
+    Ok(cx.undefined())
+}
+
+#[cfg(windows)]
+pub fn proxy_bind(cx: &mut ModuleContext) -> NeonResult<()> {
+    cx.export_function("http_proxy_create", http_proxy_create)?;
+    cx.export_function("http_proxy_forward", http_proxy_forward)?;
+    cx.export_function("http_proxy_close", http_proxy_close)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::TcpListener;
+
+    #[derive(Debug, PartialEq)]
+    enum Sent {
+        Head(Reply, bool),
+        Data(Vec<u8>, Vec<(String, String)>, bool, bool),
+        Cancel,
+    }
+
+    struct Recorder {
+        body: Mutex<Vec<Result<Vec<u8>, u32>>>,
+        sent: Mutex<Vec<Sent>>,
+    }
+
+    impl Recorder {
+        fn new(body: Vec<Result<Vec<u8>, u32>>) -> Self {
+            Self { body: Mutex::new(body), sent: Mutex::new(Vec::new()) }
+        }
+
+        fn sent(self) -> Vec<Sent> {
+            self.sent.into_inner().unwrap()
+        }
+    }
+
+    impl Downstream for Recorder {
+        fn read(&self, buf: &mut [u8]) -> Result<usize, u32> {
+            let mut body = self.body.lock().unwrap();
+            if body.len() < 1 {
+                return Ok(0);
+            }
+
+            let data = body.remove(0)?;
+            buf[..data.len()].copy_from_slice(&data);
+            Ok(data.len())
+        }
+
+        fn send_head(&self, reply: Reply, more: bool) -> u32 {
+            self.sent.lock().unwrap().push(Sent::Head(reply, more));
+            0
+        }
+
+        fn send_data(&self, data: Vec<Vec<u8>>, trailers: Vec<(String, String)>, more: bool, disconnect: bool) -> u32 {
+            self.sent.lock().unwrap().push(Sent::Data(data.concat(), trailers, more, disconnect));
+            0
+        }
+
+        fn cancel(&self) {
+            self.sent.lock().unwrap().push(Sent::Cancel);
+        }
+    }
+
+    fn read_until(stream: &mut TcpStream, end: &[u8]) -> String {
+        let mut data = Vec::new();
+        let mut byte = [0u8; 1];
+        while !data.ends_with(end) && stream.read(&mut byte).unwrap() > 0 {
+            data.push(byte[0]);
+        }
+
+        String::from_utf8(data).unwrap()
+    }
+
+    fn upstream<F>(f: F) -> (String, std::thread::JoinHandle<(String, String)>) where F: FnOnce(&mut TcpStream) + Send + 'static {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let handle = spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let head = read_until(&mut stream, b"\r\n\r\n");
+            let body = if head.contains("chunked") { read_until(&mut stream, LAST_CHUNK) } else { String::new() };
+            f(&mut stream);
+            (head, body)
+        });
+
+        (addr, handle)
+    }
+
+    #[test]
+    fn local_upstream() {
+        let (addr, upstream) = upstream(|stream| {
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close, X-Hop\r\nX-Hop: 1\r\nX-Kept: 2\r\n\r\n").unwrap();
+            stream.write_all(b"hello").unwrap();
+        });
+
+        let proxy = Proxy::new(&addr, "X-Forwarded-User", 5000, 1);
+        let mut info = RequestInfo::fake(1, 0, "POST", "http://localhost/api?x=1", &[
+            ("Host", "localhost"),
+            ("Connection", "keep-alive, X-Secret"),
+            ("X-Secret", "s"),
+            ("X-Forwarded-User", "mallory"),
+            ("X-Forwarded-For", "10.0.0.1"),
+        ]);
+
+        info.body = true;
+        info.remote = Some("192.0.2.5:5000".parse().unwrap());
+
+        let client = Recorder::new(vec![Ok(b"ab".to_vec()), Ok(b"c".to_vec())]);
+        let user = Some((String::from("alice"), String::from("S-1-5-21-1")));
+        assert_eq!(proxy.forward(&client, &info, user), 200);
+
+        let (head, body) = upstream.join().unwrap();
+        assert_eq!(head, concat!(
+            "POST /api?x=1 HTTP/1.1\r\n",
+            "Host: localhost\r\n",
+            "X-Forwarded-For: 10.0.0.1, 192.0.2.5\r\n",
+            "X-Forwarded-Proto: http\r\n",
+            "X-Forwarded-Host: localhost\r\n",
+            "X-Forwarded-User: alice\r\n",
+            "X-Forwarded-User-Sid: S-1-5-21-1\r\n",
+            "Transfer-Encoding: chunked\r\n",
+            "Connection: close\r\n\r\n",
+        ));
+
+        assert_eq!(body, "2\r\nab\r\n1\r\nc\r\n0\r\n\r\n");
+        assert_eq!(client.sent(), vec![
+            Sent::Head(Reply::new(200, "OK").header("Content-Length", "5").header("X-Kept", "2"), true),
+            Sent::Data(b"hello".to_vec(), Vec::new(), true, false),
+            Sent::Data(Vec::new(), Vec::new(), false, false),
+        ]);
+    }
+
+    fn relay(response: &'static [u8]) -> Vec<Sent> {
+        let (addr, upstream) = upstream(move |stream| stream.write_all(response).unwrap());
+        let proxy = Proxy::new(&addr, "X-Forwarded-User", 5000, 1);
+        let client = Recorder::new(Vec::new());
+        assert_eq!(proxy.forward(&client, &RequestInfo::fake(1, 0, "GET", "/", &[]), None), 200);
+        upstream.join().unwrap();
+        client.sent()
+    }
+
+    #[test]
+    fn rechunks_bodies() {
+        let sent = relay(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\nX-Sum: 9\r\n\r\n");
+        assert_eq!(sent, vec![
+            Sent::Head(Reply::new(200, "OK").header("Transfer-Encoding", "chunked"), true),
+            Sent::Data(b"5\r\nhello\r\n".to_vec(), Vec::new(), true, false),
+            Sent::Data(b"0\r\n".to_vec(), vec![(String::from("X-Sum"), String::from("9"))], false, false),
+        ]);
+
+        let sent = relay(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nhi\r\n0\r\n\r\n");
+        assert_eq!(sent[2], Sent::Data(LAST_CHUNK.to_vec(), Vec::new(), false, false));
+    }
+
+    #[test]
+    fn disconnects_unframed_bodies() {
+        let sent = relay(b"HTTP/1.0 200 OK\r\nX-Kept: 2\r\n\r\nhello");
+        assert_eq!(sent, vec![
+            Sent::Head(Reply::new(200, "OK").header("X-Kept", "2"), true),
+            Sent::Data(b"hello".to_vec(), Vec::new(), true, false),
+            Sent::Data(Vec::new(), Vec::new(), false, true),
+        ]);
+    }
+
+    #[test]
+    fn oversized_upstream_heads() {
+        let (addr, upstream) = upstream(|stream| {
+            stream.write_all(b"HTTP/1.1 200 OK\r\n").unwrap();
+            stream.write_all(format!("X-Fill: {}\r\n\r\n", "a".repeat(MAX_HEAD)).as_bytes()).ok();
+        });
+
+        let proxy = Proxy::new(&addr, "X-Forwarded-User", 5000, 1);
+        let client = Recorder::new(Vec::new());
+        assert_eq!(proxy.forward(&client, &RequestInfo::fake(1, 0, "GET", "/", &[]), None), 502);
+        assert_eq!(client.sent(), vec![Sent::Head(Reply::new(502, "Bad Gateway").header("Content-Length", "0"), false)]);
+        upstream.join().unwrap();
+    }
+
+    #[test]
+    fn upstream_failures() {
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let proxy = Proxy::new(&addr.to_string(), "X-Forwarded-User", 5000, 1);
+        let client = Recorder::new(Vec::new());
+        assert_eq!(proxy.forward(&client, &RequestInfo::fake(1, 0, "GET", "/", &[]), None), 502);
+        assert_eq!(client.sent(), vec![Sent::Head(Reply::new(502, "Bad Gateway").header("Content-Length", "0"), false)]);
+
+        assert_eq!(Failure::from(Error::from(ErrorKind::TimedOut)).status(), 504);
+        assert_eq!(Failure::Client.status(), 400);
+    }
+
+    #[test]
+    fn client_failures() {
+        let (addr, upstream) = upstream(|_| ());
+        let proxy = Proxy::new(&addr, "X-Forwarded-User", 5000, 1);
+        let mut info = RequestInfo::fake(1, 0, "PUT", "/upload", &[("Host", "localhost")]);
+        info.body = true;
+
+        let client = Recorder::new(vec![Ok(b"ab".to_vec()), Err(1236)]);
+        assert_eq!(proxy.forward(&client, &info, None), 0);
+        assert_eq!(client.sent(), vec![Sent::Cancel]);
+        upstream.join().unwrap();
+    }
+
+    #[test]
+    fn oversized_replies() {
+        assert!(Reply::new(200, "OK").header("X-Kept", "2").fits());
+        assert!(!Reply::new(200, "OK").header("X-Big", &"a".repeat(70000)).fits());
+        assert!(!Reply::new(200, &"a".repeat(70000)).fits());
+    }
+}
@@ -0,0 +1,376 @@
+use super::message::*;
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Mutex;
+use std::sync::RwLock;
+use std::time::Instant;
+
+const MAX_CLIENTS: usize = 65536;
+
+#[derive(Clone, PartialEq)]
+pub enum RateKey {
+    Ip,
+    User,
+    Header(String),
+}
+
+pub fn parse_rate_key(value: &str) -> Option<RateKey> {
+    match value {
+        "ip" => Some(RateKey::Ip),
+        "user" => Some(RateKey::User),
+        _ => value.strip_prefix("header:").filter(|x| x.len() > 0).map(|x| RateKey::Header(x.to_string())),
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Cidr {
+    net: IpAddr,
+    bits: u32,
+}
+
+impl Cidr {
+    pub fn parse(value: &str) -> Option<Self> {
+        let (addr, bits) = match value.split_once('/') {
+            Some((addr, bits)) => (addr, Some(bits.parse::<u32>().ok()?)),
+            None => (value, None),
+        };
+
+        let net = addr.trim().parse::<IpAddr>().ok()?;
+        let max = if net.is_ipv4() { 32 } else { 128 };
+        let bits = bits.unwrap_or(max);
+        if bits > max {
+            return None;
+        }
+
+        Some(Self { net, bits })
+    }
+
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        let addr = match (self.net, addr) {
+            (IpAddr::V4(_), IpAddr::V6(v6)) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(*addr),
+            _ => *addr,
+        };
+
+        match (self.net, addr) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - self.bits).unwrap_or(0);
+                u32::from(net) & mask == u32::from(addr) & mask
+            },
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - self.bits).unwrap_or(0);
+                u128::from(net) & mask == u128::from(addr) & mask
+            },
+            _ => false,
+        }
+    }
+}
+
+pub fn parse_cidrs(list: &[String]) -> Result<Vec<Cidr>, String> {
+    list.iter()
+        .map(|x| Cidr::parse(x).ok_or_else(|| format!("Invalid CIDR: {}", x)))
+        .collect()
+}
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+    tick: u64,
+}
+
+#[derive(Clone)]
+struct RateConfig {
+    rate: f64,
+    burst: f64,
+    key: RateKey,
+}
+
+#[derive(Default)]
+pub struct RateStats {
+    pub allowed: AtomicU64,
+    pub limited: AtomicU64,
+    pub denied: AtomicU64,
+}
+
+type ClientKey = (u64, String);
+
+// Buckets are evicted least recently used first; `order` maps each bucket's last use to its key.
+#[derive(Default)]
+struct Buckets {
+    map: HashMap<ClientKey, Bucket>,
+    order: BTreeMap<u64, ClientKey>,
+    tick: u64,
+}
+
+impl Buckets {
+    fn touch(&mut self, key: &ClientKey, config: &RateConfig, now: Instant, capacity: usize) -> &mut Bucket {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some(bucket) = self.map.get(key) {
+            self.order.remove(&bucket.tick);
+        } else {
+            while self.map.len() >= capacity {
+                let oldest = match self.order.keys().next() {
+                    Some(oldest) => *oldest,
+                    None => break,
+                };
+
+                if let Some(evicted) = self.order.remove(&oldest) {
+                    self.map.remove(&evicted);
+                }
+            }
+        }
+
+        self.order.insert(tick, key.clone());
+        let bucket = self.map.entry(key.clone()).or_insert(Bucket { tokens: config.burst, last: now, tick });
+        bucket.tick = tick;
+        bucket
+    }
+
+    fn clear(&mut self, group: u64) {
+        self.map.retain(|(x, _), _| *x != group);
+        self.order.retain(|_, (x, _)| *x != group);
+    }
+}
+
+// Configurations are keyed by URL group; group 0 is the queue default for groups without their own.
+pub struct RateLimiter {
+    configs: RwLock<HashMap<u64, RateConfig>>,
+    allow: RwLock<Vec<Cidr>>,
+    deny: RwLock<Vec<Cidr>>,
+    buckets: Mutex<Buckets>,
+    capacity: usize,
+    pub stats: RateStats,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self {
+            configs: RwLock::new(HashMap::new()),
+            allow: RwLock::new(Vec::new()),
+            deny: RwLock::new(Vec::new()),
+            buckets: Mutex::new(Buckets::default()),
+            capacity: MAX_CLIENTS,
+            stats: RateStats::default(),
+        }
+    }
+
+    pub fn config(&self, group: u64, rate: f64, burst: f64, key: RateKey) {
+        if let Ok(mut configs) = self.configs.write() {
+            if rate > 0.0 {
+                configs.insert(group, RateConfig { rate, burst: burst.max(1.0), key });
+            } else {
+                configs.remove(&group);
+            }
+        }
+
+        if let Ok(mut buckets) = self.buckets.lock() {
+            buckets.clear(group);
+        }
+    }
+
+    pub fn lists(&self, allow: Vec<Cidr>, deny: Vec<Cidr>) {
+        if let Ok(mut list) = self.allow.write() {
+            *list = allow;
+        }
+
+        if let Ok(mut list) = self.deny.write() {
+            *list = deny;
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        let config = self.configs.read().map(|x| x.len() > 0).unwrap_or(false);
+        let deny = self.deny.read().map(|x| x.len() > 0).unwrap_or(false);
+        config || deny
+    }
+
+    pub fn wants_user(&self) -> bool {
+        match self.configs.read() {
+            Ok(configs) => configs.values().any(|x| x.key == RateKey::User),
+            Err(_) => false,
+        }
+    }
+
+    pub fn clients(&self) -> usize {
+        self.buckets.lock().map(|x| x.map.len()).unwrap_or(0)
+    }
+
+    fn find(&self, group: u64) -> Option<(u64, RateConfig)> {
+        let configs = self.configs.read().ok()?;
+        match configs.get(&group) {
+            Some(config) => Some((group, config.clone())),
+            None => configs.get(&0).map(|x| (0, x.clone())),
+        }
+    }
+
+    pub fn take(&self, group: u64, key: &str, now: Instant) -> Result<(), u64> {
+        let (group, config) = match self.find(group) {
+            Some(found) => found,
+            None => return Ok(()),
+        };
+
+        let mut buckets = match self.buckets.lock() {
+            Ok(buckets) => buckets,
+            Err(_) => return Ok(()),
+        };
+
+        let bucket = buckets.touch(&(group, key.to_string()), &config, now, self.capacity);
+        let elapsed = now.saturating_duration_since(bucket.last).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * config.rate).min(config.burst);
+        bucket.last = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        Err(((1.0 - bucket.tokens) / config.rate).ceil().max(1.0) as u64)
+    }
+
+    // A request without the configured header or user is counted against its client address instead.
+    fn key(&self, info: &RequestInfo, user: Option<&str>) -> Option<String> {
+        let (_, config) = self.find(info.group)?;
+        let ip = || info.remote.map(|x| format!("ip:{}", x.ip()));
+        match &config.key {
+            RateKey::Ip => ip(),
+            RateKey::User => user.map(|x| format!("user:{}", x)).or_else(ip),
+            RateKey::Header(name) => info.header(name).map(|x| format!("header:{}", x)).or_else(ip),
+        }
+    }
+
+    pub fn admit(&self, info: &RequestInfo, user: Option<&str>) -> Option<Reply> {
+        if let Some(addr) = info.remote.map(|x| x.ip()) {
+            let allowed = self.allow.read().map(|x| x.iter().any(|x| x.contains(&addr))).unwrap_or(false);
+            if allowed {
+                self.stats.allowed.fetch_add(1, Relaxed);
+                return None;
+            }
+
+            let denied = self.deny.read().map(|x| x.iter().any(|x| x.contains(&addr))).unwrap_or(false);
+            if denied {
+                self.stats.denied.fetch_add(1, Relaxed);
+                return Some(Reply::new(403, "Forbidden").header("Content-Length", "0"));
+            }
+        }
+
+        if let Some(key) = self.key(info, user) {
+            if let Err(retry) = self.take(info.group, &key, Instant::now()) {
+                self.stats.limited.fetch_add(1, Relaxed);
+                return Some(Reply::new(429, "Too Many Requests")
+                    .header("Retry-After", &retry.to_string())
+                    .header("Content-Length", "0"));
+            }
+        }
+
+        self.stats.allowed.fetch_add(1, Relaxed);
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    fn request(group: u64, remote: &str, headers: &[(&str, &str)]) -> RequestInfo {
+        let mut info = RequestInfo::fake(1, group, "GET", "/", headers);
+        info.remote = Some(remote.parse().unwrap());
+        info
+    }
+
+    fn status(reply: Option<Reply>) -> u16 {
+        reply.map(|x| x.status).unwrap_or(200)
+    }
+
+    #[test]
+    fn parses_keys_and_cidrs() {
+        assert!(parse_rate_key("ip") == Some(RateKey::Ip));
+        assert!(parse_rate_key("header:X-Api-Key") == Some(RateKey::Header(String::from("X-Api-Key"))));
+        assert!(parse_rate_key("header:").is_none());
+
+        let net = Cidr::parse("10.0.0.0/8").unwrap();
+        assert!(net.contains(&"10.1.2.3".parse().unwrap()));
+        assert!(net.contains(&"::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!net.contains(&"11.0.0.1".parse().unwrap()));
+        assert!(Cidr::parse("::1/129").is_none());
+        assert!(parse_cidrs(&[String::from("1.2.3.4"), String::from("x")]).is_err());
+    }
+
+    #[test]
+    fn refills_buckets() {
+        let limiter = RateLimiter::new();
+        limiter.config(0, 2.0, 2.0, RateKey::Ip);
+
+        let now = Instant::now();
+        assert_eq!(limiter.take(0, "a", now), Ok(()));
+        assert_eq!(limiter.take(0, "a", now), Ok(()));
+        assert_eq!(limiter.take(0, "a", now), Err(1));
+        assert_eq!(limiter.take(0, "a", now + Duration::from_millis(500)), Ok(()));
+        assert_eq!(limiter.take(0, "b", now), Ok(()));
+    }
+
+    #[test]
+    fn missing_header_falls_back_to_ip() {
+        let limiter = RateLimiter::new();
+        limiter.config(0, 0.001, 1.0, RateKey::Header(String::from("X-Api-Key")));
+
+        assert_eq!(status(limiter.admit(&request(0, "192.0.2.1:1000", &[]), None)), 200);
+        assert_eq!(status(limiter.admit(&request(0, "192.0.2.1:1001", &[]), None)), 429);
+        assert_eq!(status(limiter.admit(&request(0, "192.0.2.2:1000", &[]), None)), 200);
+        assert_eq!(status(limiter.admit(&request(0, "192.0.2.1:1000", &[("X-Api-Key", "k")]), None)), 200);
+        assert_eq!(status(limiter.admit(&request(0, "192.0.2.3:1000", &[("X-Api-Key", "k")]), None)), 429);
+    }
+
+    #[test]
+    fn anonymous_users_fall_back_to_ip() {
+        let limiter = RateLimiter::new();
+        limiter.config(0, 0.001, 1.0, RateKey::User);
+
+        assert_eq!(status(limiter.admit(&request(0, "192.0.2.1:1000", &[]), None)), 200);
+        assert_eq!(status(limiter.admit(&request(0, "192.0.2.1:1001", &[]), None)), 429);
+        assert_eq!(status(limiter.admit(&request(0, "192.0.2.2:1000", &[]), None)), 200);
+        assert_eq!(status(limiter.admit(&request(0, "192.0.2.1:1000", &[]), Some("S-1-5-21"))), 200);
+        assert_eq!(status(limiter.admit(&request(0, "192.0.2.3:1000", &[]), Some("S-1-5-21"))), 429);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut limiter = RateLimiter::new();
+        limiter.capacity = 2;
+        limiter.config(0, 0.001, 1.0, RateKey::Ip);
+
+        let now = Instant::now();
+        assert_eq!(limiter.take(0, "a", now), Ok(()));
+        assert_eq!(limiter.take(0, "b", now), Ok(()));
+        assert!(limiter.take(0, "a", now).is_err());
+        assert_eq!(limiter.take(0, "c", now), Ok(()));
+        assert_eq!(limiter.clients(), 2);
+
+        assert!(limiter.take(0, "a", now).is_err());
+        assert_eq!(limiter.take(0, "b", now), Ok(()));
+    }
+
+    #[test]
+    fn limits_per_group() {
+        let limiter = RateLimiter::new();
+        let api = url_group("rates-api");
+        let web = url_group("rates-web");
+        limiter.config(0, 0.001, 1.0, RateKey::Ip);
+        limiter.config(api, 0.001, 2.0, RateKey::Ip);
+
+        assert_eq!(status(limiter.admit(&request(api, "192.0.2.1:1", &[]), None)), 200);
+        assert_eq!(status(limiter.admit(&request(api, "192.0.2.1:1", &[]), None)), 200);
+        assert_eq!(status(limiter.admit(&request(api, "192.0.2.1:1", &[]), None)), 429);
+        assert_eq!(status(limiter.admit(&request(web, "192.0.2.1:1", &[]), None)), 200);
+        assert_eq!(status(limiter.admit(&request(0, "192.0.2.1:1", &[]), None)), 429);
+
+        limiter.config(api, 0.0, 0.0, RateKey::Ip);
+        assert_eq!(status(limiter.admit(&request(api, "192.0.2.1:1", &[]), None)), 429);
+        assert!(limiter.active());
+    }
+}
@@ -0,0 +1,672 @@
+use neon::prelude::*;
+
+use super::support::*;
+
+use ring::digest;
+use ring::rand::SecureRandom;
+use ring::rand::SystemRandom;
+use serde_json::json;
+use serde_json::Value;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::os::windows::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::sync::RwLock;
+use std::thread::sleep;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+const LOCK_RETRIES: u32 = 200;
+
+// Lets the holder of the lock rename a new copy over the file it has open.
+const FILE_SHARE_DELETE: u32 = 4;
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_millis() as u64).unwrap_or(0)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionRecord {
+    pub data: String,
+    pub created: u64,
+    pub expires: u64,
+}
+
+impl SessionRecord {
+    fn parse(text: &str) -> Option<(Self, u64)> {
+        let value: Value = serde_json::from_str(text).ok()?;
+        let record = Self {
+            data: String::from(value.get("data")?.as_str()?),
+            created: value.get("created")?.as_u64()?,
+            expires: value.get("expires")?.as_u64()?,
+        };
+
+        Some((record, value.get("version").and_then(|x| x.as_u64()).unwrap_or(0)))
+    }
+
+    fn render(&self, version: u64) -> String {
+        json!({ "data": self.data, "created": self.created, "expires": self.expires, "version": version }).to_string()
+    }
+}
+
+struct Entry {
+    record: SessionRecord,
+    version: u64,
+    persisted: u64,
+    used: u64,
+}
+
+// Every process attached to a queue opens the same directory; the files are authoritative and the
+// in-memory entries are only trusted while the version stored in the file matches what was last seen.
+// Files are opened without read or write sharing, so a read or a write from another process simply
+// waits its turn. Writes go to a temporary file that is renamed over the original while it is still
+// held, so a crash never leaves a session half written.
+// The entries lock is never held across file access.
+pub struct SessionStore {
+    ttl: u64,
+    max: usize,
+    sliding: bool,
+    dir: Option<PathBuf>,
+    entries: Mutex<HashMap<String, Entry>>,
+    tick: AtomicU64,
+    rng: SystemRandom,
+}
+
+impl Finalize for SessionStore {}
+
+impl SessionStore {
+    pub fn new(ttl: u64, max: usize, sliding: bool, dir: Option<PathBuf>) -> Result<Self, String> {
+        if let Some(dir) = &dir {
+            std::fs::create_dir_all(dir).map_err(|err| format!("{}: {}", dir.display(), err))?;
+        }
+
+        Ok(Self {
+            ttl,
+            max: max.max(1),
+            sliding,
+            dir,
+            entries: Mutex::new(HashMap::new()),
+            tick: AtomicU64::new(0),
+            rng: SystemRandom::new(),
+        })
+    }
+
+    // File names are hashed so arbitrary ids are safe on disk and not disclosed by a directory listing.
+    fn path(&self, id: &str) -> Option<PathBuf> {
+        let dir = self.dir.as_ref()?;
+        let hash = digest::digest(&digest::SHA256, id.as_bytes());
+        let name = hash.as_ref().iter().map(|x| format!("{:02x}", x)).collect::<String>();
+        Some(dir.join(format!("{}.session", name)))
+    }
+
+    fn open(path: &PathBuf, create: bool) -> Result<Option<File>, String> {
+        for _ in 0..LOCK_RETRIES {
+            let result = OpenOptions::new().read(true).write(true).create(create).share_mode(FILE_SHARE_DELETE).open(path);
+            match result {
+                Ok(file) => return Ok(Some(file)),
+                Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+                Err(_) => sleep(Duration::from_millis(1)),
+            }
+        }
+
+        Err(format!("{}: still locked", path.display()))
+    }
+
+    fn read(file: &mut File) -> Option<(SessionRecord, u64)> {
+        let mut text = String::new();
+        file.read_to_string(&mut text).ok()?;
+        SessionRecord::parse(&text)
+    }
+
+    fn load(path: &PathBuf) -> Result<Option<(SessionRecord, u64)>, String> {
+        match Self::open(path, false)? {
+            Some(mut file) => Ok(Self::read(&mut file)),
+            None => Ok(None),
+        }
+    }
+
+    // The caller keeps the original open until the rename is done, which keeps other writers out.
+    fn write(path: &PathBuf, record: &SessionRecord, version: u64) -> Result<(), String> {
+        let temp = path.with_extension("tmp");
+        let text = record.render(version);
+        let result = File::create(&temp)
+            .and_then(|mut file| file.write_all(text.as_bytes()).and_then(|_| file.sync_all()))
+            .and_then(|_| std::fs::rename(&temp, path));
+
+        if result.is_err() {
+            std::fs::remove_file(&temp).ok();
+        }
+
+        result.map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    fn save(path: &PathBuf, record: &SessionRecord) -> Result<u64, String> {
+        let mut file = Self::open(path, true)?.ok_or_else(|| format!("{}: not created", path.display()))?;
+        let version = Self::read(&mut file).map_or(0, |x| x.1) + 1;
+        Self::write(path, record, version)?;
+        drop(file);
+        Ok(version)
+    }
+
+    // Read, change and write back under one exclusive open so concurrent updates from other processes are not lost.
+    fn update<F>(path: &PathBuf, f: F) -> Result<Option<(SessionRecord, u64)>, String> where F: FnOnce(&mut SessionRecord) -> bool {
+        let mut file = match Self::open(path, false)? {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+
+        let (mut record, version) = match Self::read(&mut file) {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+
+        if !f(&mut record) {
+            return Ok(None);
+        }
+
+        Self::write(path, &record, version + 1)?;
+        drop(file);
+        Ok(Some((record, version + 1)))
+    }
+
+    fn remove(path: &PathBuf) -> Result<(), String> {
+        for _ in 0..LOCK_RETRIES {
+            match std::fs::remove_file(path) {
+                Ok(()) => return Ok(()),
+                Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+                Err(_) => sleep(Duration::from_millis(1)),
+            }
+        }
+
+        Err(format!("{}: still locked", path.display()))
+    }
+
+    fn evict(&self, map: &mut HashMap<String, Entry>) {
+        if map.len() <= self.max {
+            return;
+        }
+
+        let mut used = map.values().map(|x| x.used).collect::<Vec<_>>();
+        let count = map.len() - (self.max * 7 / 8).max(1);
+        used.select_nth_unstable(count - 1);
+
+        let limit = used[count - 1];
+        map.retain(|_, x| x.used > limit);
+    }
+
+    fn entries(&self) -> Result<MutexGuard<HashMap<String, Entry>>, String> {
+        self.entries.lock().map_err(|_| String::from("Session store poisoned"))
+    }
+
+    fn cached(&self, id: &str) -> Result<Option<(SessionRecord, u64, u64)>, String> {
+        Ok(self.entries()?.get(id).map(|x| (x.record.clone(), x.version, x.persisted)))
+    }
+
+    fn remember(&self, id: &str, record: SessionRecord, version: u64, persisted: u64) -> Result<(), String> {
+        let used = self.tick.fetch_add(1, Relaxed);
+        let mut map = self.entries()?;
+        map.insert(String::from(id), Entry { record, version, persisted, used });
+        self.evict(&mut map);
+        Ok(())
+    }
+
+    fn discard(&self, id: &str) -> Result<(), String> {
+        self.entries()?.remove(id);
+        Ok(())
+    }
+
+    pub fn create(&self, key: Option<&str>) -> Result<(String, SessionRecord), String> {
+        let id = match key {
+            Some(key) => String::from(key),
+            None => {
+                let mut bytes = [0u8; 32];
+                self.rng.fill(&mut bytes).map_err(|_| String::from("Random session id failed"))?;
+                base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+            }
+        };
+
+        let created = now();
+        let record = SessionRecord { data: String::from("{}"), created, expires: created + self.ttl };
+        let mut version = 0;
+        if let Some(path) = self.path(&id) {
+            version = Self::save(&path, &record)?;
+        }
+
+        self.remember(&id, record.clone(), version, record.expires)?;
+        Ok((id, record))
+    }
+
+    // Sliding renewals are written back at most once per quarter of the lifetime to keep reads cheap.
+    pub fn get(&self, id: &str) -> Result<Option<SessionRecord>, String> {
+        let path = self.path(id);
+        let cached = self.cached(id)?;
+        let (mut record, mut version, mut persisted) = match &path {
+            Some(path) => match Self::load(path)? {
+                Some((_, version)) if cached.as_ref().map_or(false, |x| x.1 == version) => cached.unwrap(),
+                Some((record, version)) => {
+                    let persisted = record.expires;
+                    (record, version, persisted)
+                },
+                None => {
+                    self.discard(id)?;
+                    return Ok(None);
+                },
+            },
+            None => match cached {
+                Some(cached) => cached,
+                None => return Ok(None),
+            },
+        };
+
+        let time = now();
+        if record.expires <= time {
+            self.discard(id)?;
+            if let Some(path) = &path {
+                Self::remove(path)?;
+            }
+
+            return Ok(None);
+        }
+
+        if self.sliding {
+            let expires = time + self.ttl;
+            record.expires = expires;
+            if let Some(path) = &path {
+                if expires.saturating_sub(persisted) > self.ttl / 4 {
+                    match Self::update(path, |x| { x.expires = x.expires.max(expires); true })? {
+                        Some((updated, next)) => {
+                            persisted = updated.expires;
+                            record = updated;
+                            version = next;
+                        },
+                        None => {
+                            self.discard(id)?;
+                            return Ok(None);
+                        },
+                    }
+                }
+            }
+        }
+
+        self.remember(id, record.clone(), version, persisted)?;
+        Ok(Some(record))
+    }
+
+    pub fn set(&self, id: &str, data: String) -> Result<bool, String> {
+        let current = match self.get(id)? {
+            Some(record) => record,
+            None => return Ok(false),
+        };
+
+        let path = match self.path(id) {
+            Some(path) => path,
+            None => {
+                return Ok(match self.entries()?.get_mut(id) {
+                    Some(entry) => {
+                        entry.record.data = data;
+                        true
+                    },
+                    None => false,
+                });
+            }
+        };
+
+        let time = now();
+        let result = Self::update(&path, |x| {
+            x.data = data;
+            x.expires = x.expires.max(current.expires);
+            x.expires > time
+        })?;
+
+        match result {
+            Some((record, version)) => {
+                let persisted = record.expires;
+                self.remember(id, record, version, persisted)?;
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+
+    pub fn destroy(&self, id: &str) -> Result<(), String> {
+        self.discard(id)?;
+        match self.path(id) {
+            Some(path) => Self::remove(&path),
+            None => Ok(()),
+        }
+    }
+
+    pub fn prune(&self) -> usize {
+        let time = now();
+        let mut count = 0;
+        if let Ok(mut map) = self.entries.lock() {
+            let before = map.len();
+            map.retain(|_, x| x.record.expires > time);
+            count = before - map.len();
+        }
+
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => return count,
+        };
+
+        let list = match std::fs::read_dir(dir) {
+            Ok(list) => list,
+            Err(_) => return count,
+        };
+
+        for item in list.flatten() {
+            let path = item.path();
+            if path.extension().map(|x| x != "session").unwrap_or(true) {
+                continue;
+            }
+
+            if let Ok(Some((record, _))) = Self::load(&path) {
+                if record.expires <= time && std::fs::remove_file(&path).is_ok() {
+                    count += 1;
+                }
+            }
+        }
+
+        count
+    }
+}
+
+pub struct SessionBinding {
+    pub store: Arc<SessionStore>,
+    pub cookie: String,
+    pub by_user: bool,
+}
+
+pub struct Sessions {
+    binding: RwLock<Option<Arc<SessionBinding>>>,
+}
+
+impl Sessions {
+    pub fn new() -> Self {
+        Self { binding: RwLock::new(None) }
+    }
+
+    pub fn config(&self, binding: Option<SessionBinding>) {
+        if let Ok(mut value) = self.binding.write() {
+            *value = binding.map(Arc::new);
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.binding.read().map(|x| x.is_some()).unwrap_or(false)
+    }
+
+    pub fn wants_user(&self) -> bool {
+        self.binding.read().ok().and_then(|x| x.as_ref().map(|x| x.by_user)).unwrap_or(false)
+    }
+
+    // Authenticated users get a session keyed by their SID; everyone else is looked up by cookie,
+    // and only by a signed or sealed cookie once cookie keys are configured.
+    // The user is a principal key such as "sid:S-1-5-..." or "jwt:subject", so identities from
+    // different sources never share a session.
+    pub fn bind(&self, cookies: &[(String, String)], trusted: Option<&[(String, String)]>, user: Option<&str>) -> Option<(String, SessionRecord)> {
+        let binding = self.binding.read().ok()?.clone()?;
+        if binding.by_user {
+            if let Some(user) = user {
+                let id = format!("user:{}", user);
+                if let Some(record) = binding.store.get(&id).ok()? {
+                    return Some((id, record));
+                }
+
+                return binding.store.create(Some(&id)).ok();
+            }
+        }
+
+        let list = trusted.unwrap_or(cookies);
+        let (_, id) = list.iter().find(|(name, _)| name == &binding.cookie)?;
+        if id.starts_with("user:") {
+            return None;
+        }
+
+        binding.store.get(id).ok()?.map(|x| (id.clone(), x))
+    }
+}
+
+pub fn session_to_js<'a, T>(cx: &mut T, id: &str, record: &SessionRecord) -> JsResult<'a, JsObject> where T: Context<'a> {
+    let obj = cx.empty_object();
+    let js_id = cx.string(id);
+    obj.set(cx, "id", js_id)?;
+
+    let js_data = cx.string(&record.data);
+    obj.set(cx, "data", js_data)?;
+
+    let js_created = cx.number(record.created as f64);
+    obj.set(cx, "created", js_created)?;
+
+    let js_expires = cx.number(record.expires as f64);
+    obj.set(cx, "expires", js_expires)?;
+
+    Ok(obj)
+}
+
+fn session_store_create(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let mut i = 0;
+    let ttl = cx.arg_f64(&mut i)? as u64;
+    let max = cx.arg_u32(&mut i)? as usize;
+    let mut sliding = true;
+    let mut dir = None;
+    if cx.arg_opt(&mut i) {
+        sliding = cx.arg_bool(&mut i)?;
+    }
+
+    if cx.arg_opt(&mut i) {
+        dir = Some(PathBuf::from(cx.arg_string(&mut i)?));
+    }
+
+    match SessionStore::new(ttl, max, sliding, dir) {
+        Ok(store) => Ok(cx.export(store)),
+        Err(err) => cx.throw_type_error(err),
+    }
+}
+
+fn session_create(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let store = cx.import::<SessionStore>(&mut i)?;
+    let builder = cx.task(move || store.create(None));
+    let promise = builder.promise(move |mut cx, result| {
+        match result {
+            Ok((id, record)) => session_to_js(&mut cx, &id, &record),
+            Err(err) => cx.throw_type_error(err),
+        }
+    });
+
+    Ok(promise)
+}
+
+fn session_get(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let store = cx.import::<SessionStore>(&mut i)?;
+    let id = cx.arg_string(&mut i)?;
+    let builder = cx.task(move || store.get(&id).map(|x| x.map(|record| (id, record))));
+    let promise = builder.promise(move |mut cx, result| {
+        match result {
+            Ok(Some((id, record))) => Ok(session_to_js(&mut cx, &id, &record)?.upcast::<JsValue>()),
+            Ok(None) => Ok(cx.null().upcast()),
+            Err(err) => cx.throw_type_error(err),
+        }
+    });
+
+    Ok(promise)
+}
+
+fn session_set(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let store = cx.import::<SessionStore>(&mut i)?;
+    let id = cx.arg_string(&mut i)?;
+    let data = cx.arg_string(&mut i)?;
+    let builder = cx.task(move || store.set(&id, data));
+    let promise = builder.promise(move |mut cx, result| {
+        match result {
+            Ok(value) => Ok(cx.boolean(value)),
+            Err(err) => cx.throw_type_error(err),
+        }
+    });
+
+    Ok(promise)
+}
+
+fn session_destroy(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let store = cx.import::<SessionStore>(&mut i)?;
+    let id = cx.arg_string(&mut i)?;
+    let builder = cx.task(move || store.destroy(&id));
+    let promise = builder.promise(move |mut cx, result| {
+        match result {
+            Ok(()) => Ok(cx.undefined()),
+            Err(err) => cx.throw_type_error(err),
+        }
+    });
+
+    Ok(promise)
+}
+
+fn session_prune(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let store = cx.import::<SessionStore>(&mut i)?;
+    let builder = cx.task(move || store.prune());
+    let promise = builder.promise(move |mut cx, value| {
+        Ok(cx.number(value as f64))
+    });
+
+    Ok(promise)
+}
+
+fn session_store_close(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    cx.dispose::<SessionStore>(0)?;
+    Ok(cx.undefined())
+}
+
+pub fn session_bind(cx: &mut ModuleContext) -> NeonResult<()> {
+    cx.export_function("session_store_create", session_store_create)?;
+    cx.export_function("session_create", session_create)?;
+    cx.export_function("session_get", session_get)?;
+    cx.export_function("session_set", session_set)?;
+    cx.export_function("session_destroy", session_destroy)?;
+    cx.export_function("session_prune", session_prune)?;
+    cx.export_function("session_store_close", session_store_close)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("plugin-sessions-{}-{}", std::process::id(), name));
+        std::fs::remove_dir_all(&dir).ok();
+        dir
+    }
+
+    #[test]
+    fn memory_round_trip() {
+        let store = SessionStore::new(60000, 10, true, None).unwrap();
+        let (id, record) = store.create(None).unwrap();
+        assert_eq!(record.data, "{}");
+        assert!(store.set(&id, String::from("{\"a\":1}")).unwrap());
+        assert_eq!(store.get(&id).unwrap().unwrap().data, "{\"a\":1}");
+
+        store.destroy(&id).unwrap();
+        assert_eq!(store.get(&id).unwrap(), None);
+        assert!(!store.set(&id, String::from("{}")).unwrap());
+    }
+
+    #[test]
+    fn expiry() {
+        let store = SessionStore::new(0, 10, false, None).unwrap();
+        let (id, _) = store.create(None).unwrap();
+        assert_eq!(store.get(&id).unwrap(), None);
+        assert_eq!(store.prune(), 0);
+    }
+
+    #[test]
+    fn eviction_keeps_recent() {
+        let store = SessionStore::new(60000, 8, false, None).unwrap();
+        let ids = (0..9).map(|_| store.create(None).unwrap().0).collect::<Vec<_>>();
+        assert_eq!(store.get(&ids[0]).unwrap(), None);
+        assert!(store.get(&ids[8]).unwrap().is_some());
+    }
+
+    #[test]
+    fn shared_directory_versions() {
+        let path = dir("shared");
+        let one = SessionStore::new(60000, 10, true, Some(path.clone())).unwrap();
+        let two = SessionStore::new(60000, 10, true, Some(path.clone())).unwrap();
+        let (id, _) = one.create(Some("user:sid:S-1-5-18")).unwrap();
+        assert_eq!(two.get(&id).unwrap().unwrap().data, "{}");
+
+        // Writes within the same clock tick must still be seen by the other store.
+        assert!(one.set(&id, String::from("1")).unwrap());
+        assert_eq!(two.get(&id).unwrap().unwrap().data, "1");
+        assert!(two.set(&id, String::from("2")).unwrap());
+        assert_eq!(one.get(&id).unwrap().unwrap().data, "2");
+
+        let file = one.path(&id).unwrap();
+        let text = std::fs::read_to_string(&file).unwrap();
+        assert_eq!(SessionRecord::parse(&text).unwrap().1, 3);
+        assert_eq!(std::fs::read_dir(&path).unwrap().count(), 1);
+
+        two.destroy(&id).unwrap();
+        assert_eq!(one.get(&id).unwrap(), None);
+        std::fs::remove_dir_all(&path).ok();
+    }
+
+    #[test]
+    fn locked_file_is_an_error() {
+        let path = dir("locked");
+        let store = SessionStore::new(60000, 10, true, Some(path.clone())).unwrap();
+        let (id, _) = store.create(None).unwrap();
+        let file = store.path(&id).unwrap();
+        let held = OpenOptions::new().read(true).share_mode(0).open(&file).unwrap();
+
+        assert!(store.get(&id).is_err());
+        assert!(store.set(&id, String::from("1")).is_err());
+        assert!(store.destroy(&id).is_err());
+
+        drop(held);
+        assert!(store.get(&id).unwrap().is_some());
+        std::fs::remove_dir_all(&path).ok();
+    }
+
+    #[test]
+    fn prune_removes_expired_files() {
+        let path = dir("prune");
+        let store = SessionStore::new(0, 10, false, Some(path.clone())).unwrap();
+        store.create(None).unwrap();
+        store.create(None).unwrap();
+        assert_eq!(store.prune(), 4);
+        assert_eq!(std::fs::read_dir(&path).unwrap().count(), 0);
+        std::fs::remove_dir_all(&path).ok();
+    }
+
+    #[test]
+    fn user_sessions_are_not_reachable_by_cookie() {
+        let store = Arc::new(SessionStore::new(60000, 10, true, None).unwrap());
+        let sessions = Sessions::new();
+        sessions.config(Some(SessionBinding { store: store.clone(), cookie: String::from("sid"), by_user: true }));
+
+        let (id, _) = sessions.bind(&[], None, Some("jwt:alice")).unwrap();
+        assert_eq!(id, "user:jwt:alice");
+        assert_ne!(sessions.bind(&[], None, Some("cert:alice")).unwrap().0, id);
+
+        let cookies = vec![(String::from("sid"), id.clone())];
+        assert_eq!(sessions.bind(&cookies, None, None), None);
+    }
+}
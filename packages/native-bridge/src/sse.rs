@@ -0,0 +1,471 @@
+use neon::prelude::*;
+use neon::types::Deferred;
+
+use super::http::*;
+use super::message::*;
+use super::support::*;
+
+use windows::Win32::Foundation::*;
+use windows::Win32::Networking::HttpServer::*;
+
+use std::cell::RefCell;
+use std::sync::mpsc::sync_channel;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::mpsc::SyncSender;
+use std::sync::mpsc::TrySendError;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread::spawn;
+use std::time::Duration;
+
+fn strip_line(value: &str) -> String {
+    value.chars().filter(|x| *x != '\r' && *x != '\n').collect()
+}
+
+pub fn encode_event(data: &str, event: Option<&str>, id: Option<&str>, retry: Option<u32>) -> String {
+    let mut result = String::new();
+    if let Some(id) = id {
+        result.push_str(&format!("id: {}\n", strip_line(id)));
+    }
+
+    if let Some(event) = event {
+        result.push_str(&format!("event: {}\n", strip_line(event)));
+    }
+
+    if let Some(retry) = retry {
+        result.push_str(&format!("retry: {}\n", retry));
+    }
+
+    let normal = data.replace("\r\n", "\n").replace('\r', "\n");
+    for line in normal.split('\n') {
+        result.push_str("data: ");
+        result.push_str(line);
+        result.push('\n');
+    }
+
+    result.push('\n');
+    result
+}
+
+// Events queued per channel before send reports backpressure.
+const DEFAULT_CAPACITY: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SendStatus {
+    Sent,
+    Full,
+    Closed,
+}
+
+impl SendStatus {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sent => "sent",
+            Self::Full => "full",
+            Self::Closed => "closed",
+        }
+    }
+}
+
+struct SseState {
+    tx: Option<SyncSender<Vec<u8>>>,
+    closed: bool,
+    waiters: Vec<(Channel, Deferred)>,
+}
+
+pub struct SseChannel {
+    state: Mutex<SseState>,
+    chunked: bool,
+}
+
+impl Finalize for SseChannel {}
+
+impl SseChannel {
+    pub fn open(req: Arc<Request>, id: u64, connection: u64, chunked: bool, keep_alive: u32, retry: Option<u32>, capacity: usize) -> Arc<Self> {
+        let (tx, rx) = sync_channel(capacity.max(1));
+        let result = Arc::new(Self {
+            state: Mutex::new(SseState {
+                tx: Some(tx),
+                closed: false,
+                waiters: Vec::new(),
+            }),
+            chunked,
+        });
+
+        let mut interval = keep_alive as u64;
+        if interval < 1 {
+            interval = 15000;
+        }
+
+        // Dropping the sender wakes the writer as soon as HTTP.sys sees the client go, not at the next keep-alive.
+        let arc = result.clone();
+        req.wait_disconnect(connection, move |_| arc.close());
+
+        let arc = result.clone();
+        spawn(move || {
+            arc.run(req, id, rx, Duration::from_millis(interval), retry);
+            arc.finish();
+        });
+
+        result
+    }
+
+    fn frame(&self, data: &[u8]) -> Vec<u8> {
+        if !self.chunked {
+            return data.to_vec();
+        }
+
+        let mut result = format!("{:x}\r\n", data.len()).into_bytes();
+        result.extend_from_slice(data);
+        result.extend_from_slice(b"\r\n");
+        result
+    }
+
+    fn run(&self, req: Arc<Request>, id: u64, rx: Receiver<Vec<u8>>, interval: Duration, retry: Option<u32>) {
+        let mut reply = Reply::new(200, "OK")
+            .header("Content-Type", "text/event-stream; charset=utf-8")
+            .header("Cache-Control", "no-cache")
+            .header("X-Accel-Buffering", "no");
+
+        if self.chunked {
+            reply = reply.header("Transfer-Encoding", "chunked");
+        }
+
+        if let Some(retry) = retry {
+            reply = reply.body(self.frame(format!("retry: {}\n\n", retry).as_bytes()));
+        }
+
+        let (err, _) = block_on(|done| {
+            reply.send(&req, id, HTTP_SEND_RESPONSE_FLAG_MORE_DATA, move |err, size| done((err, size)));
+        }).unwrap_or((ERROR_OPERATION_ABORTED.0, 0));
+
+        if err != 0 {
+            return;
+        }
+
+        loop {
+            let data = match rx.recv_timeout(interval) {
+                Ok(data) => self.frame(&data),
+                Err(RecvTimeoutError::Timeout) => self.frame(b": keep-alive\n\n"),
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+
+            let (err, _) = block_on(|done| {
+                send_body(&req, id, HTTP_SEND_RESPONSE_FLAG_MORE_DATA, vec![data], Vec::new(), move |err, size| done((err, size)));
+            }).unwrap_or((ERROR_OPERATION_ABORTED.0, 0));
+
+            if err != 0 {
+                return;
+            }
+        }
+
+        let mut tail = Vec::new();
+        if self.chunked {
+            tail.push(b"0\r\n\r\n".to_vec());
+        }
+
+        block_on(|done| {
+            send_body(&req, id, 0, tail, Vec::new(), move |err, size| done((err, size)));
+        }).ok();
+    }
+
+    fn finish(&self) {
+        let mut waiters = Vec::new();
+        if let Ok(mut state) = self.state.lock() {
+            state.closed = true;
+            state.tx = None;
+            waiters.append(&mut state.waiters);
+        }
+
+        for (tx, def) in waiters {
+            def.settle_with(&tx, move |mut cx| {
+                Ok(cx.undefined())
+            });
+        }
+    }
+
+    pub fn send(&self, data: Vec<u8>) -> SendStatus {
+        if let Ok(state) = self.state.lock() {
+            if let Some(tx) = state.tx.as_ref() {
+                return match tx.try_send(data) {
+                    Ok(()) => SendStatus::Sent,
+                    Err(TrySendError::Full(_)) => SendStatus::Full,
+                    Err(TrySendError::Disconnected(_)) => SendStatus::Closed,
+                };
+            }
+        }
+
+        SendStatus::Closed
+    }
+
+    pub fn close(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.tx = None;
+        }
+    }
+
+    pub fn closed(&self) -> bool {
+        match self.state.lock() {
+            Ok(state) => state.closed,
+            Err(_) => true,
+        }
+    }
+}
+
+pub struct SseHub {
+    channels: Mutex<Vec<Arc<SseChannel>>>,
+}
+
+impl Finalize for SseHub {}
+
+impl SseHub {
+    pub fn add(&self, channel: Arc<SseChannel>) {
+        if let Ok(mut vec) = self.channels.lock() {
+            if !vec.iter().any(|x| Arc::ptr_eq(x, &channel)) {
+                vec.push(channel);
+            }
+        }
+    }
+
+    pub fn remove(&self, channel: &Arc<SseChannel>) {
+        if let Ok(mut vec) = self.channels.lock() {
+            vec.retain(|x| !Arc::ptr_eq(x, channel));
+        }
+    }
+
+    pub fn send(&self, data: &[u8]) -> u32 {
+        let mut count = 0;
+        if let Ok(mut vec) = self.channels.lock() {
+            vec.retain(|x| {
+                match x.send(data.to_vec()) {
+                    SendStatus::Sent => count += 1,
+                    SendStatus::Full => (),
+                    SendStatus::Closed => return false,
+                }
+
+                true
+            });
+        }
+
+        count
+    }
+}
+
+fn arg_event(cx: &mut FunctionContext, i: &mut i32) -> NeonResult<Vec<u8>> {
+    let data = cx.arg_string(i)?;
+    let mut event = None;
+    let mut id = None;
+    let mut retry = None;
+    if cx.arg_opt(i) {
+        event = Some(cx.arg_string(i)?);
+    }
+
+    if cx.arg_opt(i) {
+        id = Some(cx.arg_string(i)?);
+    }
+
+    if cx.arg_opt(i) {
+        retry = Some(cx.arg_u32(i)?);
+    }
+
+    let text = encode_event(&data, event.as_deref(), id.as_deref(), retry);
+    Ok(text.into_bytes())
+}
+
+fn http_sse_open(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let mut i = 0;
+    let arc = cx.import::<Request>(&mut i)?;
+    let id = cx.arg_u64(&mut i)?;
+    let connection = cx.arg_u64(&mut i)?;
+    let chunked = cx.arg_bool(&mut i)?;
+    let mut keep_alive = 0;
+    let mut retry = None;
+    let mut capacity = DEFAULT_CAPACITY;
+    if cx.arg_opt(&mut i) {
+        keep_alive = cx.arg_u32(&mut i)?;
+    }
+
+    if cx.arg_opt(&mut i) {
+        retry = Some(cx.arg_u32(&mut i)?);
+    }
+
+    if cx.arg_opt(&mut i) {
+        capacity = cx.arg_u32(&mut i)? as usize;
+    }
+
+    let channel = SseChannel::open(arc, id, connection, chunked, keep_alive, retry, capacity);
+    let result = cx.boxed(RefCell::new(Some(channel)));
+    Ok(result.upcast())
+}
+
+fn http_sse_send(mut cx: FunctionContext) -> JsResult<JsString> {
+    let mut i = 0;
+    let arc = cx.import::<SseChannel>(&mut i)?;
+    let data = arg_event(&mut cx, &mut i)?;
+    let status = arc.send(data);
+
+    Ok(cx.string(status.name()))
+}
+
+fn http_sse_comment(mut cx: FunctionContext) -> JsResult<JsString> {
+    let mut i = 0;
+    let arc = cx.import::<SseChannel>(&mut i)?;
+    let text = cx.arg_string(&mut i)?;
+    let status = arc.send(format!(": {}\n\n", strip_line(&text)).into_bytes());
+
+    Ok(cx.string(status.name()))
+}
+
+fn http_sse_closed(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let arc = cx.import::<SseChannel>(&mut i)?;
+    let tx = cx.channel();
+    let (def, promise) = cx.promise();
+    if let Ok(mut state) = arc.state.lock() {
+        if !state.closed {
+            state.waiters.push((tx, def));
+            return Ok(promise);
+        }
+    }
+
+    def.settle_with(&tx, move |mut cx| {
+        Ok(cx.undefined())
+    });
+
+    Ok(promise)
+}
+
+fn http_sse_close(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let mut i = 0;
+    let arc = cx.import::<SseChannel>(&mut i)?;
+    arc.close();
+    cx.dispose::<SseChannel>(0)?;
+
+    Ok(cx.undefined())
+}
+
+fn http_sse_hub_create(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let hub = SseHub {
+        channels: Mutex::new(Vec::new()),
+    };
+
+    Ok(cx.export(hub))
+}
+
+fn http_sse_hub_add(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let mut i = 0;
+    let hub = cx.import::<SseHub>(&mut i)?;
+    let channel = cx.import::<SseChannel>(&mut i)?;
+    if !channel.closed() {
+        hub.add(channel);
+    }
+
+    Ok(cx.undefined())
+}
+
+fn http_sse_hub_remove(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let mut i = 0;
+    let hub = cx.import::<SseHub>(&mut i)?;
+    let channel = cx.import::<SseChannel>(&mut i)?;
+    hub.remove(&channel);
+
+    Ok(cx.undefined())
+}
+
+fn http_sse_hub_send(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let mut i = 0;
+    let hub = cx.import::<SseHub>(&mut i)?;
+    let data = arg_event(&mut cx, &mut i)?;
+    let count = hub.send(&data);
+
+    Ok(cx.number(count))
+}
+
+fn http_sse_hub_close(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    cx.dispose::<SseHub>(0)?;
+    Ok(cx.undefined())
+}
+
+pub fn sse_bind(cx: &mut ModuleContext) -> NeonResult<()> {
+    cx.export_function("http_sse_open", http_sse_open)?;
+    cx.export_function("http_sse_send", http_sse_send)?;
+    cx.export_function("http_sse_comment", http_sse_comment)?;
+    cx.export_function("http_sse_closed", http_sse_closed)?;
+    cx.export_function("http_sse_close", http_sse_close)?;
+
+    cx.export_function("http_sse_hub_create", http_sse_hub_create)?;
+    cx.export_function("http_sse_hub_add", http_sse_hub_add)?;
+    cx.export_function("http_sse_hub_remove", http_sse_hub_remove)?;
+    cx.export_function("http_sse_hub_send", http_sse_hub_send)?;
+    cx.export_function("http_sse_hub_close", http_sse_hub_close)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queued(capacity: usize) -> (Arc<SseChannel>, Receiver<Vec<u8>>) {
+        let (tx, rx) = sync_channel(capacity);
+        let channel = SseChannel {
+            state: Mutex::new(SseState {
+                tx: Some(tx),
+                closed: false,
+                waiters: Vec::new(),
+            }),
+            chunked: true,
+        };
+
+        (Arc::new(channel), rx)
+    }
+
+    #[test]
+    fn encodes_events() {
+        assert_eq!(encode_event("a\r\nb\rc", Some("tick"), Some("7\n"), Some(500)), "id: 7\nevent: tick\nretry: 500\ndata: a\ndata: b\ndata: c\n\n");
+        assert_eq!(encode_event("", None, None, None), "data: \n\n");
+    }
+
+    #[test]
+    fn frames_chunks() {
+        let (channel, _rx) = queued(1);
+        assert_eq!(channel.frame(b"hello"), b"5\r\nhello\r\n");
+    }
+
+    #[test]
+    fn reports_backpressure() {
+        let (channel, rx) = queued(2);
+        assert_eq!(channel.send(b"1".to_vec()), SendStatus::Sent);
+        assert_eq!(channel.send(b"2".to_vec()), SendStatus::Sent);
+        assert_eq!(channel.send(b"3".to_vec()), SendStatus::Full);
+
+        assert_eq!(rx.recv().unwrap(), b"1");
+        assert_eq!(channel.send(b"3".to_vec()), SendStatus::Sent);
+
+        channel.close();
+        assert_eq!(channel.send(b"4".to_vec()), SendStatus::Closed);
+        assert_eq!(rx.recv().unwrap(), b"2");
+        assert_eq!(rx.recv().unwrap(), b"3");
+        assert!(rx.recv().is_err());
+    }
+
+    #[test]
+    fn hub_keeps_slow_channels() {
+        let hub = SseHub {
+            channels: Mutex::new(Vec::new()),
+        };
+
+        let (fast, _fast_rx) = queued(4);
+        let (slow, _slow_rx) = queued(1);
+        let (gone, gone_rx) = queued(4);
+        hub.add(fast.clone());
+        hub.add(slow.clone());
+        hub.add(gone.clone());
+        drop(gone_rx);
+
+        assert_eq!(hub.send(b"a"), 2);
+        assert_eq!(hub.send(b"b"), 1);
+        assert_eq!(hub.channels.lock().unwrap().len(), 2);
+    }
+}
@@ -2,6 +2,8 @@ use neon::prelude::*;
 use neon::types::buffer::*;
 
 use std::cell::RefCell;
+use std::slice::from_raw_parts;
+use std::sync::mpsc::channel;
 use std::sync::Arc;
 use std::sync::RwLock;
 
@@ -46,18 +48,120 @@ impl<T> Delegate<T> {
     }
 }
 
+pub struct Block {
+    root: Root<JsBuffer>,
+    ptr: *mut u8,
+    len: usize,
+}
+
+unsafe impl Send for Block {
+
+}
+
+impl Block {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn whole(&self) -> Slice {
+        Slice { ptr: self.ptr, len: self.len }
+    }
+
+    pub fn slice(&self, off: usize, len: usize) -> Option<Slice> {
+        self.whole().slice(off, len)
+    }
+
+    pub fn cstr(&self, off: usize, len: usize, width: usize) -> Option<Slice> {
+        self.whole().cstr(off, len, width)
+    }
+
+    // Unroots the buffer on the JS thread once nothing points into it anymore.
+    pub fn release<'a, C: Context<'a>>(self, cx: &mut C) {
+        self.root.drop(cx);
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Slice {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Slice {
+    pub fn new(data: &mut [u8]) -> Self {
+        Self { ptr: data.as_mut_ptr(), len: data.len() }
+    }
+
+    pub fn ptr(&self) -> *const u8 {
+        self.ptr
+    }
+
+    pub fn mut_ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn slice(&self, off: usize, len: usize) -> Option<Slice> {
+        let end = off.checked_add(len)?;
+        if end > self.len {
+            return None;
+        }
+
+        Some(Slice { ptr: unsafe { self.ptr.add(off) }, len })
+    }
+
+    pub fn cstr(&self, off: usize, len: usize, width: usize) -> Option<Slice> {
+        if width == 0 {
+            return None;
+        }
+
+        let result = self.slice(off, len)?;
+        let tail = self.slice(off + len, width)?;
+        if result.ptr as usize % width != 0 || len % width != 0 {
+            return None;
+        }
+
+        if unsafe { tail.bytes() }.iter().any(|x| *x != 0) {
+            return None;
+        }
+
+        Some(result)
+    }
+
+    pub unsafe fn bytes<'b>(&self) -> &'b [u8] {
+        from_raw_parts(self.ptr, self.len)
+    }
+}
+
+// Fails when the completion is dropped without being called, e.g. because the queue was closed.
+pub fn block_on<T, F>(f: F) -> Result<T, &'static str> where T: Send + 'static, F: FnOnce(Box<dyn FnOnce(T) + Send + 'static>) {
+    let (tx, rx) = channel();
+    f(Box::new(move |value| {
+        tx.send(value).ok();
+    }));
+
+    rx.recv().map_err(|_| "Completion dropped.")
+}
+
 pub trait FunctionContextEx<'a> {
     fn arg_opt(&mut self, i: &mut i32) -> bool;
 
     fn arg_bool(&mut self, i: &mut i32) -> NeonResult<bool>;
     fn arg_buffer(&mut self, i: &mut i32) -> JsResult<'a, JsBuffer>;
+    fn arg_block(&mut self, i: &mut i32) -> NeonResult<Block>;
     fn arg_string(&mut self, i: &mut i32) -> NeonResult<String>;
+    fn arg_strings(&mut self, i: &mut i32) -> NeonResult<Vec<String>>;
 
     fn arg_u16(&mut self, i: &mut i32) -> NeonResult<u16>;
     fn arg_i32(&mut self, i: &mut i32) -> NeonResult<i32>;
     fn arg_u32(&mut self, i: &mut i32) -> NeonResult<u32>;
     fn arg_u64(&mut self, i: &mut i32) -> NeonResult<u64>;
-    fn arg_ptr(&mut self, i: &mut i32, block: &Handle<'a, JsBuffer>) -> NeonResult<(*const u8, usize)>;
+    fn arg_f64(&mut self, i: &mut i32) -> NeonResult<f64>;
+    fn arg_slice(&mut self, i: &mut i32, block: &Block) -> NeonResult<Slice>;
+    fn arg_cstr(&mut self, i: &mut i32, block: &Block, width: usize) -> NeonResult<Slice>;
 
     fn export<T: Finalize + Send + Sync + 'static>(&mut self, value: T) -> Handle<'a, JsValue>;
     fn import<T: Finalize + Send + Sync + 'static>(&mut self, i: &mut i32) -> NeonResult<Arc<T>>;
@@ -92,6 +196,18 @@ impl<'a> FunctionContextEx<'a> for FunctionContext<'a> {
         Ok(result)
     }
 
+    fn arg_block(&mut self, i: &mut i32) -> NeonResult<Block> {
+        let mut buf = self.argument::<JsBuffer>(*i)?;
+        *i += 1;
+
+        let root = buf.root(self);
+        let slice = buf.as_mut_slice(self);
+        let ptr = slice.as_mut_ptr();
+        let len = slice.len();
+
+        Ok(Block { root, ptr, len })
+    }
+
     fn arg_string(&mut self, i: &mut i32) -> NeonResult<String> {
         let result = self.argument::<JsString>(*i)?.value(self);
         *i += 1;
@@ -99,6 +215,18 @@ impl<'a> FunctionContextEx<'a> for FunctionContext<'a> {
         Ok(result)
     }
 
+    fn arg_strings(&mut self, i: &mut i32) -> NeonResult<Vec<String>> {
+        let array = self.argument::<JsArray>(*i)?.to_vec(self)?;
+        *i += 1;
+
+        let mut result = Vec::with_capacity(array.len());
+        for value in array {
+            result.push(value.downcast_or_throw::<JsString, _>(self)?.value(self));
+        }
+
+        Ok(result)
+    }
+
     fn arg_u16(&mut self, i: &mut i32) -> NeonResult<u16> {
         let result = self.argument::<JsNumber>(*i)?.value(self);
         *i += 1;
@@ -127,11 +255,29 @@ impl<'a> FunctionContextEx<'a> for FunctionContext<'a> {
         Ok(**result)
     }
 
-    fn arg_ptr(&mut self, i: &mut i32, block: &Handle<'a, JsBuffer>) -> NeonResult<(*const u8, usize)> {
+    fn arg_f64(&mut self, i: &mut i32) -> NeonResult<f64> {
+        let result = self.argument::<JsNumber>(*i)?.value(self);
+        *i += 1;
+
+        Ok(result)
+    }
+
+    fn arg_slice(&mut self, i: &mut i32, block: &Block) -> NeonResult<Slice> {
         let off = self.arg_u32(i)? as usize;
         let len = self.arg_u32(i)? as usize;
-        let ptr = block.as_slice(self)[off..off].as_ptr();
-        Ok((ptr, len))
+        match block.slice(off, len) {
+            Some(slice) => Ok(slice),
+            None => self.throw_range_error(format!("Slice out of bounds: {}+{} > {}", off, len, block.len())),
+        }
+    }
+
+    fn arg_cstr(&mut self, i: &mut i32, block: &Block, width: usize) -> NeonResult<Slice> {
+        let off = self.arg_u32(i)? as usize;
+        let len = self.arg_u32(i)? as usize;
+        match block.cstr(off, len, width) {
+            Some(slice) => Ok(slice),
+            None => self.throw_range_error(format!("String not terminated: {}+{} in {}", off, len, block.len())),
+        }
     }
 
     fn export<T: Finalize + Send + Sync + 'static>(&mut self, value: T) -> Handle<'a, JsValue> {
@@ -157,3 +303,88 @@ impl<'a> FunctionContextEx<'a> for FunctionContext<'a> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 as usize
+        }
+
+        fn pick(&mut self, limit: usize) -> usize {
+            match self.next() % 8 {
+                0 => usize::MAX - self.next() % 4,
+                _ => self.next() % (limit + 4),
+            }
+        }
+    }
+
+    fn whole(data: &mut [u8]) -> Slice {
+        Slice::new(data)
+    }
+
+    #[test]
+    fn block_on_completions() {
+        assert_eq!(block_on(|done| done(7)), Ok(7));
+        assert_eq!(block_on(|done: Box<dyn FnOnce(u32) + Send>| drop(done)), Err("Completion dropped."));
+    }
+
+    #[test]
+    fn slice_bounds() {
+        let mut data = vec![1u8; 8];
+        let all = whole(&mut data);
+        assert_eq!(all.slice(8, 0).map(|x| x.len()), Some(0));
+        assert!(all.slice(7, 2).is_none());
+        assert!(all.slice(usize::MAX, 2).is_none());
+        assert!(all.slice(2, usize::MAX).is_none());
+    }
+
+    #[test]
+    fn cstr_terminators() {
+        let mut data = vec![0u8; 16];
+        data[..4].copy_from_slice(b"?a=1");
+        let all = whole(&mut data);
+        assert_eq!(all.cstr(0, 4, 1).map(|x| unsafe { x.bytes() }.to_vec()), Some(b"?a=1".to_vec()));
+        assert!(all.cstr(0, 3, 1).is_none());
+        assert!(all.cstr(0, 16, 1).is_none());
+        assert!(all.cstr(0, 4, 0).is_none());
+        assert!(all.cstr(1, 4, 2).is_none());
+    }
+
+    #[test]
+    fn fuzz_slices() {
+        let mut rng = Rng(0x9e3779b97f4a7c15);
+        for _ in 0..20000 {
+            let size = rng.next() % 64;
+            let mut data: Vec<u8> = (0..size).map(|_| (rng.next() % 3) as u8).collect();
+            let base = data.as_ptr() as usize;
+            let all = whole(&mut data);
+            let off = rng.pick(size);
+            let len = rng.pick(size);
+            let width = [0, 1, 2, 4][rng.next() % 4];
+
+            match all.slice(off, len) {
+                Some(slice) => {
+                    assert!(off + len <= size);
+                    assert_eq!(slice.ptr() as usize, base + off);
+                    assert_eq!(slice.len(), len);
+                },
+                None => assert!(off.checked_add(len).map_or(true, |end| end > size)),
+            }
+
+            if let Some(slice) = all.cstr(off, len, width) {
+                assert!(off + len + width <= size);
+                assert_eq!(slice.ptr() as usize % width, 0);
+                assert_eq!(len % width, 0);
+                assert!(data[off + len..off + len + width].iter().all(|x| *x == 0));
+            }
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use neon::prelude::*;
+
+use super::http::*;
+use super::support::*;
+
+use windows::Win32::Networking::HttpServer::*;
+use windows::Win32::Networking::WinSock::TCP_INFO_v0;
+use windows::Win32::Networking::WinSock::TCP_INFO_v1;
+use windows::Win32::System::Performance::QueryPerformanceFrequency;
+
+use std::slice::from_raw_parts;
+
+// Indices into HTTP_REQUEST_TIMING_TYPE; inspection and delegation only appear when a filter or another queue is involved.
+const TIMING_PHASES: [(&str, usize, usize); 12] = [
+    ("tlsCertificateLoad", 2, 3),
+    ("tlsHandshakeLeg1", 4, 5),
+    ("tlsHandshakeLeg2", 6, 7),
+    ("tlsAttributesQuery", 8, 9),
+    ("tlsClientCertQuery", 10, 11),
+    ("http2HeaderDecode", 13, 14),
+    ("requestHeaderParse", 15, 16),
+    ("requestRouting", 17, 18),
+    ("requestInspection", 19, 21),
+    ("requestDelegation", 22, 24),
+    ("requestQueue", 25, 26),
+    ("total", 0, 26),
+];
+
+pub fn request_timing(req: &HTTP_REQUEST_V2) -> Option<Vec<(&'static str, f64)>> {
+    unsafe {
+        let mut freq = 0i64;
+        if !QueryPerformanceFrequency(&mut freq).as_bool() || freq < 1 {
+            return None;
+        }
+
+        let slice = from_raw_parts(req.pRequestInfo, req.RequestInfoCount as usize);
+        for info in slice {
+            if info.InfoType == HttpRequestInfoTypeRequestTiming {
+                let timing = &*(info.pInfo as *const HTTP_REQUEST_TIMING_INFO);
+                let count = (timing.RequestTimingCount as usize).min(timing.RequestTiming.len());
+                let ticks = &timing.RequestTiming[..count];
+                let mut result = Vec::new();
+                for (name, start, end) in TIMING_PHASES {
+                    if end < count && ticks[start] > 0 && ticks[end] >= ticks[start] {
+                        let ms = (ticks[end] - ticks[start]) as f64 * 1000.0 / freq as f64;
+                        result.push((name, ms));
+                    }
+                }
+
+                return Some(result);
+            }
+        }
+    }
+
+    None
+}
+
+pub fn timing_to_js<'a, C: Context<'a>>(cx: &mut C, timing: &[(&str, f64)]) -> JsResult<'a, JsObject> {
+    let obj = cx.empty_object();
+    for (name, ms) in timing {
+        let js_ms = cx.number(*ms);
+        obj.set(cx, *name, js_ms)?;
+    }
+
+    Ok(obj)
+}
+
+fn set_number<'a>(cx: &mut FunctionContext<'a>, obj: Handle<'a, JsObject>, key: &str, value: f64) -> NeonResult<()> {
+    let js_value = cx.number(value);
+    obj.set(cx, key, js_value)?;
+
+    Ok(())
+}
+
+fn v0_to_js<'a>(cx: &mut FunctionContext<'a>, obj: Handle<'a, JsObject>, info: &TCP_INFO_v0) -> NeonResult<()> {
+    set_number(cx, obj, "state", info.State.0 as f64)?;
+    set_number(cx, obj, "mss", info.Mss as f64)?;
+    set_number(cx, obj, "connectionTimeMs", info.ConnectionTimeMs as f64)?;
+    set_number(cx, obj, "rttUs", info.RttUs as f64)?;
+    set_number(cx, obj, "minRttUs", info.MinRttUs as f64)?;
+    set_number(cx, obj, "bytesInFlight", info.BytesInFlight as f64)?;
+    set_number(cx, obj, "cwnd", info.Cwnd as f64)?;
+    set_number(cx, obj, "sndWnd", info.SndWnd as f64)?;
+    set_number(cx, obj, "rcvWnd", info.RcvWnd as f64)?;
+    set_number(cx, obj, "rcvBuf", info.RcvBuf as f64)?;
+    set_number(cx, obj, "bytesOut", info.BytesOut as f64)?;
+    set_number(cx, obj, "bytesIn", info.BytesIn as f64)?;
+    set_number(cx, obj, "bytesReordered", info.BytesReordered as f64)?;
+    set_number(cx, obj, "bytesRetrans", info.BytesRetrans as f64)?;
+    set_number(cx, obj, "fastRetrans", info.FastRetrans as f64)?;
+    set_number(cx, obj, "dupAcksIn", info.DupAcksIn as f64)?;
+    set_number(cx, obj, "timeoutEpisodes", info.TimeoutEpisodes as f64)?;
+    set_number(cx, obj, "synRetrans", info.SynRetrans as f64)?;
+
+    let js_timestamps = cx.boolean(info.TimestampsEnabled.as_bool());
+    obj.set(cx, "timestamps", js_timestamps)?;
+
+    Ok(())
+}
+
+fn v1_to_js<'a>(cx: &mut FunctionContext<'a>, obj: Handle<'a, JsObject>, info: &TCP_INFO_v1) -> NeonResult<()> {
+    set_number(cx, obj, "state", info.State.0 as f64)?;
+    set_number(cx, obj, "mss", info.Mss as f64)?;
+    set_number(cx, obj, "connectionTimeMs", info.ConnectionTimeMs as f64)?;
+    set_number(cx, obj, "rttUs", info.RttUs as f64)?;
+    set_number(cx, obj, "minRttUs", info.MinRttUs as f64)?;
+    set_number(cx, obj, "bytesInFlight", info.BytesInFlight as f64)?;
+    set_number(cx, obj, "cwnd", info.Cwnd as f64)?;
+    set_number(cx, obj, "sndWnd", info.SndWnd as f64)?;
+    set_number(cx, obj, "rcvWnd", info.RcvWnd as f64)?;
+    set_number(cx, obj, "rcvBuf", info.RcvBuf as f64)?;
+    set_number(cx, obj, "bytesOut", info.BytesOut as f64)?;
+    set_number(cx, obj, "bytesIn", info.BytesIn as f64)?;
+    set_number(cx, obj, "bytesReordered", info.BytesReordered as f64)?;
+    set_number(cx, obj, "bytesRetrans", info.BytesRetrans as f64)?;
+    set_number(cx, obj, "fastRetrans", info.FastRetrans as f64)?;
+    set_number(cx, obj, "dupAcksIn", info.DupAcksIn as f64)?;
+    set_number(cx, obj, "timeoutEpisodes", info.TimeoutEpisodes as f64)?;
+    set_number(cx, obj, "synRetrans", info.SynRetrans as f64)?;
+    set_number(cx, obj, "sndLimTransRwin", info.SndLimTransRwin as f64)?;
+    set_number(cx, obj, "sndLimTimeRwin", info.SndLimTimeRwin as f64)?;
+    set_number(cx, obj, "sndLimBytesRwin", info.SndLimBytesRwin as f64)?;
+    set_number(cx, obj, "sndLimTransCwnd", info.SndLimTransCwnd as f64)?;
+    set_number(cx, obj, "sndLimTimeCwnd", info.SndLimTimeCwnd as f64)?;
+    set_number(cx, obj, "sndLimBytesCwnd", info.SndLimBytesCwnd as f64)?;
+    set_number(cx, obj, "sndLimTransSnd", info.SndLimTransSnd as f64)?;
+    set_number(cx, obj, "sndLimTimeSnd", info.SndLimTimeSnd as f64)?;
+    set_number(cx, obj, "sndLimBytesSnd", info.SndLimBytesSnd as f64)?;
+
+    let js_timestamps = cx.boolean(info.TimestampsEnabled.as_bool());
+    obj.set(cx, "timestamps", js_timestamps)?;
+
+    Ok(())
+}
+
+fn http_request_tcp_info(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let mut i = 0;
+    let arc = cx.import::<Request>(&mut i)?;
+    let id = cx.arg_u64(&mut i)?;
+    let obj = cx.empty_object();
+    match arc.query::<TCP_INFO_v1>(id, HttpRequestPropertyTcpInfoV1) {
+        Ok(info) => {
+            set_number(&mut cx, obj, "version", 1.0)?;
+            v1_to_js(&mut cx, obj, &info)?;
+        },
+        Err(_) => match arc.query::<TCP_INFO_v0>(id, HttpRequestPropertyTcpInfoV0) {
+            Ok(info) => {
+                set_number(&mut cx, obj, "version", 0.0)?;
+                v0_to_js(&mut cx, obj, &info)?;
+            },
+            Err((hint, err)) => return cx.throw_type_error(format!("{}: {}", hint, err)),
+        },
+    }
+
+    Ok(obj)
+}
+
+pub fn tcpinfo_bind(cx: &mut ModuleContext) -> NeonResult<()> {
+    cx.export_function("http_request_tcp_info", http_request_tcp_info)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ffi::c_void;
+    use std::mem::size_of;
+
+    fn timing(ticks: &[(usize, u64)]) -> Option<Vec<(&'static str, f64)>> {
+        let mut timing = HTTP_REQUEST_TIMING_INFO::default();
+        timing.RequestTimingCount = 27;
+        for (i, value) in ticks {
+            timing.RequestTiming[*i] = *value;
+        }
+
+        let mut info = HTTP_REQUEST_INFO {
+            InfoType: HttpRequestInfoTypeRequestTiming,
+            InfoLength: size_of::<HTTP_REQUEST_TIMING_INFO>() as u32,
+            pInfo: &mut timing as *mut _ as *mut c_void,
+        };
+
+        let mut req = HTTP_REQUEST_V2::default();
+        req.RequestInfoCount = 1;
+        req.pRequestInfo = &mut info;
+        request_timing(&req)
+    }
+
+    #[test]
+    fn phases() {
+        let result = timing(&[(0, 1000), (15, 1100), (16, 1300), (25, 1500), (26, 2000)]).unwrap();
+        let names: Vec<_> = result.iter().map(|(x, _)| *x).collect();
+        assert_eq!(names, vec!["requestHeaderParse", "requestQueue", "total"]);
+
+        let parse = result[0].1;
+        assert!(parse > 0.0);
+        assert!((result[1].1 / parse - 2.5).abs() < 1e-9);
+        assert!((result[2].1 / parse - 5.0).abs() < 1e-9);
+
+        let backwards = timing(&[(0, 1000), (26, 500)]).unwrap();
+        assert!(backwards.is_empty());
+
+        let mut none: [HTTP_REQUEST_INFO; 0] = [];
+        let mut req = HTTP_REQUEST_V2::default();
+        req.pRequestInfo = none.as_mut_ptr();
+        assert!(request_timing(&req).is_none());
+    }
+}
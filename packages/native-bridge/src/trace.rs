@@ -0,0 +1,231 @@
+use super::support::*;
+
+use neon::prelude::*;
+use tracing::field::Field;
+use tracing::field::Visit;
+use tracing::span::Attributes;
+use tracing::span::Id;
+use tracing::span::Record;
+use tracing::Event;
+use tracing::Level;
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context as LayerContext;
+use tracing_subscriber::layer::Layer;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+
+use std::fmt::Debug;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::atomic::AtomicU8;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Once;
+
+const LEVEL_NAMES: [&str; 6] = ["off", "error", "warn", "info", "debug", "trace"];
+
+static LEVEL: AtomicU8 = AtomicU8::new(0);
+static SINK: Mutex<Option<Sink>> = Mutex::new(None);
+static INSTALL: Once = Once::new();
+
+enum Sink {
+    File(File),
+    Callback(Channel, Arc<Root<JsFunction>>),
+}
+
+fn level_rank(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 1,
+        Level::WARN => 2,
+        Level::INFO => 3,
+        Level::DEBUG => 4,
+        Level::TRACE => 5,
+    }
+}
+
+#[derive(Default)]
+struct Fields(String);
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if self.0.len() > 0 {
+            self.0.push(' ');
+        }
+
+        if field.name() == "message" {
+            self.0.push_str(&format!("{:?}", value));
+        } else {
+            self.0.push_str(&format!("{}={:?}", field.name(), value));
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record_debug(field, &value);
+    }
+}
+
+struct Bridge;
+
+impl<S> Layer<S> for Bridge where S: Subscriber + for<'a> LookupSpan<'a> {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, cx: LayerContext<'_, S>) {
+        if let Some(span) = cx.span(id) {
+            let mut fields = Fields::default();
+            attrs.record(&mut fields);
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, cx: LayerContext<'_, S>) {
+        if let Some(span) = cx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<Fields>() {
+                values.record(fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, cx: LayerContext<'_, S>) {
+        let level = event.metadata().level();
+        if level_rank(level) > LEVEL.load(Relaxed) {
+            return;
+        }
+
+        let mut line = format!("{} {}", level, event.metadata().target());
+        if let Some(scope) = cx.event_scope(event) {
+            for span in scope.from_root() {
+                line.push_str(&format!(" {}", span.name()));
+                if let Some(fields) = span.extensions().get::<Fields>() {
+                    if fields.0.len() > 0 {
+                        line.push_str(&format!("{{{}}}", fields.0));
+                    }
+                }
+            }
+        }
+
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        line.push_str(": ");
+        line.push_str(&fields.0);
+
+        emit(level, line);
+    }
+}
+
+fn emit(level: &Level, line: String) {
+    let sink = match SINK.lock() {
+        Ok(sink) => sink,
+        Err(_) => return,
+    };
+
+    match sink.as_ref() {
+        Some(Sink::File(file)) => {
+            let mut file = file;
+            writeln!(file, "{}", line).ok();
+        },
+        Some(Sink::Callback(channel, callback)) => {
+            let level = LEVEL_NAMES[level_rank(level) as usize];
+            let callback = callback.clone();
+            channel.send(move |mut cx| {
+                let this = cx.undefined();
+                let js_level = cx.string(level);
+                let js_line = cx.string(line);
+                let args = vec![js_level.upcast::<JsValue>(), js_line.upcast()];
+                callback.to_inner(&mut cx).call(&mut cx, this, args)?;
+                Ok(())
+            });
+        },
+        None => (),
+    }
+}
+
+fn install() {
+    INSTALL.call_once(|| {
+        let subscriber = tracing_subscriber::registry().with(Bridge);
+        tracing::subscriber::set_global_default(subscriber).ok();
+    });
+}
+
+fn trace_config(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let mut i = 0;
+    let level = cx.arg_string(&mut i)?;
+    let rank = match LEVEL_NAMES.iter().position(|x| x.eq_ignore_ascii_case(&level)) {
+        Some(rank) => rank as u8,
+        None => return cx.throw_type_error(format!("Unknown trace level: {}", level)),
+    };
+
+    let mut sink = None;
+    if cx.arg_opt(&mut i) {
+        let target = cx.argument::<JsValue>(i)?;
+        if let Ok(callback) = target.downcast::<JsFunction, _>(&mut cx) {
+            // Tracing must not keep the event loop alive on its own.
+            let mut channel = cx.channel();
+            channel.unref(&mut cx);
+            sink = Some(Sink::Callback(channel, Arc::new(callback.root(&mut cx))));
+        } else {
+            let path = cx.arg_string(&mut i)?;
+            let file = OpenOptions::new().create(true).append(true).open(&path);
+            match file {
+                Ok(file) => sink = Some(Sink::File(file)),
+                Err(err) => return cx.throw_type_error(format!("{}: {}", path, err)),
+            }
+        }
+    }
+
+    if sink.is_none() {
+        LEVEL.store(0, Relaxed);
+    } else {
+        install();
+        LEVEL.store(rank, Relaxed);
+    }
+
+    let previous = match SINK.lock() {
+        Ok(mut value) => std::mem::replace(&mut *value, sink),
+        Err(_) => None,
+    };
+
+    drop(previous);
+    Ok(cx.undefined())
+}
+
+pub fn trace_bind(cx: &mut ModuleContext) -> NeonResult<()> {
+    cx.export_function("trace_config", trace_config)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tracing::debug_span;
+    use tracing::trace;
+
+    #[test]
+    fn bridge_formats_and_filters() {
+        let path = std::env::temp_dir().join(format!("plugin-trace-{}.log", std::process::id()));
+        let file = OpenOptions::new().create(true).write(true).truncate(true).open(&path).unwrap();
+        *SINK.lock().unwrap() = Some(Sink::File(file));
+
+        let subscriber = tracing_subscriber::registry().with(Bridge);
+        tracing::subscriber::with_default(subscriber, || {
+            LEVEL.store(5, Relaxed);
+            let span = debug_span!(parent: None, "send", id = 7u64, flags = 2u32);
+            let _guard = span.enter();
+            trace!(err = 0u32, "issue");
+
+            LEVEL.store(4, Relaxed);
+            trace!(err = 1u32, "hidden");
+        });
+
+        *SINK.lock().unwrap() = None;
+        LEVEL.store(0, Relaxed);
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("TRACE "));
+        assert!(lines[0].ends_with(" send{id=7 flags=2}: issue err=0"));
+    }
+}
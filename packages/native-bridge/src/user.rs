@@ -66,29 +66,23 @@ unsafe fn resize<T>(vec: &mut Vec<T>, size: usize) -> *mut c_void {
     return vec.as_mut_ptr() as *mut c_void;
 }
 
-unsafe fn add_type_sid<'a, T>(cx: &mut T, name: Handle<'a, JsString>, psid: PSID) -> JsResult<'a, JsArray> where T: Context<'a> {
+unsafe fn sid_string(psid: PSID) -> Option<String> {
     let mut value = PSTR::null();
-    let list = cx.empty_array();
-    let result = ConvertSidToStringSidA(psid, &mut value);
-    if result.as_bool() {
+    let mut result = None;
+    if ConvertSidToStringSidA(psid, &mut value).as_bool() {
         if let Ok(sid) = value.to_string() {
-            list.set(cx, 0, name)?;
-            
-            let value = cx.string(sid);
-            list.set(cx, 1, value)?;
+            result = Some(sid);
         }
 
         LocalFree(value.as_ptr() as isize);
     }
 
-    return Ok(list)
+    result
 }
 
-pub fn user_groups_internal<'a, T>(cx: &mut T, handle: HANDLE, user_only: bool) -> JsResult<'a, JsValue> where T: Context<'a> {
+pub fn token_groups_internal(handle: HANDLE, user_only: bool) -> Vec<(&'static str, String)> {
     unsafe {
-        let js_result = cx.empty_array();
-        let js_user = cx.string("user");
-        let mut i = 0;
+        let mut list = Vec::new();
         let mut size = 0;
         let mut buf = Vec::<u8>::new();
         let mut ptr = resize(&mut buf, size as usize);
@@ -100,23 +94,15 @@ pub fn user_groups_internal<'a, T>(cx: &mut T, handle: HANDLE, user_only: bool)
 
         if result.as_bool() {
             let user = &*(ptr as *const TOKEN_USER);
-            let list = add_type_sid(cx, js_user, user.User.Sid)?;
-            if list.len(cx) > 0 {
-                js_result.set(cx, i, list)?;
-                i += 1;
-
-                if user_only {
-                    return list.get(cx, 1);
-                }
+            if let Some(sid) = sid_string(user.User.Sid) {
+                list.push(("user", sid));
             }
         }
 
         if user_only {
-            return Ok(cx.undefined().upcast());
+            return list;
         }
 
-        let js_group = cx.string("group");
-        let js_deny_only = cx.string("deny-only-group");
         let mut result = GetTokenInformation(handle, TokenGroups, Some(ptr), buf.capacity() as u32, &mut size);
         if !result.as_bool() && GetLastError() == ERROR_INSUFFICIENT_BUFFER {
             ptr = resize(&mut buf, size as usize);
@@ -128,24 +114,64 @@ pub fn user_groups_internal<'a, T>(cx: &mut T, handle: HANDLE, user_only: bool)
             let slice = from_raw_parts(groups.Groups.as_ptr(), groups.GroupCount as usize);
             for group in slice {
                 if group.Attributes & 4 != 0 {
-                    let list = add_type_sid(cx, js_group, group.Sid)?;
-                    if list.len(cx) > 0 {
-                        js_result.set(cx, i, list)?;
-                        i += 1;
+                    if let Some(sid) = sid_string(group.Sid) {
+                        list.push(("group", sid));
                     }
                 }
 
                 if group.Attributes & 16 != 0 {
-                    let list = add_type_sid(cx, js_deny_only, group.Sid)?;
-                    if list.len(cx) > 0 {
-                        js_result.set(cx, i, list)?;
-                        i += 1;
+                    if let Some(sid) = sid_string(group.Sid) {
+                        list.push(("deny-only-group", sid));
                     }
                 }
             }
         }
 
-        Ok(js_result.upcast())
+        list
+    }
+}
+
+pub fn groups_to_js<'a, T>(cx: &mut T, groups: &[(&str, String)]) -> JsResult<'a, JsArray> where T: Context<'a> {
+    let js_result = cx.empty_array();
+    for (i, (kind, sid)) in groups.iter().enumerate() {
+        let list = cx.empty_array();
+        let js_kind = cx.string(kind);
+        list.set(cx, 0, js_kind)?;
+
+        let js_sid = cx.string(sid);
+        list.set(cx, 1, js_sid)?;
+        js_result.set(cx, i as u32, list)?;
+    }
+
+    Ok(js_result)
+}
+
+pub fn user_groups_internal<'a, T>(cx: &mut T, handle: HANDLE, user_only: bool) -> JsResult<'a, JsValue> where T: Context<'a> {
+    let groups = token_groups_internal(handle, user_only);
+    if user_only {
+        if let Some((_, sid)) = groups.first() {
+            return Ok(cx.string(sid).upcast());
+        }
+
+        return Ok(cx.undefined().upcast());
+    }
+
+    Ok(groups_to_js(cx, &groups)?.upcast())
+}
+
+pub fn lookup_sid_internal(sid: &str) -> Option<String> {
+    unsafe {
+        let mut psid = PSID::default();
+        let sid_str = wide(sid);
+        let converted = ConvertStringSidToSidW(wide_ptr(&sid_str), &mut psid);
+        let mut result = None;
+        if converted.as_bool() {
+            let mut resolver = Resolver::new();
+            result = resolver.resolve(psid);
+            LocalFree(psid.0 as isize);
+        }
+
+        result
     }
 }
 
@@ -193,32 +219,17 @@ fn user_close(mut cx: FunctionContext) -> JsResult<JsUndefined> {
 }
 
 fn user_lookup_sid(mut cx: FunctionContext) -> JsResult<JsValue> {
-    unsafe {
-        let mut i = 0;
-        let sid = cx.arg_string(&mut i)?;
-        let builder = cx.task(move || {
-            let mut psid = PSID::default();
-            let sid_str = wide(&sid);
-            let converted = ConvertStringSidToSidW(wide_ptr(&sid_str), &mut psid);
-            let mut result = String::from("");
-            if converted.as_bool() {
-                let mut resolver = Resolver::new();
-                if let Some(value) = resolver.resolve(psid) {
-                    result = value;
-                }
+    let mut i = 0;
+    let sid = cx.arg_string(&mut i)?;
+    let builder = cx.task(move || {
+        lookup_sid_internal(&sid).unwrap_or_default()
+    });
 
-                LocalFree(psid.0 as isize);
-            }
-    
-            result
-        });
-
-        let promise = builder.promise(move |mut cx, value| {
-            Ok(cx.string(value))
-        });
+    let promise = builder.promise(move |mut cx, value| {
+        Ok(cx.string(value))
+    });
 
-        Ok(promise.upcast())
-    }
+    Ok(promise.upcast())
 }
 
 pub fn user_bind(cx: &mut ModuleContext) -> NeonResult<()> {
@@ -228,3 +239,38 @@ pub fn user_bind(cx: &mut ModuleContext) -> NeonResult<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process_token() -> HANDLE {
+        unsafe {
+            let mut token = HANDLE::default();
+            assert!(OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token).as_bool());
+            token
+        }
+    }
+
+    #[test]
+    fn token_groups() {
+        let token = process_token();
+        let user = token_groups_internal(token, true);
+        let all = token_groups_internal(token, false);
+        unsafe {
+            CloseHandle(token);
+        }
+
+        assert_eq!(user.len(), 1);
+        assert_eq!(user[0].0, "user");
+        assert!(user[0].1.starts_with("S-1-"));
+        assert_eq!(all[0], user[0]);
+        assert!(all.iter().any(|(kind, sid)| *kind == "group" && sid == "S-1-1-0"));
+    }
+
+    #[test]
+    fn lookups() {
+        assert!(lookup_sid_internal("S-1-5-18").unwrap().ends_with("SYSTEM"));
+        assert_eq!(lookup_sid_internal("not a sid"), None);
+    }
+}
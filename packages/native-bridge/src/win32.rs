@@ -1,12 +1,17 @@
 use neon::types::Finalize;
+use tracing::debug;
+use tracing::Span;
 
 use windows::core::PCWSTR;
 use windows::Win32::Foundation::*;
+use windows::Win32::Networking::HttpServer::HTTP_REQUEST_PROPERTY;
 use windows::Win32::System::IO::*;
 
+use std::ffi::c_void;
 use std::ffi::OsString;
 use std::os::windows::ffi::OsStrExt;
 use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicI64;
 use std::sync::atomic::Ordering::Relaxed;
 use std::sync::Arc;
 
@@ -21,6 +26,18 @@ pub fn wide_ptr(data: &Vec<u16>) -> PCWSTR {
     return PCWSTR(Vec::as_ptr(data));
 }
 
+// Not projected by the windows crate.
+#[link(name = "httpapi")]
+extern "system" {
+    pub fn HttpQueryRequestProperty(queue: HANDLE, id: u64, property: HTTP_REQUEST_PROPERTY, qualifier: *const c_void, qualifier_size: u32, output: *mut c_void, output_size: u32, returned: *mut u32, overlapped: *mut OVERLAPPED) -> u32;
+}
+
+static PENDING_IO: AtomicI64 = AtomicI64::new(0);
+
+pub fn pending_io() -> i64 {
+    PENDING_IO.load(Relaxed)
+}
+
 struct OverlappedEx(OVERLAPPED, *mut (dyn FnOnce(*mut OVERLAPPED, u32) + Send + 'static));
 
 pub fn into_async<F>(f: F) -> *mut OVERLAPPED where F: FnOnce(*mut OVERLAPPED, u32) + Send + 'static {
@@ -54,7 +71,10 @@ impl HandleRef {
 
     pub fn wrap<F>(self: &Arc<Self>, f: F) -> *mut OVERLAPPED where F: FnOnce(u32, u32) + Send + 'static {
         let h = self.clone();
+        let span = Span::current();
+        PENDING_IO.fetch_add(1, Relaxed);
         into_async(move |o, err| unsafe {
+            PENDING_IO.fetch_sub(1, Relaxed);
             let mut result = err;
             let mut size = 0u32;
             if result == ERROR_IO_PENDING.0 {
@@ -70,6 +90,7 @@ impl HandleRef {
                 }
             }
     
+            span.in_scope(|| debug!(err = result, size, "complete"));
             f(result, size);
         })
     }
@@ -114,3 +135,28 @@ unsafe impl<T> Send for SendRef<T> {
 unsafe impl<T> Sync for SendRef<T> {
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn wide_strings() {
+        assert_eq!(wide("ab"), vec![97, 98, 0]);
+        assert_eq!(wide(""), vec![0]);
+        assert_eq!(wide("\u{1F600}").len(), 3);
+
+        let value = wide("queue");
+        assert_eq!(unsafe { wide_ptr(&value).to_string() }.unwrap(), "queue");
+    }
+
+    #[test]
+    fn async_callbacks() {
+        let (tx, rx) = channel();
+        let ptr = into_async(move |o, err| tx.send((o as usize, err)).unwrap());
+        call_async(ptr, 87);
+        assert_eq!(rx.recv().unwrap(), (ptr as usize, 87));
+    }
+}
@@ -0,0 +1,312 @@
+use neon::prelude::*;
+use neon::types::buffer::*;
+use neon::types::Deferred;
+
+use super::codec::*;
+use super::http::*;
+use super::message::*;
+use super::support::*;
+use super::user::*;
+
+use windows::Win32::Foundation::*;
+use windows::Win32::Networking::HttpServer::*;
+
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::mpsc::channel;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread::spawn;
+
+type WireJob = Box<dyn FnOnce(&mut WireState) + Send>;
+
+struct WireState {
+    req: Arc<Request>,
+    id: u64,
+    decoder: ResponseDecoder,
+    upgraded: Arc<AtomicBool>,
+}
+
+impl WireState {
+    fn apply(&self, events: Vec<WireEvent>) -> u32 {
+        let decoder = &self.decoder;
+        let chunked = decoder.chunked();
+        for event in events {
+            let (err, _) = match event {
+                WireEvent::Head(reply) => {
+                    let more = !decoder.empty();
+                    let mut flags = if more { HTTP_SEND_RESPONSE_FLAG_MORE_DATA } else { 0 };
+                    if decoder.opaque() {
+                        flags |= HTTP_SEND_RESPONSE_FLAG_OPAQUE;
+                    }
+
+                    let result = block_on(|done| {
+                        reply.send(&self.req, self.id, flags, move |err, size| done((err, size)));
+                    }).unwrap_or((ERROR_OPERATION_ABORTED.0, 0));
+
+                    if result.0 == 0 && decoder.opaque() {
+                        self.upgraded.store(true, Relaxed);
+                    }
+
+                    result
+                },
+                WireEvent::Data(data) => {
+                    let data = if chunked { encode_chunk(&data) } else { data };
+                    block_on(|done| {
+                        send_body(&self.req, self.id, HTTP_SEND_RESPONSE_FLAG_MORE_DATA, vec![data], Vec::new(), move |err, size| done((err, size)));
+                    }).unwrap_or((ERROR_OPERATION_ABORTED.0, 0))
+                },
+                WireEvent::End(trailers) => {
+                    if decoder.empty() {
+                        continue;
+                    }
+
+                    let mut tail = Vec::new();
+                    if chunked && trailers.len() > 0 {
+                        tail.push(b"0\r\n".to_vec());
+                    } else if chunked {
+                        tail.push(LAST_CHUNK.to_vec());
+                    }
+
+                    let flags = if decoder.delimited() { HTTP_SEND_RESPONSE_FLAG_DISCONNECT } else { 0 };
+
+                    block_on(|done| {
+                        send_body(&self.req, self.id, flags, tail, trailers, move |err, size| done((err, size)));
+                    }).unwrap_or((ERROR_OPERATION_ABORTED.0, 0))
+                },
+            };
+
+            if err != 0 {
+                return err;
+            }
+        }
+
+        0
+    }
+
+    // An upstream that fails before its head was relayed still gets the client a response.
+    fn fail(&self, msg: String) -> String {
+        if !self.decoder.started() {
+            let reply = Reply::new(502, "Bad Gateway").header("Content-Length", "0");
+            block_on(|done| {
+                reply.send(&self.req, self.id, HTTP_SEND_RESPONSE_FLAG_DISCONNECT, move |err, size| done((err, size)));
+            }).ok();
+        }
+
+        msg
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<u32, String> {
+        let events = self.decoder.push(data).map_err(|x| self.fail(x))?;
+        Ok(self.apply(events))
+    }
+
+    fn end(&mut self) -> Result<u32, String> {
+        let events = self.decoder.finish().map_err(|x| self.fail(x))?;
+        Ok(self.apply(events))
+    }
+}
+
+// Each responder owns one worker thread that runs its writes in order; the thread exits once the
+// responder is closed and the queue drains.
+pub struct WireResponder {
+    jobs: Mutex<Sender<WireJob>>,
+    upgraded: Arc<AtomicBool>,
+}
+
+impl Finalize for WireResponder {}
+
+impl WireResponder {
+    pub fn new(req: Arc<Request>, id: u64, head_only: bool) -> Self {
+        let (tx, rx) = channel::<WireJob>();
+        let upgraded = Arc::new(AtomicBool::new(false));
+        let mut state = WireState {
+            req,
+            id,
+            decoder: ResponseDecoder::new(head_only),
+            upgraded: upgraded.clone(),
+        };
+
+        spawn(move || {
+            for job in rx {
+                job(&mut state);
+            }
+        });
+
+        Self { jobs: Mutex::new(tx), upgraded }
+    }
+
+    fn run<F>(&self, f: F) -> bool where F: FnOnce(&mut WireState) + Send + 'static {
+        match self.jobs.lock() {
+            Ok(jobs) => jobs.send(Box::new(f)).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    pub fn upgraded(&self) -> bool {
+        self.upgraded.load(Relaxed)
+    }
+}
+
+fn http_wire_receive(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let arc = cx.import::<Request>(&mut i)?;
+    let size = cx.arg_u32(&mut i)?;
+    let tx = cx.channel();
+    let (def, promise) = cx.promise();
+    arc.receive_admitted(size, move |err, vec, result| {
+        let mut info_opt = None;
+        let mut user_sid = None;
+        if err == 0 {
+            info_opt = Some(RequestInfo::from(&result.0));
+            if let Some(user) = find_user_token(&result.0) {
+                user_sid = token_groups_internal(user.0, true).first().map(|x| x.1.clone());
+            }
+        }
+
+        drop(vec);
+        def.settle_with(&tx, move |mut cx| {
+            let obj = cx.empty_object();
+            let js_err = cx.number(err);
+            obj.set(&mut cx, "code", js_err)?;
+
+            if let Some(info) = info_opt {
+                let (head, chunked) = encode_request_head(&info);
+                let js_id = cx.boxed(info.id);
+                obj.set(&mut cx, "id", js_id)?;
+
+                let mut js_head = cx.buffer(head.len())?;
+                js_head.as_mut_slice(&mut cx).copy_from_slice(&head);
+                obj.set(&mut cx, "head", js_head)?;
+
+                let js_chunked = cx.boolean(chunked);
+                obj.set(&mut cx, "chunked", js_chunked)?;
+
+                let js_body = cx.boolean(info.body);
+                obj.set(&mut cx, "body", js_body)?;
+
+                let js_verb = cx.string(&info.verb);
+                obj.set(&mut cx, "verb", js_verb)?;
+
+                let js_upgrade = cx.boolean(is_upgrade(&info));
+                obj.set(&mut cx, "upgrade", js_upgrade)?;
+
+                if let Some(sid) = user_sid {
+                    let js_sid = cx.string(sid);
+                    obj.set(&mut cx, "user_sid", js_sid)?;
+                }
+            }
+
+            Ok(obj)
+        });
+    });
+
+    Ok(promise)
+}
+
+fn http_wire_read(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let arc = cx.import::<Request>(&mut i)?;
+    let id = cx.arg_u64(&mut i)?;
+    let chunked = cx.arg_bool(&mut i)?;
+    let size = cx.arg_u32(&mut i)?.max(4096) as usize;
+    let tx = cx.channel();
+    let (def, promise) = cx.promise();
+    // The buffer moves into the completion, so only HTTP.sys touches it until the read finishes.
+    let mut buf = vec![0u8; size];
+    let slice = Slice::new(&mut buf);
+    arc.receive_data(id, slice, move |err, size| {
+        let eof = err == ERROR_HANDLE_EOF.0 || (err == 0 && size == 0);
+        let mut data = buf[..size as usize].to_vec();
+        if chunked && eof {
+            data = LAST_CHUNK.to_vec();
+        } else if chunked && err == 0 {
+            data = encode_chunk(&data);
+        }
+
+        def.settle_with(&tx, move |mut cx| {
+            let obj = cx.empty_object();
+            let code = if eof { 0 } else { err };
+            let js_err = cx.number(code);
+            obj.set(&mut cx, "code", js_err)?;
+
+            let js_eof = cx.boolean(eof);
+            obj.set(&mut cx, "eof", js_eof)?;
+
+            let mut js_data = cx.buffer(data.len())?;
+            js_data.as_mut_slice(&mut cx).copy_from_slice(&data);
+            obj.set(&mut cx, "data", js_data)?;
+
+            Ok(obj)
+        });
+    });
+
+    Ok(promise)
+}
+
+fn http_wire_responder(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let mut i = 0;
+    let req = cx.import::<Request>(&mut i)?;
+    let id = cx.arg_u64(&mut i)?;
+    let head_only = cx.arg_bool(&mut i)?;
+    Ok(cx.export(WireResponder::new(req, id, head_only)))
+}
+
+fn settle_result(def: Deferred, tx: Channel, result: Result<u32, String>) {
+    def.settle_with(&tx, move |mut cx| {
+        match result {
+            Ok(err) => Ok(cx.number(err)),
+            Err(msg) => cx.throw_type_error(msg),
+        }
+    });
+}
+
+fn http_wire_write(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let arc = cx.import::<WireResponder>(&mut i)?;
+    let block = cx.arg_buffer(&mut i)?;
+    let data = block.as_slice(&cx).to_vec();
+    let tx = cx.channel();
+    let (def, promise) = cx.promise();
+    if !arc.run(move |state| settle_result(def, tx, state.write(&data))) {
+        return cx.throw_type_error("Responder closed.");
+    }
+
+    Ok(promise)
+}
+
+fn http_wire_end(mut cx: FunctionContext) -> JsResult<JsPromise> {
+    let mut i = 0;
+    let arc = cx.import::<WireResponder>(&mut i)?;
+    let tx = cx.channel();
+    let (def, promise) = cx.promise();
+    if !arc.run(move |state| settle_result(def, tx, state.end())) {
+        return cx.throw_type_error("Responder closed.");
+    }
+
+    Ok(promise)
+}
+
+fn http_wire_upgraded(mut cx: FunctionContext) -> JsResult<JsBoolean> {
+    let mut i = 0;
+    let arc = cx.import::<WireResponder>(&mut i)?;
+    Ok(cx.boolean(arc.upgraded()))
+}
+
+fn http_wire_close(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    cx.dispose::<WireResponder>(0)?;
+    Ok(cx.undefined())
+}
+
+pub fn wire_bind(cx: &mut ModuleContext) -> NeonResult<()> {
+    cx.export_function("http_wire_receive", http_wire_receive)?;
+    cx.export_function("http_wire_read", http_wire_read)?;
+    cx.export_function("http_wire_responder", http_wire_responder)?;
+    cx.export_function("http_wire_write", http_wire_write)?;
+    cx.export_function("http_wire_end", http_wire_end)?;
+    cx.export_function("http_wire_upgraded", http_wire_upgraded)?;
+    cx.export_function("http_wire_close", http_wire_close)?;
+
+    Ok(())
+}